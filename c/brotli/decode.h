@@ -13,6 +13,7 @@
 #define BROTLI_DEC_DECODE_H_
 
 #include <brotli/port.h>
+#include <brotli/shared_dictionary.h>
 #include <brotli/types.h>
 
 #if defined(__cplusplus) || defined(c_plusplus)
@@ -45,7 +46,13 @@ typedef enum {
   /** Partially done; should be called again with more input. */
   BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT = 2,
   /** Partially done; should be called again with more output. */
-  BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT = 3
+  BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT = 3,
+  /**
+   * Partially done; the work budget of ::BrotliDecoderDecompressStreamBudgeted
+   * ran out. Only that function returns it. Should be called again, as after
+   * ::BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT.
+   */
+  BROTLI_DECODER_RESULT_YIELD = 4
 } BrotliDecoderResult;
 
 /**
@@ -91,8 +98,9 @@ typedef enum {
   BROTLI_ERROR_CODE(_ERROR_FORMAT_, PADDING_2, -15) SEPARATOR              \
   BROTLI_ERROR_CODE(_ERROR_FORMAT_, DISTANCE, -16) SEPARATOR               \
                                                                            \
-  /* -17..-18 codes are reserved */                                        \
+  /* -17 code is reserved */                                               \
                                                                            \
+  BROTLI_ERROR_CODE(_ERROR_, COMPOUND_DICTIONARY, -18) SEPARATOR           \
   BROTLI_ERROR_CODE(_ERROR_, DICTIONARY_NOT_SET, -19) SEPARATOR            \
   BROTLI_ERROR_CODE(_ERROR_, INVALID_ARGUMENTS, -20) SEPARATOR             \
                                                                            \
@@ -167,6 +175,53 @@ typedef enum BrotliDecoderParameter {
 BROTLI_DEC_API BROTLI_BOOL BrotliDecoderSetParameter(
     BrotliDecoderState* state, BrotliDecoderParameter param, uint32_t value);
 
+/**
+ * Adds LZ77 prefix dictionary, adds or replaces built-in static dictionary and
+ * transforms.
+ *
+ * Up to ::SHARED_BROTLI_MAX_COMPOUND_DICTS prefixes can be attached; backward
+ * references that reach past the window address them, the last attached
+ * prefix being the closest. A serialized dictionary may also bring custom
+ * word lists and transforms, used for references past the prefixes in place
+ * of the built-in ones. Unlike the reference decoder, the data is copied,
+ * so it may be released as soon as this function returns.
+ *
+ * @note Dictionaries can NOT be attached after actual decoding is started.
+ *
+ * @param state decoder instance
+ * @param type dictionary data format
+ * @param data_size length of memory region pointed by @p data
+ * @param data dictionary data in format corresponding to @p type
+ * @returns ::BROTLI_FALSE if dictionary is corrupted,
+ *          or dictionary count limit is reached
+ * @returns ::BROTLI_TRUE if dictionary is accepted / attached
+ */
+BROTLI_DEC_API BROTLI_BOOL BrotliDecoderAttachDictionary(
+    BrotliDecoderState* state, BrotliSharedDictionaryType type,
+    size_t data_size, const uint8_t data[BROTLI_ARRAY_PARAM(data_size)]);
+
+/**
+ * Supplies the RFC 7932 static dictionary to a decoder built with the
+ * external-static-dictionary feature, which leaves it out of the library.
+ * Without it, streams that refer to the dictionary fail with
+ * ::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET.
+ *
+ * The data is not copied: it must stay valid and unchanged until the decoder
+ * is destroyed, and may be shared between decoders or kept in ROM.
+ *
+ * @note The dictionary can NOT be set after actual decoding is started.
+ *
+ * @param state decoder instance
+ * @param data_size length of memory region pointed by @p data
+ * @param data the static dictionary
+ * @returns ::BROTLI_FALSE if the length or SHA-256 of @p data is not that of
+ *          the static dictionary, or decoding has started
+ * @returns ::BROTLI_TRUE if the dictionary is accepted
+ */
+BROTLI_DEC_API BROTLI_BOOL BrotliDecoderSetStaticDictionary(
+    BrotliDecoderState* state,
+    size_t data_size, const uint8_t data[BROTLI_ARRAY_PARAM(data_size)]);
+
 /**
  * Creates an instance of ::BrotliDecoderState and initializes it.
  *
@@ -218,12 +273,81 @@ BROTLI_DEC_API BrotliDecoderResult BrotliDecoderDecompress(
     size_t* decoded_size,
     uint8_t decoded_buffer[BROTLI_ARRAY_PARAM(*decoded_size)]);
 
+/**
+ * Callback that supplies compressed input to
+ * ::BrotliDecoderDecompressWithCallbacks.
+ *
+ * @param opaque callback handle
+ * @param buffer destination for at most @p size compressed bytes
+ * @param size capacity of @p buffer
+ * @returns number of bytes stored in @p buffer, @c 0 at end of input, or a
+ *          negative value on error
+ */
+typedef ptrdiff_t (*brotli_decoder_read_func)(void* opaque, uint8_t* buffer,
+                                              size_t size);
+
+/**
+ * Callback that receives decompressed output from
+ * ::BrotliDecoderDecompressWithCallbacks.
+ *
+ * Partial writes are allowed; the remaining bytes are offered again.
+ *
+ * @param opaque callback handle
+ * @param data decompressed bytes, only valid during the call
+ * @param size number of bytes at @p data, at least @c 1
+ * @returns number of bytes consumed (at least @c 1), or a non-positive value
+ *          on error
+ */
+typedef ptrdiff_t (*brotli_decoder_write_func)(void* opaque,
+                                               const uint8_t* data,
+                                               size_t size);
+
+/** Result type for ::BrotliDecoderDecompressWithCallbacks. */
+typedef enum {
+  /** Corrupted or truncated input, or an internal decoder error. */
+  BROTLI_DECODER_CALLBACK_RESULT_ERROR = 0,
+  /** The whole stream was decoded and written. */
+  BROTLI_DECODER_CALLBACK_RESULT_SUCCESS = 1,
+  /** ::brotli_decoder_read_func reported an error. */
+  BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR = 2,
+  /** ::brotli_decoder_write_func reported an error. */
+  BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR = 3
+} BrotliDecoderCallbackResult;
+
+/**
+ * Decompresses a whole stream, pulling input from @p read_func and pushing
+ * output to @p write_func until the stream ends.
+ *
+ * Memory is obtained from the default allocator.
+ *
+ * @param read_func callback that supplies compressed data
+ * @param write_func callback that consumes decompressed data
+ * @param opaque callback handle passed to both callbacks
+ * @returns ::BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR or
+ *          ::BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR if a callback failed;
+ * @returns ::BROTLI_DECODER_CALLBACK_RESULT_ERROR if input is corrupted or
+ *          truncated, or a callback is @c NULL;
+ * @returns ::BROTLI_DECODER_CALLBACK_RESULT_SUCCESS otherwise
+ */
+BROTLI_DEC_API BrotliDecoderCallbackResult BrotliDecoderDecompressWithCallbacks(
+    brotli_decoder_read_func read_func,
+    brotli_decoder_write_func write_func,
+    void* opaque);
+
 BROTLI_DEC_API BrotliDecoderReturnInfo BrotliDecoderDecompressWithReturnInfo(
     size_t encoded_size,
     const uint8_t encoded_buffer[BROTLI_ARRAY_PARAM(encoded_size)],
     size_t decoded_size,
     uint8_t decoded_buffer[BROTLI_ARRAY_PARAM(decoded_size)]);
 
+/** The scratch area ::BrotliDecoderDecompressPreallocEx ran out of. */
+typedef enum {
+  BROTLI_DECODER_SCRATCH_NONE = 0,
+  BROTLI_DECODER_SCRATCH_U8 = 1,
+  BROTLI_DECODER_SCRATCH_U32 = 2,
+  BROTLI_DECODER_SCRATCH_HC = 3
+} BrotliDecoderScratchArea;
+
 BROTLI_DEC_API BrotliDecoderReturnInfo BrotliDecoderDecompressPrealloc(
     size_t encoded_size,
     const uint8_t encoded_buffer[BROTLI_ARRAY_PARAM(encoded_size)],
@@ -237,6 +361,58 @@ BROTLI_DEC_API BrotliDecoderReturnInfo BrotliDecoderDecompressPrealloc(
     HuffmanCode scratch_hc_buffer[BROTLI_ARRAY_PARAM(scratch_hc_size)]
     );
 
+/**
+ * ::BrotliDecoderDecompressPrealloc that also reports which scratch area
+ * ran out.
+ *
+ * If one of them runs out, the result carries the allocation error of the
+ * failed request and, unless @p exhausted is @c NULL, the area is stored in
+ * @p *exhausted.
+ */
+BROTLI_DEC_API BrotliDecoderReturnInfo BrotliDecoderDecompressPreallocEx(
+    size_t encoded_size,
+    const uint8_t encoded_buffer[BROTLI_ARRAY_PARAM(encoded_size)],
+    size_t decoded_size,
+    uint8_t decoded_buffer[BROTLI_ARRAY_PARAM(decoded_size)],
+    size_t scratch_u8_size,
+    uint8_t scratch_u8_buffer[BROTLI_ARRAY_PARAM(scratch_u8_size)],
+    size_t scratch_u32_size,
+    uint32_t scratch_u32_buffer[BROTLI_ARRAY_PARAM(scratch_u32_size)],
+    size_t scratch_hc_size,
+    HuffmanCode scratch_hc_buffer[BROTLI_ARRAY_PARAM(scratch_hc_size)],
+    BrotliDecoderScratchArea* exhausted);
+
+/** Sizes, in elements, of the scratch areas of ::BrotliDecoderDecompressPrealloc. */
+typedef struct BrotliDecoderScratchSizesStruct {
+    size_t u8_size;
+    size_t u32_size;
+    size_t hc_size;
+} BrotliDecoderScratchSizes;
+
+/**
+ * Scratch sizes with which ::BrotliDecoderDecompressPrealloc decodes any
+ * stream whose window is at most 2^@p window_bits bytes.
+ *
+ * Smaller scratch areas can make ::BrotliDecoderDecompressPrealloc fail
+ * with a @c BROTLI_DECODER_ERROR_ALLOC_* error.
+ */
+BROTLI_DEC_API BrotliDecoderScratchSizes BrotliDecoderPreallocScratchSizes(
+    uint32_t window_bits);
+
+/**
+ * Scratch sizes with which ::BrotliDecoderDecompressPrealloc decodes the
+ * stream starting at @p encoded_buffer.
+ *
+ * Only the stream header and the first meta-block header are read.
+ *
+ * @returns ::BROTLI_DECODER_SUCCESS and fills @p sizes, or the error that
+ *          makes the header unreadable
+ */
+BROTLI_DEC_API BrotliDecoderErrorCode BrotliDecoderPreallocScratchSizesForStream(
+    size_t encoded_size,
+    const uint8_t encoded_buffer[BROTLI_ARRAY_PARAM(encoded_size)],
+    BrotliDecoderScratchSizes* sizes);
+
 /**
  * Decompresses the input stream to the output stream.
  *
@@ -279,6 +455,27 @@ BROTLI_DEC_API BrotliDecoderResult BrotliDecoderDecompressStream(
   BrotliDecoderState* state, size_t* available_in, const uint8_t** next_in,
   size_t* available_out, uint8_t** next_out, size_t* total_out);
 
+/**
+ * Decompresses the input stream to the output stream, doing a bounded amount
+ * of work.
+ *
+ * Behaves like ::BrotliDecoderDecompressStream, except that it returns
+ * ::BROTLI_DECODER_RESULT_YIELD once it has decoded @p max_commands commands
+ * or @p max_output bytes, after writing out everything decoded so far. This
+ * lets a single-threaded caller interleave a long stream with other work.
+ * The budget is checked between commands, so a call may overrun it slightly.
+ *
+ * @param max_commands commands to decode per call; @c 0 means no limit
+ * @param max_output bytes to decode and write per call; @c 0 means no limit
+ * @returns ::BROTLI_DECODER_RESULT_YIELD if the budget ran out while output
+ *          space remained; call again with the updated arguments
+ * @returns otherwise, the same as ::BrotliDecoderDecompressStream
+ */
+BROTLI_DEC_API BrotliDecoderResult BrotliDecoderDecompressStreamBudgeted(
+  BrotliDecoderState* state, size_t* available_in, const uint8_t** next_in,
+  size_t* available_out, uint8_t** next_out, size_t* total_out,
+  size_t max_commands, size_t max_output);
+
 /**
  * Checks if decoder has more output.
  *
@@ -371,6 +568,87 @@ BROTLI_DEC_API BrotliDecoderErrorCode BrotliDecoderGetErrorCode(
 BROTLI_DEC_API const char* BrotliDecoderGetErrorString(
     const BrotliDecoderState* state);
 
+/** Where decoding stopped, see ::BrotliDecoderGetErrorInfo. */
+typedef struct BrotliDecoderErrorInfoStruct {
+  /** Last saved error code. */
+  BrotliDecoderErrorCode code;
+  /** Number of compressed bytes that were fully decoded. */
+  size_t compressed_offset;
+  /** Number of bits (0-7) of the next compressed byte that were decoded. */
+  uint32_t bit_position;
+  /** Number of bytes decompressed so far. */
+  size_t decompressed_offset;
+  /** Name of the decoder state, e.g. "STATE_CONTEXT_MAP_1". */
+  const char* state_name;
+} BrotliDecoderErrorInfo;
+
+/**
+ * Acquires the error code together with the position in the compressed and
+ * decompressed streams and the decoder state where decoding stopped.
+ *
+ * After ::BrotliDecoderDecompressStream returns ::BROTLI_DECODER_RESULT_ERROR
+ * the compressed position points at the bit that could not be decoded.
+ *
+ * @param state decoder instance
+ * @returns error details; the code is
+ *          ::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS if @p state is @c NULL
+ */
+BROTLI_DEC_API BrotliDecoderErrorInfo BrotliDecoderGetErrorInfo(
+    const BrotliDecoderState* state);
+
+/**
+ * Acquires the message of the last internal panic caught on the calling
+ * thread.
+ *
+ * Functions without a decoder instance report such panics only as
+ * ::BROTLI_DECODER_ERROR_UNREACHABLE or a generic error; this recovers the
+ * reason. The string stays valid until the next panic on the same thread.
+ *
+ * @returns panic message, or an empty string if there was none
+ */
+BROTLI_DEC_API const char* BrotliDecoderGetPanicMessage(void);
+
+/**
+ * Callback to fire on metadata block start.
+ *
+ * After this callback is fired, if @p size is not @c 0, it is followed by
+ * ::brotli_decoder_metadata_chunk_func as more metadata block contents become
+ * accessible.
+ *
+ * @param opaque callback handle
+ * @param size size of metadata block
+ */
+typedef void (*brotli_decoder_metadata_start_func)(void* opaque, size_t size);
+
+/**
+ * Callback to fire on metadata block chunk becomes available.
+ *
+ * This function can be invoked multiple times per metadata block; block should
+ * be considered finished when sum of @p size matches the announced metadata
+ * block size. Chunks contents pointed by @p data are transient and shouldn't
+ * be accessed after leaving the callback.
+ *
+ * @param opaque callback handle
+ * @param data pointer to metadata contents
+ * @param size size of metadata block chunk, at least @c 1
+ */
+typedef void (*brotli_decoder_metadata_chunk_func)(void* opaque,
+                                                   const uint8_t* data,
+                                                   size_t size);
+
+/**
+ * Sets callback for receiving metadata blocks.
+ *
+ * @param state decoder instance
+ * @param start_func callback on metadata block start
+ * @param chunk_func callback on metadata block chunk
+ * @param opaque callback handle
+ */
+BROTLI_DEC_API void BrotliDecoderSetMetadataCallbacks(
+    BrotliDecoderState* state,
+    brotli_decoder_metadata_start_func start_func,
+    brotli_decoder_metadata_chunk_func chunk_func, void* opaque);
+
 /**
  * Converts error code to a c-string.
  */
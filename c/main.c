@@ -39,15 +39,24 @@ void simple_prealloc_test() {
                                    0xa8, 0xe0, 0x35, 0x07};
     const unsigned char key[] = "THIS IS A TEST OF THE EMERGENCY BROADCAST SYSTEM";
     unsigned char output[sizeof(key) * 2];
-    size_t decoded_size = sizeof(output) * 2;
-    unsigned char scratch_u8[131072] = {0};
-    uint32_t scratch_u32[16384] = {0};
-    HuffmanCode HuffmanCodeZero = {0,0};
-    HuffmanCode scratch_hc[65536] = {HuffmanCodeZero};
-    BrotliDecoderReturnInfo ret = BrotliDecoderDecompressPrealloc(sizeof(brotli_file), brotli_file, decoded_size, output,
-                                                                  sizeof(scratch_u8), scratch_u8,
-                                                                  sizeof(scratch_u32) / sizeof(uint32_t), scratch_u32,
-                                                                  sizeof(scratch_hc) / sizeof(HuffmanCode), scratch_hc);
+    size_t decoded_size = sizeof(output);
+    BrotliDecoderScratchSizes sizes;
+    BrotliDecoderReturnInfo ret;
+    unsigned char *scratch_u8;
+    uint32_t *scratch_u32;
+    HuffmanCode *scratch_hc;
+    assert(BrotliDecoderPreallocScratchSizesForStream(sizeof(brotli_file), brotli_file, &sizes)
+           == BROTLI_DECODER_SUCCESS);
+    scratch_u8 = calloc(sizes.u8_size, sizeof(unsigned char));
+    scratch_u32 = calloc(sizes.u32_size, sizeof(uint32_t));
+    scratch_hc = calloc(sizes.hc_size, sizeof(HuffmanCode));
+    ret = BrotliDecoderDecompressPrealloc(sizeof(brotli_file), brotli_file, decoded_size, output,
+                                          sizes.u8_size, scratch_u8,
+                                          sizes.u32_size, scratch_u32,
+                                          sizes.hc_size, scratch_hc);
+    free(scratch_u8);
+    free(scratch_u32);
+    free(scratch_hc);
     assert(ret.decoded_size == sizeof(key));
     assert(memcmp(output, key, sizeof(key) - 1) == 0);
     assert(output[sizeof(key) - 1] == '\n');
@@ -69,18 +78,275 @@ void negative_test() {
 
     unsigned char *o_ptr = &obuffer[0];
     const char * to_be_printed;
+    BrotliDecoderErrorInfo info;
     BrotliDecoderResult rest = BrotliDecoderDecompressStream(state, &avail_in, &i_ptr, &avail_out, &o_ptr, &total_out);
     assert(return_info.result == BROTLI_DECODER_RESULT_ERROR);
     assert(return_info.code == BROTLI_DECODER_ERROR_FORMAT_CONTEXT_MAP_REPEAT);
     assert(rest ==  BROTLI_DECODER_RESULT_ERROR);
     to_be_printed = BrotliDecoderGetErrorString(state);
     assert(strcmp(to_be_printed, "ERROR_FORMAT_CONTEXT_MAP_REPEAT") == 0);
+    info = BrotliDecoderGetErrorInfo(state);
+    assert(info.code == BROTLI_DECODER_ERROR_FORMAT_CONTEXT_MAP_REPEAT);
+    assert(info.compressed_offset == 10 && info.bit_position == 6);
+    assert(info.decompressed_offset == 0);
+    assert(strcmp(info.state_name, "STATE_CONTEXT_MAP_1") == 0);
+    BrotliDecoderDestroyInstance(state);
+}
+
+/* One meta-block with a single 9-byte copy at distance 9, just past the
+   (empty) window, so it lands in the attached dictionaries. */
+static const unsigned char compound_file[] = {0x02, 0x01, 0x00, 0x00, 0x04, 0x40, 0x1c, 0x12, 0x13};
+
+static BrotliDecoderResult decode_all(BrotliDecoderState *state, const unsigned char *input,
+                                      size_t input_size, unsigned char *output, size_t *output_size) {
+    size_t avail_in = input_size;
+    size_t avail_out = *output_size;
+    const unsigned char *i_ptr = input;
+    unsigned char *o_ptr = output;
+    BrotliDecoderResult rest = BrotliDecoderDecompressStream(state, &avail_in, &i_ptr, &avail_out, &o_ptr, NULL);
+    *output_size = o_ptr - output;
+    return rest;
+}
+
+void attach_dictionary_test() {
+    const unsigned char serialized[] = {0x91, 0x00, 10, '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+                                        0, 0, 1, 0, 0, 0};
+    unsigned char output[64];
+    size_t output_size = sizeof(output);
+    int i;
+    BrotliDecoderState * state = BrotliDecoderCreateInstance(custom_alloc, custom_free, &custom_alloc_data);
+    assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 10, (const uint8_t*)"0123456789"));
+    assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 10, (const uint8_t*)"abcdefghij"));
+    assert(decode_all(state, compound_file, sizeof(compound_file), output, &output_size) == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(output_size == 9);
+    assert(memcmp(output, "bcdefghij", 9) == 0);
+    /* too late once decoding started */
+    assert(!BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 10, (const uint8_t*)"0123456789"));
+    BrotliDecoderDestroyInstance(state);
+
+    state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_SERIALIZED, sizeof(serialized), serialized));
+    output_size = sizeof(output);
+    assert(decode_all(state, compound_file, sizeof(compound_file), output, &output_size) == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(output_size == 9);
+    assert(memcmp(output, "123456789", 9) == 0);
+    BrotliDecoderDestroyInstance(state);
+
+    state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    assert(!BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_SERIALIZED, sizeof(serialized) - 1, serialized));
+    for (i = 0; i < SHARED_BROTLI_MAX_COMPOUND_DICTS; ++i) {
+        assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 1, (const uint8_t*)"x"));
+    }
+    assert(!BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 1, (const uint8_t*)"x"));
+    BrotliDecoderDestroyInstance(state);
+
+    /* only the real static dictionary is accepted */
+    state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    assert(!BrotliDecoderSetStaticDictionary(state, 10, (const uint8_t*)"0123456789"));
+    BrotliDecoderDestroyInstance(state);
+
+    /* a custom list of 16 nine-byte words: the copy is word 8 */
+    {
+        static const char words[] = "alphabetsbackwardscrossworddangerouseducationfantastic"
+            "gardeninghappinessinvisiblejellyfishknowledgelightningmountainsnightfall"
+            "orchestrapineapple";
+        unsigned char word_list[4 + 28 + 16 * 9 + 5] = {0x91, 0x00, 0, 1};
+        word_list[4 + 9 - 4] = 4;
+        memcpy(word_list + 4 + 28, words, 16 * 9);
+        /* no transform lists, one dictionary: word list 0 with the built-in transforms */
+        memcpy(word_list + 4 + 28 + 16 * 9, "\0\1\0\0\0", 5);
+        state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+        assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_SERIALIZED, sizeof(word_list), word_list));
+        output_size = sizeof(output);
+        assert(decode_all(state, compound_file, sizeof(compound_file), output, &output_size) == BROTLI_DECODER_RESULT_SUCCESS);
+        assert(output_size == 9);
+        assert(memcmp(output, "invisible", 9) == 0);
+        BrotliDecoderDestroyInstance(state);
+    }
+
+    /* a 9-byte copy at distance 5 runs past the end of the dictionary */
+    {
+        unsigned char overrun_file[sizeof(compound_file)];
+        memcpy(overrun_file, compound_file, sizeof(compound_file));
+        overrun_file[8] = 0x12;
+        state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+        assert(BrotliDecoderAttachDictionary(state, BROTLI_SHARED_DICTIONARY_RAW, 10, (const uint8_t*)"0123456789"));
+        output_size = sizeof(output);
+        assert(decode_all(state, overrun_file, sizeof(overrun_file), output, &output_size) == BROTLI_DECODER_RESULT_ERROR);
+        assert(BrotliDecoderGetErrorCode(state) == BROTLI_DECODER_ERROR_COMPOUND_DICTIONARY);
+        assert(strcmp(BrotliDecoderGetErrorString(state), "ERROR_COMPOUND_DICTIONARY") == 0);
+        BrotliDecoderDestroyInstance(state);
+    }
+}
+
+struct metadata_record {
+    size_t announced;
+    size_t received;
+    char data[64];
+};
+void record_metadata_start(void *opaque, size_t size) {
+    struct metadata_record *record = (struct metadata_record*)opaque;
+    record->announced += size;
+}
+void record_metadata_chunk(void *opaque, const uint8_t *data, size_t size) {
+    struct metadata_record *record = (struct metadata_record*)opaque;
+    assert(size >= 1 && record->received + size <= sizeof(record->data));
+    memcpy(record->data + record->received, data, size);
+    record->received += size;
+}
+
+void metadata_callback_test() {
+    /* a metadata meta-block, then the compound_file meta-block */
+    const unsigned char brotli_file[] = {0x2c, 0x07, 'h', 'e', 'l', 'l', 'o', ',', ' ', 'm', 'e', 't', 'a',
+                                         'd', 'a', 't', 'a', 0x81, 0x00, 0x00, 0x00, 0x02, 0x20, 0x0e, 0x89, 0x09};
+    struct metadata_record record = {0, 0, {0}};
+    unsigned char output[16];
+    size_t avail_out = sizeof(output);
+    unsigned char *o_ptr = output;
+    size_t i;
+    BrotliDecoderResult rest = BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT;
+    BrotliDecoderState * state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    BrotliDecoderSetMetadataCallbacks(state, record_metadata_start, record_metadata_chunk, &record);
+    /* feed one byte at a time */
+    for (i = 0; i < sizeof(brotli_file); ++i) {
+        size_t avail_in = 1;
+        const unsigned char *i_ptr = &brotli_file[i];
+        rest = BrotliDecoderDecompressStream(state, &avail_in, &i_ptr, &avail_out, &o_ptr, NULL);
+        assert(avail_in == 0);
+    }
+    assert(rest == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(record.announced == 15);
+    assert(record.received == 15);
+    assert(memcmp(record.data, "hello, metadata", 15) == 0);
+    assert(o_ptr - output == 9);
+    BrotliDecoderDestroyInstance(state);
+}
+
+void set_parameter_test() {
+    unsigned char output[16];
+    size_t output_size = sizeof(output);
+    BrotliDecoderState * state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    assert(BrotliDecoderSetParameter(state, BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION, 1));
+    assert(BrotliDecoderSetParameter(state, BROTLI_DECODER_PARAM_LARGE_WINDOW, 1));
+    assert(!BrotliDecoderSetParameter(state, (BrotliDecoderParameter)2, 1));
+    assert(decode_all(state, compound_file, sizeof(compound_file), output, &output_size) == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(!BrotliDecoderSetParameter(state, BROTLI_DECODER_PARAM_LARGE_WINDOW, 0));
+    BrotliDecoderDestroyInstance(state);
+}
+
+struct callback_io {
+    const unsigned char *input;
+    size_t input_size;
+    unsigned char output[64];
+    size_t output_size;
+    int fail_write;
+};
+ptrdiff_t callback_read(void *opaque, uint8_t *buffer, size_t size) {
+    struct callback_io *io = (struct callback_io*)opaque;
+    if (io->input == NULL) {
+        return -1;
+    }
+    /* hand out one byte per call */
+    if (size == 0 || io->input_size == 0) {
+        return 0;
+    }
+    buffer[0] = io->input[0];
+    ++io->input;
+    --io->input_size;
+    return 1;
+}
+ptrdiff_t callback_write(void *opaque, const uint8_t *data, size_t size) {
+    struct callback_io *io = (struct callback_io*)opaque;
+    if (io->fail_write || io->output_size + size > sizeof(io->output)) {
+        return -1;
+    }
+    memcpy(io->output + io->output_size, data, size);
+    io->output_size += size;
+    return size;
+}
+
+void decompress_with_callbacks_test() {
+    const unsigned char brotli_file[] = {0x1b, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d, 0x39, 0x37, 0xb5, 0x02,
+                                   0x48, 0x10, 0x95, 0x2a, 0x9a, 0xea, 0x42, 0x0e, 0x51, 0xa4, 0x16, 0xb9,
+                                   0xcb, 0xf5, 0xf8, 0x5c, 0x64, 0xb9, 0x2f, 0xc9, 0x6a, 0x3f, 0xb1, 0xdc,
+                                   0xa8, 0xe0, 0x35, 0x07};
+    const unsigned char key[] = "THIS IS A TEST OF THE EMERGENCY BROADCAST SYSTEM\n";
+    struct callback_io io = {brotli_file, sizeof(brotli_file), {0}, 0, 0};
+    assert(BrotliDecoderDecompressWithCallbacks(callback_read, callback_write, &io)
+           == BROTLI_DECODER_CALLBACK_RESULT_SUCCESS);
+    assert(io.output_size == sizeof(key) - 1);
+    assert(memcmp(io.output, key, sizeof(key) - 1) == 0);
+
+    io.input = brotli_file;
+    io.input_size = sizeof(brotli_file) - 1;
+    io.output_size = 0;
+    assert(BrotliDecoderDecompressWithCallbacks(callback_read, callback_write, &io)
+           == BROTLI_DECODER_CALLBACK_RESULT_ERROR);
+
+    io.input = NULL;
+    assert(BrotliDecoderDecompressWithCallbacks(callback_read, callback_write, &io)
+           == BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR);
+
+    io.input = brotli_file;
+    io.input_size = sizeof(brotli_file);
+    io.fail_write = 1;
+    assert(BrotliDecoderDecompressWithCallbacks(callback_read, callback_write, &io)
+           == BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR);
+}
+
+void scratch_too_small_test() {
+    /* no scratch space at all: the allocation error comes with the area that ran out */
+    const unsigned char brotli_file[] = {0x1b, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d};
+    unsigned char output[64];
+    BrotliDecoderReturnInfo ret;
+    BrotliDecoderScratchArea exhausted = BROTLI_DECODER_SCRATCH_NONE;
+    ret = BrotliDecoderDecompressPreallocEx(sizeof(brotli_file), brotli_file, sizeof(output), output,
+                                            0, NULL, 0, NULL, 0, NULL, &exhausted);
+    assert(ret.result == BROTLI_DECODER_RESULT_ERROR);
+    assert(ret.code == BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP);
+    assert(strcmp(ret.error, "ERROR_ALLOC_CONTEXT_MAP") == 0);
+    assert(exhausted == BROTLI_DECODER_SCRATCH_HC);
+    /* running out is an error, not a panic */
+    assert(strcmp(BrotliDecoderGetPanicMessage(), "") == 0);
+}
+
+void budgeted_test() {
+    /* a one byte output budget yields after every byte */
+    const unsigned char brotli_file[] = {0x1b, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d, 0x39, 0x37, 0xb5, 0x02,
+                                   0x48, 0x10, 0x95, 0x2a, 0x9a, 0xea, 0x42, 0x0e, 0x51, 0xa4, 0x16, 0xb9,
+                                   0xcb, 0xf5, 0xf8, 0x5c, 0x64, 0xb9, 0x2f, 0xc9, 0x6a, 0x3f, 0xb1, 0xdc,
+                                   0xa8, 0xe0, 0x35, 0x07};
+    const unsigned char key[] = "THIS IS A TEST OF THE EMERGENCY BROADCAST SYSTEM";
+    unsigned char output[sizeof(key) * 2];
+    const uint8_t *next_in = brotli_file;
+    uint8_t *next_out = output;
+    size_t available_in = sizeof(brotli_file);
+    size_t available_out = sizeof(output);
+    size_t yields = 0;
+    BrotliDecoderResult res;
+    BrotliDecoderState *state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    while ((res = BrotliDecoderDecompressStreamBudgeted(state, &available_in, &next_in,
+                                                        &available_out, &next_out, NULL,
+                                                        0, 1)) == BROTLI_DECODER_RESULT_YIELD) {
+        assert((size_t)(next_out - output) == yields + 1);
+        ++yields;
+    }
+    assert(res == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(yields == sizeof(key) - 1);
+    assert(memcmp(output, key, sizeof(key) - 1) == 0);
+    BrotliDecoderDestroyInstance(state);
 }
 
 int main() {
     simple_test();
     simple_prealloc_test();
     negative_test();
+    attach_dictionary_test();
+    metadata_callback_test();
+    set_parameter_test();
+    decompress_with_callbacks_test();
+    scratch_too_small_test();
+    budgeted_test();
     BrotliDecoderState * state = BrotliDecoderCreateInstance(custom_alloc, custom_free, &custom_alloc_data);
     unsigned char ibuffer[4096];
     unsigned char obuffer[4096];
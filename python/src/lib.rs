@@ -0,0 +1,260 @@
+//! Python bindings: `decompress`, the streaming `Decompressor` and the
+//! file-like `DecompressorReader`. Decoding runs with the GIL released.
+use decompressor::{BrotliDecoderErrorCode, BrotliDecoderErrorStr, BrotliDecompressStream,
+                   BrotliResult, BrotliState, StandardAlloc};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+const OUTPUT_CHUNK_SIZE: usize = 65536;
+
+create_exception!(brotli_decompressor, BrotliError, PyException,
+                  "Base class of all decoding errors; args are (message, BrotliDecoderErrorCode value).");
+create_exception!(brotli_decompressor, FormatError, BrotliError, "The input is not valid brotli.");
+create_exception!(brotli_decompressor, TruncatedError, BrotliError, "The input ended inside the stream.");
+create_exception!(brotli_decompressor, TrailingDataError, BrotliError,
+                  "The input continues after the end of the stream.");
+create_exception!(brotli_decompressor, OutputLimitError, BrotliError, "The output exceeds max_output.");
+create_exception!(brotli_decompressor, DictionaryError, BrotliError, "A dictionary reference could not be resolved.");
+create_exception!(brotli_decompressor, AllocError, BrotliError, "The decoder ran out of memory.");
+create_exception!(brotli_decompressor, InternalError, BrotliError, "The decoder reached an impossible state.");
+
+enum DecodeError {
+  Code(BrotliDecoderErrorCode),
+  Truncated,
+  TrailingData,
+  OutputLimit,
+}
+
+impl From<DecodeError> for PyErr {
+  fn from(e: DecodeError) -> PyErr {
+    use decompressor::BrotliDecoderErrorCode::*;
+    match e {
+      DecodeError::Truncated =>
+        TruncatedError::new_err(("stream is truncated", BROTLI_DECODER_NEEDS_MORE_INPUT as i32)),
+      DecodeError::TrailingData =>
+        TrailingDataError::new_err(("data after the end of the stream",
+                                    BROTLI_DECODER_ERROR_INVALID_ARGUMENTS as i32)),
+      DecodeError::OutputLimit =>
+        OutputLimitError::new_err(("output exceeds max_output", BROTLI_DECODER_NEEDS_MORE_OUTPUT as i32)),
+      DecodeError::Code(code) => {
+        let args = (BrotliDecoderErrorStr(code).trim_end_matches('\0'), code as i32);
+        // grouped like the error code list in c/brotli/decode.h
+        match code as i32 {
+          -16..=-1 => FormatError::new_err(args),
+          -19..=-17 => DictionaryError::new_err(args),
+          -30..=-21 => AllocError::new_err(args),
+          -31 => InternalError::new_err(args),
+          _ => BrotliError::new_err(args),
+        }
+      }
+    }
+  }
+}
+
+struct Stream {
+  state: BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>,
+  total_out: usize,
+  done: bool,
+}
+
+impl Stream {
+  fn new() -> Self {
+    Stream {
+      state: BrotliState::new(StandardAlloc::default(), StandardAlloc::default(), StandardAlloc::default()),
+      total_out: 0,
+      done: false,
+    }
+  }
+
+  // Appends everything chunk decodes to to output, failing once output would
+  // grow past max_output.
+  fn process(&mut self, chunk: &[u8], output: &mut Vec<u8>, max_output: Option<usize>)
+             -> Result<(), DecodeError> {
+    let mut available_in = chunk.len();
+    let mut input_offset = 0;
+    loop {
+      if self.done {
+        if available_in != 0 {
+          return Err(DecodeError::TrailingData);
+        }
+        return Ok(());
+      }
+      let mut output_offset = output.len();
+      let mut available_out = OUTPUT_CHUNK_SIZE;
+      if let Some(limit) = max_output {
+        if output_offset >= limit {
+          // one more byte tells a stream that ends here from a longer one
+          available_out = 1;
+        } else {
+          available_out = std::cmp::min(available_out, limit - output_offset);
+        }
+      }
+      output.resize(output_offset + available_out, 0);
+      let result = BrotliDecompressStream(&mut available_in, &mut input_offset, chunk,
+                                          &mut available_out, &mut output_offset, output,
+                                          &mut self.total_out, &mut self.state);
+      output.truncate(output_offset);
+      if max_output.is_some_and(|limit| output.len() > limit) {
+        return Err(DecodeError::OutputLimit);
+      }
+      match result {
+        BrotliResult::NeedsMoreOutput => {}
+        BrotliResult::NeedsMoreInput => return Ok(()),
+        BrotliResult::ResultSuccess => self.done = true,
+        BrotliResult::ResultFailure => return Err(DecodeError::Code(self.state.error_code)),
+      }
+    }
+  }
+}
+
+/// decompress(data, max_output=None)
+/// --
+///
+/// Decompresses a complete brotli stream.
+#[pyfunction]
+#[pyo3(signature = (data, max_output=None))]
+fn decompress<'py>(py: Python<'py>, data: &[u8], max_output: Option<usize>) -> PyResult<Bound<'py, PyBytes>> {
+  let output = py.allow_threads(|| {
+    let mut stream = Stream::new();
+    let mut output = Vec::new();
+    stream.process(data, &mut output, max_output)?;
+    if !stream.done {
+      return Err(DecodeError::Truncated);
+    }
+    Ok(output)
+  })?;
+  Ok(PyBytes::new(py, &output))
+}
+
+/// Incremental decoder: feed compressed chunks to process() and check
+/// is_finished() once the input has ended.
+#[pyclass]
+struct Decompressor {
+  stream: Stream,
+}
+
+#[pymethods]
+impl Decompressor {
+  #[new]
+  fn new() -> Self {
+    Decompressor { stream: Stream::new() }
+  }
+
+  /// Decodes chunk and returns the bytes that became available.
+  fn process<'py>(&mut self, py: Python<'py>, chunk: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let stream = &mut self.stream;
+    let output = py.allow_threads(|| {
+      let mut output = Vec::new();
+      stream.process(chunk, &mut output, None).map(|_| output)
+    })?;
+    Ok(PyBytes::new(py, &output))
+  }
+
+  /// True once the end of the brotli stream has been decoded.
+  fn is_finished(&self) -> bool {
+    self.stream.done
+  }
+}
+
+/// Read-only file object that decompresses the brotli stream read from the
+/// wrapped binary file object.
+#[pyclass]
+struct DecompressorReader {
+  inner: Option<PyObject>,
+  stream: Stream,
+  pending: Vec<u8>,
+  pending_offset: usize,
+  eof: bool,
+}
+
+#[pymethods]
+impl DecompressorReader {
+  #[new]
+  fn new(fileobj: PyObject) -> Self {
+    DecompressorReader {
+      inner: Some(fileobj),
+      stream: Stream::new(),
+      pending: Vec::new(),
+      pending_offset: 0,
+      eof: false,
+    }
+  }
+
+  /// read(size=-1)
+  /// --
+  ///
+  /// Returns up to size decompressed bytes, or everything that is left if
+  /// size is negative; an empty result means the stream has ended.
+  #[pyo3(signature = (size=-1))]
+  fn read<'py>(&mut self, py: Python<'py>, size: isize) -> PyResult<Bound<'py, PyBytes>> {
+    let inner = match self.inner {
+      Some(ref inner) => inner.clone_ref(py),
+      None => return Err(pyo3::exceptions::PyValueError::new_err("I/O operation on closed file")),
+    };
+    while !self.eof && (size < 0 || self.pending.len() - self.pending_offset < size as usize) {
+      let chunk = inner.call_method1(py, "read", (OUTPUT_CHUNK_SIZE,))?;
+      let chunk = chunk.bind(py).downcast::<PyBytes>()?.as_bytes();
+      if chunk.is_empty() {
+        self.eof = true;
+        if !self.stream.done {
+          return Err(DecodeError::Truncated.into());
+        }
+        break;
+      }
+      self.pending.drain(..self.pending_offset);
+      self.pending_offset = 0;
+      let (stream, pending) = (&mut self.stream, &mut self.pending);
+      py.allow_threads(|| stream.process(chunk, pending, None))?;
+    }
+    let available = self.pending.len() - self.pending_offset;
+    let count = if size < 0 { available } else { std::cmp::min(size as usize, available) };
+    let ret = PyBytes::new(py, &self.pending[self.pending_offset..self.pending_offset + count]);
+    self.pending_offset += count;
+    Ok(ret)
+  }
+
+  fn readable(&self) -> bool {
+    true
+  }
+
+  /// Closes the reader and the wrapped file object.
+  fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+    if let Some(inner) = self.inner.take() {
+      inner.call_method0(py, "close")?;
+    }
+    Ok(())
+  }
+
+  #[getter]
+  fn closed(&self) -> bool {
+    self.inner.is_none()
+  }
+
+  fn __enter__(slf: Py<Self>) -> Py<Self> {
+    slf
+  }
+
+  fn __exit__(&mut self, py: Python<'_>, _exc_type: PyObject, _exc: PyObject, _tb: PyObject) -> PyResult<bool> {
+    self.close(py)?;
+    Ok(false)
+  }
+}
+
+#[pymodule]
+fn brotli_decompressor(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add_function(wrap_pyfunction!(decompress, m)?)?;
+  m.add_class::<Decompressor>()?;
+  m.add_class::<DecompressorReader>()?;
+  let py = m.py();
+  m.add("BrotliError", py.get_type::<BrotliError>())?;
+  m.add("FormatError", py.get_type::<FormatError>())?;
+  m.add("TruncatedError", py.get_type::<TruncatedError>())?;
+  m.add("TrailingDataError", py.get_type::<TrailingDataError>())?;
+  m.add("OutputLimitError", py.get_type::<OutputLimitError>())?;
+  m.add("DictionaryError", py.get_type::<DictionaryError>())?;
+  m.add("AllocError", py.get_type::<AllocError>())?;
+  m.add("InternalError", py.get_type::<InternalError>())?;
+  Ok(())
+}
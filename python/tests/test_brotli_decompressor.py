@@ -0,0 +1,87 @@
+import io
+import os
+import threading
+import unittest
+
+import brotli_decompressor
+
+TESTDATA = os.path.join(os.path.dirname(__file__), '..', '..', 'testdata')
+
+
+def testdata(name):
+    with open(os.path.join(TESTDATA, name), 'rb') as f:
+        return f.read()
+
+
+ALICE = testdata('alice29.txt')
+ALICE_BR = testdata('alice29.txt.compressed')
+
+
+class DecompressTest(unittest.TestCase):
+    def test_decompress(self):
+        self.assertEqual(brotli_decompressor.decompress(ALICE_BR), ALICE)
+
+    def test_max_output(self):
+        self.assertEqual(brotli_decompressor.decompress(ALICE_BR, max_output=len(ALICE)), ALICE)
+        with self.assertRaises(brotli_decompressor.OutputLimitError):
+            brotli_decompressor.decompress(ALICE_BR, max_output=len(ALICE) - 1)
+
+    def test_truncated(self):
+        with self.assertRaises(brotli_decompressor.TruncatedError):
+            brotli_decompressor.decompress(ALICE_BR[:-1])
+
+    def test_trailing_data(self):
+        with self.assertRaises(brotli_decompressor.TrailingDataError):
+            brotli_decompressor.decompress(ALICE_BR + b'\0')
+
+    def test_corrupt(self):
+        corrupt = bytearray(ALICE_BR)
+        corrupt[9] ^= 0xff
+        with self.assertRaises(brotli_decompressor.FormatError) as ctx:
+            brotli_decompressor.decompress(bytes(corrupt))
+        message, code = ctx.exception.args
+        self.assertTrue(message.startswith('ERROR_FORMAT_'))
+        self.assertTrue(-16 <= code <= -1)
+        self.assertIsInstance(ctx.exception, brotli_decompressor.BrotliError)
+
+    def test_threads(self):
+        results = []
+        threads = [threading.Thread(target=lambda: results.append(brotli_decompressor.decompress(ALICE_BR)))
+                   for _ in range(4)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        self.assertEqual(results, [ALICE] * 4)
+
+
+class DecompressorTest(unittest.TestCase):
+    def test_chunks(self):
+        decompressor = brotli_decompressor.Decompressor()
+        output = []
+        for i in range(0, len(ALICE_BR), 1000):
+            self.assertFalse(decompressor.is_finished())
+            output.append(decompressor.process(ALICE_BR[i:i + 1000]))
+        self.assertTrue(decompressor.is_finished())
+        self.assertEqual(b''.join(output), ALICE)
+        with self.assertRaises(brotli_decompressor.TrailingDataError):
+            decompressor.process(b'\0')
+
+
+class DecompressorReaderTest(unittest.TestCase):
+    def test_read(self):
+        with brotli_decompressor.DecompressorReader(io.BytesIO(ALICE_BR)) as reader:
+            self.assertTrue(reader.readable())
+            self.assertEqual(reader.read(10), ALICE[:10])
+            self.assertEqual(reader.read(), ALICE[10:])
+            self.assertEqual(reader.read(), b'')
+        self.assertTrue(reader.closed)
+
+    def test_truncated(self):
+        reader = brotli_decompressor.DecompressorReader(io.BytesIO(ALICE_BR[:-1]))
+        with self.assertRaises(brotli_decompressor.TruncatedError):
+            reader.read()
+
+
+if __name__ == '__main__':
+    unittest.main()
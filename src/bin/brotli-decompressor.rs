@@ -1,4 +1,6 @@
 #![allow(unused_imports)]
+// str::strip_prefix is newer than the compilers the default build supports.
+#![allow(clippy::manual_strip)]
 // Assert at compile time that the default build contains no unsafe code:
 // the only unsafe in this binary lives behind the "unsafe" and "seccomp"
 // features, plus the ffi-api test module.
@@ -8,6 +10,7 @@ mod integration_tests;
 mod error_handling_tests;
 mod ffi_stream_tests;
 mod tests;
+mod cli_tests;
 extern crate brotli_decompressor;
 extern crate core;
 #[macro_use]
@@ -94,12 +97,24 @@ impl<T: core::clone::Clone> alloc_no_stdlib::Allocator<T> for HeapAllocator<T> {
 #[allow(unused_imports)]
 use alloc_no_stdlib::{SliceWrapper,SliceWrapperMut, StackAllocator, AllocatedStackMemory, Allocator, bzero};
 use brotli_decompressor::HuffmanCode;
+use brotli_decompressor::{AccountingAlloc, AllocStats, MemStats, SharedCustomDictionary,
+                          BrotliStaticDictionary, BrotliDecoderSetStaticDictionary};
+use brotli_decompressor::{BrotliState, BrotliDecompressStream, BrotliResult,
+                          BrotliDecoderErrorCode, BrotliDecoderGetErrorCode};
 
 use std::io::{self, Error, ErrorKind, Read, Write};
 
 use std::env;
+use std::cmp;
+use std::fmt;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 
 use std::path::Path;
 
@@ -108,6 +123,8 @@ use std::path::Path;
 
 
 
+// Only the unsandboxed decompress() writes through a CustomWrite.
+#[cfg_attr(feature="seccomp", allow(dead_code))]
 struct IoWriterWrapper<'a, OutputType: Write + 'a>(&'a mut OutputType);
 
 
@@ -205,7 +222,7 @@ pub fn decompress<InputType, OutputType>(r: &mut InputType,
 extern {
   fn calloc(n_elem : usize, el_size : usize) -> *mut u8;
   fn free(ptr : *mut u8);
-  fn syscall(value : i32) -> i32;
+  fn syscall(value : i32, ...) -> i32;
   fn prctl(operation : i32, flags : u32) -> i32;
 }
 #[cfg(feature="seccomp")]
@@ -225,6 +242,29 @@ pub fn decompress<InputType, OutputType>(r: &mut InputType,
   where InputType: Read,
         OutputType: Write
 {
+  let mut stats = StreamStats::default();
+  match decompress_sandboxed(r, w, buffer_size, &dict[..], false, None, &mut stats) {
+    Ok(()) => Ok(()),
+    Err(CliError::Io(e)) => Err(e),
+    Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+  }
+}
+
+// Decodes one stream with seccomp strict mode active. All memory is set aside
+// before the sandbox is entered, and on success the process exits here, with
+// a raw exit syscall, since nothing else is allowed once the sandbox is on.
+#[cfg(feature="seccomp")]
+fn decompress_sandboxed<InputType, OutputType>(r: &mut InputType,
+                                               w: &mut OutputType,
+                                               buffer_size: usize,
+                                               dict: &[u8],
+                                               large_window: bool,
+                                               max_output: Option<u64>,
+                                               stats: &mut StreamStats)
+                                               -> Result<(), CliError>
+  where InputType: Read,
+        OutputType: Write
+{
 
   let mut u8_buffer = unsafe {define_allocator_memory_pool!(4, u8, [0; 1024 * 1024 * 200], calloc)};
   let mut u32_buffer = unsafe {define_allocator_memory_pool!(4, u32, [0; 16384], calloc)};
@@ -237,33 +277,22 @@ pub fn decompress<InputType, OutputType>(r: &mut InputType,
   // length is taken from the cell's slice length, so any trailing slack would
   // corrupt the dictionary.
   let mut custom_dict = alloc_u8.alloc_cell(dict.len());
-  custom_dict.slice_mut().clone_from_slice(&dict[..]);
+  custom_dict.slice_mut().clone_from_slice(dict);
   let mut input_buffer = alloc_u8.alloc_cell(buffer_size);
   let mut output_buffer = alloc_u8.alloc_cell(buffer_size);
+  let mut state = BrotliState::new_with_custom_dictionary(alloc_u8, alloc_u32, alloc_hc, custom_dict);
+  state.large_window = large_window;
   let ret = unsafe{prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT)};
   if ret != 0 {
      panic!("Unable to activate seccomp");
   }
-  // Reborrow w so it survives the call: the success path exits via a raw
-  // syscall(60) that bypasses the writer flush a normal main() return would do,
-  // so we must flush the output buffer ourselves before exiting.
-  let result = brotli_decompressor::BrotliDecompressCustomIoCustomDict(&mut IoReaderWrapper::<InputType>(r),
-                                          &mut IoWriterWrapper::<OutputType>(&mut *w),
-                                          input_buffer.slice_mut(),
-                                          output_buffer.slice_mut(),
-                                          alloc_u8,
-                                          alloc_u32,
-                                          alloc_hc,
-                                          custom_dict,
-                                          Error::new(ErrorKind::UnexpectedEof, "Unexpected EOF"));
-  match result {
-      Err(e) => Err(e),
-      Ok(()) => {
-        w.flush()?;
-        unsafe{syscall(60);};
-        unreachable!()
-      },
-   }
+  // decode_with_state flushes w before it succeeds, which matters because the
+  // exit below bypasses the flush a normal main() return would do.
+  decode_with_state(&mut state, input_buffer.slice_mut(), output_buffer.slice_mut(), r, w,
+                    max_output, stats, &mut None)?;
+  // exit(0)
+  unsafe{syscall(60, 0);};
+  unreachable!()
 }
 
 
@@ -322,39 +351,987 @@ fn writeln_time<OutputType: Write>(strm: &mut OutputType,
   writeln!(strm, "{:} {:} {:}.{:09}", v0, data, v1, v2)
 }
 
-fn main() {
-  let mut dictionary = Vec::<u8>::new();
+const MAX_DICTIONARY_SIZE: usize = 50331660;
+const BUFFER_SIZE: usize = 65536;
+
+const USAGE: &str = "Usage: brotli-decompressor [OPTION]... [FILE]...
+Decompress brotli FILEs (by default, in place, removing the .br suffix).
+
+  -c, --stdout         write to standard output, keep input files
+  -o, --output=FILE    write output to FILE, keep input (single input only)
+  -k, --keep           keep (don't delete) input files
+  -f, --force          overwrite existing output files
+  -r, --recursive      decompress every .br file below the given directories
+  -j, --jobs=N         decompress up to N files at a time
+  -t, --test           check integrity of the inputs, discarding the output
+  -l, --list           list sizes, ratio, window bits, meta-block count and
+                       whether each stream ends cleanly
+      --large-window   accept large-window brotli streams (default)
+      --no-large-window
+                       reject streams that need a window above 16 MiB
+      --max-output=N   fail once a stream decodes to more than N bytes
+                       (suffixes K, M and G are accepted)
+      --mem-stats      report the decoder's peak memory use for each stream
+                       on standard error
+      --progress       show throughput and, for files, percent complete on
+                       standard error while decompressing
+  -dict=FILE           use FILE as a custom dictionary
+      --static-dictionary=FILE
+                       use FILE as the RFC 7932 static dictionary, for builds
+                       that leave it out
+  -h, --help           show this help and exit
+
+With no FILE, or when FILE is -, read standard input.
+Exit status is 0 on success, 1 if any file failed and 2 on usage errors.";
+
+#[derive(Debug)]
+pub enum CliError {
+  Io(io::Error),
+  Corrupt(BrotliDecoderErrorCode),
+  Truncated,
+  TrailingGarbage,
+  OutputLimit(u64),
+  WorkerPanicked,
+}
+
+impl From<io::Error> for CliError {
+  fn from(e: io::Error) -> Self {
+    CliError::Io(e)
+  }
+}
+
+impl fmt::Display for CliError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CliError::Io(ref e) => write!(f, "{}", e),
+      CliError::Corrupt(code) => write!(f, "corrupt input: {:?}", code),
+      CliError::Truncated => write!(f, "unexpected end of input"),
+      CliError::TrailingGarbage => write!(f, "trailing garbage after brotli stream"),
+      CliError::OutputLimit(limit) => write!(f, "output exceeds --max-output limit of {} bytes", limit),
+      CliError::WorkerPanicked => write!(f, "worker thread panicked"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliOptions {
+  pub to_stdout: bool,
+  pub output: Option<String>,
+  pub keep: bool,
+  pub force: bool,
+  pub test: bool,
+  pub list: bool,
+  pub recursive: bool,
+  pub jobs: usize,
+  pub large_window: bool,
+  pub max_output: Option<u64>,
+  pub mem_stats: bool,
+  pub progress: bool,
+  pub dictionary: Option<String>,
+  pub static_dictionary: Option<String>,
+  pub inputs: Vec<String>,
+}
+
+impl Default for CliOptions {
+  fn default() -> Self {
+    CliOptions {
+      to_stdout: false,
+      output: None,
+      keep: false,
+      force: false,
+      test: false,
+      list: false,
+      recursive: false,
+      jobs: 1,
+      large_window: true,
+      max_output: None,
+      mem_stats: false,
+      progress: false,
+      dictionary: None,
+      static_dictionary: None,
+      inputs: Vec::new(),
+    }
+  }
+}
+
+pub enum ParsedArgs {
+  Run(CliOptions),
+  Help,
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+  let (digits, multiplier) = match value.chars().last() {
+    Some('k') | Some('K') => (&value[..value.len() - 1], 1u64 << 10),
+    Some('m') | Some('M') => (&value[..value.len() - 1], 1u64 << 20),
+    Some('g') | Some('G') => (&value[..value.len() - 1], 1u64 << 30),
+    _ => (value, 1u64),
+  };
+  match digits.parse::<u64>() {
+    Ok(n) => n.checked_mul(multiplier).ok_or_else(|| format!("size too large: {}", value)),
+    Err(_) => Err(format!("invalid size: {}", value)),
+  }
+}
+
+fn parse_jobs(value: &str) -> Result<usize, String> {
+  match value.parse::<usize>() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err(format!("invalid number of jobs: {}", value)),
+  }
+}
+
+// Parses the arguments following the program name. Errors are usage errors.
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<ParsedArgs, String> {
+  let mut opts = CliOptions::default();
   let mut double_dash = false;
-  let mut input: Option<File> = None;
-  let mut output: Option<File> = None;
-  for argument in env::args().skip(1) {
+  let mut args = args.peekable();
+  while let Some(argument) = args.next() {
+    if double_dash || argument == "-" || !argument.starts_with('-') {
+      opts.inputs.push(argument);
+      continue;
+    }
     if argument == "--" {
       double_dash = true;
       continue;
     }
-    if argument.starts_with("-dict=") && !double_dash {
-      let mut dict_file = File::open(&Path::new(&argument[6..])).unwrap();
-      dict_file.read_to_end(&mut dictionary).unwrap();
-      if dictionary.len() > 50331660 {
-          panic!("Dictionary larger than 50331660");
+    if argument.starts_with("-dict=") {
+      opts.dictionary = Some(argument["-dict=".len()..].to_string());
+      continue;
+    }
+    if argument.starts_with("--") {
+      let (name, inline_value) = match argument.find('=') {
+        Some(idx) => (&argument[..idx], Some(argument[idx + 1..].to_string())),
+        None => (&argument[..], None),
+      };
+      match name {
+        "--output" | "--max-output" | "--jobs" | "--static-dictionary" => {
+          let value = match inline_value {
+            Some(v) => v,
+            None => match args.next() {
+              Some(v) => v,
+              None => return Err(format!("option {} requires an argument", name)),
+            },
+          };
+          match name {
+            "--output" => opts.output = Some(value),
+            "--jobs" => opts.jobs = parse_jobs(&value)?,
+            "--static-dictionary" => opts.static_dictionary = Some(value),
+            _ => opts.max_output = Some(parse_size(&value)?),
+          }
+          continue;
+        }
+        _ => {}
+      }
+      if inline_value.is_some() {
+        return Err(format!("option {} does not take an argument", name));
+      }
+      match name {
+        "--stdout" => opts.to_stdout = true,
+        "--keep" => opts.keep = true,
+        "--force" => opts.force = true,
+        "--test" => opts.test = true,
+        "--list" => opts.list = true,
+        "--recursive" => opts.recursive = true,
+        "--decompress" => {}
+        "--large-window" => opts.large_window = true,
+        "--no-large-window" => opts.large_window = false,
+        "--mem-stats" => opts.mem_stats = true,
+        "--progress" => opts.progress = true,
+        "--help" => return Ok(ParsedArgs::Help),
+        _ => return Err(format!("unknown option {}", name)),
       }
       continue;
     }
-    if input.is_none() {
-       input = Some(File::open(&Path::new(&argument)).unwrap());
-    } else if output.is_none() {
-       output = Some(File::create(&Path::new(&argument)).unwrap());
-    } else {
-       panic!("Cannot specify more than 2 filename args (input, output)")
+    // clustered short options, e.g. -kf or -co FILE
+    let flags: Vec<char> = argument[1..].chars().collect();
+    for (index, flag) in flags.iter().enumerate() {
+      match *flag {
+        'c' => opts.to_stdout = true,
+        'k' => opts.keep = true,
+        'f' => opts.force = true,
+        't' => opts.test = true,
+        'l' => opts.list = true,
+        'd' => {}
+        'h' => return Ok(ParsedArgs::Help),
+        'r' => opts.recursive = true,
+        'o' | 'j' => {
+          let rest: String = flags[index + 1..].iter().collect();
+          let value = if !rest.is_empty() {
+            rest
+          } else {
+            match args.next() {
+              Some(v) => v,
+              None => return Err(format!("option -{} requires an argument", flag)),
+            }
+          };
+          if *flag == 'o' {
+            opts.output = Some(value);
+          } else {
+            opts.jobs = parse_jobs(&value)?;
+          }
+          break;
+        }
+        other => return Err(format!("unknown option -{}", other)),
+      }
     }
   }
-  if input.is_none() {
-    decompress(&mut io::stdin(), &mut io::stdout(), 65536, dictionary).unwrap();
+  if opts.output.is_some() && opts.to_stdout {
+    return Err("-o cannot be combined with -c".to_string());
+  }
+  if opts.output.is_some() && opts.inputs.len() > 1 {
+    return Err("-o requires exactly one input file".to_string());
+  }
+  Ok(ParsedArgs::Run(opts))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamStats {
+  // Bytes of input consumed by the decoder.
+  pub compressed_size: u64,
+  pub uncompressed_size: u64,
+  // Zero until the stream header has been decoded.
+  pub window_bits: u32,
+  pub meta_blocks: usize,
+}
+
+pub type CliBrotliState = BrotliState<AccountingAlloc<HeapAllocator<u8>>,
+                                      AccountingAlloc<HeapAllocator<u32>>,
+                                      AccountingAlloc<HeapAllocator<HuffmanCode>>>;
+
+// How often --progress redraws its line.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// Formats one --progress line. The percentage is of the compressed input, so
+// it is only shown when the input size is known.
+pub fn format_progress(name: &str,
+                       total_in: u64,
+                       input_size: Option<u64>,
+                       total_out: u64,
+                       elapsed: Duration)
+                       -> String {
+  let percent = match input_size {
+    Some(size) if size != 0 => format!("{:3}% ", cmp::min(total_in * 100 / size, 100)),
+    _ => String::new(),
+  };
+  let seconds = elapsed.as_secs_f64();
+  let rate = if seconds > 0.0 {
+    total_out as f64 / seconds / (1024.0 * 1024.0)
   } else {
-    if output.is_none() {
-      decompress(&mut input.unwrap(), &mut io::stdout(), 65536, dictionary).unwrap();
+    0.0
+  };
+  format!("{}: {}{} -> {} bytes, {:.1} MiB/s", name, percent, total_in, total_out, rate)
+}
+
+// Keeps the --progress line for one stream up to date on standard error.
+pub struct ProgressMeter {
+  name: String,
+  input_size: Option<u64>,
+  start: Instant,
+  last_drawn: Instant,
+}
+
+impl ProgressMeter {
+  pub fn new(name: &str, input_size: Option<u64>) -> Self {
+    let now = Instant::now();
+    ProgressMeter {
+      name: name.to_string(),
+      input_size,
+      start: now,
+      last_drawn: now,
+    }
+  }
+
+  // Redraws the line if PROGRESS_INTERVAL has passed since it was last drawn
+  // (or since the start), and always once the stream is done.
+  fn update(&mut self, total_in: u64, total_out: u64, done: bool) {
+    let now = Instant::now();
+    if !done && now - self.last_drawn < PROGRESS_INTERVAL {
+      return;
+    }
+    self.last_drawn = now;
+    let line = format_progress(&self.name, total_in, self.input_size, total_out,
+                               now - self.start);
+    let stderr = io::stderr();
+    let mut err = stderr.lock();
+    let _ = write!(err, "\rbrotli-decompressor: {}{}", line, if done { "\n" } else { "" });
+    let _ = err.flush();
+  }
+}
+
+#[cfg(feature="alloc")]
+fn new_cli_state(alloc_u8: HeapAllocator<u8>, dict: Arc<[u8]>) -> CliBrotliState {
+  BrotliState::new_with_shared_dictionary(
+    AccountingAlloc::new(alloc_u8),
+    AccountingAlloc::new(HeapAllocator::<u32> { default_value: 0 }),
+    AccountingAlloc::new(HeapAllocator::<HuffmanCode> { default_value: HuffmanCode::default() }),
+    SharedCustomDictionary::Arc(dict))
+}
+
+#[cfg(feature="alloc")]
+fn reset_cli_state(state: &mut CliBrotliState, large_window: bool, _dict: &Arc<[u8]>) {
+  state.reset(large_window);
+}
+
+// Without the alloc feature the library cannot hold an Arc, so each stream
+// gets its own copy, which the decoder frees once it has seeded the window.
+#[cfg(not(feature="alloc"))]
+fn new_cli_state(alloc_u8: HeapAllocator<u8>, dict: Arc<[u8]>) -> CliBrotliState {
+  BrotliState::new_with_custom_dictionary(
+    AccountingAlloc::new(alloc_u8),
+    AccountingAlloc::new(HeapAllocator::<u32> { default_value: 0 }),
+    AccountingAlloc::new(HeapAllocator::<HuffmanCode> { default_value: HuffmanCode::default() }),
+    Rebox::<u8>::from(dict.to_vec()))
+}
+
+#[cfg(not(feature="alloc"))]
+fn reset_cli_state(state: &mut CliBrotliState, large_window: bool, dict: &Arc<[u8]>) {
+  let old = core::mem::replace(&mut state.custom_dict, Rebox::<u8>::from(dict.to_vec()));
+  state.alloc_u8.free_cell(old);
+  state.reset(large_window);
+}
+
+// A decoder and its I/O buffers, kept alive across streams so that batch runs
+// do not rebuild the state for every file. Decoders share the dictionary
+// rather than each keeping a copy.
+pub struct StreamDecoder {
+  state: CliBrotliState,
+  dict: Arc<[u8]>,
+  input: Rebox<u8>,
+  output: Rebox<u8>,
+  progress: Option<ProgressMeter>,
+  fresh: bool,
+}
+
+impl StreamDecoder {
+  pub fn new(dict: &[u8]) -> Self {
+    StreamDecoder::with_shared_dictionary(Arc::from(dict))
+  }
+
+  pub fn with_shared_dictionary(dict: Arc<[u8]>) -> Self {
+    let mut alloc_u8 = HeapAllocator::<u8> { default_value: 0 };
+    let input = alloc_u8.alloc_cell(BUFFER_SIZE);
+    let output = alloc_u8.alloc_cell(BUFFER_SIZE);
+    StreamDecoder {
+      state: new_cli_state(alloc_u8, dict.clone()),
+      dict,
+      input,
+      output,
+      progress: None,
+      fresh: true,
+    }
+  }
+
+  pub fn dictionary(&self) -> &[u8] {
+    &self.dict
+  }
+
+  // Supplies the static dictionary for builds that leave it out. Only works
+  // before the first stream; the decoder then keeps it for every stream.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    BrotliDecoderSetStaticDictionary(&mut self.state, dictionary)
+  }
+
+  // Shows progress for the next stream decoded.
+  pub fn set_progress(&mut self, meter: ProgressMeter) {
+    self.progress = Some(meter);
+  }
+
+  // Memory the decoder allocated for the most recent stream.
+  pub fn mem_stats(&self) -> MemStats {
+    self.state.mem_stats()
+  }
+
+  // Decodes one complete brotli stream from r into w, filling in stats as it
+  // goes so they stay meaningful when decoding fails part way through. Input
+  // remaining after the end of the stream is an error.
+  pub fn decode<InputType, OutputType>(&mut self,
+                                       r: &mut InputType,
+                                       w: &mut OutputType,
+                                       large_window: bool,
+                                       max_output: Option<u64>,
+                                       stats: &mut StreamStats)
+                                       -> Result<(), CliError>
+    where InputType: Read,
+          OutputType: Write
+  {
+    if !self.fresh {
+      reset_cli_state(&mut self.state, large_window, &self.dict);
+    }
+    self.state.reset_mem_stats();
+    self.fresh = false;
+    let mut progress = self.progress.take();
+    self.state.large_window = large_window;
+    decode_with_state(&mut self.state, self.input.slice_mut(), self.output.slice_mut(), r, w,
+                      max_output, stats, &mut progress)
+  }
+}
+
+// The decoding loop behind StreamDecoder::decode, for any allocators: the
+// seccomp build runs it on memory set aside before the sandbox is entered.
+#[allow(clippy::too_many_arguments)]
+fn decode_with_state<InputType, OutputType, AllocU8, AllocU32, AllocHC>(
+    state: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+    input: &mut [u8],
+    output: &mut [u8],
+    r: &mut InputType,
+    w: &mut OutputType,
+    max_output: Option<u64>,
+    stats: &mut StreamStats,
+    progress: &mut Option<ProgressMeter>)
+    -> Result<(), CliError>
+  where InputType: Read,
+        OutputType: Write,
+        AllocU8: Allocator<u8>,
+        AllocU32: Allocator<u32>,
+        AllocHC: Allocator<HuffmanCode>
+{
+  let mut available_in: usize = 0;
+  let mut input_offset: usize = 0;
+  let mut total_out: usize = 0;
+  let result = loop {
+    let mut available_out = output.len();
+    let mut output_offset: usize = 0;
+    let result = BrotliDecompressStream(&mut available_in,
+                                        &mut input_offset,
+                                        input,
+                                        &mut available_out,
+                                        &mut output_offset,
+                                        output,
+                                        &mut total_out,
+                                        state);
+    stats.window_bits = state.window_bits;
+    stats.meta_blocks = state.meta_block_count;
+    if output_offset != 0 {
+      stats.uncompressed_size += output_offset as u64;
+      if let Some(limit) = max_output {
+        if stats.uncompressed_size > limit {
+          break Err(CliError::OutputLimit(limit));
+        }
+      }
+      if let Err(e) = w.write_all(&output[..output_offset]) {
+        break Err(CliError::Io(e));
+      }
+    }
+    match result {
+      BrotliResult::NeedsMoreInput => {
+        input_offset = 0;
+        available_in = match IoReaderWrapper::<InputType>(r).read(input) {
+          Ok(0) => break Err(CliError::Truncated),
+          Ok(n) => n,
+          Err(e) => break Err(CliError::Io(e)),
+        };
+        stats.compressed_size += available_in as u64;
+      }
+      BrotliResult::NeedsMoreOutput => {}
+      BrotliResult::ResultSuccess => break Ok(()),
+      BrotliResult::ResultFailure => {
+        break Err(CliError::Corrupt(BrotliDecoderGetErrorCode(state)));
+      }
+    }
+    if let Some(ref mut meter) = progress {
+      meter.update(stats.compressed_size - available_in as u64, stats.uncompressed_size,
+                   false);
+    }
+  };
+  stats.compressed_size -= available_in as u64;
+  if let Some(ref mut meter) = progress {
+    meter.update(stats.compressed_size, stats.uncompressed_size, true);
+  }
+  result?;
+  if available_in != 0 || IoReaderWrapper::<InputType>(r).read(input)? != 0 {
+    return Err(CliError::TrailingGarbage);
+  }
+  w.flush()?;
+  Ok(())
+}
+
+// One-off version of StreamDecoder::decode.
+pub fn decode_stream<InputType, OutputType>(r: &mut InputType,
+                                            w: &mut OutputType,
+                                            dict: &[u8],
+                                            large_window: bool,
+                                            max_output: Option<u64>,
+                                            stats: &mut StreamStats)
+                                            -> Result<(), CliError>
+  where InputType: Read,
+        OutputType: Write
+{
+  StreamDecoder::new(dict).decode(r, w, large_window, max_output, stats)
+}
+
+// Decodes one complete brotli stream from r into w, returning the number of
+// bytes written.
+#[cfg(not(feature="seccomp"))]
+pub fn decompress_stream<InputType, OutputType>(r: &mut InputType,
+                                                w: &mut OutputType,
+                                                decoder: &mut StreamDecoder,
+                                                large_window: bool,
+                                                max_output: Option<u64>)
+                                                -> Result<u64, CliError>
+  where InputType: Read,
+        OutputType: Write
+{
+  let mut stats = StreamStats::default();
+  decoder.decode(r, w, large_window, max_output, &mut stats)?;
+  Ok(stats.uncompressed_size)
+}
+
+// The seccomp build hands the stream to decompress_sandboxed(), which only
+// returns on failure: once the output is flushed it exits the process itself.
+#[cfg(feature="seccomp")]
+pub fn decompress_stream<InputType, OutputType>(r: &mut InputType,
+                                                w: &mut OutputType,
+                                                decoder: &mut StreamDecoder,
+                                                large_window: bool,
+                                                max_output: Option<u64>)
+                                                -> Result<u64, CliError>
+  where InputType: Read,
+        OutputType: Write
+{
+  let mut stats = StreamStats::default();
+  decompress_sandboxed(r, w, BUFFER_SIZE, decoder.dictionary(), large_window, max_output,
+                       &mut stats)?;
+  Ok(stats.uncompressed_size)
+}
+
+// Name of the file that decompressing input in place produces.
+pub fn output_name(input: &str) -> Option<&str> {
+  if input.len() > 3 && input.ends_with(".br") {
+    Some(&input[..input.len() - 3])
+  } else {
+    None
+  }
+}
+
+enum Destination {
+  Discard,
+  Stdout,
+  File(String),
+}
+
+fn decompress_to<InputType: Read>(r: &mut InputType,
+                                  dest: &Destination,
+                                  decoder: &mut StreamDecoder,
+                                  opts: &CliOptions)
+                                  -> Result<u64, CliError> {
+  match *dest {
+    Destination::Discard => {
+      decompress_stream(r, &mut io::sink(), decoder, opts.large_window, opts.max_output)
+    }
+    Destination::Stdout => {
+      let stdout = io::stdout();
+      let mut w = io::BufWriter::new(stdout.lock());
+      decompress_stream(r, &mut w, decoder, opts.large_window, opts.max_output)
+    }
+    Destination::File(ref path) => {
+      let file = if opts.force {
+        File::create(path)
+      } else {
+        OpenOptions::new().write(true).create_new(true).open(path)
+      };
+      let file = match file {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+          return Err(CliError::Io(Error::new(ErrorKind::AlreadyExists,
+                                             format!("{} already exists; use -f to overwrite",
+                                                     path))));
+        }
+        Err(e) => return Err(CliError::Io(e)),
+      };
+      let mut w = io::BufWriter::new(file);
+      let result = decompress_stream(r, &mut w, decoder, opts.large_window, opts.max_output);
+      drop(w);
+      if result.is_err() {
+        let _ = fs::remove_file(path);
+      }
+      result
+    }
+  }
+}
+
+// Decompresses a single input ("-" is standard input) according to opts.
+pub fn process_input(input: &str,
+                     decoder: &mut StreamDecoder,
+                     opts: &CliOptions)
+                     -> Result<u64, CliError> {
+  let dest = if opts.test {
+    Destination::Discard
+  } else if let Some(ref output) = opts.output {
+    Destination::File(output.clone())
+  } else if opts.to_stdout || input == "-" {
+    Destination::Stdout
+  } else {
+    match output_name(input) {
+      Some(name) => Destination::File(name.to_string()),
+      None => {
+        return Err(CliError::Io(Error::new(ErrorKind::InvalidInput,
+                                           "unknown suffix -- ignored")));
+      }
+    }
+  };
+  if input == "-" {
+    if opts.progress {
+      decoder.set_progress(ProgressMeter::new(display_name(input), None));
+    }
+    let stdin = io::stdin();
+    let mut r = stdin.lock();
+    return decompress_to(&mut r, &dest, decoder, opts);
+  }
+  let metadata = fs::metadata(input)?;
+  if metadata.is_dir() {
+    return Err(CliError::Io(Error::new(ErrorKind::InvalidInput, "is a directory -- ignored")));
+  }
+  if opts.progress {
+    decoder.set_progress(ProgressMeter::new(input, Some(metadata.len())));
+  }
+  let mut r = File::open(input)?;
+  let size = decompress_to(&mut r, &dest, decoder, opts)?;
+  drop(r);
+  // -o names an output of the caller's choosing, so the input stays as well
+  if let Destination::File(_) = dest {
+    if !opts.keep && opts.output.is_none() {
+      fs::remove_file(input)?;
+    }
+  }
+  Ok(size)
+}
+
+fn load_dictionary(path: &str) -> Result<Vec<u8>, io::Error> {
+  let mut dictionary = Vec::<u8>::new();
+  File::open(Path::new(path))?.take(MAX_DICTIONARY_SIZE as u64 + 1)
+                              .read_to_end(&mut dictionary)?;
+  if dictionary.len() > MAX_DICTIONARY_SIZE {
+    return Err(Error::new(ErrorKind::InvalidInput,
+                          format!("dictionary larger than {} bytes", MAX_DICTIONARY_SIZE)));
+  }
+  Ok(dictionary)
+}
+
+// The static dictionary only has to outlive the decoders, which live as long
+// as the process; without the alloc feature there is no Arc to hold it in.
+#[cfg(feature="alloc")]
+fn into_shared_dictionary(data: Vec<u8>) -> SharedCustomDictionary {
+  SharedCustomDictionary::Arc(Arc::from(data))
+}
+
+#[cfg(not(feature="alloc"))]
+fn into_shared_dictionary(data: Vec<u8>) -> SharedCustomDictionary {
+  SharedCustomDictionary::Static(Box::leak(data.into_boxed_slice()))
+}
+
+fn load_static_dictionary(path: &str) -> Result<BrotliStaticDictionary, io::Error> {
+  let data = fs::read(path)?;
+  BrotliStaticDictionary::new(into_shared_dictionary(data)).ok_or_else(|| {
+    Error::new(ErrorKind::InvalidData, "not the brotli static dictionary")
+  })
+}
+
+// A decoder for batch work, with the static dictionary if one was given.
+fn new_batch_decoder(dict: Arc<[u8]>,
+                     static_dict: Option<&BrotliStaticDictionary>) -> StreamDecoder {
+  let mut decoder = StreamDecoder::with_shared_dictionary(dict);
+  if let Some(static_dict) = static_dict {
+    decoder.set_static_dictionary(static_dict);
+  }
+  decoder
+}
+
+fn display_name(input: &str) -> &str {
+  if input == "-" { "(stdin)" } else { input }
+}
+
+fn ratio(stats: &StreamStats) -> f64 {
+  if stats.uncompressed_size == 0 {
+    return 0.0;
+  }
+  100.0 * (1.0 - stats.compressed_size as f64 / stats.uncompressed_size as f64)
+}
+
+fn write_list_row<OutType: Write>(out: &mut OutType,
+                                  stats: &StreamStats,
+                                  clean: &str,
+                                  name: &str)
+                                  -> io::Result<()> {
+  writeln!(out, "{:>12} {:>12} {:>5.1}% {:>5} {:>7} {:>5} {}",
+           stats.compressed_size, stats.uncompressed_size, ratio(stats),
+           stats.window_bits, stats.meta_blocks, clean, name)
+}
+
+fn write_alloc_stats<ErrType: Write>(err: &mut ErrType,
+                                     category: &str,
+                                     stats: &AllocStats)
+                                     -> io::Result<()> {
+  writeln!(err, "  {:<8} peak {:>10} bytes in {:>4} allocations",
+           category, stats.peak_bytes, stats.allocations)
+}
+
+// Prints the --mem-stats report for one stream.
+fn write_mem_stats<ErrType: Write>(err: &mut ErrType,
+                                   name: &str,
+                                   stats: &MemStats)
+                                   -> io::Result<()> {
+  writeln!(err, "brotli-decompressor: {}: peak memory {} bytes", name, stats.peak_bytes())?;
+  write_alloc_stats(err, "u8", &stats.u8)?;
+  write_alloc_stats(err, "u32", &stats.u32)?;
+  write_alloc_stats(err, "huffman", &stats.hc)
+}
+
+// Decodes every input into io::sink() and prints a gzip -l style table of
+// what was found. Streams that do not end cleanly still get a row.
+fn list<OutType: Write, ErrType: Write>(inputs: &[String],
+                                        decoder: &mut StreamDecoder,
+                                        opts: &CliOptions,
+                                        out: &mut OutType,
+                                        err: &mut ErrType)
+                                        -> i32 {
+  let mut exit_code = 0;
+  let mut totals = StreamStats::default();
+  let _ = writeln!(out, "  compressed uncompressed  ratio wbits  blocks clean name");
+  for input in inputs.iter() {
+    let mut stats = StreamStats::default();
+    let result = if input == "-" {
+      let stdin = io::stdin();
+      let mut r = stdin.lock();
+      decoder.decode(&mut r, &mut io::sink(), opts.large_window, None, &mut stats)
     } else {
-      decompress(&mut input.unwrap(), &mut output.unwrap(), 65536, dictionary).unwrap();
+      match File::open(input) {
+        Ok(mut r) => decoder.decode(&mut r, &mut io::sink(), opts.large_window, None,
+                                    &mut stats),
+        Err(e) => {
+          let _ = writeln!(err, "brotli-decompressor: {}: {}", display_name(input), e);
+          exit_code = 1;
+          continue;
+        }
+      }
+    };
+    let clean = match result {
+      Ok(()) => "yes",
+      Err(e) => {
+        let _ = writeln!(err, "brotli-decompressor: {}: {}", display_name(input), e);
+        exit_code = 1;
+        "no"
+      }
+    };
+    let _ = write_list_row(out, &stats, clean, display_name(input));
+    if opts.mem_stats {
+      let _ = write_mem_stats(err, display_name(input), &decoder.mem_stats());
     }
+    totals.compressed_size += stats.compressed_size;
+    totals.uncompressed_size += stats.uncompressed_size;
+    totals.meta_blocks += stats.meta_blocks;
+    totals.window_bits = cmp::max(totals.window_bits, stats.window_bits);
+  }
+  if inputs.len() > 1 {
+    let clean = if exit_code == 0 { "yes" } else { "no" };
+    let _ = write_list_row(out, &totals, clean, "(totals)");
   }
+  let _ = out.flush();
+  exit_code
+}
+
+fn walk_dir(dir: &Path, files: &mut Vec<String>, failures: &mut Vec<(String, CliError)>) {
+  let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+  let mut entries = match entries {
+    Ok(entries) => entries,
+    Err(e) => {
+      failures.push((dir.to_string_lossy().into_owned(), CliError::Io(e)));
+      return;
+    }
+  };
+  entries.sort_by_key(|entry| entry.path());
+  for entry in entries {
+    let path = entry.path();
+    match entry.file_type() {
+      Ok(ref t) if t.is_dir() => walk_dir(&path, files, failures),
+      Ok(ref t) if t.is_file() => {
+        if path.extension() == Some(std::ffi::OsStr::new("br")) {
+          match path.to_str() {
+            Some(name) => files.push(name.to_string()),
+            None => failures.push((path.to_string_lossy().into_owned(),
+                                   CliError::Io(Error::new(ErrorKind::InvalidData,
+                                                           "file name is not valid UTF-8")))),
+          }
+        }
+      }
+      Ok(_) => {}
+      Err(e) => failures.push((path.to_string_lossy().into_owned(), CliError::Io(e))),
+    }
+  }
+}
+
+// Replaces each directory named on the command line by the .br files below
+// it, in a stable order. Without --recursive the inputs are used as given.
+pub fn collect_inputs(opts: &CliOptions, failures: &mut Vec<(String, CliError)>) -> Vec<String> {
+  if opts.inputs.is_empty() {
+    return vec![String::from("-")];
+  }
+  if !opts.recursive {
+    return opts.inputs.clone();
+  }
+  let mut files = Vec::new();
+  for input in opts.inputs.iter() {
+    if input != "-" && fs::metadata(input).map(|m| m.is_dir()).unwrap_or(false) {
+      walk_dir(Path::new(input), &mut files, failures);
+    } else {
+      files.push(input.clone());
+    }
+  }
+  files
+}
+
+// Decompresses every input, handing each outcome and the memory its decoder
+// used to report on the calling thread as soon as it is known. With more than
+// one job the files are shared out to a pool of worker threads, each of which
+// keeps one StreamDecoder for all the files it handles.
+pub fn process_batch<F>(inputs: &[String],
+                        dict: &Arc<[u8]>,
+                        static_dict: Option<&BrotliStaticDictionary>,
+                        opts: &CliOptions,
+                        mut report: F)
+  where F: FnMut(&str, Result<u64, CliError>, &MemStats)
+{
+  // Writing to stdout (or reading stdin) only makes sense one file at a
+  // time, and the seccomp build exits after its first stream.
+  let jobs = if opts.to_stdout || cfg!(feature="seccomp") ||
+                inputs.iter().any(|input| input == "-") {
+    1
+  } else {
+    cmp::min(opts.jobs, inputs.len())
+  };
+  if jobs <= 1 {
+    let mut decoder = new_batch_decoder(dict.clone(), static_dict);
+    for input in inputs.iter() {
+      let result = process_input(input, &mut decoder, opts);
+      report(input, result, &decoder.mem_stats());
+    }
+    return;
+  }
+  let shared_inputs = Arc::new(inputs.to_vec());
+  let shared_opts = Arc::new(opts.clone());
+  let next_input = Arc::new(AtomicUsize::new(0));
+  let (sender, receiver) = mpsc::channel();
+  let mut workers = Vec::with_capacity(jobs);
+  for _ in 0..jobs {
+    let inputs = shared_inputs.clone();
+    let dict = dict.clone();
+    let static_dict = static_dict.cloned();
+    let opts = shared_opts.clone();
+    let next_input = next_input.clone();
+    let sender = sender.clone();
+    workers.push(thread::spawn(move || {
+      let mut decoder = new_batch_decoder(dict, static_dict.as_ref());
+      loop {
+        let index = next_input.fetch_add(1, Ordering::SeqCst);
+        if index >= inputs.len() {
+          break;
+        }
+        let result = process_input(&inputs[index], &mut decoder, &opts);
+        if sender.send((index, result, decoder.mem_stats())).is_err() {
+          break;
+        }
+      }
+    }));
+  }
+  drop(sender);
+  let mut reported = vec![false; inputs.len()];
+  for (index, result, mem_stats) in receiver {
+    reported[index] = true;
+    report(&inputs[index], result, &mem_stats);
+  }
+  for worker in workers {
+    let _ = worker.join();
+  }
+  // anything a panicking worker claimed but never finished
+  for (index, done) in reported.iter().enumerate() {
+    if !done {
+      report(&inputs[index], Err(CliError::WorkerPanicked), &MemStats::default());
+    }
+  }
+}
+
+// Processes every input, writing listings to out and reporting failures on
+// err, and returns the exit code.
+pub fn run<OutType: Write, ErrType: Write>(opts: &CliOptions,
+                                           out: &mut OutType,
+                                           err: &mut ErrType)
+                                           -> i32 {
+  let dictionary: Arc<[u8]> = match opts.dictionary {
+    Some(ref path) => match load_dictionary(path) {
+      Ok(d) => Arc::from(d),
+      Err(e) => {
+        let _ = writeln!(err, "brotli-decompressor: {}: {}", path, e);
+        return 1;
+      }
+    },
+    None => Arc::from(Vec::new()),
+  };
+  let static_dictionary = match opts.static_dictionary {
+    Some(ref path) => match load_static_dictionary(path) {
+      Ok(d) => Some(d),
+      Err(e) => {
+        let _ = writeln!(err, "brotli-decompressor: {}: {}", path, e);
+        return 1;
+      }
+    },
+    None => None,
+  };
+  let mut failures = Vec::new();
+  let inputs = collect_inputs(opts, &mut failures);
+  // parse_args only sees the arguments; a directory may expand to many files
+  if opts.output.is_some() && inputs.len() + failures.len() > 1 {
+    let _ = writeln!(err, "brotli-decompressor: -o requires exactly one input file");
+    return 2;
+  }
+  let mut exit_code = 0;
+  for (name, e) in failures.iter() {
+    let _ = writeln!(err, "brotli-decompressor: {}: {}", name, e);
+    exit_code = 1;
+  }
+  if opts.list {
+    let mut decoder = new_batch_decoder(dictionary.clone(), static_dictionary.as_ref());
+    return cmp::max(exit_code, list(&inputs[..], &mut decoder, opts, out, err));
+  }
+  let mut failed = failures.len();
+  process_batch(&inputs[..], &dictionary, static_dictionary.as_ref(), opts, |input, result, mem_stats| {
+    if opts.mem_stats {
+      let _ = write_mem_stats(err, display_name(input), mem_stats);
+    }
+    if let Err(e) = result {
+      let _ = writeln!(err, "brotli-decompressor: {}: {}", display_name(input), e);
+      failed += 1;
+    }
+  });
+  let total = inputs.len() + failures.len();
+  if failed != 0 {
+    if total > 1 {
+      let _ = writeln!(err, "brotli-decompressor: {} of {} files failed", failed, total);
+    }
+    exit_code = 1;
+  }
+  exit_code
+}
+
+// run() on the process's standard streams. Standard error is locked for each
+// write rather than for the whole run, since worker threads draw their
+// --progress lines on it while the main thread reports failures there.
+pub fn run_with_std_streams(opts: &CliOptions) -> i32 {
+  let stdout = io::stdout();
+  let mut out = stdout.lock();
+  run(opts, &mut out, &mut io::stderr())
+}
+
+fn main() {
+  let opts = match parse_args(env::args().skip(1)) {
+    Ok(ParsedArgs::Run(opts)) => opts,
+    Ok(ParsedArgs::Help) => {
+      println!("{}", USAGE);
+      return;
+    }
+    Err(msg) => {
+      eprintln!("brotli-decompressor: {}", msg);
+      eprintln!("Try 'brotli-decompressor --help' for more information.");
+      process::exit(2);
+    }
+  };
+  let code = run_with_std_streams(&opts);
+  process::exit(code);
 }
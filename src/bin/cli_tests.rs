@@ -0,0 +1,375 @@
+#![cfg(test)]
+#![cfg(not(feature="seccomp"))]
+// Some helpers are only used by tests that need the built-in static dictionary.
+#![cfg_attr(feature="external-static-dictionary", allow(dead_code))]
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use super::brotli_decompressor::BrotliDecoderErrorCode;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use super::{parse_args, process_input, run, run_with_std_streams, output_name, decode_stream, format_progress, CliError,
+            CliOptions, ParsedArgs, ProgressMeter, StreamDecoder, StreamStats};
+
+static ALICE_BR: &[u8] = include_bytes!("../../testdata/alice29.txt.compressed");
+static ALICE: &[u8] = include_bytes!("../../testdata/alice29.txt");
+#[cfg(feature="external-static-dictionary")]
+static STATIC_DICTIONARY: &str = "testdata/dictionary.bin";
+
+fn parse(args: &[&str]) -> Result<CliOptions, String> {
+  match parse_args(args.iter().map(|s| s.to_string()))? {
+    ParsedArgs::Run(opts) => Ok(opts),
+    ParsedArgs::Help => Err("help".to_string()),
+  }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+  let mut dir = std::env::temp_dir();
+  dir.push(format!("brotli-decompressor-cli-{}-{}", name, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+fn write_file(dir: &Path, name: &str, data: &[u8]) -> String {
+  let path = dir.join(name);
+  fs::File::create(&path).unwrap().write_all(data).unwrap();
+  path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_parse_args() {
+  let opts = parse(&["-kf", "--no-large-window", "--max-output=4k", "a.br", "--", "-c"]).unwrap();
+  assert!(opts.keep && opts.force && !opts.to_stdout);
+  assert!(!opts.large_window);
+  assert_eq!(opts.max_output, Some(4096));
+  assert_eq!(opts.inputs, vec!["a.br".to_string(), "-c".to_string()]);
+
+  let opts = parse(&["-o", "out", "-dict=words", "--test", "-"]).unwrap();
+  assert_eq!(opts.output, Some("out".to_string()));
+  assert_eq!(opts.dictionary, Some("words".to_string()));
+  assert!(opts.test && opts.large_window && !opts.list);
+  assert!(parse(&["-l", "a.br"]).unwrap().list);
+  assert_eq!(opts.inputs, vec!["-".to_string()]);
+  let opts = parse(&["-rj4", "dir"]).unwrap();
+  assert!(opts.recursive);
+  assert_eq!(opts.jobs, 4);
+  assert_eq!(parse(&["--jobs=2", "--recursive"]).unwrap().jobs, 2);
+  assert!(parse(&["-j", "0"]).is_err());
+
+  assert_eq!(parse(&["--max-output", "2M"]).unwrap().max_output, Some(2 << 20));
+  assert_eq!(parse(&["--help"]).unwrap_err(), "help");
+  assert!(parse(&["-x"]).is_err());
+  assert!(parse(&["--max-output=lots"]).is_err());
+  assert!(parse(&["-c", "-o", "out", "a.br"]).is_err());
+  assert!(parse(&["-o", "out", "a.br", "b.br"]).is_err());
+}
+
+#[test]
+fn test_output_name() {
+  assert_eq!(output_name("dir/file.txt.br"), Some("dir/file.txt"));
+  assert_eq!(output_name("file.txt"), None);
+  assert_eq!(output_name(".br"), None);
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_in_place_keep_and_force() {
+  let dir = scratch_dir("in-place");
+  let input = write_file(&dir, "alice.txt.br", ALICE_BR);
+  let output = dir.join("alice.txt");
+  let mut opts = CliOptions { keep: true, ..CliOptions::default() };
+  assert_eq!(process_input(&input, &mut StreamDecoder::new(&[]), &opts).unwrap(), ALICE.len() as u64);
+  assert_eq!(&fs::read(&output).unwrap()[..], ALICE);
+
+  // refuses to clobber the existing output unless forced
+  match process_input(&input, &mut StreamDecoder::new(&[]), &opts) {
+    Err(CliError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+    _ => panic!("expected the existing output to be preserved"),
+  }
+  assert_eq!(&fs::read(&output).unwrap()[..], ALICE);
+
+  opts.keep = false;
+  opts.force = true;
+  process_input(&input, &mut StreamDecoder::new(&[]), &opts).unwrap();
+  assert_eq!(&fs::read(&output).unwrap()[..], ALICE);
+  assert!(fs::metadata(&input).is_err());
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_output_option() {
+  let dir = scratch_dir("output-option");
+  let input = write_file(&dir, "alice.txt.br", ALICE_BR);
+  let output = dir.join("decoded").to_str().unwrap().to_string();
+  let mut opts = CliOptions {
+    output: Some(output.clone()),
+    inputs: vec![input.clone()],
+    #[cfg(feature="external-static-dictionary")]
+    static_dictionary: Some(STATIC_DICTIONARY.to_string()),
+    ..CliOptions::default()
+  };
+  assert_eq!(run(&opts, &mut io::sink(), &mut io::sink()), 0);
+  assert_eq!(&fs::read(&output).unwrap()[..], ALICE);
+  assert!(fs::metadata(&input).is_ok());
+
+  // a directory that expands to several files is refused before any is read
+  fs::remove_file(&output).unwrap();
+  write_file(&dir, "again.txt.br", ALICE_BR);
+  opts.recursive = true;
+  opts.inputs = vec![dir.to_str().unwrap().to_string()];
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut io::sink(), &mut err), 2);
+  assert!(String::from_utf8(err).unwrap().contains("-o requires exactly one input file"));
+  assert!(fs::metadata(&output).is_err());
+  assert!(fs::metadata(&input).is_ok());
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_failures_keep_input_and_remove_partial_output() {
+  let dir = scratch_dir("failures");
+  let truncated = write_file(&dir, "truncated.br", &ALICE_BR[..ALICE_BR.len() / 2]);
+  let mut opts = CliOptions::default();
+  let mut decoder = StreamDecoder::new(&[]);
+  match process_input(&truncated, &mut decoder, &opts) {
+    Err(CliError::Truncated) => {}
+    _ => panic!("expected truncated input"),
+  }
+  assert!(fs::metadata(&truncated).is_ok());
+  assert!(fs::metadata(dir.join("truncated")).is_err());
+
+  let corrupt = write_file(&dir, "corrupt.br", b"\x11\x3f");
+  match process_input(&corrupt, &mut decoder, &opts) {
+    Err(CliError::Corrupt(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS)) => {}
+    _ => panic!("expected corrupt input"),
+  }
+
+  let mut garbage = ALICE_BR.to_vec();
+  garbage.push(0);
+  let garbage = write_file(&dir, "garbage.br", &garbage);
+  opts.test = true;
+  match process_input(&garbage, &mut decoder, &opts) {
+    Err(CliError::TrailingGarbage) => {}
+    _ => panic!("expected trailing garbage"),
+  }
+
+  let limited = write_file(&dir, "limited.br", ALICE_BR);
+  opts.test = false;
+  opts.max_output = Some(1000);
+  match process_input(&limited, &mut decoder, &opts) {
+    Err(CliError::OutputLimit(1000)) => {}
+    _ => panic!("expected the output limit to trip"),
+  }
+  assert!(fs::metadata(dir.join("limited")).is_err());
+
+  let unknown = write_file(&dir, "plain.txt", ALICE);
+  opts.max_output = None;
+  opts.inputs = vec![unknown, limited.clone()];
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut io::sink(), &mut err), 1);
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("plain.txt: unknown suffix"));
+  assert!(!err.contains("limited.br"));
+  assert_eq!(&fs::read(dir.join("limited")).unwrap()[..], ALICE);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_list() {
+  let dir = scratch_dir("list");
+  let alice = write_file(&dir, "alice.txt.br", ALICE_BR);
+  let truncated = write_file(&dir, "truncated.br", &ALICE_BR[..ALICE_BR.len() / 2]);
+  let mut stats = StreamStats::default();
+  decode_stream(&mut &ALICE_BR[..], &mut io::sink(), &[], true, None, &mut stats).unwrap();
+  assert_eq!(stats.compressed_size, ALICE_BR.len() as u64);
+  assert_eq!(stats.uncompressed_size, ALICE.len() as u64);
+  assert_eq!(stats.window_bits, 22);
+  assert_eq!(stats.meta_blocks, 1);
+
+  let opts = CliOptions {
+    list: true,
+    inputs: vec![alice.clone(), truncated.clone()],
+    ..CliOptions::default()
+  };
+  let mut out = Vec::<u8>::new();
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut out, &mut err), 1);
+  let out = String::from_utf8(out).unwrap();
+  let lines: Vec<&str> = out.lines().collect();
+  assert_eq!(lines.len(), 4);
+  let row: Vec<&str> = lines[1].split_whitespace().collect();
+  assert_eq!(row[0], ALICE_BR.len().to_string());
+  assert_eq!(row[1], ALICE.len().to_string());
+  assert_eq!(row[3], "22");
+  assert_eq!(row[4], stats.meta_blocks.to_string());
+  assert_eq!(row[5], "yes");
+  assert_eq!(row[6], alice);
+  assert!(lines[2].contains(" no "));
+  assert!(lines[3].ends_with("(totals)"));
+  assert!(String::from_utf8(err).unwrap().contains("truncated.br: unexpected end of input"));
+  // listing never touches the inputs
+  assert!(fs::metadata(&alice).is_ok() && fs::metadata(dir.join("alice.txt")).is_err());
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_parallel_recursive_batch() {
+  let dir = scratch_dir("batch");
+  fs::create_dir_all(dir.join("nested/deeper")).unwrap();
+  let mut expected = Vec::new();
+  for i in 0..12 {
+    let sub = ["", "nested/", "nested/deeper/"][i % 3];
+    let name = format!("{}file{}.txt.br", sub, i);
+    write_file(&dir, &name, ALICE_BR);
+    expected.push(dir.join(format!("{}file{}.txt", sub, i)));
+  }
+  let corrupt = write_file(&dir, "nested/corrupt.br", &ALICE_BR[..1000]);
+  let plain = write_file(&dir, "nested/plain.txt", b"not brotli");
+  let opts = CliOptions {
+    recursive: true,
+    jobs: 4,
+    inputs: vec![dir.to_str().unwrap().to_string()],
+    ..CliOptions::default()
+  };
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut io::sink(), &mut err), 1);
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("corrupt.br: unexpected end of input"));
+  assert!(err.ends_with("1 of 13 files failed\n"));
+  for output in expected.iter() {
+    assert_eq!(&fs::read(output).unwrap()[..], ALICE);
+  }
+  // failures and files without a .br suffix are left alone
+  assert!(fs::metadata(&corrupt).is_ok());
+  assert!(fs::metadata(dir.join("nested/corrupt")).is_err());
+  assert_eq!(&fs::read(&plain).unwrap()[..], b"not brotli");
+  assert!(fs::metadata(dir.join("file0.txt.br")).is_err());
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_decoder_reuse_after_failure() {
+  let mut decoder = StreamDecoder::new(&[]);
+  let mut out = Vec::new();
+  let mut stats = StreamStats::default();
+  assert!(decoder.decode(&mut &ALICE_BR[..2000], &mut out, true, None, &mut stats).is_err());
+  for _ in 0..2 {
+    out.clear();
+    stats = StreamStats::default();
+    decoder.decode(&mut &ALICE_BR[..], &mut out, true, None, &mut stats).unwrap();
+    assert_eq!(&out[..], ALICE);
+    assert_eq!(stats.compressed_size, ALICE_BR.len() as u64);
+  }
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[test]
+fn test_mem_stats() {
+  assert!(parse(&["--mem-stats", "a.br"]).unwrap().mem_stats);
+  assert!(parse(&["--mem-stats=yes", "a.br"]).is_err());
+  let dir = scratch_dir("mem-stats");
+  let alice = write_file(&dir, "alice.txt.br", ALICE_BR);
+  let opts = CliOptions {
+    test: true,
+    mem_stats: true,
+    inputs: vec![alice.clone(), alice.clone()],
+    ..CliOptions::default()
+  };
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut io::sink(), &mut err), 0);
+  let err = String::from_utf8(err).unwrap();
+  let lines: Vec<&str> = err.lines().collect();
+  assert_eq!(lines.len(), 8);
+  assert!(lines[0].starts_with(&format!("brotli-decompressor: {}: peak memory ", alice)));
+  assert!(lines[1].trim_start().starts_with("u8 "));
+  assert!(lines[3].trim_start().starts_with("huffman "));
+  // the reused decoder measures the second stream on its own
+  assert_eq!(lines[..4], lines[4..]);
+
+  let mut decoder = StreamDecoder::new(&[]);
+  let mut stats = StreamStats::default();
+  decoder.decode(&mut &ALICE_BR[..], &mut io::sink(), true, None, &mut stats).unwrap();
+  let memory = decoder.mem_stats();
+  assert!(memory.u8.peak_bytes >= ALICE.len());
+  assert!(lines[0].ends_with(&format!(" {} bytes", memory.peak_bytes())));
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_progress() {
+  assert!(parse(&["--progress", "a.br"]).unwrap().progress);
+  assert!(!parse(&["a.br"]).unwrap().progress);
+  assert_eq!(format_progress("a.br", 512, Some(2048), 3 << 20, Duration::from_secs(2)),
+             "a.br:  25% 512 -> 3145728 bytes, 1.5 MiB/s");
+  assert_eq!(format_progress("(stdin)", 512, None, 0, Duration::from_secs(0)),
+             "(stdin): 512 -> 0 bytes, 0.0 MiB/s");
+  assert_eq!(format_progress("empty.br", 0, Some(0), 0, Duration::from_secs(1)),
+             "empty.br: 0 -> 0 bytes, 0.0 MiB/s");
+
+  // the meter only lasts for the next stream and does not change the output
+  let mut decoder = StreamDecoder::new(&[]);
+  #[cfg(feature="external-static-dictionary")]
+  assert!(decoder.set_static_dictionary(&super::load_static_dictionary(STATIC_DICTIONARY).unwrap()));
+  decoder.set_progress(ProgressMeter::new("alice", Some(ALICE_BR.len() as u64)));
+  for _ in 0..2 {
+    let mut output = Vec::new();
+    let mut stats = StreamStats::default();
+    decoder.decode(&mut &ALICE_BR[..], &mut output, true, None, &mut stats).unwrap();
+    assert!(output[..] == ALICE[..]);
+  }
+}
+
+#[test]
+fn test_progress_with_jobs() {
+  // workers draw progress on standard error while the main thread reports
+  // there too, so neither may keep it locked
+  let dir = scratch_dir("progress-jobs");
+  let inputs = (0..4).map(|i| write_file(&dir, &format!("{}.br", i), ALICE_BR)).collect();
+  let opts = CliOptions {
+    test: true,
+    progress: true,
+    jobs: 3,
+    inputs,
+    #[cfg(feature="external-static-dictionary")]
+    static_dictionary: Some(STATIC_DICTIONARY.to_string()),
+    ..CliOptions::default()
+  };
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || {
+    let _ = sender.send(run_with_std_streams(&opts));
+  });
+  assert_eq!(receiver.recv_timeout(Duration::from_secs(60)), Ok(0));
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(feature="external-static-dictionary")]
+#[test]
+fn test_static_dictionary_flag() {
+  assert_eq!(parse(&["--static-dictionary=d.bin", "a.br"]).unwrap().static_dictionary,
+             Some("d.bin".to_string()));
+  let dir = scratch_dir("static-dictionary");
+  let alice = write_file(&dir, "alice.txt.br", ALICE_BR);
+  let mut opts = CliOptions {
+    test: true,
+    inputs: vec![alice.clone(), alice.clone()],
+    ..CliOptions::default()
+  };
+  // without the dictionary the stream cannot be decoded
+  assert_eq!(run(&opts, &mut io::sink(), &mut io::sink()), 1);
+
+  opts.static_dictionary = Some(STATIC_DICTIONARY.to_string());
+  assert_eq!(run(&opts, &mut io::sink(), &mut io::sink()), 0);
+
+  // a file that is not the RFC 7932 dictionary is refused up front
+  opts.static_dictionary = Some(alice.clone());
+  let mut err = Vec::<u8>::new();
+  assert_eq!(run(&opts, &mut io::sink(), &mut err), 1);
+  assert!(String::from_utf8(err).unwrap().contains("not the brotli static dictionary"));
+  let _ = fs::remove_dir_all(&dir);
+}
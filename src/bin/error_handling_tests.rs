@@ -1,5 +1,7 @@
 #![cfg(test)]
 #![cfg(feature="std")]
+// Some helpers are only used by tests that need the built-in static dictionary.
+#![cfg_attr(feature="external-static-dictionary", allow(dead_code))]
 use std::io::{self, Read, Cursor};
 
 extern crate brotli_decompressor;
@@ -123,6 +125,7 @@ impl Read for R2 {
     }
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn would_block_more() {
     // Reference synchronous decoding.
@@ -177,6 +180,7 @@ fn would_block_more() {
 // reference (`libbrotlidec`) and Go implementations (cbrotli / andybalholm)
 // in their corresponding tests; both reject all corruptions below as
 // PADDING_2 / _ERROR_FORMAT_PADDING_2.
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_padding_2_rejection() {
     // Valid encoding of "the quick brown fox jumps over the lazy dog twice
@@ -1,4 +1,6 @@
 #![cfg(test)]
+// Some helpers are only used by tests that need the built-in static dictionary.
+#![cfg_attr(feature="external-static-dictionary", allow(dead_code))]
 extern crate core;
 use std::io;
 #[cfg(feature="std")]
@@ -328,7 +330,7 @@ fn test_streaming_leftover_buffer() {
 }
 
 #[test]
-#[cfg(feature="std")]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
 fn test_reader_uni() {
   reader_helper(include_bytes!("../../testdata/random_then_unicode.compressed"),
                                            include_bytes!("../../testdata/random_then_unicode"), 121)
@@ -548,6 +550,7 @@ fn test_random1024small_buffer() {
                                            1024);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_as_you_like_it() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/asyoulik.txt.compressed"),
@@ -567,16 +570,19 @@ fn test_negative_hypothesis() {
 }
 static ALICE29_BR: &'static [u8] = include_bytes!("../../testdata/alice29.txt.compressed");
 static ALICE29: &'static [u8] = include_bytes!("../../testdata/alice29.txt");
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_alice29() {
   assert_decompressed_input_matches_output(ALICE29_BR, ALICE29, 65536, 65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn benchmark_alice29() {
   benchmark_decompressed_input(ALICE29_BR, ALICE29, 65536, 65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_alice1() {
   assert_decompressed_input_matches_output(ALICE29_BR, ALICE29, 1, 65536);
@@ -749,6 +755,7 @@ fn test_empty18() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn lcet10() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/lcet10.txt.compressed"),
@@ -765,6 +772,7 @@ fn test_mapsdatazrh() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_monkey() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/monkey.compressed"),
@@ -773,6 +781,7 @@ fn test_monkey() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_monkey1() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/monkey.compressed"),
@@ -781,6 +790,7 @@ fn test_monkey1() {
                                            1);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_monkey3() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/monkey.compressed"),
@@ -789,6 +799,7 @@ fn test_monkey3() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_plrabn12() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/plrabn12.txt.compressed"),
@@ -883,6 +894,7 @@ fn test_zeros() {
 }
 
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_reset() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/metablock_reset.compressed"),
@@ -891,6 +903,7 @@ fn test_metablock_reset() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_section_4_distance_symbol_0() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/fuzz502.compressed"),
@@ -899,6 +912,7 @@ fn test_metablock_section_4_distance_symbol_0() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_intact_distance_ring_buffer0() {
   static BR:&'static[u8] = &[0x1b, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x80, 0xe3, 0xb4, 0x0d, 0x00, 0x00,
@@ -911,6 +925,7 @@ fn test_intact_distance_ring_buffer0() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_intact_distance_ring_buffer1() {
   static BR:&'static[u8] = &[0x1b, 0x09, 0x00, 0x00, 0x00, 0x00, 0x80, 0xe3, 0xb4, 0x0d, 0x00, 0x00,
@@ -924,6 +939,7 @@ fn test_intact_distance_ring_buffer1() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_intact_distance_ring_buffer2() {
   static BR:&'static[u8] = &[0x1b, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x80, 0xe3, 0xb4, 0x0d, 0x00, 0x00,
@@ -936,6 +952,7 @@ fn test_intact_distance_ring_buffer2() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_reset1_65536() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/metablock_reset.compressed"),
@@ -944,6 +961,7 @@ fn test_metablock_reset1_65536() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_reset65536_1() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/metablock_reset.compressed"),
@@ -952,6 +970,7 @@ fn test_metablock_reset65536_1() {
                                            1);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_reset1() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/metablock_reset.compressed"),
@@ -960,6 +979,7 @@ fn test_metablock_reset1() {
                                            1);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_metablock_reset3() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/metablock_reset.compressed"),
@@ -977,6 +997,7 @@ fn test_broken_file() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_ends_with_truncated_dictionary() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/ends_with_truncated_dictionary.\
@@ -986,6 +1007,7 @@ fn test_ends_with_truncated_dictionary() {
                                            65536);
 }
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_random_then_unicode() {
   assert_decompressed_input_matches_output(include_bytes!("../../testdata/random_then_unicode.\
@@ -994,6 +1016,7 @@ fn test_random_then_unicode() {
                                            65536,
                                            65536);
 }
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_large_window() {
   assert_huge_file_input_matches_output(include_bytes!("../../testdata/rnd_chunk.br"),
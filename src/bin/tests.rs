@@ -177,6 +177,7 @@ fn test_10x_10y() {
 }
 
 
+#[cfg(not(feature="external-static-dictionary"))]
 #[test]
 fn test_alice() {
   let in_buf = include_bytes!("../../testdata/alice29.txt.compressed");
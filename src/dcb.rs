@@ -0,0 +1,151 @@
+// Dictionary-compressed brotli, the dcb content encoding of HTTP Compression
+// Dictionary Transport: a magic number and the SHA-256 of the dictionary come
+// before a brotli stream that uses the dictionary as a raw (compound) dictionary.
+// The dictionaries are looked up by hash in a registry the caller provides.
+use core;
+#[cfg(feature="std")]
+use std::collections::HashMap;
+#[cfg(feature="std")]
+use std::hash::BuildHasher;
+#[cfg(feature="std")]
+use std::io::{self, Error, ErrorKind, Read};
+#[cfg(feature="std")]
+use alloc_crate::vec::Vec;
+#[cfg(feature="std")]
+use reader::Decompressor;
+#[cfg(feature="std")]
+use shared_dictionary::BrotliSharedDictionaryType;
+use sha256::sha256;
+
+/// The four bytes a dcb stream starts with.
+pub const DCB_MAGIC: [u8; 4] = [0xff, 0x44, 0x43, 0x42];
+/// Length of the SHA-256 of the dictionary that follows the magic.
+pub const DCB_HASH_LEN: usize = 32;
+/// Length of the whole header, before the brotli stream.
+pub const DCB_HEADER_LEN: usize = 36;
+
+/// Why a dcb stream could not be started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DcbError {
+  /// The stream is shorter than the header or does not start with DCB_MAGIC.
+  BadHeader,
+  /// The registry has no dictionary with the hash from the header, or the
+  /// one it returned has a different hash.
+  UnknownDictionary,
+}
+
+impl core::fmt::Display for DcbError {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    match *self {
+      DcbError::BadHeader => f.write_str("invalid dcb header"),
+      DcbError::UnknownDictionary => f.write_str("unknown dcb dictionary"),
+    }
+  }
+}
+
+#[cfg(feature="std")]
+impl std::error::Error for DcbError {}
+
+/// Dictionaries that dcb streams may refer to, keyed by their SHA-256.
+pub trait DictionaryRegistry {
+  fn get_dictionary(&self, hash: &[u8; DCB_HASH_LEN]) -> Option<&[u8]>;
+}
+
+impl DictionaryRegistry for [([u8; DCB_HASH_LEN], &[u8])] {
+  fn get_dictionary(&self, hash: &[u8; DCB_HASH_LEN]) -> Option<&[u8]> {
+    self.iter().find(|entry| entry.0 == *hash).map(|entry| entry.1)
+  }
+}
+
+#[cfg(feature="std")]
+impl<S: BuildHasher> DictionaryRegistry for HashMap<[u8; DCB_HASH_LEN], Vec<u8>, S> {
+  fn get_dictionary(&self, hash: &[u8; DCB_HASH_LEN]) -> Option<&[u8]> {
+    self.get(hash).map(|dictionary| &dictionary[..])
+  }
+}
+
+/// The SHA-256 of a dictionary, which is what dcb headers and registries
+/// identify it by.
+pub fn dictionary_hash(dictionary: &[u8]) -> [u8; DCB_HASH_LEN] {
+  sha256(dictionary)
+}
+
+/// Returns the dictionary hash from the first DCB_HEADER_LEN bytes of a dcb
+/// stream.
+pub fn parse_dcb_header(header: &[u8]) -> Result<[u8; DCB_HASH_LEN], DcbError> {
+  if header.len() < DCB_HEADER_LEN || header[..DCB_MAGIC.len()] != DCB_MAGIC {
+    return Err(DcbError::BadHeader);
+  }
+  let mut hash = [0u8; DCB_HASH_LEN];
+  hash.clone_from_slice(&header[DCB_MAGIC.len()..DCB_HEADER_LEN]);
+  Ok(hash)
+}
+
+/// Parses a dcb header and finds its dictionary in registry, checking the
+/// hash of what the registry returns. The brotli stream starts right after
+/// the header and is decoded with the dictionary attached as a raw dictionary.
+pub fn find_dcb_dictionary<'a, Registry: DictionaryRegistry + ?Sized>(
+  header: &[u8],
+  registry: &'a Registry) -> Result<&'a [u8], DcbError> {
+  let hash = parse_dcb_header(header)?;
+  match registry.get_dictionary(&hash) {
+    Some(dictionary) if dictionary_hash(dictionary) == hash => Ok(dictionary),
+    _ => Err(DcbError::UnknownDictionary),
+  }
+}
+
+// dcb bodies use the dictionary as a raw (compound) dictionary, whose bytes
+// stay addressable however far the output runs past the window.
+#[cfg(feature="std")]
+fn new_decompressor<R: Read>(r: R, buffer_size: usize, dictionary: &[u8]) -> io::Result<Decompressor<R>> {
+  let mut decompressor = Decompressor::new(r, buffer_size);
+  if !decompressor.attach_dictionary(BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_RAW, dictionary) {
+    return Err(Error::new(ErrorKind::InvalidInput, "dcb dictionary too large"));
+  }
+  Ok(decompressor)
+}
+
+/// Decompresses a dcb stream. The header is read and the dictionary looked
+/// up on construction; a bad header fails with ErrorKind::InvalidData and an
+/// unknown dictionary with ErrorKind::NotFound, both carrying a DcbError.
+#[cfg(feature="std")]
+pub struct DcbDecompressor<R: Read>(Decompressor<R>);
+
+#[cfg(feature="std")]
+impl<R: Read> DcbDecompressor<R> {
+  pub fn new<Registry: DictionaryRegistry + ?Sized>(mut r: R,
+                                                     buffer_size: usize,
+                                                     registry: &Registry)
+                                                     -> io::Result<Self> {
+    let mut header = [0u8; DCB_HEADER_LEN];
+    if let Err(e) = r.read_exact(&mut header) {
+      if e.kind() == ErrorKind::UnexpectedEof {
+        return Err(Error::new(ErrorKind::InvalidData, DcbError::BadHeader));
+      }
+      return Err(e);
+    }
+    match find_dcb_dictionary(&header, registry) {
+      Ok(dictionary) => Ok(DcbDecompressor(new_decompressor(r, buffer_size, dictionary)?)),
+      Err(DcbError::BadHeader) => Err(Error::new(ErrorKind::InvalidData, DcbError::BadHeader)),
+      Err(DcbError::UnknownDictionary) => {
+        Err(Error::new(ErrorKind::NotFound, DcbError::UnknownDictionary))
+      }
+    }
+  }
+  pub fn get_ref(&self) -> &R {
+    self.0.get_ref()
+  }
+  pub fn get_mut(&mut self) -> &mut R {
+    self.0.get_mut()
+  }
+  pub fn into_inner(self) -> R {
+    self.0.into_inner()
+  }
+}
+
+#[cfg(feature="std")]
+impl<R: Read> Read for DcbDecompressor<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.0.read(buf)
+  }
+}
@@ -23,10 +23,16 @@ use state::{BlockTypeAndLengthState, BrotliRunningContextMapState, BrotliRunning
             BrotliRunningHuffmanState, BrotliRunningMetablockHeaderState,
             BrotliRunningReadBlockLengthState, BrotliRunningState, BrotliRunningTreeGroupState,
             BrotliRunningUncompressedState, kLiteralContextBits,
-            BrotliDecoderErrorCode,
+            BrotliDecoderErrorCode, BrotliDecoderMetadataStartFunc,
+            BrotliDecoderMetadataChunkFunc, BrotliDecoderProgressFunc, SharedCustomDictionary,
 };
+use shared_dictionary::{BrotliSharedDictionaryType, DecodeSharedDictionary,
+                        FillSharedDictionaryTables, SharedDictionaryId,
+                        TransformSharedDictionaryWord, SHARED_BROTLI_MAX_COMPOUND_DICTS,
+                        SHARED_BROTLI_MAX_DICTIONARY_WORD_LENGTH,
+                        SHARED_BROTLI_MIN_DICTIONARY_WORD_LENGTH};
 use context::{kContextLookup};
-use ::dictionary::{kBrotliDictionary, kBrotliDictionaryOffsetsByLength,
+use ::dictionary::{BrotliIsStaticDictionary, kBrotliDictionaryOffsetsByLength,
                    kBrotliDictionarySizeBitsByLength, kBrotliMaxDictionaryWordLength,
                    kBrotliMinDictionaryWordLength};
 pub use huffman::{HuffmanCode, HuffmanTreeGroup};
@@ -39,6 +45,8 @@ pub enum BrotliResult {
   ResultFailure = 0,
 }
 const kBrotliWindowGap: u32 = 16;
+const kBrotliMinWbits: u32 = 10;
+const kBrotliMaxWbits: u32 = 24;
 const kBrotliLargeMinWbits: u32 = 10;
 const kBrotliLargeMaxWbits: u32 = 30;
 const kBrotliMaxPostfix: usize = 3;
@@ -1252,6 +1260,247 @@ pub fn lg_window_size(first_byte: u8, second_byte: u8) -> Result<(u8, u8), ()> {
 
 }
 
+// We need the slack region past the ring buffer for the following reasons:
+// - doing up to two 16-byte copies for fast backward copying
+// - inserting transformed dictionary word (5 prefix + 24 base + 8 suffix)
+pub(crate) const kRingBufferWriteAheadSlack: usize = 42;
+// Room past the end of the ring buffer for the bytes copies write ahead and
+// for the longest word a static dictionary reference can produce: what
+// RingBufferSlack gives a decoder without a serialized shared dictionary.
+pub(crate) const kRingBufferSlack: usize =
+  kRingBufferWriteAheadSlack + kBrotliMaxDictionaryWordLength as usize;
+// Worst case number of Huffman trees in one tree group: context map
+// entries are bytes, and there are at most 256 block types.
+const kMaxTreesPerGroup: usize = 256;
+
+/// Header of a single meta-block, as reported by `inspect_header`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrotliMetaBlockHeaderInfo {
+  /// MLEN: uncompressed bytes in the meta-block (bytes skipped for metadata).
+  pub mlen: usize,
+  pub is_last: bool,
+  pub is_uncompressed: bool,
+  pub is_metadata: bool,
+}
+
+/// Summary of a brotli stream computed from its first bytes by `inspect_header`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrotliHeaderInfo {
+  pub window_bits: u8,
+  pub large_window: bool,
+  /// Bytes of the ring buffer a default (non-strict) decoder allocates,
+  /// including the slack past the window. A serialized shared dictionary
+  /// whose transforms make words longer needs more slack.
+  pub ringbuffer_size: usize,
+  /// Worst case u8 cells needed besides the ring buffer. Like scratch_u32
+  /// and scratch_hc, this does not depend on the header: it is the same
+  /// constant for every stream that is not empty.
+  pub scratch_u8: usize,
+  /// Worst case u32 cells needed.
+  pub scratch_u32: usize,
+  /// Worst case HuffmanCode cells needed.
+  pub scratch_hc: usize,
+  /// True if the first meta-block is ISLAST and ISEMPTY, so the stream decodes to nothing.
+  pub is_empty_stream: bool,
+  /// None if the input ends before the first meta-block header is complete.
+  pub first_metablock: Option<BrotliMetaBlockHeaderInfo>,
+}
+
+struct HeaderBitReader<'a> {
+  input: &'a [u8],
+  bit_pos: usize,
+}
+
+impl<'a> HeaderBitReader<'a> {
+  fn read(&mut self, n_bits: u32) -> Option<u32> {
+    let mut val = 0u32;
+    for i in 0..n_bits {
+      let byte = match self.input.get(self.bit_pos >> 3) {
+        Some(byte) => *byte,
+        None => return None,
+      };
+      val |= (((byte >> (self.bit_pos & 7)) & 1) as u32) << i;
+      self.bit_pos += 1;
+    }
+    Some(val)
+  }
+}
+
+fn InspectWindowBits(br: &mut HeaderBitReader) -> Result<(u8, bool), BrotliDecoderErrorCode> {
+  let more = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT;
+  if br.read(1).ok_or(more)? == 0 {
+    return Ok((16, false));
+  }
+  let n = br.read(3).ok_or(more)?;
+  if n != 0 {
+    return Ok((17 + n as u8, false));
+  }
+  let n = br.read(3).ok_or(more)?;
+  if n == 1 {
+    if br.read(1).ok_or(more)? != 0 {
+      return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS);
+    }
+    let wbits = br.read(6).ok_or(more)?;
+    if !(kBrotliLargeMinWbits..=kBrotliLargeMaxWbits).contains(&wbits) {
+      return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS);
+    }
+    return Ok((wbits as u8, true));
+  }
+  if n != 0 {
+    return Ok((8 + n as u8, false));
+  }
+  Ok((17, false))
+}
+
+// Mirrors DecodeMetaBlockLength; None means the input was exhausted.
+fn InspectMetaBlockHeader(br: &mut HeaderBitReader)
+                          -> Result<Option<BrotliMetaBlockHeaderInfo>, BrotliDecoderErrorCode> {
+  macro_rules! read_or_none {
+    ($n: expr) => {
+      match br.read($n) {
+        Some(bits) => bits,
+        None => return Ok(None),
+      }
+    };
+  }
+  let mut info = BrotliMetaBlockHeaderInfo {
+    mlen: 0,
+    is_last: read_or_none!(1) != 0,
+    is_uncompressed: false,
+    is_metadata: false,
+  };
+  if info.is_last && read_or_none!(1) != 0 {
+    return Ok(Some(info));
+  }
+  let nibbles = read_or_none!(2);
+  if nibbles == 3 {
+    info.is_metadata = true;
+    if read_or_none!(1) != 0 {
+      return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_RESERVED);
+    }
+    let size_bytes = read_or_none!(2);
+    if size_bytes == 0 {
+      return Ok(Some(info));
+    }
+    for i in 0..size_bytes {
+      let bits = read_or_none!(8);
+      if i + 1 == size_bytes && size_bytes > 1 && bits == 0 {
+        return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_META_NIBBLE);
+      }
+      info.mlen |= (bits as usize) << (i * 8);
+    }
+  } else {
+    let size_nibbles = nibbles + 4;
+    for i in 0..size_nibbles {
+      let bits = read_or_none!(4);
+      if i + 1 == size_nibbles && size_nibbles > 4 && bits == 0 {
+        return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_EXUBERANT_NIBBLE);
+      }
+      info.mlen |= (bits as usize) << (i * 4);
+    }
+    if !info.is_last {
+      info.is_uncompressed = read_or_none!(1) != 0;
+    }
+  }
+  info.mlen += 1;
+  Ok(Some(info))
+}
+
+/// Parses the stream header and the first meta-block header without allocating.
+/// Returns BROTLI_DECODER_NEEDS_MORE_INPUT if the window bits are not yet available
+/// and a format error if the header is invalid.
+pub fn inspect_header(input: &[u8]) -> Result<BrotliHeaderInfo, BrotliDecoderErrorCode> {
+  inspect_header_with_dictionary(input, 0)
+}
+
+/// Like inspect_header, for a decoder given a custom dictionary of
+/// custom_dict_size bytes, which the ring buffer has to hold as well.
+pub fn inspect_header_with_dictionary(input: &[u8], custom_dict_size: usize)
+                                      -> Result<BrotliHeaderInfo, BrotliDecoderErrorCode> {
+  let mut br = HeaderBitReader {
+    input,
+    bit_pos: 0,
+  };
+  let (window_bits, large_window) = InspectWindowBits(&mut br)?;
+  let first_metablock = InspectMetaBlockHeader(&mut br)?;
+  let is_empty_stream = match first_metablock {
+    Some(ref mb) => mb.is_last && !mb.is_metadata && mb.mlen == 0,
+    None => false,
+  };
+  let persistent_hc = 7 * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize;
+  if is_empty_stream {
+    return Ok(BrotliHeaderInfo {
+      window_bits,
+      large_window,
+      ringbuffer_size: 0,
+      scratch_u8: 0,
+      scratch_u32: 0,
+      scratch_hc: persistent_hc,
+      is_empty_stream: true,
+      first_metablock,
+    });
+  }
+  let last_metablock_len = match first_metablock {
+    Some(ref mb) if mb.is_metadata => None,
+    Some(ref mb) if mb.is_last => Some(mb.mlen),
+    Some(ref mb) if mb.is_uncompressed => {
+      // Like BrotliAllocateRingBuffer, peek past the uncompressed data, which
+      // starts at the next byte, for an ISLAST and ISEMPTY header.
+      match input.get(((br.bit_pos + 7) >> 3) + mb.mlen) {
+        Some(next_block_header) if next_block_header & 3 == 3 => Some(mb.mlen),
+        _ => None,
+      }
+    }
+    _ => None,
+  };
+  let ringbuffer_size = RingBufferSizeForWindow(window_bits as u32, custom_dict_size, last_metablock_len);
+  Ok(BrotliHeaderInfo {
+    window_bits,
+    large_window,
+    ringbuffer_size: ringbuffer_size + kRingBufferSlack,
+    scratch_u8: kMaxTreesPerGroup + (kMaxTreesPerGroup << kLiteralContextBits) +
+                (kMaxTreesPerGroup << kDistanceContextBits),
+    scratch_u32: 3 * kMaxTreesPerGroup,
+    scratch_hc: persistent_hc +
+                3 * kMaxTreesPerGroup * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize,
+    is_empty_stream: false,
+    first_metablock,
+  })
+}
+
+// Ring buffer size, without slack, for a 2^window_bits window. If the
+// stream ends with a meta-block of last_metablock_len bytes, the ring buffer
+// only has to hold that and the custom dictionary (which is cut down to the
+// window less 16 bytes). It keeps at least 32 bytes so that the last two
+// bytes are there for context.
+fn RingBufferSizeForWindow(window_bits: u32,
+                           custom_dict_size: usize,
+                           last_metablock_len: Option<usize>)
+                           -> usize {
+  let window_size = 1usize << window_bits;
+  let mut ringbuffer_size = window_size;
+  if let Some(mlen) = last_metablock_len {
+    let custom_dict_size = core::cmp::min(custom_dict_size, window_size - 16);
+    while ringbuffer_size >= (custom_dict_size + mlen + 16) * 2 && ringbuffer_size > 32 {
+      ringbuffer_size >>= 1;
+    }
+  }
+  ringbuffer_size
+}
+
+// kRingBufferSlack, widened for the longer words custom transforms of a
+// shared dictionary can produce.
+fn RingBufferSlack<AllocU8: alloc::Allocator<u8>,
+                   AllocU32: alloc::Allocator<u32>,
+                   AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &BrotliState<AllocU8, AllocU32, AllocHC>) -> usize {
+  if s.shared_dict.num_dictionaries == 0 {
+    return kRingBufferSlack;
+  }
+  core::cmp::max(kRingBufferSlack,
+                 kRingBufferWriteAheadSlack + SHARED_BROTLI_MAX_DICTIONARY_WORD_LENGTH +
+                 s.shared_dict.max_affix_len)
+}
 
 fn bzero(data: &mut [u8]) {
   for iter in data.iter_mut() {
@@ -1809,12 +2058,9 @@ fn BrotliAllocateRingBuffer<AllocU8: alloc::Allocator<u8>,
                             AllocU32: alloc::Allocator<u32>,
                             AllocHC: alloc::Allocator<HuffmanCode>>
   (s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
-   input: &[u8])
+   input: &[u8],
+   lent_dict: &[u8])
    -> bool {
-  // We need the slack region for the following reasons:
-  // - doing up to two 16-byte copies for fast backward copying
-  // - inserting transformed dictionary word (5 prefix + 24 base + 8 suffix)
-  const kRingBufferWriteAheadSlack: i32 = 42;
   let mut is_last = s.is_last_metablock;
   s.ringbuffer_size = 1 << s.window_bits;
 
@@ -1830,30 +2076,33 @@ fn BrotliAllocateRingBuffer<AllocU8: alloc::Allocator<u8>,
   }
   let max_dict_size = s.ringbuffer_size as usize - 16;
   {
+    let full_dict = match s.shared_custom_dict {
+      Some(ref dict) => dict.slice(),
+      None if s.custom_dict.slice().is_empty() => lent_dict,
+      None => s.custom_dict.slice(),
+    };
     let custom_dict = if s.custom_dict_size as usize > max_dict_size {
-      let cd = fast_slice!((s.custom_dict)[(s.custom_dict_size as usize - max_dict_size); s.custom_dict_size as usize]);
+      let cd = fast!((full_dict)[(s.custom_dict_size as usize - max_dict_size); s.custom_dict_size as usize]);
       s.custom_dict_size = max_dict_size as isize;
       cd
     } else {
-      fast_slice!((s.custom_dict)[0; s.custom_dict_size as usize])
+      fast!((full_dict)[0; s.custom_dict_size as usize])
     };
 
-    // We need at least 2 bytes of ring buffer size to get the last two
-    // bytes for context from there
-    if is_last != 0 && s.canny_ringbuffer_allocation {
-      while (s.ringbuffer_size as isize >= (s.custom_dict_size + s.meta_block_remaining_len as isize + 16) * 2 && s.ringbuffer_size as isize > 32) {
-        s.ringbuffer_size >>= 1;
-      }
-    }
-    if s.ringbuffer_size > (1 << s.window_bits) {
-      s.ringbuffer_size = (1 << s.window_bits);
-    }
+    let last_metablock_len = if is_last != 0 && s.canny_ringbuffer_allocation {
+      Some(s.meta_block_remaining_len as usize)
+    } else {
+      None
+    };
+    s.ringbuffer_size = RingBufferSizeForWindow(s.window_bits,
+                                                s.custom_dict_size as usize,
+                                                last_metablock_len) as i32;
 
     s.ringbuffer_mask = s.ringbuffer_size - 1;
-    s.ringbuffer = s.alloc_u8
-      .alloc_cell((s.ringbuffer_size as usize + kRingBufferWriteAheadSlack as usize +
-                   kBrotliMaxDictionaryWordLength as usize));
-    if (s.ringbuffer.slice().len() == 0) {
+    let slack = RingBufferSlack(s);
+    s.ringbuffer = s.alloc_u8.alloc_cell(s.ringbuffer_size as usize + slack);
+    if (s.ringbuffer.slice().len() < s.ringbuffer_size as usize + slack) {
+      s.alloc_u8.free_cell(core::mem::take(&mut s.ringbuffer));
       return false;
     }
     fast_mut!((s.ringbuffer.slice_mut())[s.ringbuffer_size as usize - 1]) = 0;
@@ -1881,7 +2130,7 @@ mod tests {
     state.window_bits = 16;
     state.is_last_metablock = 1;
     state.canny_ringbuffer_allocation = canny;
-    assert!(BrotliAllocateRingBuffer(&mut state, &[]));
+    assert!(BrotliAllocateRingBuffer(&mut state, &[], &[]));
     state.ringbuffer_size
   }
 
@@ -2319,6 +2568,316 @@ pub fn BrotliDecoderIsFinished<AllocU8: alloc::Allocator<u8>,
   }
 }
 
+// Adds a dictionary to a decoder that has not consumed any input yet. A raw
+// dictionary is an LZ77 prefix; a serialized one is a shared dictionary whose
+// prefix, if any, is used the same way. Up to SHARED_BROTLI_MAX_COMPOUND_DICTS
+// prefixes can be attached, each is copied into memory from alloc_u8.
+// Backward distances just past the window address the concatenated prefixes
+// from their end, ahead of the static dictionary.
+// The word lists, transform lists and context map of a serialized dictionary
+// replace the built-in static dictionary; only one dictionary that uses them
+// can be attached. They are copied as well.
+pub fn BrotliDecoderAttachDictionary<AllocU8: alloc::Allocator<u8>,
+                                     AllocU32: alloc::Allocator<u32>,
+                                     AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  dict_type: BrotliSharedDictionaryType,
+  data: &[u8]) -> bool {
+  if !matches!(s.state, BrotliRunningState::BROTLI_STATE_UNINITED) {
+    return false;
+  }
+  let shared = match dict_type {
+    BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_RAW => None,
+    BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_SERIALIZED => {
+      match DecodeSharedDictionary(data) {
+        Some(shared) => Some(shared),
+        None => return false,
+      }
+    }
+  };
+  let prefix = match shared {
+    Some(ref shared) => shared.prefix,
+    None => data,
+  };
+  // A serialized dictionary without a prefix does not use up a slot.
+  let attach_prefix = shared.is_none() || !prefix.is_empty();
+  if attach_prefix {
+    if s.num_compound_dicts == SHARED_BROTLI_MAX_COMPOUND_DICTS {
+      return false;
+    }
+    if s.compound_dict.slice().len() + prefix.len() > kBrotliMaxAllowedDistance as usize {
+      return false;
+    }
+  }
+  let mut lists = AllocU8::AllocatedMemory::default();
+  let mut tables = AllocU32::AllocatedMemory::default();
+  let custom = match shared {
+    Some(ref shared) if !shared.layout.IsBuiltIn() => Some(shared),
+    _ => None,
+  };
+  if let Some(shared) = custom {
+    if s.shared_dict.num_dictionaries != 0 {
+      return false;
+    }
+    lists = s.alloc_u8.alloc_cell(shared.lists.len());
+    tables = s.alloc_u32.alloc_cell(shared.layout.TablesSize());
+    if lists.slice().len() != shared.lists.len() ||
+       tables.slice().len() != shared.layout.TablesSize() ||
+       !FillSharedDictionaryTables(shared.lists, &shared.layout, tables.slice_mut()) {
+      s.alloc_u8.free_cell(lists);
+      s.alloc_u32.free_cell(tables);
+      return false;
+    }
+    lists.slice_mut().clone_from_slice(shared.lists);
+  }
+  if !prefix.is_empty() {
+    let old_size = s.compound_dict.slice().len();
+    let new_size = old_size + prefix.len();
+    let mut compound_dict = s.alloc_u8.alloc_cell(new_size);
+    if compound_dict.slice().len() != new_size {
+      s.alloc_u8.free_cell(compound_dict);
+      s.alloc_u8.free_cell(lists);
+      s.alloc_u32.free_cell(tables);
+      return false;
+    }
+    compound_dict.slice_mut()[..old_size].clone_from_slice(s.compound_dict.slice());
+    compound_dict.slice_mut()[old_size..].clone_from_slice(prefix);
+    s.alloc_u8.free_cell(core::mem::replace(&mut s.compound_dict, compound_dict));
+  }
+  if attach_prefix {
+    s.num_compound_dicts += 1;
+  }
+  if let Some(shared) = custom {
+    s.shared_dict_lists = lists;
+    s.shared_dict_tables = tables;
+    s.shared_dict = shared.layout;
+  }
+  true
+}
+
+/// The RFC 7932 static dictionary, supplied at runtime for builds with the
+/// external-static-dictionary feature, where it is not compiled in. It is
+/// checked against its length and SHA-256 once, when made, so that any number
+/// of decoders can then be given it without hashing it again. The data is
+/// read in place, so it can sit in ROM or be shared between decoders.
+#[derive(Clone, Debug)]
+pub struct BrotliStaticDictionary(SharedCustomDictionary);
+
+impl BrotliStaticDictionary {
+  /// Returns None if dictionary is not the static dictionary.
+  pub fn new(dictionary: SharedCustomDictionary) -> Option<Self> {
+    if BrotliIsStaticDictionary(dictionary.slice()) {
+      Some(BrotliStaticDictionary(dictionary))
+    } else {
+      None
+    }
+  }
+}
+
+// Gives a decoder that has not consumed any input the static dictionary.
+// Returns false, leaving the decoder untouched, once decoding has started.
+pub fn BrotliDecoderSetStaticDictionary<AllocU8: alloc::Allocator<u8>,
+                                        AllocU32: alloc::Allocator<u32>,
+                                        AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  dictionary: &BrotliStaticDictionary) -> bool {
+  if !matches!(s.state, BrotliRunningState::BROTLI_STATE_UNINITED) {
+    return false;
+  }
+  s.static_dict = dictionary.0.clone();
+  true
+}
+
+// Installs callbacks that receive the contents of metadata meta-blocks, which
+// are otherwise skipped. opaque is passed back to both callbacks untouched.
+pub fn BrotliDecoderSetMetadataCallbacks<AllocU8: alloc::Allocator<u8>,
+                                         AllocU32: alloc::Allocator<u32>,
+                                         AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  start_func: Option<BrotliDecoderMetadataStartFunc>,
+  chunk_func: Option<BrotliDecoderMetadataChunkFunc>,
+  opaque: usize) {
+  s.metadata_start_func = start_func;
+  s.metadata_chunk_func = chunk_func;
+  s.metadata_callback_opaque = opaque;
+}
+
+/// Has the decoder call progress_func(opaque, total_in, total_out) each time
+/// at least interval more bytes have been decompressed, and once more when
+/// the stream ends. total_in counts the compressed bytes consumed. Reports
+/// are made at the end of a BrotliDecompressStream call, so they come no more
+/// often than the caller's output buffer fills; with an interval of 0 every
+/// call that makes progress reports.
+pub fn BrotliDecoderSetProgressCallback<AllocU8: alloc::Allocator<u8>,
+                                        AllocU32: alloc::Allocator<u32>,
+                                        AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  progress_func: Option<BrotliDecoderProgressFunc>,
+  interval: usize,
+  opaque: usize) {
+  s.progress_func = progress_func;
+  s.progress_interval = interval;
+  s.progress_opaque = opaque;
+}
+
+/// Has BrotliDecompressStream return BrotliResult::NeedsMoreOutput (and
+/// BrotliDecompressStreamBudgeted BrotliBudgetedResult::Yield) between
+/// meta-blocks, even with output space left, so that the caller can record a BrotliDecoderGetResumePoint there.
+/// BrotliDecoderStoppedAtMetaBlockBoundary tells these stops apart from a
+/// full output buffer. Boundaries reached while the decoder is completing a
+/// read from its internal input buffer are passed over.
+pub fn BrotliDecoderSetStopAtMetaBlockBoundaries<AllocU8: alloc::Allocator<u8>,
+                                                 AllocU32: alloc::Allocator<u32>,
+                                                 AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  stop: bool) {
+  s.stop_at_boundaries = stop;
+}
+
+/// Whether the last BrotliDecompressStream call stopped at a meta-block
+/// boundary, as asked by BrotliDecoderSetStopAtMetaBlockBoundaries.
+pub fn BrotliDecoderStoppedAtMetaBlockBoundary<AllocU8: alloc::Allocator<u8>,
+                                               AllocU32: alloc::Allocator<u32>,
+                                               AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &BrotliState<AllocU8, AllocU32, AllocHC>) -> bool {
+  s.stopped_at_boundary
+}
+
+/// The start of a meta-block, with the decoder state that carries over from
+/// earlier ones. Along with the window returned by BrotliDecoderGetWindow it
+/// is all BrotliDecoderResumeAt needs to start decoding there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BrotliResumePoint {
+  /// Bits of compressed stream before the meta-block header.
+  pub bit_position: u64,
+  /// Bytes decoded before the meta-block.
+  pub decoded_position: u64,
+  pub window_bits: u32,
+  pub large_window: bool,
+  /// The last four backward distances, as the decoder keeps them: the most
+  /// recent is dist_rb[(dist_rb_idx - 1) & 3].
+  pub dist_rb: [i32; 4],
+  pub dist_rb_idx: i32,
+}
+
+/// Where the decoder is, if the last call stopped at a meta-block boundary.
+/// bit_position counts from the start of the input given to the decoder, or
+/// from the resume point's start if the decoder was resumed.
+pub fn BrotliDecoderGetResumePoint<AllocU8: alloc::Allocator<u8>,
+                                   AllocU32: alloc::Allocator<u32>,
+                                   AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &BrotliState<AllocU8, AllocU32, AllocHC>) -> Option<BrotliResumePoint> {
+  if !s.stopped_at_boundary {
+    return None;
+  }
+  // A lent dictionary is not kept, so until it has seeded the ring buffer
+  // there is no window to resume from.
+  if s.ringbuffer.slice().is_empty() && s.custom_dictionary().len() < s.custom_dict_size as usize {
+    return None;
+  }
+  Some(BrotliResumePoint {
+    bit_position: (s.progress_total_in as u64) * 8 -
+                  bit_reader::BrotliGetAvailableBits(&s.br) as u64,
+    decoded_position: s.resume_decoded_base + DecodedPosition(s, s.pos) as u64,
+    window_bits: s.window_bits,
+    large_window: s.large_window,
+    dist_rb: s.dist_rb,
+    dist_rb_idx: s.dist_rb_idx & 3,
+  })
+}
+
+/// The bytes that meta-blocks after the boundary the decoder stopped at may
+/// copy from: the end of the custom dictionary and the output so far, at
+/// most the window size less 16 bytes. The window is returned in two parts,
+/// as it may wrap around the decoder's ring buffer. Empty unless
+/// BrotliDecoderGetResumePoint would return a point.
+pub fn BrotliDecoderGetWindow<AllocU8: alloc::Allocator<u8>,
+                              AllocU32: alloc::Allocator<u32>,
+                              AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &BrotliState<AllocU8, AllocU32, AllocHC>) -> (&[u8], &[u8]) {
+  if BrotliDecoderGetResumePoint(s).is_none() {
+    return (&[], &[]);
+  }
+  let max_window = s.max_backward_distance as usize;
+  if s.ringbuffer.slice().is_empty() {
+    let dict = fast!((s.custom_dictionary())[0; s.custom_dict_size as usize]);
+    return (&dict[dict.len() - core::cmp::min(dict.len(), max_window)..], &[]);
+  }
+  let history = DecodedPosition(s, s.pos) + s.custom_dict_size as usize;
+  let size = core::cmp::min(history, max_window);
+  let ringbuffer = fast_slice!((s.ringbuffer)[0; s.ringbuffer_size as usize]);
+  let pos = s.pos as usize;
+  if size <= pos {
+    (&ringbuffer[pos - size..pos], &[])
+  } else {
+    (&ringbuffer[ringbuffer.len() - (size - pos)..], &ringbuffer[..pos])
+  }
+}
+
+// Whether point has a window size the format allows and backward distances
+// a stream with that window could have used: positive, and no further back
+// than the window plus a compound dictionary of compound_dict_size bytes.
+// Points come from untrusted bytes such as a serialized index, and larger
+// distances would overflow in TakeDistanceFromRingBuffer.
+pub(crate) fn ResumePointIsValid(point: &BrotliResumePoint, compound_dict_size: usize) -> bool {
+  let (min_bits, max_bits) = if point.large_window {
+    (kBrotliLargeMinWbits, kBrotliLargeMaxWbits)
+  } else {
+    (kBrotliMinWbits, kBrotliMaxWbits)
+  };
+  if point.window_bits < min_bits || point.window_bits > max_bits {
+    return false;
+  }
+  let max_distance = core::cmp::min(
+    ((1u64 << point.window_bits) - kBrotliWindowGap as u64) + compound_dict_size as u64,
+    kBrotliMaxAllowedDistance as u64);
+  point.dist_rb.iter().all(|&distance| distance > 0 && distance as u64 <= max_distance)
+}
+
+/// Sets up a decoder that has not consumed any input to decode from a
+/// resume point onwards; window is what BrotliDecoderGetWindow returned
+/// there, joined together. Input must then start with the byte holding
+/// bit_position. The window replaces any custom dictionary, and the
+/// decoder's output starts at decoded_position. Returns false, leaving the
+/// decoder untouched, if the point does not fit the decoder, holds distances
+/// its window could not have used, or memory runs out.
+pub fn BrotliDecoderResumeAt<AllocU8: alloc::Allocator<u8>,
+                             AllocU32: alloc::Allocator<u32>,
+                             AllocHC: alloc::Allocator<HuffmanCode>>(
+  s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+  point: &BrotliResumePoint,
+  window: &[u8]) -> bool {
+  if !matches!(s.state, BrotliRunningState::BROTLI_STATE_UNINITED) {
+    return false;
+  }
+  if !ResumePointIsValid(point, s.compound_dict.slice().len()) ||
+     (point.large_window && !s.large_window) ||
+     window.len() > (1usize << point.window_bits) - kBrotliWindowGap as usize {
+    return false;
+  }
+  let mut custom_dict = s.alloc_u8.alloc_cell(window.len());
+  if custom_dict.slice().len() != window.len() {
+    s.alloc_u8.free_cell(custom_dict);
+    return false;
+  }
+  custom_dict.slice_mut().clone_from_slice(window);
+  s.alloc_u8.free_cell(core::mem::replace(&mut s.custom_dict, custom_dict));
+  s.shared_custom_dict = None;
+  s.custom_dict_size = window.len() as isize;
+  // the window is the stream's own history, so it seeds literal contexts
+  s.custom_dict_avoid_context_seed = false;
+  s.window_bits = point.window_bits;
+  s.large_window = point.large_window;
+  s.dist_rb = point.dist_rb;
+  s.dist_rb_idx = point.dist_rb_idx;
+  s.resume_skip_bits = (point.bit_position & 7) as u32;
+  s.resume_decoded_base = point.decoded_position;
+  s.progress_total_in = (point.bit_position >> 3) as usize;
+  s.progress_reported_in = s.progress_total_in;
+  s.state = BrotliRunningState::BROTLI_STATE_INITIALIZE;
+  true
+}
+
 pub fn BrotliDecoderGetErrorCode<AllocU8: alloc::Allocator<u8>,
                                AllocU32: alloc::Allocator<u32>,
                                AllocHC: alloc::Allocator<HuffmanCode>>(
@@ -2326,6 +2885,67 @@ pub fn BrotliDecoderGetErrorCode<AllocU8: alloc::Allocator<u8>,
   s.error_code
 }
 
+// Consumes the contents of a metadata meta-block, handing them to the
+// metadata chunk callback if one is set.
+fn SkipMetadataBlock<AllocU8: alloc::Allocator<u8>,
+                     AllocU32: alloc::Allocator<u32>,
+                     AllocHC: alloc::Allocator<HuffmanCode>>
+  (s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+   input: &[u8])
+   -> BrotliDecoderErrorCode {
+  if s.meta_block_remaining_len == 0 {
+    return BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS;
+  }
+  // Drain accumulator.
+  if bit_reader::BrotliGetAvailableBits(&s.br) >= 8 {
+    let mut buffer = [0u8; 8];
+    let nbytes = core::cmp::min((bit_reader::BrotliGetAvailableBits(&s.br) >> 3) as i32,
+                                s.meta_block_remaining_len);
+    bit_reader::BrotliCopyBytes(&mut buffer[..], &mut s.br, nbytes as u32, input);
+    if let Some(chunk_func) = s.metadata_chunk_func {
+      chunk_func(s.metadata_callback_opaque, &buffer[..nbytes as usize]);
+    }
+    s.meta_block_remaining_len -= nbytes;
+    if s.meta_block_remaining_len == 0 {
+      return BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS;
+    }
+  }
+  // Direct access to metadata is possible.
+  let nbytes = core::cmp::min(bit_reader::BrotliGetRemainingBytes(&s.br) as i32,
+                              s.meta_block_remaining_len);
+  if nbytes > 0 {
+    let next_in = s.br.next_in as usize;
+    if let Some(chunk_func) = s.metadata_chunk_func {
+      chunk_func(s.metadata_callback_opaque,
+                 fast!((input)[next_in ; next_in + nbytes as usize]));
+    }
+    s.br.next_in += nbytes as u32;
+    s.br.avail_in -= nbytes as u32;
+    s.meta_block_remaining_len -= nbytes;
+    if s.meta_block_remaining_len == 0 {
+      return BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS;
+    }
+  }
+  BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT
+}
+
+// Copies as much of the pending compound dictionary reference as fits
+// before the ringbuffer end; returns the number of bytes written at pos.
+fn CopyFromCompoundDictionary<AllocU8: alloc::Allocator<u8>,
+                              AllocU32: alloc::Allocator<u32>,
+                              AllocHC: alloc::Allocator<HuffmanCode>>
+  (s: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+   pos: i32)
+   -> i32 {
+  let len = core::cmp::min(s.compound_copy_remaining, s.ringbuffer_size - pos);
+  let src = s.compound_copy_offset as usize;
+  fast_slice_mut!((s.ringbuffer)[pos as usize ; (pos + len) as usize])
+    .clone_from_slice(fast_slice!((s.compound_dict)[src ; src + len as usize]));
+  s.compound_copy_offset += len;
+  s.compound_copy_remaining -= len;
+  len
+}
+
 #[inline(always)]
 fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
                            AllocU32: alloc::Allocator<u32>,
@@ -2359,6 +2979,15 @@ fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
     loop {
       match s.state {
         BrotliRunningState::BROTLI_STATE_COMMAND_BEGIN => {
+          // Out of budget: stop between commands, where the decoder can
+          // resume as after NEEDS_MORE_OUTPUT. Bytes borrowed into the
+          // internal input buffer must be consumed first.
+          if (s.budget_commands_left == 0 || DecodedPosition(s, pos) >= s.budget_decoded_limit) &&
+             s.buffer_length == 0 {
+            s.budget_exhausted = true;
+            result = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_OUTPUT;
+            break; // return
+          }
           if (!CheckInputAmount(safe, &s.br, 28)) {
             // 156 bits + 7 bytes
             mark_unlikely();
@@ -2379,6 +3008,7 @@ fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
             result = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT;
             break; // return
           }
+          s.budget_commands_left = s.budget_commands_left.saturating_sub(1);
           BROTLI_LOG!("[ProcessCommandsInternal] pos = %d insert = %d copy = %d distance = %d\n",
               pos, i, s.copy_length, s.distance_code);
           if (i == 0) {
@@ -2594,10 +3224,66 @@ fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
             if s.distance_code > kBrotliMaxAllowedDistance as i32 {
               return BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_DISTANCE;
             }
-            if (i >= kBrotliMinDictionaryWordLength as i32 &&
+            let compound_dict_size = s.compound_dict.slice().len() as i32;
+            if (s.distance_code - s.max_distance - 1 < compound_dict_size) {
+              let address = compound_dict_size - (s.distance_code - s.max_distance);
+              if (compound_dict_size - address < i) {
+                result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_COMPOUND_DICTIONARY;
+                break; // return
+              }
+              // update the recent distances cache
+              fast_mut!((s.dist_rb)[(s.dist_rb_idx & 3) as usize]) = s.distance_code;
+              s.dist_rb_idx += 1;
+              s.meta_block_remaining_len -= i;
+              s.compound_copy_offset = address;
+              s.compound_copy_remaining = i;
+              pos += CopyFromCompoundDictionary(s, pos);
+              if (pos >= s.ringbuffer_size) {
+                s.state = BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRITE_1;
+                break; // return
+              }
+            } else if (s.shared_dict.num_dictionaries != 0 &&
+                i >= SHARED_BROTLI_MIN_DICTIONARY_WORD_LENGTH as i32 &&
+                i <= SHARED_BROTLI_MAX_DICTIONARY_WORD_LENGTH as i32) {
+              let address = s.distance_code - s.max_distance - 1 - compound_dict_size;
+              let p1 = fast_slice!((s.ringbuffer)[((pos - 1) & s.ringbuffer_mask) as usize]);
+              let p2 = fast_slice!((s.ringbuffer)[((pos - 2) & s.ringbuffer_mask) as usize]);
+              let dict_id = SharedDictionaryId(&s.shared_dict, s.context_lookup, p1, p2);
+              s.dist_rb_idx += s.distance_context;
+              match TransformSharedDictionaryWord(fast_slice_mut!((s.ringbuffer)[pos as usize;]),
+                                                  &s.shared_dict,
+                                                  s.static_dict.slice(),
+                                                  s.shared_dict_lists.slice(),
+                                                  s.shared_dict_tables.slice(),
+                                                  dict_id,
+                                                  i,
+                                                  address) {
+                Ok(len) => {
+                  pos += len;
+                  s.meta_block_remaining_len -= len;
+                  if (pos >= s.ringbuffer_size) {
+                    s.state = BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRITE_1;
+                    break; // return
+                  }
+                }
+                Err(error) => {
+                  BROTLI_LOG!(
+                    "Invalid backward reference. pos: %d distance: %d len: %d bytes left: %d\n",
+                    pos, s.distance_code, i,
+                    s.meta_block_remaining_len);
+                  result = error;
+                  break; // return
+                }
+              }
+            } else if (i >= kBrotliMinDictionaryWordLength as i32 &&
                 i <= kBrotliMaxDictionaryWordLength as i32) {
+              let static_dict = s.static_dict.slice();
+              if static_dict.is_empty() {
+                result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET;
+                break; // return
+              }
               let mut offset = fast!((kBrotliDictionaryOffsetsByLength)[i as usize]) as i32;
-              let word_id = s.distance_code - s.max_distance - 1;
+              let word_id = s.distance_code - s.max_distance - 1 - compound_dict_size;
               let shift = fast!((kBrotliDictionarySizeBitsByLength)[i as usize]);
               let mask = bit_reader::BitMask(shift as u32) as i32;
               let word_idx = word_id & mask;
@@ -2606,7 +3292,7 @@ fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
               offset += word_idx * i;
               if (transform_idx < kNumTransforms) {
                 let mut len = i;
-                let word = fast!((kBrotliDictionary)[offset as usize ; (offset + len) as usize]);
+                let word = fast!((static_dict)[offset as usize ; (offset + len) as usize]);
                 if (transform_idx == 0) {
                   fast_slice_mut!((s.ringbuffer)[pos as usize ; ((pos + len) as usize)])
                     .clone_from_slice(word);
@@ -2743,6 +3429,14 @@ fn ProcessCommandsInternal<AllocU8: alloc::Allocator<u8>,
   result
 }
 
+// Bytes decoded into the ring buffer since the start of the stream.
+fn DecodedPosition<AllocU8: alloc::Allocator<u8>,
+                   AllocU32: alloc::Allocator<u32>,
+                   AllocHC: alloc::Allocator<HuffmanCode>>
+  (s: &BrotliState<AllocU8, AllocU32, AllocHC>, pos: i32) -> usize {
+  s.rb_roundtrips.wrapping_mul(s.ringbuffer_size as usize).wrapping_add(pos as usize)
+}
+
 fn ProcessCommands<AllocU8: alloc::Allocator<u8>,
                    AllocU32: alloc::Allocator<u32>,
                    AllocHC: alloc::Allocator<HuffmanCode>>
@@ -2779,6 +3473,101 @@ pub fn BrotliMaxDistanceSymbol(ndirect: u32, npostfix: u32) -> u32{
 pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
                               AllocU32: alloc::Allocator<u32>,
                               AllocHC: alloc::Allocator<HuffmanCode>>
+  (available_in: &mut usize,
+   input_offset: &mut usize,
+   xinput: &[u8],
+   available_out: &mut usize,
+   output_offset: &mut usize,
+   output: &mut [u8],
+   total_out: &mut usize,
+   s: &mut BrotliState<AllocU8, AllocU32, AllocHC>)
+   -> BrotliResult {
+  let offered_in = *available_in;
+  s.stopped_at_boundary = false;
+  let result = DecompressStream(available_in, input_offset, xinput, available_out,
+                                output_offset, output, total_out, None, s);
+  ReportProgress(s, offered_in - *available_in, &result);
+  result
+}
+
+/// BrotliDecompressStream with a custom dictionary that the caller lends on
+/// every call instead of handing it to the decoder, so it can stay in
+/// borrowed memory such as a memory map or a per-request buffer. The decoder
+/// must not have a custom dictionary of its own. Lend the same bytes on each
+/// call of a stream; the size is fixed when the stream starts, and calls
+/// lending a different size, or continuing the stream with
+/// BrotliDecompressStream, fail with BROTLI_DECODER_ERROR_INVALID_ARGUMENTS.
+#[allow(clippy::too_many_arguments)]
+pub fn BrotliDecompressStreamWithDictionary<AllocU8: alloc::Allocator<u8>,
+                                            AllocU32: alloc::Allocator<u32>,
+                                            AllocHC: alloc::Allocator<HuffmanCode>>
+  (available_in: &mut usize,
+   input_offset: &mut usize,
+   input: &[u8],
+   available_out: &mut usize,
+   output_offset: &mut usize,
+   output: &mut [u8],
+   total_out: &mut usize,
+   dictionary: &[u8],
+   s: &mut BrotliState<AllocU8, AllocU32, AllocHC>)
+   -> BrotliResult {
+  let offered_in = *available_in;
+  s.stopped_at_boundary = false;
+  let result = DecompressStream(available_in, input_offset, input, available_out,
+                                output_offset, output, total_out, Some(dictionary), s);
+  ReportProgress(s, offered_in - *available_in, &result);
+  result
+}
+
+// Makes dictionary, lent for one call, the custom dictionary of a decoder
+// without one of its own. Its size is taken when a stream starts, and every
+// later call of the stream must lend as many bytes; None, from the entry
+// points that take no dictionary, is only accepted for streams that started
+// without one.
+fn LendCustomDictionary<AllocU8: alloc::Allocator<u8>,
+                        AllocU32: alloc::Allocator<u32>,
+                        AllocHC: alloc::Allocator<HuffmanCode>>
+  (s: &mut BrotliState<AllocU8, AllocU32, AllocHC>, dictionary: Option<&[u8]>) -> bool {
+  if matches!(s.state, BrotliRunningState::BROTLI_STATE_UNINITED) {
+    if let Some(dictionary) = dictionary {
+      if s.shared_custom_dict.is_some() || !s.custom_dict.slice().is_empty() {
+        return false;
+      }
+      s.custom_dict_size = dictionary.len() as isize;
+      s.custom_dict_avoid_context_seed = !dictionary.is_empty();
+    }
+    s.lent_dict_size = dictionary.map(|dictionary| dictionary.len());
+    return true;
+  }
+  s.lent_dict_size == dictionary.map(|dictionary| dictionary.len())
+}
+
+// Counts the input a call consumed and calls the progress callback, if any,
+// once interval more bytes have been decoded since the last report, and when
+// the stream ends.
+fn ReportProgress<AllocU8: alloc::Allocator<u8>,
+                  AllocU32: alloc::Allocator<u32>,
+                  AllocHC: alloc::Allocator<HuffmanCode>>
+  (s: &mut BrotliState<AllocU8, AllocU32, AllocHC>, consumed: usize, result: &BrotliResult) {
+  s.progress_total_in += consumed;
+  if let Some(progress_func) = s.progress_func {
+    let total_out = s.partial_pos_out;
+    if (s.progress_total_in, total_out) == (s.progress_reported_in, s.progress_reported_out) {
+      return;
+    }
+    let finished = matches!(*result, BrotliResult::ResultSuccess);
+    if finished || total_out - s.progress_reported_out >= s.progress_interval {
+      s.progress_reported_in = s.progress_total_in;
+      s.progress_reported_out = total_out;
+      progress_func(s.progress_opaque, s.progress_total_in, total_out);
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn DecompressStream<AllocU8: alloc::Allocator<u8>,
+                    AllocU32: alloc::Allocator<u32>,
+                    AllocHC: alloc::Allocator<HuffmanCode>>
   (available_in: &mut usize,
    input_offset: &mut usize,
    xinput: &[u8],
@@ -2786,6 +3575,7 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
    mut output_offset: &mut usize,
    mut output: &mut [u8],
    mut total_out: &mut usize,
+   lent_dict: Option<&[u8]>,
    mut s: &mut BrotliState<AllocU8, AllocU32, AllocHC>)
    -> BrotliResult {
 
@@ -2796,6 +3586,10 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
   if is_fatal(s.error_code) {
     return BrotliResult::ResultFailure;
   }
+  if !LendCustomDictionary(s, lent_dict) {
+    return SaveErrorCode!(s, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS);
+  }
+  let lent_dict = lent_dict.unwrap_or(&[]);
   if *available_in as u64 >= (1u64 << 32) {
     return SaveErrorCode!(s, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS);
   }
@@ -2898,7 +3692,8 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           }
           _ => {
             // Fail or needs more output.
-            if s.buffer_length != 0 {
+            let buffered = s.buffer_length != 0;
+            if buffered {
               // Just consumed the buffered input and produced some output. Otherwise
               // it would result in "needs more input". Reset internal buffer.
               s.buffer_length = 0;
@@ -2910,6 +3705,13 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
               *available_in = s.br.avail_in as usize;
               *input_offset = s.br.next_in as usize;
             }
+            if is_fatal(result) {
+              // Bytes of the internal buffer were already taken from the caller.
+              s.error_unread_bits = bit_reader::BrotliGetAvailableBits(&s.br);
+              if buffered {
+                s.error_unread_bits += s.br.avail_in << 3;
+              }
+            }
           }
         }
         break;
@@ -2950,20 +3752,37 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           s.state = BrotliRunningState::BROTLI_STATE_INITIALIZE;
         }
         BrotliRunningState::BROTLI_STATE_INITIALIZE => {
+          if s.window_bits > s.max_window_bits {
+            result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS;
+            break;
+          }
           s.max_backward_distance = (1 << s.window_bits) - kBrotliWindowGap as i32;
           s.max_backward_distance_minus_custom_dict_size = (s.max_backward_distance as isize -
                                                            s.custom_dict_size) as i32;
 
+          // The constructor's allocation may have failed; try once more
+          // before giving up on the stream.
+          if s.context_map_table.slice().len() < huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize {
+            s.alloc_hc.free_cell(core::mem::take(&mut s.context_map_table));
+            s.context_map_table = s.alloc_hc.alloc_cell(huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize);
+            if s.context_map_table.slice().len() < huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize {
+              result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP;
+              break;
+            }
+          }
+
           // (formerly) Allocate memory for both block_type_trees and block_len_trees.
           s.block_type_length_state.block_type_trees = s.alloc_hc
             .alloc_cell(3 * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize);
-          if (s.block_type_length_state.block_type_trees.slice().len() == 0) {
+          if (s.block_type_length_state.block_type_trees.slice().len() <
+              3 * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize) {
             result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES;
             break;
           }
           s.block_type_length_state.block_len_trees = s.alloc_hc
             .alloc_cell(3 * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize);
-          if (s.block_type_length_state.block_len_trees.slice().len() == 0) {
+          if (s.block_type_length_state.block_len_trees.slice().len() <
+              3 * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize) {
             result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES;
             break;
           }
@@ -2972,6 +3791,15 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           // No break, continue to next state
         }
         BrotliRunningState::BROTLI_STATE_METABLOCK_BEGIN => {
+          if s.resume_skip_bits != 0 {
+            let mut _bits: u32 = 0;
+            if !bit_reader::BrotliSafeReadBits(&mut s.br, s.resume_skip_bits, &mut _bits,
+                                               local_input) {
+              result = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT;
+              break;
+            }
+            s.resume_skip_bits = 0;
+          }
           s.BrotliStateMetablockBegin();
           BROTLI_LOG_UINT!(s.pos);
           s.state = BrotliRunningState::BROTLI_STATE_METABLOCK_HEADER;
@@ -2983,6 +3811,7 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
             BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS => {}
             _ => break,
           }
+          s.meta_block_count += 1;
           BROTLI_LOG_UINT!(s.is_last_metablock);
           BROTLI_LOG_UINT!(s.meta_block_remaining_len);
           BROTLI_LOG_UINT!(s.is_metadata);
@@ -2994,13 +3823,16 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           }
           if s.is_metadata != 0 {
             s.state = BrotliRunningState::BROTLI_STATE_METADATA;
+            if let Some(start_func) = s.metadata_start_func {
+              start_func(s.metadata_callback_opaque, s.meta_block_remaining_len as usize);
+            }
             break;
           }
           if s.meta_block_remaining_len == 0 {
             s.state = BrotliRunningState::BROTLI_STATE_METABLOCK_DONE;
             break;
           }
-          if s.ringbuffer.slice().len() == 0 && !BrotliAllocateRingBuffer(&mut s, local_input) {
+          if s.ringbuffer.slice().len() == 0 && !BrotliAllocateRingBuffer(&mut s, local_input, lent_dict) {
             result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_2;
             break;
           }
@@ -3029,15 +3861,7 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           break;
         }
         BrotliRunningState::BROTLI_STATE_METADATA => {
-          while s.meta_block_remaining_len > 0 {
-            let mut bits = 0u32;
-            // Read one byte and ignore it.
-            if !bit_reader::BrotliSafeReadBits(&mut s.br, 8, &mut bits, local_input) {
-              result = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT;
-              break;
-            }
-            s.meta_block_remaining_len -= 1;
-          }
+          result = SkipMetadataBlock(s, local_input);
           if let BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS = result {
             s.state = BrotliRunningState::BROTLI_STATE_METABLOCK_DONE
           }
@@ -3153,7 +3977,8 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           s.distance_postfix_mask = bit_reader::BitMask(s.distance_postfix_bits) as i32;
           s.context_modes = s.alloc_u8
             .alloc_cell(fast!((s.block_type_length_state.num_block_types)[0]) as usize);
-          if (s.context_modes.slice().len() == 0) {
+          if (s.context_modes.slice().len() <
+              fast!((s.block_type_length_state.num_block_types)[0]) as usize) {
             result = BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MODES;
             break;
           }
@@ -3223,10 +4048,10 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
                                    num_distance_codes as u16,
                                    max_distance_symbol as u16,
                                    s.num_dist_htrees as u16);
-            if (s.literal_hgroup.codes.slice().len() == 0 ||
-                s.insert_copy_hgroup.codes.slice().len() == 0 ||
-                s.distance_hgroup.codes.slice().len() == 0) {
-              return SaveErrorCode!(s, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_UNREACHABLE);
+            if (!s.literal_hgroup.is_allocated() ||
+                !s.insert_copy_hgroup.is_allocated() ||
+                !s.distance_hgroup.is_allocated()) {
+              return SaveErrorCode!(s, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS);
             }
 
           /*{
@@ -3316,6 +4141,13 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           }
           match s.state {
             BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRITE_1 => {
+              if s.compound_copy_remaining != 0 {
+                let pos = s.pos;
+                s.pos += CopyFromCompoundDictionary(s, pos);
+                if s.pos >= s.ringbuffer_size {
+                  continue;
+                }
+              }
               if (s.meta_block_remaining_len <= 0) {
                 // Next metablock, if any
                 s.state = BrotliRunningState::BROTLI_STATE_METABLOCK_DONE;
@@ -3360,6 +4192,13 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
           s.BrotliStateCleanupAfterMetablock();
           if (s.is_last_metablock == 0) {
             s.state = BrotliRunningState::BROTLI_STATE_METABLOCK_BEGIN;
+            // Stopping needs the input position to be exact, which it is not
+            // while the internal buffer is in use; such boundaries are passed.
+            if s.stop_at_boundaries && s.buffer_length == 0 && !s.should_wrap_ringbuffer &&
+               (s.ringbuffer.slice().is_empty() || s.pos < s.ringbuffer_size) {
+              s.stopped_at_boundary = true;
+              result = BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_OUTPUT;
+            }
             break;
           }
           if (!bit_reader::BrotliJumpToByteBoundary(&mut s.br)) {
@@ -3401,3 +4240,131 @@ pub fn BrotliDecompressStream<AllocU8: alloc::Allocator<u8>,
 
   SaveErrorCode!(s, result)
 }
+
+/// Caps the work a single BrotliDecompressStreamBudgeted call may do. Zero
+/// leaves a limit off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BrotliWorkBudget {
+  /// Commands (insert and copy pairs) to decode before yielding.
+  pub max_commands: usize,
+  /// Bytes to decode, and to write to the output, before yielding.
+  pub max_output: usize,
+}
+
+/// What a BrotliDecompressStreamBudgeted call did: the outcomes of
+/// BrotliResult, plus Yield for a call that stopped before running out of
+/// input or output space.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrotliBudgetedResult {
+  ResultSuccess = 1,
+  NeedsMoreInput = 2,
+  NeedsMoreOutput = 3,
+  /// The budget ran out, or the decoder stopped at a meta-block boundary.
+  Yield = 4,
+  ResultFailure = 0,
+}
+
+/// BrotliDecompressStream for callers that must not block for long, such as
+/// event loops and kernels: once the call has used up budget it hands over
+/// everything decoded so far and returns BrotliBudgetedResult::Yield, even if
+/// output space is left. Resume by calling again. Stops at meta-block
+/// boundaries yield too.
+/// The budget is checked between commands, so a call can overrun it by up to
+/// one command, and headers and uncompressed meta-blocks only ever stop for
+/// want of input or output.
+#[allow(clippy::too_many_arguments)]
+pub fn BrotliDecompressStreamBudgeted<AllocU8: alloc::Allocator<u8>,
+                                      AllocU32: alloc::Allocator<u32>,
+                                      AllocHC: alloc::Allocator<HuffmanCode>>
+  (available_in: &mut usize,
+   input_offset: &mut usize,
+   input: &[u8],
+   available_out: &mut usize,
+   output_offset: &mut usize,
+   output: &mut [u8],
+   total_out: &mut usize,
+   budget: &BrotliWorkBudget,
+   s: &mut BrotliState<AllocU8, AllocU32, AllocHC>)
+   -> BrotliBudgetedResult {
+  DecompressStreamBudgeted(available_in, input_offset, input, available_out, output_offset,
+                           output, total_out, budget, None, s)
+}
+
+// BrotliDecompressStreamBudgeted, lending dictionary as
+// BrotliDecompressStreamWithDictionary does if there is one.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn DecompressStreamBudgeted<AllocU8: alloc::Allocator<u8>,
+                                       AllocU32: alloc::Allocator<u32>,
+                                       AllocHC: alloc::Allocator<HuffmanCode>>
+  (available_in: &mut usize,
+   input_offset: &mut usize,
+   input: &[u8],
+   available_out: &mut usize,
+   output_offset: &mut usize,
+   output: &mut [u8],
+   total_out: &mut usize,
+   budget: &BrotliWorkBudget,
+   dictionary: Option<&[u8]>,
+   s: &mut BrotliState<AllocU8, AllocU32, AllocHC>)
+   -> BrotliBudgetedResult {
+  let mut yielded = false;
+  // The output limit doubles as a cap on the space handed to the decoder.
+  let mut capped_out = *available_out;
+  if budget.max_output != 0 && capped_out > budget.max_output {
+    capped_out = budget.max_output;
+  }
+  let withheld_out = *available_out - capped_out;
+  if budget.max_commands != 0 {
+    s.budget_commands_left = budget.max_commands;
+  }
+  if budget.max_output != 0 {
+    s.budget_decoded_limit = DecodedPosition(s, s.pos).saturating_add(budget.max_output);
+  }
+  s.budget_exhausted = false;
+  s.stopped_at_boundary = false;
+  let offered_in = *available_in;
+  let result = DecompressStream(available_in,
+                                input_offset,
+                                input,
+                                &mut capped_out,
+                                output_offset,
+                                output,
+                                total_out,
+                                dictionary,
+                                s);
+  let budget_exhausted = s.budget_exhausted;
+  s.budget_commands_left = core::usize::MAX;
+  s.budget_decoded_limit = core::usize::MAX;
+  s.budget_exhausted = false;
+  let result = match result {
+    BrotliResult::NeedsMoreOutput if s.stopped_at_boundary => {
+      yielded = true;
+      BrotliResult::NeedsMoreOutput
+    }
+    BrotliResult::NeedsMoreOutput if budget_exhausted || withheld_out != 0 => {
+      if budget_exhausted && !s.ringbuffer.slice().is_empty() {
+        // Pass on what the commands produced rather than leaving it in the
+        // ring buffer until it wraps.
+        let (xresult, _) = WriteRingBuffer(&mut capped_out, Some(output), output_offset,
+                                           total_out, true, s);
+        if is_fatal(xresult) {
+          SaveErrorCode!(s, xresult);
+          return BrotliBudgetedResult::ResultFailure;
+        }
+      }
+      yielded = capped_out + withheld_out != 0;
+      BrotliResult::NeedsMoreOutput
+    }
+    other => other,
+  };
+  *available_out = capped_out + withheld_out;
+  ReportProgress(s, offered_in - *available_in, &result);
+  match result {
+    BrotliResult::ResultSuccess => BrotliBudgetedResult::ResultSuccess,
+    BrotliResult::NeedsMoreInput => BrotliBudgetedResult::NeedsMoreInput,
+    BrotliResult::NeedsMoreOutput if yielded => BrotliBudgetedResult::Yield,
+    BrotliResult::NeedsMoreOutput => BrotliBudgetedResult::NeedsMoreOutput,
+    BrotliResult::ResultFailure => BrotliBudgetedResult::ResultFailure,
+  }
+}
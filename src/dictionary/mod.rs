@@ -1,3 +1,4 @@
+use sha256::sha256;
 
 #[allow(non_upper_case_globals)]
 pub const kBrotliDictionaryOffsetsByLength: [u32; 25] =
@@ -15,7 +16,34 @@ pub const kBrotliMinDictionaryWordLength: u8 = 4;
 pub const kBrotliMaxDictionaryWordLength: u8 = 24;
 
 #[allow(non_upper_case_globals)]
-pub static kBrotliDictionary: [u8; 122784] =
+pub const kBrotliDictionarySize: usize = 122784;
+
+// SHA-256 of the static dictionary, as given in RFC 7932 appendix A.
+#[allow(non_upper_case_globals)]
+pub const kBrotliDictionarySha256: [u8; 32] =
+  [0x20, 0xe4, 0x2e, 0xb1, 0xb5, 0x11, 0xc2, 0x18, 0x06, 0xd4, 0xd2, 0x27, 0xd0, 0x7e, 0x5d, 0xd0,
+   0x68, 0x77, 0xd8, 0xce, 0x7b, 0x3a, 0x81, 0x7f, 0x37, 0x8f, 0x31, 0x36, 0x53, 0xf3, 0x5c, 0x70];
+
+// Whether data is the static dictionary, checked by length and hash.
+pub fn BrotliIsStaticDictionary(data: &[u8]) -> bool {
+  data.len() == kBrotliDictionarySize && sha256(data) == kBrotliDictionarySha256
+}
+
+// The static dictionary decoders start with: the built-in table, or nothing
+// when it is compiled out and callers supply it at runtime.
+#[cfg(not(feature="external-static-dictionary"))]
+pub fn BrotliBuiltInStaticDictionary() -> &'static [u8] {
+  &kBrotliDictionary[..]
+}
+
+#[cfg(feature="external-static-dictionary")]
+pub fn BrotliBuiltInStaticDictionary() -> &'static [u8] {
+  &[]
+}
+
+#[cfg(not(feature="external-static-dictionary"))]
+#[allow(non_upper_case_globals)]
+pub static kBrotliDictionary: [u8; kBrotliDictionarySize] =
   [0x74, 0x69, 0x6d, 0x65, 0x64, 0x6f, 0x77, 0x6e, 0x6c, 0x69, 0x66, 0x65, 0x6c, 0x65, 0x66, 0x74,
    0x62, 0x61, 0x63, 0x6b, 0x63, 0x6f, 0x64, 0x65, 0x64, 0x61, 0x74, 0x61, 0x73, 0x68, 0x6f, 0x77,
    0x6f, 0x6e, 0x6c, 0x79, 0x73, 0x69, 0x74, 0x65, 0x63, 0x69, 0x74, 0x79, 0x6f, 0x70, 0x65, 0x6e,
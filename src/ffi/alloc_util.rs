@@ -73,7 +73,15 @@ impl<Ty:Sized+Default+Clone> alloc::Allocator<Ty> for SubclassableAllocator {
             return MemoryBlock::<Ty>::default();
         }
         if let Some(alloc_fn) = self.alloc.alloc_func {
-            let ptr = alloc_fn(self.alloc.opaque, size * core::mem::size_of::<Ty>());
+            // a failed allocation is an empty block, which the decoder
+            // reports as the matching BROTLI_DECODER_ERROR_ALLOC_* code
+            let ptr = match size.checked_mul(core::mem::size_of::<Ty>()) {
+                Some(alloc_size) => alloc_fn(self.alloc.opaque, alloc_size),
+                None => core::ptr::null_mut(),
+            };
+            if ptr.is_null() {
+                return MemoryBlock::<Ty>::default();
+            }
             let typed_ptr = unsafe {core::mem::transmute::<*mut c_void, *mut Ty>(ptr)};
             let slice_ref = unsafe {super::slice_from_raw_parts_or_nil_mut(typed_ptr, size)};
             for item in slice_ref.iter_mut() {
@@ -81,7 +89,12 @@ impl<Ty:Sized+Default+Clone> alloc::Allocator<Ty> for SubclassableAllocator {
             }
             return MemoryBlock(unsafe{Box::from_raw(slice_ref)})
         }
-        MemoryBlock(vec![Ty::default();size].into_boxed_slice())
+        let mut cells = Vec::new();
+        if cells.try_reserve_exact(size).is_err() {
+            return MemoryBlock::<Ty>::default();
+        }
+        cells.resize(size, Ty::default());
+        MemoryBlock(cells.into_boxed_slice())
     }
     fn free_cell(&mut self, mut bv:MemoryBlock<Ty>) {
         if (*bv.0).len() != 0 {
@@ -174,7 +187,15 @@ impl<Ty:Sized+Default+Clone> alloc::Allocator<Ty> for SubclassableAllocator {
             return MemoryBlock::<Ty>::default();
         }
         if let Some(alloc_fn) = self.alloc.alloc_func {
-            let ptr = alloc_fn(self.alloc.opaque, size * core::mem::size_of::<Ty>());
+            // a failed allocation is an empty block, which the decoder
+            // reports as the matching BROTLI_DECODER_ERROR_ALLOC_* code
+            let ptr = match size.checked_mul(core::mem::size_of::<Ty>()) {
+                Some(alloc_size) => alloc_fn(self.alloc.opaque, alloc_size),
+                None => core::ptr::null_mut(),
+            };
+            if ptr.is_null() {
+                return MemoryBlock::<Ty>::default();
+            }
             let typed_ptr = unsafe {core::mem::transmute::<*mut c_void, *mut Ty>(ptr)};
             let slice_ref = unsafe {super::slice_from_raw_parts_or_nil_mut(typed_ptr, size)};
             for item in slice_ref.iter_mut() {
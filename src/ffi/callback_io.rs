@@ -0,0 +1,55 @@
+use ::io_wrappers::{CustomRead, CustomWrite};
+use super::interface::{c_void, BrotliDecoderCallbackResult};
+
+// Why a callback driven decode stopped early; each maps to its own
+// BrotliDecoderCallbackResult so C callers can tell their I/O failures
+// apart from corrupt input.
+pub enum CallbackIoError {
+  Read,
+  Write,
+  InvalidData,
+}
+
+impl From<CallbackIoError> for BrotliDecoderCallbackResult {
+  fn from(e: CallbackIoError) -> Self {
+    match e {
+      CallbackIoError::Read => BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR,
+      CallbackIoError::Write => BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR,
+      CallbackIoError::InvalidData => BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_ERROR,
+    }
+  }
+}
+
+pub struct CReader {
+  pub read_func: extern "C" fn(opaque: *mut c_void, buffer: *mut u8, size: usize) -> isize,
+  pub opaque: *mut c_void,
+}
+
+impl CustomRead<CallbackIoError> for CReader {
+  fn read(&mut self, data: &mut [u8]) -> Result<usize, CallbackIoError> {
+    let ret = (self.read_func)(self.opaque, data.as_mut_ptr(), data.len());
+    if ret < 0 || ret as usize > data.len() {
+      return Err(CallbackIoError::Read);
+    }
+    Ok(ret as usize)
+  }
+}
+
+pub struct CWriter {
+  pub write_func: extern "C" fn(opaque: *mut c_void, data: *const u8, size: usize) -> isize,
+  pub opaque: *mut c_void,
+}
+
+impl CustomWrite<CallbackIoError> for CWriter {
+  fn write(&mut self, data: &[u8]) -> Result<usize, CallbackIoError> {
+    let ret = (self.write_func)(self.opaque, data.as_ptr(), data.len());
+    // a writer that makes no progress would spin write_all forever
+    if ret <= 0 || ret as usize > data.len() {
+      return Err(CallbackIoError::Write);
+    }
+    Ok(ret as usize)
+  }
+  fn flush(&mut self) -> Result<(), CallbackIoError> {
+    Ok(())
+  }
+}
@@ -1,4 +1,5 @@
-use ::BrotliResult;
+use ::{BrotliBudgetedResult, BrotliResult};
+use ::BrotliDecoderErrorCode;
 #[allow(non_camel_case_types)]
 #[repr(u8)]
 pub enum c_void{
@@ -12,6 +13,18 @@ pub enum BrotliDecoderParameter {
     BROTLI_DECODER_PARAM_LARGE_WINDOW = 1,
 }
 
+impl BrotliDecoderParameter {
+  // C callers may pass any integer; values this library does not know about
+  // are rejected instead of being transmuted into the enum.
+  pub fn from_u32(value: u32) -> Option<Self> {
+    match value {
+      0 => Some(BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION),
+      1 => Some(BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW),
+      _ => None,
+    }
+  }
+}
+
 
 #[repr(C)]
 pub enum BrotliDecoderResult {
@@ -19,6 +32,7 @@ pub enum BrotliDecoderResult {
     BROTLI_DECODER_RESULT_SUCCESS = 1,
     BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT = 2,
     BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT = 3,
+    BROTLI_DECODER_RESULT_YIELD = 4,
 }
 
 
@@ -32,6 +46,18 @@ impl From<BrotliResult> for BrotliDecoderResult {
     }
   }
 }
+
+impl From<BrotliBudgetedResult> for BrotliDecoderResult {
+  fn from(r: BrotliBudgetedResult) -> Self {
+    match r {
+      BrotliBudgetedResult::ResultSuccess => BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS,
+      BrotliBudgetedResult::ResultFailure => BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR,
+      BrotliBudgetedResult::NeedsMoreInput => BrotliDecoderResult::BROTLI_DECODER_RESULT_NEEDS_MORE_INPUT,
+      BrotliBudgetedResult::NeedsMoreOutput => BrotliDecoderResult::BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT,
+      BrotliBudgetedResult::Yield => BrotliDecoderResult::BROTLI_DECODER_RESULT_YIELD,
+    }
+  }
+}
 pub type brotli_alloc_func = Option<extern "C" fn(data: *mut c_void, size: usize) -> *mut c_void>;
 
 pub type brotli_free_func = Option<extern "C" fn(data: *mut c_void, ptr: *mut c_void) -> ()>;
@@ -47,3 +73,41 @@ pub struct CAllocator {
 
 unsafe impl Send for CAllocator {
 }
+
+pub type brotli_decoder_metadata_start_func = Option<extern "C" fn(opaque: *mut c_void, size: usize)>;
+
+pub type brotli_decoder_metadata_chunk_func = Option<extern "C" fn(opaque: *mut c_void, data: *const u8, size: usize)>;
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct CMetadataCallbacks {
+    pub start_func: brotli_decoder_metadata_start_func,
+    pub chunk_func: brotli_decoder_metadata_chunk_func,
+    pub opaque: *mut c_void,
+}
+
+unsafe impl Send for CMetadataCallbacks {
+}
+
+pub type brotli_decoder_read_func = Option<extern "C" fn(opaque: *mut c_void, buffer: *mut u8, size: usize) -> isize>;
+
+pub type brotli_decoder_write_func = Option<extern "C" fn(opaque: *mut c_void, data: *const u8, size: usize) -> isize>;
+
+#[repr(C)]
+pub enum BrotliDecoderCallbackResult {
+    BROTLI_DECODER_CALLBACK_RESULT_ERROR = 0,
+    BROTLI_DECODER_CALLBACK_RESULT_SUCCESS = 1,
+    BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR = 2,
+    BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR = 3,
+}
+
+#[repr(C)]
+pub struct BrotliDecoderErrorInfo {
+    pub code: BrotliDecoderErrorCode,
+    // input bytes that were fully decoded before decoding stopped
+    pub compressed_offset: usize,
+    // bits of the byte at compressed_offset that were decoded as well (0-7)
+    pub bit_position: u32,
+    pub decompressed_offset: usize,
+    pub state_name: *const u8,
+}
@@ -1,18 +1,29 @@
 #[cfg(feature="std")]
 use std::{thread,panic, io, boxed, any, string};
 #[cfg(feature="std")]
+use std::cell::Cell;
+#[cfg(feature="std")]
 use std::io::Write;
 use core;
 use core::slice;
 use core::ops;
 pub mod interface;
 pub mod alloc_util;
+mod callback_io;
 use self::alloc_util::SubclassableAllocator;
+use self::callback_io::{CallbackIoError, CReader, CWriter};
 use alloc::{Allocator, SliceWrapper, SliceWrapperMut, StackAllocator, AllocatedStackMemory, bzero};
 use self::interface::{CAllocator, c_void, BrotliDecoderParameter, BrotliDecoderResult, brotli_alloc_func, brotli_free_func};
+use self::interface::{CMetadataCallbacks, brotli_decoder_metadata_start_func, brotli_decoder_metadata_chunk_func};
+use self::interface::{BrotliDecoderCallbackResult, brotli_decoder_read_func, brotli_decoder_write_func};
+use self::interface::BrotliDecoderErrorInfo;
+use ::shared_dictionary::BrotliSharedDictionaryType;
+use ::state::SharedCustomDictionary;
 use ::BrotliResult;
 use ::BrotliDecoderReturnInfo;
 use ::brotli_decode;
+use ::io_wrappers::{CustomRead, write_all};
+use ::reader::DecompressorCustomIo;
 pub use ::HuffmanCode;
 pub use super::state::{BrotliDecoderErrorCode, BrotliState};
 
@@ -91,6 +102,9 @@ pub struct BrotliDecoderState {
     pub decompressor: ::BrotliState<SubclassableAllocator,
                                     SubclassableAllocator,
                                     SubclassableAllocator>,
+    pub metadata_callbacks: CMetadataCallbacks,
+    // input bytes consumed so far, for BrotliDecoderGetErrorInfo
+    pub total_in: usize,
 }
 
 #[cfg(not(feature="std"))]
@@ -128,6 +142,12 @@ pub unsafe extern fn BrotliDecoderCreateInstance(
       let to_box = BrotliDecoderState {
         custom_allocator: allocators.clone(),
         decompressor: decompressor,
+        metadata_callbacks: CMetadataCallbacks {
+          start_func: None,
+          chunk_func: None,
+          opaque: core::ptr::null_mut(),
+        },
+        total_in: 0,
       };
       if let Some(alloc) = alloc_func {
         if free_func.is_none() {
@@ -154,7 +174,7 @@ pub unsafe extern fn BrotliDecoderCreateInstance(
 
 #[no_mangle]
 pub unsafe extern "C" fn BrotliDecoderSetParameter(state_ptr: *mut BrotliDecoderState,
-                                             selector: BrotliDecoderParameter,
+                                             selector: u32,
                                              value: u32) -> i32 {
   if state_ptr.is_null() {
     return 0;
@@ -164,6 +184,10 @@ pub unsafe extern "C" fn BrotliDecoderSetParameter(state_ptr: *mut BrotliDecoder
     &super::state::BrotliRunningState::BROTLI_STATE_UNINITED => {},
     _ => return 0,
   }
+  let selector = match BrotliDecoderParameter::from_u32(selector) {
+    Some(selector) => selector,
+    None => return 0,
+  };
   match selector {
     BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION => {
       state.canny_ringbuffer_allocation = value == 0;
@@ -175,6 +199,119 @@ pub unsafe extern "C" fn BrotliDecoderSetParameter(state_ptr: *mut BrotliDecoder
   1
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderAttachDictionary(
+  state_ptr: *mut BrotliDecoderState,
+  dict_type: u32,
+  data_size: usize,
+  data: *const u8,
+) -> i32 {
+  if state_ptr.is_null() {
+    return 0;
+  }
+  let dict_type = match dict_type {
+    0 => BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_RAW,
+    1 => BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_SERIALIZED,
+    _ => return 0,
+  };
+  let data = match checked_slice_from_raw_parts_or_nil(data, data_size) {
+    Some(data) => data,
+    None => return 0,
+  };
+  match catch_panic(|| {
+    super::decode::BrotliDecoderAttachDictionary(&mut (*state_ptr).decompressor, dict_type, data)
+  }) {
+    Ok(true) => 1,
+    Ok(false) => 0,
+    Err(mut e) => {
+      error_print(state_ptr, &mut e);
+      0
+    },
+  }
+}
+
+/// Supplies the static dictionary left out by the external-static-dictionary
+/// feature.
+///
+/// # Safety
+///
+/// data must hold data_size bytes that stay valid and unchanged until the
+/// decoder is destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderSetStaticDictionary(
+  state_ptr: *mut BrotliDecoderState,
+  data_size: usize,
+  data: *const u8,
+) -> i32 {
+  if state_ptr.is_null() {
+    return 0;
+  }
+  let data: &'static [u8] = match checked_slice_from_raw_parts_or_nil(data, data_size) {
+    Some(data) => data,
+    None => return 0,
+  };
+  match catch_panic(|| {
+    match super::decode::BrotliStaticDictionary::new(SharedCustomDictionary::Static(data)) {
+      Some(dictionary) =>
+        super::decode::BrotliDecoderSetStaticDictionary(&mut (*state_ptr).decompressor,
+                                                        &dictionary),
+      None => false,
+    }
+  }) {
+    Ok(true) => 1,
+    Ok(false) => 0,
+    Err(mut e) => {
+      error_print(state_ptr, &mut e);
+      0
+    },
+  }
+}
+
+// The Rust-side callbacks get the address of the C callbacks stored in the
+// (never moved) BrotliDecoderState as their opaque value.
+fn metadata_start_trampoline(opaque: usize, size: usize) {
+  let callbacks = unsafe { &*(opaque as *const CMetadataCallbacks) };
+  if let Some(start_func) = callbacks.start_func {
+    start_func(callbacks.opaque, size);
+  }
+}
+
+fn metadata_chunk_trampoline(opaque: usize, data: &[u8]) {
+  let callbacks = unsafe { &*(opaque as *const CMetadataCallbacks) };
+  if let Some(chunk_func) = callbacks.chunk_func {
+    chunk_func(callbacks.opaque, data.as_ptr(), data.len());
+  }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderSetMetadataCallbacks(
+  state_ptr: *mut BrotliDecoderState,
+  start_func: brotli_decoder_metadata_start_func,
+  chunk_func: brotli_decoder_metadata_chunk_func,
+  opaque: *mut c_void,
+) {
+  if state_ptr.is_null() {
+    return;
+  }
+  (*state_ptr).metadata_callbacks = CMetadataCallbacks {
+    start_func,
+    chunk_func,
+    opaque,
+  };
+  let callbacks_address = &(*state_ptr).metadata_callbacks as *const CMetadataCallbacks as usize;
+  super::decode::BrotliDecoderSetMetadataCallbacks(
+    &mut (*state_ptr).decompressor,
+    start_func.map(|_| metadata_start_trampoline as ::BrotliDecoderMetadataStartFunc),
+    chunk_func.map(|_| metadata_chunk_trampoline as ::BrotliDecoderMetadataChunkFunc),
+    callbacks_address,
+  );
+}
+
+/// Decodes using only the given scratch areas; see brotli_decode_prealloc.
+///
+/// # Safety
+///
+/// Each buffer must hold its stated number of elements.
 #[no_mangle]
 pub unsafe extern fn BrotliDecoderDecompressPrealloc(
   encoded_size: usize,
@@ -187,6 +324,34 @@ pub unsafe extern fn BrotliDecoderDecompressPrealloc(
   scratch_u32_buffer: *mut u32,
   scratch_hc_size: usize,
   scratch_hc_buffer: *mut HuffmanCode,
+) -> BrotliDecoderReturnInfo {
+  BrotliDecoderDecompressPreallocEx(encoded_size, encoded_buffer, decoded_size, decoded_buffer,
+                                    scratch_u8_size, scratch_u8_buffer,
+                                    scratch_u32_size, scratch_u32_buffer,
+                                    scratch_hc_size, scratch_hc_buffer,
+                                    core::ptr::null_mut())
+}
+
+/// BrotliDecoderDecompressPrealloc that also reports the scratch area an
+/// ALLOC_* error came from; see brotli_decode_prealloc_with_area.
+///
+/// # Safety
+///
+/// Each buffer must hold its stated number of elements, and exhausted must
+/// be null or writable.
+#[no_mangle]
+pub unsafe extern fn BrotliDecoderDecompressPreallocEx(
+  encoded_size: usize,
+  encoded_buffer: *const u8,
+  decoded_size: usize,
+  decoded_buffer: *mut u8,
+  scratch_u8_size: usize,
+  scratch_u8_buffer: *mut u8,
+  scratch_u32_size: usize,
+  scratch_u32_buffer: *mut u32,
+  scratch_hc_size: usize,
+  scratch_hc_buffer: *mut HuffmanCode,
+  exhausted: *mut ::BrotliDecoderScratchArea,
 ) -> BrotliDecoderReturnInfo {
   catch_panic_return_info(move || {
     let input = match checked_slice_from_raw_parts_or_nil(encoded_buffer, encoded_size) {
@@ -218,10 +383,52 @@ pub unsafe extern fn BrotliDecoderDecompressPrealloc(
       Some(scratch_hc) => scratch_hc,
       None => return invalid_argument_return_info(),
     };
-    ::brotli_decode_prealloc(input, output, scratch_u8, scratch_u32, scratch_hc)
+    let mut area = ::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE;
+    let ret = ::brotli_decode_prealloc_with_area(input, output, scratch_u8, scratch_u32, scratch_hc,
+                                                 &mut area);
+    if !exhausted.is_null() {
+      *exhausted = area;
+    }
+    ret
   })
 }
 
+/// Scratch sizes that let BrotliDecoderDecompressPrealloc decode any stream
+/// whose window is at most 2^window_bits bytes.
+#[no_mangle]
+pub extern "C" fn BrotliDecoderPreallocScratchSizes(window_bits: u32) -> ::BrotliDecoderScratchSizes {
+  ::brotli_decode_prealloc_scratch_sizes(window_bits)
+}
+
+/// Scratch sizes that let BrotliDecoderDecompressPrealloc decode the stream
+/// starting at encoded_buffer, read from its headers.
+///
+/// # Safety
+///
+/// encoded_buffer must hold encoded_size bytes and sizes must be null or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderPreallocScratchSizesForStream(
+  encoded_size: usize,
+  encoded_buffer: *const u8,
+  sizes: *mut ::BrotliDecoderScratchSizes,
+) -> BrotliDecoderErrorCode {
+  let input = match checked_slice_from_raw_parts_or_nil(encoded_buffer, encoded_size) {
+    Some(input) => input,
+    None => return BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS,
+  };
+  if sizes.is_null() {
+    return BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS;
+  }
+  match ::brotli_decode_prealloc_scratch_sizes_for_stream(input) {
+    Ok(stream_sizes) => {
+      *sizes = stream_sizes;
+      BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS
+    },
+    Err(code) => code,
+  }
+}
+
 unsafe fn brotli_decoder_decompress_with_return_info(
   encoded_size: usize,
   encoded_buffer: *const u8,
@@ -291,6 +498,59 @@ pub unsafe extern fn BrotliDecoderDecompress(
   }
 }
 
+const CALLBACK_BUFFER_SIZE: usize = 16384;
+
+/// Runs a whole decode, pulling compressed bytes from read_func and pushing
+/// decompressed bytes to write_func until the stream ends or fails.
+///
+/// # Safety
+///
+/// Both callbacks must be safe to call with opaque for the whole decode.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderDecompressWithCallbacks(
+  read_func: brotli_decoder_read_func,
+  write_func: brotli_decoder_write_func,
+  opaque: *mut c_void,
+) -> BrotliDecoderCallbackResult {
+  let (read_func, write_func) = match (read_func, write_func) {
+    (Some(read_func), Some(write_func)) => (read_func, write_func),
+    _ => return BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_ERROR,
+  };
+  match catch_panic(move || {
+    let allocators = CAllocator {
+      alloc_func: None,
+      free_func: None,
+      opaque: core::ptr::null_mut(),
+    };
+    let mut input_buffer = [0u8; CALLBACK_BUFFER_SIZE];
+    let mut output_buffer = [0u8; CALLBACK_BUFFER_SIZE];
+    let mut decompressor = DecompressorCustomIo::new(
+      CReader { read_func, opaque },
+      AllocatedStackMemory::<u8> { mem: &mut input_buffer[..] },
+      SubclassableAllocator::new(allocators.clone()),
+      SubclassableAllocator::new(allocators.clone()),
+      SubclassableAllocator::new(allocators),
+      CallbackIoError::InvalidData,
+    );
+    let mut writer = CWriter { write_func, opaque };
+    loop {
+      match decompressor.read(&mut output_buffer[..]) {
+        Ok(0) => return BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_SUCCESS,
+        Ok(size) => if let Err(e) = write_all(&mut writer, &output_buffer[..size]) {
+          return e.into();
+        },
+        Err(e) => return e.into(),
+      }
+    }
+  }) {
+    Ok(ret) => ret,
+    Err(mut readable_err) => {
+      error_print(core::ptr::null_mut(), &mut readable_err);
+      BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_ERROR
+    },
+  }
+}
+
 #[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
 fn catch_panic<T, F>(f: F) -> thread::Result<T>
 where F: FnOnce() -> T + panic::UnwindSafe {
@@ -350,8 +610,27 @@ where F: FnOnce() -> BrotliDecoderReturnInfo + panic::UnwindSafe {
     }
 }
 
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+thread_local! {
+    // message of the last panic caught on this thread, see BrotliDecoderGetPanicMessage
+    static LAST_PANIC_MESSAGE: Cell<[u8; 256]> = const { Cell::new([0u8; 256]) };
+}
+
+#[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
+fn last_panic_message() -> *const u8 {
+    LAST_PANIC_MESSAGE.with(|msg| msg.as_ptr() as *const u8)
+}
+
 #[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
 unsafe fn error_print(state_ptr: *mut BrotliDecoderState, err: &mut BrotliAdditionalErrorData) {
+    let message = if let Some(st) = err.downcast_ref::<&str>() {
+        st.as_bytes()
+    } else if let Some(st) = err.downcast_ref::<string::String>() {
+        st.as_bytes()
+    } else {
+        ::state::BrotliDecoderErrorStr(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_UNREACHABLE).as_bytes()
+    };
+    LAST_PANIC_MESSAGE.with(|msg| msg.set(copy_error_string(message)));
     if let Some(st) = err.downcast_ref::<&str>() {
         if !state_ptr.is_null() {
           (*state_ptr).decompressor.mtf_or_error_string = Err(copy_error_string(st.as_bytes()));
@@ -386,6 +665,11 @@ where F: FnOnce() -> BrotliDecoderReturnInfo {
 fn error_print(_state_ptr: *mut BrotliDecoderState, _err: &mut BrotliAdditionalErrorData) {
 }
 
+#[cfg(any(not(feature="std"), feature="pass-through-ffi-panics"))]
+fn last_panic_message() -> *const u8 {
+    b"\0".as_ptr()
+}
+
 #[no_mangle]
 pub unsafe extern fn BrotliDecoderDecompressStream(
     state_ptr: *mut BrotliDecoderState,
@@ -393,7 +677,42 @@ pub unsafe extern fn BrotliDecoderDecompressStream(
     input_buf_ptr: *mut*const u8,
     available_out: *mut usize,
     output_buf_ptr: *mut*mut u8,
-    mut total_out: *mut usize) -> BrotliDecoderResult {
+    total_out: *mut usize) -> BrotliDecoderResult {
+    decompress_stream(state_ptr, available_in, input_buf_ptr, available_out, output_buf_ptr,
+                      total_out, None)
+}
+
+/// BrotliDecoderDecompressStream that returns BROTLI_DECODER_RESULT_YIELD
+/// once it has decoded max_commands commands or max_output bytes (zero means
+/// no limit), so a long stream can be decoded in short slices. Resume after a
+/// yield as after BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT.
+///
+/// # Safety
+///
+/// Same as BrotliDecoderDecompressStream.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderDecompressStreamBudgeted(
+    state_ptr: *mut BrotliDecoderState,
+    available_in: *mut usize,
+    input_buf_ptr: *mut*const u8,
+    available_out: *mut usize,
+    output_buf_ptr: *mut*mut u8,
+    total_out: *mut usize,
+    max_commands: usize,
+    max_output: usize) -> BrotliDecoderResult {
+    decompress_stream(state_ptr, available_in, input_buf_ptr, available_out, output_buf_ptr,
+                      total_out, Some(&::BrotliWorkBudget{max_commands, max_output}))
+}
+
+// Only the budgeted entry point can return BROTLI_DECODER_RESULT_YIELD.
+unsafe fn decompress_stream(
+    state_ptr: *mut BrotliDecoderState,
+    available_in: *mut usize,
+    input_buf_ptr: *mut*const u8,
+    available_out: *mut usize,
+    output_buf_ptr: *mut*mut u8,
+    mut total_out: *mut usize,
+    budget: Option<&::BrotliWorkBudget>) -> BrotliDecoderResult {
     if state_ptr.is_null() ||
        available_in.is_null() ||
        input_buf_ptr.is_null() ||
@@ -438,7 +757,8 @@ pub unsafe extern fn BrotliDecoderDecompressStream(
                 return BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR;
             },
         };
-            result = super::decode::BrotliDecompressStream(
+        result = match budget {
+            None => super::decode::BrotliDecompressStream(
                 &mut *available_in,
                 &mut input_offset,
                 input_buf,
@@ -447,8 +767,21 @@ pub unsafe extern fn BrotliDecoderDecompressStream(
                 output_buf,
                 &mut *total_out,
                 &mut (*state_ptr).decompressor,
-            ).into();
+            ).into(),
+            Some(budget) => super::decode::BrotliDecompressStreamBudgeted(
+                    &mut *available_in,
+                    &mut input_offset,
+                    input_buf,
+                    &mut *available_out,
+                    &mut output_offset,
+                    output_buf,
+                    &mut *total_out,
+                budget,
+                &mut (*state_ptr).decompressor,
+            ).into(),
+        };
     }
+    (*state_ptr).total_in += input_offset;
     *input_buf_ptr = input_ptr.offset(input_offset as isize);
     *output_buf_ptr = output_ptr.offset(output_offset as isize);
                                            result
@@ -578,6 +911,48 @@ pub unsafe extern fn BrotliDecoderGetErrorString(state_ptr: *const BrotliDecoder
   }
   BrotliDecoderErrorString(super::decode::BrotliDecoderGetErrorCode(&(*state_ptr).decompressor))
 }
+/// Describes where decoding stopped: the error code, the position in the
+/// compressed and decompressed streams and the decoder state at that point.
+///
+/// # Safety
+///
+/// state_ptr must be null or a live decoder instance.
+#[no_mangle]
+pub unsafe extern "C" fn BrotliDecoderGetErrorInfo(state_ptr: *const BrotliDecoderState) -> BrotliDecoderErrorInfo {
+  if state_ptr.is_null() {
+    return BrotliDecoderErrorInfo {
+      code: BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS,
+      compressed_offset: 0,
+      bit_position: 0,
+      decompressed_offset: 0,
+      state_name: b"\0".as_ptr(),
+    };
+  }
+  let s = &(*state_ptr).decompressor;
+  let decoded_bits = ((*state_ptr).total_in as u64 * 8).saturating_sub(u64::from(s.error_unread_bits));
+  let decompressed_offset = if s.ringbuffer_size == 0 {
+    s.partial_pos_out
+  } else {
+    s.rb_roundtrips * s.ringbuffer_size as usize + core::cmp::min(s.pos, s.ringbuffer_size) as usize
+  };
+  BrotliDecoderErrorInfo {
+    code: s.error_code,
+    compressed_offset: (decoded_bits >> 3) as usize,
+    bit_position: (decoded_bits & 7) as u32,
+    decompressed_offset,
+    state_name: ::BrotliRunningStateStr(&s.state).as_ptr(),
+  }
+}
+
+/// Returns the message of the last panic caught by this library on the
+/// calling thread (an empty string if there was none). Calls that fail with
+/// BROTLI_DECODER_ERROR_UNREACHABLE without a decoder instance report the
+/// reason here. The string stays valid until the next panic on this thread.
+#[no_mangle]
+pub extern "C" fn BrotliDecoderGetPanicMessage() -> *const u8 {
+  last_panic_message()
+}
+
 #[no_mangle]
 pub extern fn BrotliDecoderErrorString(c: BrotliDecoderErrorCode) -> *const u8 {
     ::state::BrotliDecoderErrorStr(c).as_ptr()
@@ -590,6 +965,8 @@ pub extern fn BrotliDecoderVersion() -> u32 {
 }
 
 #[cfg(test)]
+// Some helpers are only used by tests that need the built-in static dictionary.
+#[cfg_attr(feature="external-static-dictionary", allow(dead_code))]
 mod tests {
   use super::*;
 
@@ -677,19 +1054,23 @@ mod tests {
     );
     assert_eq!(&ret.error_string[..18], b"ffi one-shot panic");
     assert_eq!(ret.error_string[18], 0);
+    // the message is kept for the caller even without a decoder instance
+    let message = unsafe { slice::from_raw_parts(BrotliDecoderGetPanicMessage(), 256) };
+    assert_eq!(message, &ret.error_string[..]);
     match ret.result {
       BrotliResult::ResultFailure => {},
       _ => panic!("expected one-shot panic to return failure"),
     }
   }
 
-  #[cfg(all(feature="std", not(feature="pass-through-ffi-panics")))]
   #[test]
-  fn prealloc_catches_scratch_exhaustion() {
+  fn prealloc_reports_exhausted_scratch() {
+    let header = [0x1bu8, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d];
+    let mut exhausted = ::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE;
     let ret = unsafe {
-      BrotliDecoderDecompressPrealloc(
-        0,
-        core::ptr::null(),
+      BrotliDecoderDecompressPreallocEx(
+        header.len(),
+        header.as_ptr(),
         0,
         core::ptr::null_mut(),
         0,
@@ -698,25 +1079,45 @@ mod tests {
         core::ptr::null_mut(),
         0,
         core::ptr::null_mut(),
+        &mut exhausted,
       )
     };
 
     assert_eq!(ret.decoded_size, 0);
     assert_eq!(
       ret.error_code as i32,
-      BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_UNREACHABLE as i32,
+      BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP as i32,
     );
+    assert_eq!(exhausted, ::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_HC);
     match ret.result {
       BrotliResult::ResultFailure => {},
       _ => panic!("expected scratch exhaustion to return failure"),
     }
   }
 
+  #[test]
+  fn prealloc_scratch_sizes_for_stream() {
+    // a 2^22 byte window, but a single 49 byte meta-block, so the ring
+    // buffer shrinks to the smallest slot
+    let header = [0x1bu8, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d];
+    let mut sizes = ::BrotliDecoderScratchSizes{u8_size: 0, u32_size: 0, hc_size: 0};
+    let code = unsafe {
+      BrotliDecoderPreallocScratchSizesForStream(header.len(), header.as_ptr(), &mut sizes)
+    };
+    assert_eq!(code as i32, BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS as i32);
+    assert_eq!(sizes, BrotliDecoderPreallocScratchSizes(10));
+    assert!(sizes.u8_size < BrotliDecoderPreallocScratchSizes(22).u8_size);
+    let code = unsafe {
+      BrotliDecoderPreallocScratchSizesForStream(header.len(), header.as_ptr(), core::ptr::null_mut())
+    };
+    assert_eq!(code as i32, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS as i32);
+  }
+
   #[test]
   fn set_parameter() {
     let set_parameter: unsafe extern "C" fn(
       *mut BrotliDecoderState,
-      BrotliDecoderParameter,
+      u32,
       u32,
     ) -> i32 = BrotliDecoderSetParameter;
 
@@ -728,14 +1129,16 @@ mod tests {
 
       assert_eq!(set_parameter(
         state,
-        BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION,
+        BrotliDecoderParameter::BROTLI_DECODER_PARAM_DISABLE_RING_BUFFER_REALLOCATION as u32,
         1,
       ), 1);
       assert!(!(*state).decompressor.canny_ringbuffer_allocation);
+      // unknown parameters are rejected rather than misinterpreted
+      assert_eq!(set_parameter(state, 2, 1), 0);
 
       assert_eq!(set_parameter(
         state,
-        BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW,
+        BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW as u32,
         1,
       ), 1);
       assert!((*state).decompressor.large_window);
@@ -744,7 +1147,7 @@ mod tests {
         super::super::state::BrotliRunningState::BROTLI_STATE_INITIALIZE;
       assert_eq!(set_parameter(
         state,
-        BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW,
+        BrotliDecoderParameter::BROTLI_DECODER_PARAM_LARGE_WINDOW as u32,
         0,
       ), 0);
       assert!((*state).decompressor.large_window);
@@ -752,4 +1155,291 @@ mod tests {
       BrotliDecoderDestroyInstance(state);
     }
   }
+
+  extern "C" fn count_metadata_start(opaque: *mut c_void, size: usize) {
+    unsafe { *(opaque as *mut usize) += size << 16 };
+  }
+
+  extern "C" fn count_metadata_chunk(opaque: *mut c_void, data: *const u8, size: usize) {
+    let data = unsafe { slice::from_raw_parts(data, size) };
+    unsafe { *(opaque as *mut usize) += data.iter().filter(|&&b| b == b'a').count() };
+  }
+
+  #[test]
+  fn attach_dictionary_and_metadata_callbacks() {
+    // metadata meta-block "hello, metadata", then a 9-byte copy at distance 9
+    let input = [0x2c, 0x07, b'h', b'e', b'l', b'l', b'o', b',', b' ', b'm', b'e', b't', b'a',
+                 b'd', b'a', b't', b'a', 0x81, 0x00, 0x00, 0x00, 0x02, 0x20, 0x0e, 0x89, 0x09];
+    let mut output = [0u8; 16];
+    let mut metadata_count = 0usize;
+    unsafe {
+      let state = BrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+      assert_eq!(BrotliDecoderAttachDictionary(state, 0, 10, b"0123456789".as_ptr()), 1);
+      assert_eq!(BrotliDecoderAttachDictionary(state, 2, 10, b"0123456789".as_ptr()), 0);
+      assert_eq!(BrotliDecoderAttachDictionary(state, 1, 10, b"0123456789".as_ptr()), 0);
+      assert_eq!(BrotliDecoderAttachDictionary(state, 0, 1, core::ptr::null()), 0);
+      BrotliDecoderSetMetadataCallbacks(state,
+                                        Some(count_metadata_start),
+                                        Some(count_metadata_chunk),
+                                        &mut metadata_count as *mut usize as *mut c_void);
+      let mut available_in = input.len();
+      let mut next_in = input.as_ptr();
+      let mut available_out = output.len();
+      let mut next_out = output.as_mut_ptr();
+      assert!(matches!(BrotliDecoderDecompressStream(state, &mut available_in, &mut next_in,
+                                                     &mut available_out, &mut next_out,
+                                                     core::ptr::null_mut()),
+                       BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS));
+      assert_eq!(output.len() - available_out, 9);
+      assert_eq!(&output[..9], b"123456789");
+      assert_eq!(metadata_count, (15 << 16) + 3);
+      assert_eq!(BrotliDecoderAttachDictionary(state, 0, 10, b"0123456789".as_ptr()), 0);
+      BrotliDecoderDestroyInstance(state);
+    }
+  }
+
+  #[cfg(not(feature="external-static-dictionary"))]
+  #[test]
+  fn attach_dictionary_with_transform_list() {
+    // a 9-byte copy at distance 9, i.e. built-in word 8 with transform 0
+    let input = [0x02, 0x01, 0x00, 0x00, 0x04, 0x40, 0x1c, 0x12, 0x13];
+    // no prefix or word lists, one transform list whose only transform
+    // uppercases the whole word
+    let serialized = [0x91, 0x00, 0, 0, 1, 1, 0, 0, 1, 0, 11, 0, 1, 0, 0, 0];
+    let offset = ::dictionary::kBrotliDictionaryOffsetsByLength[9] as usize + 8 * 9;
+    let word = &::dictionary::BrotliBuiltInStaticDictionary()[offset..offset + 9];
+    let mut output = [0u8; 16];
+    unsafe {
+      let state = BrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+      assert_eq!(BrotliDecoderAttachDictionary(state, 1, serialized.len(), serialized.as_ptr()), 1);
+      let mut available_in = input.len();
+      let mut next_in = input.as_ptr();
+      let mut available_out = output.len();
+      let mut next_out = output.as_mut_ptr();
+      assert!(matches!(BrotliDecoderDecompressStream(state, &mut available_in, &mut next_in,
+                                                     &mut available_out, &mut next_out,
+                                                     core::ptr::null_mut()),
+                       BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS));
+      assert_eq!(&output[..output.len() - available_out], &word.to_ascii_uppercase()[..]);
+      BrotliDecoderDestroyInstance(state);
+    }
+  }
+
+  // C allocator that returns NULL once opaque's budget of allocations is
+  // spent. Each block records its size in front so it can be freed.
+  #[cfg(feature="std")]
+  extern "C" fn budgeted_alloc(opaque: *mut c_void, size: usize) -> *mut c_void {
+    unsafe {
+      let budget = &mut *(opaque as *mut usize);
+      if *budget == 0 {
+        return core::ptr::null_mut();
+      }
+      *budget -= 1;
+      let block = std::alloc::alloc(std::alloc::Layout::from_size_align(size + 16, 16).unwrap());
+      *(block as *mut usize) = size;
+      block.add(16) as *mut c_void
+    }
+  }
+
+  #[cfg(feature="std")]
+  extern "C" fn budgeted_free(_opaque: *mut c_void, ptr: *mut c_void) {
+    unsafe {
+      let block = (ptr as *mut u8).sub(16);
+      let size = *(block as *mut usize);
+      std::alloc::dealloc(block, std::alloc::Layout::from_size_align(size + 16, 16).unwrap());
+    }
+  }
+
+  #[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+  #[test]
+  fn null_from_custom_allocator_is_an_alloc_error() {
+    let input = include_bytes!("../../testdata/alice29.txt.compressed");
+    let mut output = std::vec![0u8; 152089];
+    for budget in 0.. {
+      let mut remaining: usize = budget;
+      unsafe {
+        let state = BrotliDecoderCreateInstance(Some(budgeted_alloc), Some(budgeted_free),
+                                                &mut remaining as *mut usize as *mut c_void);
+        if state.is_null() {
+          // not even the instance fit
+          continue;
+        }
+        let mut available_in = input.len();
+        let mut next_in = input.as_ptr();
+        let mut available_out = output.len();
+        let mut next_out = output.as_mut_ptr();
+        let result = BrotliDecoderDecompressStream(state, &mut available_in, &mut next_in,
+                                                   &mut available_out, &mut next_out,
+                                                   core::ptr::null_mut());
+        let code = BrotliDecoderGetErrorCode(state) as i32;
+        BrotliDecoderDestroyInstance(state);
+        if let BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS = result {
+          assert!(output[..] == include_bytes!("../../testdata/alice29.txt")[..]);
+          break;
+        }
+        assert!(matches!(result, BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR));
+        assert!((BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES as i32..=
+                 BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MODES as i32).contains(&code));
+      }
+    }
+  }
+
+  #[cfg(feature="std")]
+  struct CallbackIo {
+    input: &'static [u8],
+    read_chunk: usize,
+    fail_read_at: Option<usize>,
+    output: std::vec::Vec<u8>,
+    output_limit: usize,
+  }
+
+  #[cfg(feature="std")]
+  extern "C" fn callback_read(opaque: *mut c_void, buffer: *mut u8, size: usize) -> isize {
+    let io = unsafe { &mut *(opaque as *mut CallbackIo) };
+    if matches!(io.fail_read_at, Some(at) if io.input.len() <= at) {
+      return -1;
+    }
+    let count = core::cmp::min(core::cmp::min(size, io.read_chunk), io.input.len());
+    unsafe { slice::from_raw_parts_mut(buffer, count) }.copy_from_slice(&io.input[..count]);
+    io.input = &io.input[count..];
+    count as isize
+  }
+
+  #[cfg(feature="std")]
+  extern "C" fn callback_write(opaque: *mut c_void, data: *const u8, size: usize) -> isize {
+    let io = unsafe { &mut *(opaque as *mut CallbackIo) };
+    let count = core::cmp::min(size, io.output_limit - io.output.len());
+    if count == 0 {
+      return -1;
+    }
+    // accept at most 1000 bytes per call to exercise partial writes
+    let count = core::cmp::min(count, 1000);
+    io.output.extend_from_slice(unsafe { slice::from_raw_parts(data, count) });
+    count as isize
+  }
+
+  #[cfg(feature="std")]
+  fn decompress_with_callbacks(io: &mut CallbackIo) -> BrotliDecoderCallbackResult {
+    unsafe {
+      BrotliDecoderDecompressWithCallbacks(Some(callback_read),
+                                           Some(callback_write),
+                                           io as *mut CallbackIo as *mut c_void)
+    }
+  }
+
+  #[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+  #[test]
+  fn decompress_with_callbacks_reports_each_failure() {
+    let alice_br: &'static [u8] = include_bytes!("../../testdata/alice29.txt.compressed");
+    let alice = include_bytes!("../../testdata/alice29.txt");
+    let new_io = |input: &'static [u8]| CallbackIo {
+      input,
+      read_chunk: 777,
+      fail_read_at: None,
+      output: std::vec::Vec::new(),
+      output_limit: usize::MAX,
+    };
+
+    let mut io = new_io(alice_br);
+    assert!(matches!(decompress_with_callbacks(&mut io),
+                     BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_SUCCESS));
+    assert_eq!(&io.output[..], &alice[..]);
+
+    let mut io = new_io(&alice_br[..alice_br.len() / 2]);
+    assert!(matches!(decompress_with_callbacks(&mut io),
+                     BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_ERROR));
+
+    let mut io = new_io(alice_br);
+    io.fail_read_at = Some(alice_br.len() / 2);
+    assert!(matches!(decompress_with_callbacks(&mut io),
+                     BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_READ_ERROR));
+
+    let mut io = new_io(alice_br);
+    io.output_limit = 5000;
+    assert!(matches!(decompress_with_callbacks(&mut io),
+                     BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_WRITE_ERROR));
+    assert_eq!(&io.output[..], &alice[..5000]);
+
+    assert!(matches!(unsafe { BrotliDecoderDecompressWithCallbacks(None, Some(callback_write),
+                                                                   core::ptr::null_mut()) },
+                     BrotliDecoderCallbackResult::BROTLI_DECODER_CALLBACK_RESULT_ERROR));
+  }
+
+  unsafe fn decode_error_info(input: &[u8], chunk: usize) -> BrotliDecoderErrorInfo {
+    let mut output = [0u8; 256];
+    let state = BrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+    for piece in input.chunks(chunk) {
+      let mut available_in = piece.len();
+      let mut next_in = piece.as_ptr();
+      let mut available_out = output.len();
+      let mut next_out = output.as_mut_ptr();
+      if let BrotliDecoderResult::BROTLI_DECODER_RESULT_ERROR = BrotliDecoderDecompressStream(
+          state, &mut available_in, &mut next_in, &mut available_out, &mut next_out,
+          core::ptr::null_mut()) {
+        break;
+      }
+    }
+    let info = BrotliDecoderGetErrorInfo(state);
+    BrotliDecoderDestroyInstance(state);
+    info
+  }
+
+  #[test]
+  fn error_info_locates_corruption() {
+    // byte 9 of a valid 49 byte stream was changed to 0xff
+    let corrupt = [0x1b, 0x30, 0x00, 0xe0, 0x8d, 0xd4, 0x59, 0x2d, 0x39, 0xff, 0xb5, 0x02,
+                   0x48, 0x10, 0x95, 0x2a, 0x9a, 0xea, 0x42, 0x0e, 0x51, 0xa4, 0x16, 0xb9,
+                   0xcb, 0xf5, 0xf8, 0x5c, 0x64, 0xb9, 0x2f, 0xc9, 0x6a, 0x3f, 0xb1, 0xdc,
+                   0xa8, 0xe0, 0x35, 0x07];
+    let info = unsafe { decode_error_info(&corrupt[..], corrupt.len()) };
+    assert_eq!(info.code as i32,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_CONTEXT_MAP_REPEAT as i32);
+    assert_eq!((info.compressed_offset, info.bit_position), (10, 6));
+    assert_eq!(info.decompressed_offset, 0);
+    assert_eq!(unsafe { slice::from_raw_parts(info.state_name, 20) }, b"STATE_CONTEXT_MAP_1\0");
+    // the error surfaces only once the byte at compressed_offset is available
+    let truncated = unsafe { decode_error_info(&corrupt[..10], 10) };
+    assert_eq!(truncated.code as i32, BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT as i32);
+
+    // small chunks go through the internal input buffer
+    for chunk in 1..corrupt.len() {
+      let chunked = unsafe { decode_error_info(&corrupt[..], chunk) };
+      assert_eq!((chunked.compressed_offset, chunked.bit_position), (10, 6));
+    }
+
+    let info = unsafe { BrotliDecoderGetErrorInfo(core::ptr::null()) };
+    assert_eq!(info.code as i32,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS as i32);
+  }
+  #[cfg(not(feature="external-static-dictionary"))]
+  #[test]
+  fn budgeted_stream_yields() {
+    let alice_br = include_bytes!("../../testdata/alice29.txt.compressed");
+    let alice = include_bytes!("../../testdata/alice29.txt");
+    let mut output = vec![0u8; alice.len()];
+    let mut yields = 0;
+    unsafe {
+      let state = BrotliDecoderCreateInstance(None, None, core::ptr::null_mut());
+      let mut available_in = alice_br.len();
+      let mut next_in = alice_br.as_ptr();
+      let mut available_out = output.len();
+      let mut next_out = output.as_mut_ptr();
+      loop {
+        let before = available_out;
+        let result = BrotliDecoderDecompressStreamBudgeted(state, &mut available_in, &mut next_in,
+                                                           &mut available_out, &mut next_out,
+                                                           core::ptr::null_mut(), 0, 8192);
+        if let BrotliDecoderResult::BROTLI_DECODER_RESULT_YIELD = result {
+          assert!(before != available_out && before - available_out <= 8192);
+          yields += 1;
+          continue;
+        }
+        assert!(matches!(result, BrotliDecoderResult::BROTLI_DECODER_RESULT_SUCCESS));
+        break;
+      }
+      BrotliDecoderDestroyInstance(state);
+    }
+    assert!(output[..] == alice[..]);
+    assert!(yields >= alice.len() / 8192);
+  }
 }
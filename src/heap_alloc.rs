@@ -0,0 +1,69 @@
+#![cfg(feature="alloc")]
+// Heap allocator for targets that have a global allocator but no std,
+// such as kernels and embedded firmware. Memory comes from the alloc crate.
+use core;
+use core::ops;
+use alloc_crate::boxed::Box;
+use alloc_crate::vec::Vec;
+
+/// A heap-allocated block handed out by BoxAlloc.
+pub struct BoxedSlice<T>(Box<[T]>);
+
+impl<T> core::default::Default for BoxedSlice<T> {
+    fn default() -> Self {
+       BoxedSlice::<T>(Vec::new().into_boxed_slice())
+    }
+}
+
+impl<T> From<Vec<T>> for BoxedSlice<T> {
+    fn from(data: Vec<T>) -> Self {
+       BoxedSlice::<T>(data.into_boxed_slice())
+    }
+}
+
+impl<T> ops::Index<usize> for BoxedSlice<T>{
+    type Output = T;
+    fn index(&self, index : usize) -> &T {
+        &self.0[index]
+    }
+}
+
+impl<T> ops::IndexMut<usize> for BoxedSlice<T>{
+    fn index_mut(&mut self, index : usize) -> &mut T {
+        &mut self.0[index]
+    }
+}
+
+impl<T> super::SliceWrapper<T> for BoxedSlice<T> {
+    fn slice(&self) -> & [T] {
+       &self.0[..]
+    }
+}
+
+impl<T> super::SliceWrapperMut<T> for BoxedSlice<T> {
+    fn slice_mut(&mut self) -> &mut [T] {
+       &mut self.0[..]
+    }
+}
+
+/// Allocator backed by Vec/Box from the alloc crate. Usable for u8, u32 and
+/// HuffmanCode alike, so a single value type fills all three allocator slots.
+/// Requests no Vec could ever hold come back as an empty cell, so the
+/// decoder fails with the matching BROTLI_DECODER_ERROR_ALLOC_* code; running
+/// out of heap otherwise aborts like any other Vec allocation.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BoxAlloc;
+
+impl<T : core::clone::Clone + Default> super::Allocator<T> for BoxAlloc {
+   type AllocatedMemory = BoxedSlice<T>;
+   fn alloc_cell(&mut self, len : usize) -> BoxedSlice<T> {
+       let size = core::cmp::max(core::mem::size_of::<T>(), 1);
+       if len > core::isize::MAX as usize / size {
+           return BoxedSlice::<T>::default();
+       }
+       let mut v : Vec<T> = Vec::with_capacity(len);
+       v.resize(len, T::default());
+       BoxedSlice::<T>(v.into_boxed_slice())
+   }
+   fn free_cell(&mut self, _data : BoxedSlice<T>) {}
+}
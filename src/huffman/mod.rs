@@ -70,6 +70,12 @@ impl<AllocU32 : alloc::Allocator<u32>,
         let _ = core::mem::replace(&mut self.codes,
                            alloc_hc.alloc_cell(nt * BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize));
     }
+    // False if the allocators could not provide what init asked for.
+    pub fn is_allocated(&self) -> bool {
+        let nt = self.num_htrees as usize;
+        self.htrees.slice().len() >= nt &&
+          self.codes.slice().len() >= nt * BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize
+    }
 
 //  pub fn get_tree_mut<'a>(self :&'a mut Self, index : u32, mut tree_out : &'a mut [HuffmanCode]) {
 //        let start : usize = fast!((self.htrees)[index as usize]) as usize;
@@ -137,3 +137,79 @@ impl<InputType: Write> CustomWrite<io::Error> for IntoIoWriter<InputType> {
     }
   }
 }
+
+/// Error returned through the embedded-io bridges: either the peripheral's own
+/// error or the invalid-data marker handed to DecompressorCustomIo and
+/// DecompressorWriterCustomIo for corrupt or truncated streams.
+#[cfg(feature="embedded-io")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddedIoError<E> {
+  Io(E),
+  InvalidData,
+}
+
+#[cfg(feature="embedded-io")]
+impl<E: embedded_io::Error> embedded_io::Error for EmbeddedIoError<E> {
+  fn kind(&self) -> embedded_io::ErrorKind {
+    match *self {
+      EmbeddedIoError::Io(ref e) => e.kind(),
+      EmbeddedIoError::InvalidData => embedded_io::ErrorKind::InvalidData,
+    }
+  }
+}
+
+#[cfg(feature="embedded-io")]
+pub struct EmbeddedIoReader<InputType: embedded_io::Read>(pub InputType);
+
+#[cfg(feature="embedded-io")]
+pub struct EmbeddedIoWriter<OutputType: embedded_io::Write>(pub OutputType);
+
+#[cfg(feature="embedded-io")]
+impl<InputType: embedded_io::Read> CustomRead<EmbeddedIoError<InputType::Error>> for EmbeddedIoReader<InputType> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, EmbeddedIoError<InputType::Error>> {
+    use embedded_io::Error;
+    loop {
+      match self.0.read(buf) {
+        Err(e) => {
+          match e.kind() {
+            embedded_io::ErrorKind::Interrupted => continue,
+            _ => return Err(EmbeddedIoError::Io(e)),
+          }
+        }
+        Ok(cur_read) => return Ok(cur_read),
+      }
+    }
+  }
+}
+
+#[cfg(feature="embedded-io")]
+impl<OutputType: embedded_io::Write> CustomWrite<EmbeddedIoError<OutputType::Error>> for EmbeddedIoWriter<OutputType> {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, EmbeddedIoError<OutputType::Error>> {
+    use embedded_io::Error;
+    loop {
+      match self.0.write(buf) {
+        Err(e) => {
+          match e.kind() {
+            embedded_io::ErrorKind::Interrupted => continue,
+            _ => return Err(EmbeddedIoError::Io(e)),
+          }
+        }
+        Ok(cur_written) => return Ok(cur_written),
+      }
+    }
+  }
+  fn flush(&mut self) -> Result<(), EmbeddedIoError<OutputType::Error>> {
+    use embedded_io::Error;
+    loop {
+      match self.0.flush() {
+        Err(e) => {
+          match e.kind() {
+            embedded_io::ErrorKind::Interrupted => continue,
+            _ => return Err(EmbeddedIoError::Io(e)),
+          }
+        }
+        Ok(_) => return Ok(()),
+      }
+    }
+  }
+}
@@ -5,6 +5,10 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
+// The default build still compiles on toolchains that predate the
+// associated integer constants and io::Error::other.
+#![allow(clippy::legacy_numeric_constants)]
+#![allow(clippy::io_other_error)]
 #![cfg_attr(feature="no-stdlib-ffi-binding",cfg_attr(not(feature="std"), feature(lang_items)))]
 #![cfg_attr(feature="no-stdlib-ffi-binding",cfg_attr(not(feature="std"), feature(panic_handler)))]
 // Assert at compile time that the default build contains no unsafe code:
@@ -20,6 +24,14 @@ extern crate std;
 use std::io::{self, Error, ErrorKind, Read, Write};
 #[cfg(feature="std")]
 extern crate alloc_stdlib;
+#[cfg(any(feature="std", feature="alloc"))]
+extern crate alloc as alloc_crate;
+#[cfg(feature="wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature="embedded-io")]
+extern crate embedded_io;
+#[cfg(all(test, feature="wasm", target_arch="wasm32"))]
+extern crate wasm_bindgen_test;
 #[macro_use]
 extern crate alloc_no_stdlib as alloc;
 pub use alloc::{AllocatedStackMemory, Allocator, SliceWrapper, SliceWrapperMut, StackAllocator, bzero};
@@ -27,12 +39,15 @@ use core::ops;
 
 #[cfg(feature="std")]
 pub use alloc_stdlib::StandardAlloc;
+#[cfg(feature="alloc")]
+pub use heap_alloc::{BoxAlloc, BoxedSlice};
 #[cfg(all(feature="unsafe",feature="std"))]
 pub use alloc_stdlib::HeapAlloc;
 #[macro_use]
 mod memory;
 pub mod dictionary;
 mod brotli_alloc;
+mod heap_alloc;
 #[macro_use]
 mod bit_reader;
 mod huffman;
@@ -40,28 +55,55 @@ mod state;
 mod prefix;
 mod context;
 pub mod transform;
+pub mod static_dictionary;
+pub mod shared_dictionary;
 mod test;
 mod decode;
 pub mod io_wrappers;
 pub mod reader;
 pub mod writer;
+pub mod static_decoder;
+pub mod mem_stats;
+pub mod seekable;
+mod sha256;
+pub mod dcb;
 pub use huffman::{HuffmanCode, HuffmanTreeGroup};
-pub use state::BrotliState;
+pub use state::{BrotliState, BrotliDecoderErrorCode, BrotliDecoderErrorStr, BrotliRunningStateStr,
+                BrotliDecoderMetadataStartFunc, BrotliDecoderMetadataChunkFunc,
+                BrotliDecoderProgressFunc, SharedCustomDictionary};
 #[cfg(feature="ffi-api")]
 pub mod ffi;
+#[cfg(feature="wasm")]
+pub mod wasm;
 pub use reader::{DecompressorCustomIo};
+#[cfg(feature="static-decoder")]
+pub use static_decoder::StaticDecoder;
+pub use mem_stats::{AccountingAlloc, AllocStats, MemStats};
+#[cfg(feature="alloc")]
+pub use seekable::{IndexBuilder, IndexEntry, MetaBlockIndex, SeekTable, Segment};
+#[cfg(all(feature="std", feature="alloc"))]
+pub use seekable::SeekableDecompressor;
+pub use dcb::{DcbError, DictionaryRegistry};
+#[cfg(feature="std")]
+pub use dcb::DcbDecompressor;
 
 #[cfg(feature="std")]
-pub use reader::{Decompressor};
+pub use reader::{Decompressor, DecompressorWithDictionary};
+#[cfg(feature="alloc")]
+pub use reader::{DecompressorHeap};
 
 pub use writer::{DecompressorWriterCustomIo};
 #[cfg(feature="std")]
 pub use writer::{DecompressorWriter};
+#[cfg(feature="alloc")]
+pub use writer::{DecompressorWriterHeap};
 
 // use io_wrappers::write_all;
 pub use io_wrappers::{CustomRead, CustomWrite};
 #[cfg(feature="std")]
 pub use io_wrappers::{IntoIoReader, IoReaderWrapper, IntoIoWriter, IoWriterWrapper};
+#[cfg(feature="embedded-io")]
+pub use io_wrappers::{EmbeddedIoError, EmbeddedIoReader, EmbeddedIoWriter};
 
 // interface
 // pub fn BrotliDecompressStream(mut available_in: &mut usize,
@@ -73,7 +115,16 @@ pub use io_wrappers::{IntoIoReader, IoReaderWrapper, IntoIoWriter, IoWriterWrapp
 //                               mut total_out: &mut usize,
 //                               mut s: &mut BrotliState<AllocU8, AllocU32, AllocHC>);
 
-pub use decode::{BrotliDecompressStream, BrotliResult, BrotliDecoderHasMoreOutput, BrotliDecoderIsFinished, BrotliDecoderTakeOutput};
+pub use decode::{BrotliDecompressStream, BrotliResult, BrotliDecoderHasMoreOutput, BrotliDecoderIsFinished, BrotliDecoderTakeOutput, BrotliDecoderGetErrorCode};
+pub use decode::{BrotliBudgetedResult, BrotliDecompressStreamBudgeted, BrotliDecompressStreamWithDictionary,
+                 BrotliWorkBudget};
+pub use decode::{inspect_header, inspect_header_with_dictionary, BrotliHeaderInfo, BrotliMetaBlockHeaderInfo};
+pub use decode::{BrotliDecoderAttachDictionary, BrotliDecoderSetMetadataCallbacks,
+                 BrotliDecoderSetStaticDictionary, BrotliStaticDictionary,
+                 BrotliDecoderSetProgressCallback};
+pub use decode::{BrotliResumePoint, BrotliDecoderSetStopAtMetaBlockBoundaries,
+                 BrotliDecoderStoppedAtMetaBlockBoundary, BrotliDecoderGetResumePoint, BrotliDecoderGetWindow, BrotliDecoderResumeAt};
+pub use shared_dictionary::BrotliSharedDictionaryType;
 
 
 
@@ -333,6 +384,20 @@ pub fn copy_from_to<R: io::Read, W: io::Write>(mut r: R, mut w: W) -> io::Result
   Ok(out_size)
 }
 
+/// Decompresses bytes start..end of a brotli stream, decoding no further
+/// than end, so a stream that is damaged or truncated after the range still
+/// succeeds. The result is shorter than the range if the stream ends first.
+#[cfg(feature="std")]
+pub fn decompress_range<R: Read>(r: R, range: core::ops::Range<u64>) -> io::Result<alloc_crate::vec::Vec<u8>> {
+  let mut decompressor = Decompressor::new(r, 4096);
+  let mut output = alloc_crate::vec::Vec::new();
+  if range.end <= range.start || decompressor.skip(range.start)? < range.start {
+    return Ok(output);
+  }
+  decompressor.take(range.end - range.start).read_to_end(&mut output)?;
+  Ok(output)
+}
+
 #[repr(C)]
 pub struct BrotliDecoderReturnInfo {
     pub decoded_size: usize,
@@ -371,39 +436,241 @@ impl BrotliDecoderReturnInfo {
 
 declare_stack_allocator_struct!(MemPool, 512, stack);
 
+/// Sizes, in elements, of the three scratch areas brotli_decode_prealloc takes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BrotliDecoderScratchSizes {
+    pub u8_size: usize,
+    pub u32_size: usize,
+    pub hc_size: usize,
+}
+
+/// The scratch area brotli_decode_prealloc_with_area ran out of, if any.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrotliDecoderScratchArea {
+  BROTLI_DECODER_SCRATCH_NONE = 0,
+  BROTLI_DECODER_SCRATCH_U8 = 1,
+  BROTLI_DECODER_SCRATCH_U32 = 2,
+  BROTLI_DECODER_SCRATCH_HC = 3,
+}
+
+/// Scratch sizes with which brotli_decode_prealloc decodes any stream whose
+/// window is at most 2^window_bits bytes (up to 30 for large-window streams).
+pub fn brotli_decode_prealloc_scratch_sizes(window_bits: u32) -> BrotliDecoderScratchSizes {
+  let window_bits = core::cmp::min(window_bits, 30) as usize;
+  scratch_sizes_for_ring_buffer(static_decoder::ring_buffer_size(window_bits))
+}
+
+/// Scratch sizes with which brotli_decode_prealloc decodes the stream that
+/// starts with input. Only the stream header and the first meta-block
+/// header are read, so a prefix of the stream will do.
+pub fn brotli_decode_prealloc_scratch_sizes_for_stream(input: &[u8]) -> Result<BrotliDecoderScratchSizes, BrotliDecoderErrorCode> {
+  let info = decode::inspect_header(input)?;
+  if info.is_empty_stream {
+    return Ok(BrotliDecoderScratchSizes {
+      u8_size: info.ringbuffer_size + info.scratch_u8,
+      u32_size: info.scratch_u32,
+      hc_size: info.scratch_hc,
+    });
+  }
+  Ok(scratch_sizes_for_ring_buffer(info.ringbuffer_size))
+}
+
+fn scratch_sizes_for_ring_buffer(ringbuffer_size: usize) -> BrotliDecoderScratchSizes {
+  BrotliDecoderScratchSizes {
+    u8_size: core::cmp::max(ringbuffer_size, static_decoder::MIN_RING_BUFFER_SLOT_SIZE) +
+             static_decoder::SCRATCH_U8_SLOT_SIZES.iter().sum::<usize>(),
+    u32_size: static_decoder::SCRATCH_U32_SLOT_SIZES.iter().sum(),
+    hc_size: static_decoder::SCRATCH_HC_SLOT_SIZES.iter().sum(),
+  }
+}
+
+// MemPool that comes back empty instead of panicking when no free cell is
+// large enough, and remembers that it did.
+struct ScratchPool<'a, T: 'a> {
+  pool: StackAllocator<'a, T, MemPool<'a, T>>,
+  exhausted: bool,
+}
+
+impl<'a, T: 'a> ScratchPool<'a, T> {
+  fn new(scratch: &'a mut [T], initializer: fn(&mut [T])) -> Self {
+    ScratchPool {
+      pool: MemPool::<T>::new_allocator(scratch, initializer),
+      exhausted: false,
+    }
+  }
+}
+
+impl<'a, T: 'a> Allocator<T> for ScratchPool<'a, T> {
+  type AllocatedMemory = AllocatedStackMemory<'a, T>;
+  fn alloc_cell(&mut self, len: usize) -> AllocatedStackMemory<'a, T> {
+    if len != 0 && !self.pool.system_resources.slice()[self.pool.free_list_start..]
+                        .iter().any(|cell| cell.len() >= len) {
+      self.exhausted = true;
+      return AllocatedStackMemory::<'a, T>::default();
+    }
+    self.pool.alloc_cell(len)
+  }
+  fn free_cell(&mut self, data: AllocatedStackMemory<'a, T>) {
+    self.pool.free_cell(data)
+  }
+}
+
+/// Decodes input into output using only the given scratch areas. When they
+/// are at least brotli_decode_prealloc_scratch_sizes_for_stream(input),
+/// decoding never runs out of memory; smaller areas are shared first-fit
+/// and, if one runs dry, the result is the BROTLI_DECODER_ERROR_ALLOC_* error
+/// of the allocation that failed.
 pub fn brotli_decode_prealloc(
   input: &[u8],
-  mut output: &mut[u8],
+  output: &mut[u8],
+  scratch_u8: &mut [u8],
+  scratch_u32: &mut [u32],
+  scratch_hc: &mut [HuffmanCode],
+) -> BrotliDecoderReturnInfo {
+  let mut exhausted = BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE;
+  brotli_decode_prealloc_with_area(input, output, scratch_u8, scratch_u32, scratch_hc,
+                                   &mut exhausted)
+}
+
+/// brotli_decode_prealloc that also sets exhausted to the scratch area an
+/// ALLOC_* error came from, or to BROTLI_DECODER_SCRATCH_NONE.
+pub fn brotli_decode_prealloc_with_area(
+  input: &[u8],
+  output: &mut[u8],
   scratch_u8: &mut [u8],
   scratch_u32: &mut [u32],
   scratch_hc: &mut [HuffmanCode],
+  exhausted: &mut BrotliDecoderScratchArea,
 ) -> BrotliDecoderReturnInfo {
-  let stack_u8_allocator = MemPool::<u8>::new_allocator(scratch_u8, bzero);
-  let stack_u32_allocator = MemPool::<u32>::new_allocator(scratch_u32, bzero);
-  let stack_hc_allocator = MemPool::<HuffmanCode>::new_allocator(scratch_hc, bzero);
+  *exhausted = BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE;
+  if let Ok(sizes) = brotli_decode_prealloc_scratch_sizes_for_stream(input) {
+    let min_sizes = scratch_sizes_for_ring_buffer(0);
+    if scratch_u8.len() >= core::cmp::max(sizes.u8_size, min_sizes.u8_size) &&
+       scratch_u32.len() >= min_sizes.u32_size && scratch_hc.len() >= min_sizes.hc_size {
+      let mut brotli_state = static_decoder::scratch_state(scratch_u8, scratch_u32, scratch_hc);
+      let (result, output_offset) = decode_prealloc_with(input, output, &mut brotli_state);
+      return BrotliDecoderReturnInfo::new(&brotli_state, result, output_offset);
+    }
+  }
+  let mut brotli_state = BrotliState::new(ScratchPool::new(scratch_u8, bzero),
+                                          ScratchPool::new(scratch_u32, bzero),
+                                          ScratchPool::new(scratch_hc, bzero));
+  let (result, output_offset) = decode_prealloc_with(input, output, &mut brotli_state);
+  if let BrotliResult::ResultFailure = result {
+    if brotli_state.alloc_u8.exhausted {
+      *exhausted = BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_U8;
+    } else if brotli_state.alloc_u32.exhausted {
+      *exhausted = BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_U32;
+    } else if brotli_state.alloc_hc.exhausted {
+      *exhausted = BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_HC;
+    }
+  }
+  BrotliDecoderReturnInfo::new(&brotli_state, result, output_offset)
+}
+
+fn decode_prealloc_with<AllocU8: Allocator<u8>,
+                        AllocU32: Allocator<u32>,
+                        AllocHC: Allocator<HuffmanCode>>(
+  input: &[u8],
+  mut output: &mut [u8],
+  brotli_state: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+) -> (BrotliResult, usize) {
   let mut available_out = output.len();
   let mut available_in: usize = input.len();
   let mut input_offset: usize = 0;
   let mut output_offset: usize = 0;
   let mut written: usize = 0;
-  let mut brotli_state =
-    BrotliState::new(stack_u8_allocator, stack_u32_allocator, stack_hc_allocator);
-  let result = ::BrotliDecompressStream(&mut available_in,
-                                      &mut input_offset,
-                                      &input[..],
-                                      &mut available_out,
-                                      &mut output_offset,
-                                      &mut output,
-                                      &mut written,
-                                      &mut brotli_state);
-  let return_info = BrotliDecoderReturnInfo::new(&brotli_state, result.into(), output_offset);
-  return_info    
+  let result = decompress_with_progress(&mut available_in,
+                                        &mut input_offset,
+                                        &input[..],
+                                        &mut available_out,
+                                        &mut output_offset,
+                                        &mut output,
+                                        &mut written,
+                                        brotli_state);
+  (result, output_offset)
+}
+
+// BrotliDecompressStream for callers that hand over all their input and
+// output in one go. With a progress callback set, the work is split into
+// progress_interval sized steps so that the callback fires as decoding goes
+// rather than only once at the end.
+#[allow(clippy::too_many_arguments)]
+fn decompress_with_progress<AllocU8: Allocator<u8>,
+                            AllocU32: Allocator<u32>,
+                            AllocHC: Allocator<HuffmanCode>>(
+  available_in: &mut usize,
+  input_offset: &mut usize,
+  input: &[u8],
+  available_out: &mut usize,
+  output_offset: &mut usize,
+  output: &mut [u8],
+  written: &mut usize,
+  brotli_state: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+) -> BrotliResult {
+  if brotli_state.progress_func.is_none() || brotli_state.progress_interval == 0 {
+    return ::BrotliDecompressStream(available_in, input_offset, input, available_out,
+                                    output_offset, output, written, brotli_state);
+  }
+  let budget = BrotliWorkBudget {
+    max_commands: 0,
+    max_output: brotli_state.progress_interval,
+  };
+  loop {
+    match ::BrotliDecompressStreamBudgeted(available_in, input_offset, input, available_out,
+                                           output_offset, output, written, &budget,
+                                           brotli_state) {
+      BrotliBudgetedResult::Yield => {},
+      BrotliBudgetedResult::ResultSuccess => return BrotliResult::ResultSuccess,
+      BrotliBudgetedResult::NeedsMoreInput => return BrotliResult::NeedsMoreInput,
+      BrotliBudgetedResult::NeedsMoreOutput => return BrotliResult::NeedsMoreOutput,
+      BrotliBudgetedResult::ResultFailure => return BrotliResult::ResultFailure,
+    }
+  }
 }
 
 #[cfg(not(feature="std"))]
 pub fn brotli_decode(
     input: &[u8],
     output_and_scratch: &mut[u8],
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_progress(input, output_and_scratch, None, 0, 0)
+}
+
+/// brotli_decode, calling progress as described at
+/// BrotliDecoderSetProgressCallback.
+#[cfg(not(feature="std"))]
+pub fn brotli_decode_with_progress(
+    input: &[u8],
+    output_and_scratch: &mut[u8],
+    progress: Option<BrotliDecoderProgressFunc>,
+    interval: usize,
+    opaque: usize,
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_options(input, output_and_scratch, progress, interval, opaque, None)
+}
+
+/// brotli_decode with a static dictionary supplied at runtime, for builds
+/// with the external-static-dictionary feature.
+#[cfg(not(feature="std"))]
+pub fn brotli_decode_with_static_dictionary(
+    input: &[u8],
+    output_and_scratch: &mut[u8],
+    dictionary: &BrotliStaticDictionary,
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_options(input, output_and_scratch, None, 0, 0, Some(dictionary))
+}
+
+#[cfg(not(feature="std"))]
+fn brotli_decode_with_options(
+    input: &[u8],
+    output_and_scratch: &mut[u8],
+    progress: Option<BrotliDecoderProgressFunc>,
+    interval: usize,
+    opaque: usize,
+    dictionary: Option<&BrotliStaticDictionary>,
 ) -> BrotliDecoderReturnInfo {
   let mut stack_u32_buffer = [0u32; 12 * 1024 * 6];
   let mut stack_hc_buffer = [HuffmanCode::default(); 128 * (decode::kNumInsertAndCopyCodes as usize + decode::kNumLiteralCodes as usize) + 6 * decode::kNumBlockLengthCodes as usize * huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize];
@@ -421,9 +688,9 @@ pub fn brotli_decode(
       }
   }
   let (mut output, mut scratch_space) = output_and_scratch.split_at_mut(guessed_output_size);
-  let stack_u8_allocator = MemPool::<u8>::new_allocator(&mut scratch_space, bzero);
-  let stack_u32_allocator = MemPool::<u32>::new_allocator(&mut stack_u32_buffer, bzero);
-  let stack_hc_allocator = MemPool::<HuffmanCode>::new_allocator(&mut stack_hc_buffer, bzero);
+  let stack_u8_allocator = ScratchPool::new(&mut scratch_space, bzero);
+  let stack_u32_allocator = ScratchPool::new(&mut stack_u32_buffer, bzero);
+  let stack_hc_allocator = ScratchPool::new(&mut stack_hc_buffer, bzero);
   let mut available_out = output.len();
   let mut available_in: usize = input.len();
   let mut input_offset: usize = 0;
@@ -431,22 +698,62 @@ pub fn brotli_decode(
   let mut written: usize = 0;
   let mut brotli_state =
     BrotliState::new(stack_u8_allocator, stack_u32_allocator, stack_hc_allocator);
-  let result = ::BrotliDecompressStream(&mut available_in,
-                                      &mut input_offset,
-                                      &input[..],
-                                      &mut available_out,
-                                      &mut output_offset,
-                                      &mut output,
-                                      &mut written,
-                                      &mut brotli_state);
+  BrotliDecoderSetProgressCallback(&mut brotli_state, progress, interval, opaque);
+  if let Some(dictionary) = dictionary {
+    BrotliDecoderSetStaticDictionary(&mut brotli_state, dictionary);
+  }
+  let result = decompress_with_progress(&mut available_in,
+                                        &mut input_offset,
+                                        &input[..],
+                                        &mut available_out,
+                                        &mut output_offset,
+                                        &mut output,
+                                        &mut written,
+                                        &mut brotli_state);
   let return_info = BrotliDecoderReturnInfo::new(&brotli_state, result.into(), output_offset);
   return_info    
 }
 
 #[cfg(feature="std")]
 pub fn brotli_decode(
+    input: &[u8],
+    output: &mut[u8],
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_progress(input, output, None, 0, 0)
+}
+
+/// brotli_decode, calling progress as described at
+/// BrotliDecoderSetProgressCallback.
+#[cfg(feature="std")]
+pub fn brotli_decode_with_progress(
+    input: &[u8],
+    output: &mut[u8],
+    progress: Option<BrotliDecoderProgressFunc>,
+    interval: usize,
+    opaque: usize,
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_options(input, output, progress, interval, opaque, None)
+}
+
+/// brotli_decode with a static dictionary supplied at runtime, for builds
+/// with the external-static-dictionary feature.
+#[cfg(feature="std")]
+pub fn brotli_decode_with_static_dictionary(
+    input: &[u8],
+    output: &mut[u8],
+    dictionary: &BrotliStaticDictionary,
+) -> BrotliDecoderReturnInfo {
+  brotli_decode_with_options(input, output, None, 0, 0, Some(dictionary))
+}
+
+#[cfg(feature="std")]
+fn brotli_decode_with_options(
     input: &[u8],
     mut output: &mut[u8],
+    progress: Option<BrotliDecoderProgressFunc>,
+    interval: usize,
+    opaque: usize,
+    dictionary: Option<&BrotliStaticDictionary>,
 ) -> BrotliDecoderReturnInfo {
   let mut available_out = output.len();
   let mut available_in: usize = input.len();
@@ -455,14 +762,88 @@ pub fn brotli_decode(
   let mut written: usize = 0;
   let mut brotli_state =
     BrotliState::new(StandardAlloc::default(), StandardAlloc::default(), StandardAlloc::default());
-  let result = ::BrotliDecompressStream(&mut available_in,
-                                      &mut input_offset,
-                                      &input[..],
-                                      &mut available_out,
-                                      &mut output_offset,
-                                      &mut output,
-                                      &mut written,
-                                      &mut brotli_state);
+  BrotliDecoderSetProgressCallback(&mut brotli_state, progress, interval, opaque);
+  if let Some(dictionary) = dictionary {
+    BrotliDecoderSetStaticDictionary(&mut brotli_state, dictionary);
+  }
+  let result = decompress_with_progress(&mut available_in,
+                                        &mut input_offset,
+                                        &input[..],
+                                        &mut available_out,
+                                        &mut output_offset,
+                                        &mut output,
+                                        &mut written,
+                                        &mut brotli_state);
   let return_info = BrotliDecoderReturnInfo::new(&brotli_state, result.into(), output_offset);
   return_info
 }
+
+/// Decompresses a complete brotli stream into a freshly allocated Vec.
+/// Truncated input yields Err(BROTLI_DECODER_NEEDS_MORE_INPUT); as with
+/// brotli_decode, bytes after the end of the stream are ignored.
+#[cfg(feature="alloc")]
+pub fn decompress_to_vec(input: &[u8]) -> Result<alloc_crate::vec::Vec<u8>, BrotliDecoderErrorCode> {
+  decompress_to_vec_with_progress(input, None, 0, 0)
+}
+
+/// decompress_to_vec, calling progress as described at
+/// BrotliDecoderSetProgressCallback.
+#[cfg(feature="alloc")]
+pub fn decompress_to_vec_with_progress(
+  input: &[u8],
+  progress: Option<BrotliDecoderProgressFunc>,
+  interval: usize,
+  opaque: usize,
+) -> Result<alloc_crate::vec::Vec<u8>, BrotliDecoderErrorCode> {
+  decompress_to_vec_with_options(input, progress, interval, opaque, None)
+}
+
+/// decompress_to_vec with a static dictionary supplied at runtime, for
+/// builds with the external-static-dictionary feature.
+#[cfg(feature="alloc")]
+pub fn decompress_to_vec_with_static_dictionary(
+  input: &[u8],
+  dictionary: &BrotliStaticDictionary,
+) -> Result<alloc_crate::vec::Vec<u8>, BrotliDecoderErrorCode> {
+  decompress_to_vec_with_options(input, None, 0, 0, Some(dictionary))
+}
+
+#[cfg(feature="alloc")]
+fn decompress_to_vec_with_options(
+  input: &[u8],
+  progress: Option<BrotliDecoderProgressFunc>,
+  interval: usize,
+  opaque: usize,
+  dictionary: Option<&BrotliStaticDictionary>,
+) -> Result<alloc_crate::vec::Vec<u8>, BrotliDecoderErrorCode> {
+  let mut output = alloc_crate::vec::Vec::<u8>::new();
+  let mut available_in: usize = input.len();
+  let mut input_offset: usize = 0;
+  let mut written: usize = 0;
+  let mut brotli_state = BrotliState::new(BoxAlloc, BoxAlloc, BoxAlloc);
+  BrotliDecoderSetProgressCallback(&mut brotli_state, progress, interval, opaque);
+  if let Some(dictionary) = dictionary {
+    BrotliDecoderSetStaticDictionary(&mut brotli_state, dictionary);
+  }
+  loop {
+    let mut output_offset = output.len();
+    let grow = core::cmp::max(core::cmp::max(output_offset, input.len()), 4096);
+    output.resize(output_offset + grow, 0);
+    let mut available_out = grow;
+    let result = decompress_with_progress(&mut available_in,
+                                          &mut input_offset,
+                                          input,
+                                          &mut available_out,
+                                          &mut output_offset,
+                                          &mut output,
+                                          &mut written,
+                                          &mut brotli_state);
+    output.truncate(output_offset);
+    match result {
+      BrotliResult::ResultSuccess => return Ok(output),
+      BrotliResult::NeedsMoreOutput => {},
+      BrotliResult::NeedsMoreInput => return Err(BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT),
+      BrotliResult::ResultFailure => return Err(brotli_state.error_code),
+    }
+  }
+}
@@ -0,0 +1,103 @@
+// Allocation accounting, for finding out how much memory a stream really
+// needs: wrap each of the decoder's three allocators in AccountingAlloc and
+// read the totals back once decoding is done.
+use core;
+use alloc::{Allocator, SliceWrapper};
+use huffman::HuffmanCode;
+use state::BrotliState;
+
+/// Totals for the cells handed out by one allocator. Sizes are in bytes,
+/// counting only the cells the decoder actually received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+  /// Bytes allocated and not yet freed.
+  pub current_bytes: usize,
+  /// Largest value current_bytes has reached.
+  pub peak_bytes: usize,
+  /// Number of cells allocated.
+  pub allocations: usize,
+}
+
+/// Per-category totals for a decoder built on AccountingAlloc.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemStats {
+  pub u8: AllocStats,
+  pub u32: AllocStats,
+  pub hc: AllocStats,
+}
+
+impl MemStats {
+  /// Sum of the three categories' peaks. The categories peak independently,
+  /// so this is an upper bound on the memory in use at any one time.
+  pub fn peak_bytes(&self) -> usize {
+    self.u8.peak_bytes + self.u32.peak_bytes + self.hc.peak_bytes
+  }
+}
+
+/// Wraps an allocator and keeps AllocStats for everything it hands out.
+/// Empty cells, which the decoder treats as allocation failures, are not
+/// counted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AccountingAlloc<Alloc> {
+  alloc: Alloc,
+  stats: AllocStats,
+}
+
+impl<Alloc> AccountingAlloc<Alloc> {
+  pub fn new(alloc: Alloc) -> Self {
+    AccountingAlloc { alloc, stats: AllocStats::default() }
+  }
+  pub fn stats(&self) -> AllocStats {
+    self.stats
+  }
+  /// Starts a new measurement: the peak drops to what is currently allocated
+  /// and the allocation count to zero. Useful when a decoder is reset and
+  /// reused for another stream.
+  pub fn reset_stats(&mut self) {
+    self.stats.peak_bytes = self.stats.current_bytes;
+    self.stats.allocations = 0;
+  }
+  pub fn get_ref(&self) -> &Alloc {
+    &self.alloc
+  }
+  pub fn into_inner(self) -> Alloc {
+    self.alloc
+  }
+}
+
+impl<T, Alloc: Allocator<T>> Allocator<T> for AccountingAlloc<Alloc> {
+  type AllocatedMemory = Alloc::AllocatedMemory;
+  fn alloc_cell(&mut self, len: usize) -> Alloc::AllocatedMemory {
+    let cell = self.alloc.alloc_cell(len);
+    let size = core::mem::size_of_val(cell.slice());
+    if size != 0 {
+      self.stats.current_bytes += size;
+      self.stats.peak_bytes = core::cmp::max(self.stats.peak_bytes, self.stats.current_bytes);
+      self.stats.allocations += 1;
+    }
+    cell
+  }
+  fn free_cell(&mut self, data: Alloc::AllocatedMemory) {
+    let size = core::mem::size_of_val(data.slice());
+    self.stats.current_bytes = self.stats.current_bytes.saturating_sub(size);
+    self.alloc.free_cell(data)
+  }
+}
+
+impl<AllocU8: Allocator<u8>, AllocU32: Allocator<u32>, AllocHC: Allocator<HuffmanCode>>
+  BrotliState<AccountingAlloc<AllocU8>, AccountingAlloc<AllocU32>, AccountingAlloc<AllocHC>> {
+  /// Allocation totals so far for each of the state's allocators.
+  pub fn mem_stats(&self) -> MemStats {
+    MemStats {
+      u8: self.alloc_u8.stats(),
+      u32: self.alloc_u32.stats(),
+      hc: self.alloc_hc.stats(),
+    }
+  }
+  /// Calls AccountingAlloc::reset_stats on all three allocators.
+  pub fn reset_mem_stats(&mut self) {
+    self.alloc_u8.reset_stats();
+    self.alloc_u32.reset_stats();
+    self.alloc_hc.reset_stats();
+  }
+}
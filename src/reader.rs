@@ -1,17 +1,33 @@
 #[cfg(feature="std")]
-use std::io::{self, Error, ErrorKind, Read};
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
 #[cfg(feature="std")]
 pub use alloc_stdlib::StandardAlloc;
 #[cfg(all(feature="unsafe",feature="std"))]
 pub use alloc_stdlib::HeapAlloc;
 pub use huffman::{HuffmanCode, HuffmanTreeGroup};
-pub use state::BrotliState;
+pub use state::{BrotliState, BrotliDecoderProgressFunc, SharedCustomDictionary};
 // use io_wrappers::write_all;
 pub use io_wrappers::{CustomRead, CustomWrite};
 #[cfg(feature="std")]
 pub use io_wrappers::{IntoIoReader, IoReaderWrapper, IoWriterWrapper};
-pub use super::decode::{BrotliDecompressStream, BrotliResult};
+pub use super::decode::{BrotliDecompressStream, BrotliDecompressStreamBudgeted, BrotliResult,
+                        BrotliBudgetedResult, BrotliWorkBudget};
+use decode::{BrotliStaticDictionary, DecompressStreamBudgeted};
+use shared_dictionary::BrotliSharedDictionaryType;
 pub use alloc::{AllocatedStackMemory, Allocator, SliceWrapper, SliceWrapperMut, StackAllocator};
+#[cfg(feature="alloc")]
+pub use heap_alloc::{BoxAlloc, BoxedSlice};
+pub use mem_stats::{AccountingAlloc, MemStats};
+
+// base + offset, or None when the result is negative or overflows.
+#[cfg(feature="std")]
+pub(crate) fn checked_add_signed(base: u64, offset: i64) -> Option<u64> {
+  if offset >= 0 {
+    base.checked_add(offset as u64)
+  } else {
+    base.checked_sub(offset.wrapping_neg() as u64)
+  }
+}
 
 #[cfg(feature="std")]
 pub struct DecompressorCustomAlloc<R: Read,
@@ -61,6 +77,21 @@ impl<R: Read,
                                                                                               "Invalid Data")))
     }
 
+    pub fn new_with_shared_dictionary(r: R, buffer : BufferType,
+               alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC,
+               dict: SharedCustomDictionary) -> Self {
+        DecompressorCustomAlloc::<R, BufferType, AllocU8, AllocU32, AllocHC>(
+          DecompressorCustomIo::<Error,
+                                 IntoIoReader<R>,
+                                 BufferType,
+                                 AllocU8, AllocU32, AllocHC>::new_with_shared_dictionary(IntoIoReader::<R>(r),
+                                                                                   buffer,
+                                                                                   alloc_u8, alloc_u32, alloc_hc,
+                                                                                   dict,
+                                                                                   Error::new(ErrorKind::InvalidData,
+                                                                                              "Invalid Data")))
+    }
+
     pub fn get_ref(&self) -> &R {
       &self.0.get_ref().0
     }
@@ -70,6 +101,38 @@ impl<R: Read,
     pub fn into_inner(self) -> R {
       self.0.into_inner().0
     }
+    pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+      self.0.set_work_budget(budget)
+    }
+    pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                                 interval: usize, opaque: usize) {
+      self.0.set_progress_callback(progress, interval, opaque)
+    }
+    pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+      self.0.set_static_dictionary(dictionary)
+    }
+    /// See DecompressorCustomIo::attach_dictionary.
+    pub fn attach_dictionary(&mut self, dict_type: BrotliSharedDictionaryType, data: &[u8]) -> bool {
+      self.0.attach_dictionary(dict_type, data)
+    }
+    pub fn skip(&mut self, amount: u64) -> io::Result<u64> {
+      self.0.skip(amount)
+    }
+}
+#[cfg(feature="std")]
+impl<R: Read,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > DecompressorCustomAlloc<R,
+                                                                BufferType,
+                                                                AccountingAlloc<AllocU8>,
+                                                                AccountingAlloc<AllocU32>,
+                                                                AccountingAlloc<AllocHC>> {
+    /// Memory the decoder has allocated so far, by category.
+    pub fn mem_stats(&self) -> MemStats {
+      self.0.mem_stats()
+    }
 }
 #[cfg(feature="std")]
 impl<R: Read,
@@ -85,6 +148,54 @@ impl<R: Read,
        self.0.read(buf)
     }
 }
+#[cfg(feature="std")]
+impl<R: Read + Seek,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > Seek for DecompressorCustomAlloc<R,
+                                                                         BufferType,
+                                                                         AllocU8,
+                                                                         AllocU32,
+                                                                         AllocHC> {
+  /// Seeks within the decompressed stream. Forward seeks decode and discard
+  /// up to the new position; backward ones rewind the inner reader to where
+  /// the stream started and decode again from there. SeekFrom::End decodes
+  /// the rest of the stream to learn its size. As with files, seeking past
+  /// the end is allowed and reads there return Ok(0).
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let target = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::Current(offset) => checked_add_signed(self.0.position, offset),
+      SeekFrom::End(offset) => {
+        self.0.skip(core::u64::MAX)?;
+        checked_add_signed(self.0.state.partial_pos_out as u64, offset)
+      }
+    };
+    let target = match target {
+      Some(target) => target,
+      None => return Err(Error::new(ErrorKind::InvalidInput,
+                                    "invalid seek to a negative or overflowing position")),
+    };
+    let mut decoded = self.0.state.partial_pos_out as u64;
+    if target < decoded {
+      if self.0.state.custom_dictionary().len() < self.0.state.custom_dict_size as usize {
+        return Err(Error::new(ErrorKind::Other,
+                              "cannot seek backwards once an owned custom dictionary is freed; \
+                               use a shared dictionary instead"));
+      }
+      let read = self.0.input_read;
+      self.get_mut().seek(SeekFrom::Current(-(read as i64)))?;
+      self.0.restart();
+      self.0.error_if_invalid_data = Some(Error::new(ErrorKind::InvalidData, "Invalid Data"));
+      decoded = 0;
+    }
+    self.0.position = decoded;
+    self.0.skip(target - decoded)?;
+    self.0.position = target;
+    Ok(target)
+  }
+}
 
 
 #[cfg(not(any(feature="unsafe", not(feature="std"))))]
@@ -117,6 +228,23 @@ impl<R: Read> Decompressor<R> {
                                                                               StandardAlloc::default(),
                                                                               dict))
   }
+  /// Decodes with a custom dictionary that is shared rather than copied,
+  /// such as an Arc<[u8]> used by many decompressors at once.
+  pub fn new_with_shared_dictionary(r: R, buffer_size: usize, dict: SharedCustomDictionary) -> Self {
+    let mut alloc = StandardAlloc::default();
+    let buffer = <StandardAlloc as Allocator<u8>>::alloc_cell(&mut alloc, if buffer_size == 0 {4096} else {buffer_size});
+    Decompressor::<R>(DecompressorCustomAlloc::<R,
+                                                <StandardAlloc
+                                                 as Allocator<u8>>::AllocatedMemory,
+                                                StandardAlloc,
+                                                StandardAlloc,
+                                                StandardAlloc>::new_with_shared_dictionary(r,
+                                                                              buffer,
+                                                                              alloc,
+                                                                              StandardAlloc::default(),
+                                                                              StandardAlloc::default(),
+                                                                              dict))
+  }
 
   pub fn get_ref(&self) -> &R {
     &self.0.get_ref()
@@ -127,6 +255,27 @@ impl<R: Read> Decompressor<R> {
   pub fn into_inner(self) -> R {
     self.0.into_inner()
   }
+  /// See DecompressorCustomIo::set_work_budget.
+  pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+    self.0.set_work_budget(budget)
+  }
+  /// See DecompressorCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
+  /// See DecompressorCustomIo::attach_dictionary.
+  pub fn attach_dictionary(&mut self, dict_type: BrotliSharedDictionaryType, data: &[u8]) -> bool {
+    self.0.attach_dictionary(dict_type, data)
+  }
+  /// See DecompressorCustomIo::skip.
+  pub fn skip(&mut self, amount: u64) -> Result<u64, Error> {
+    self.0.skip(amount)
+  }
 }
 
 
@@ -161,6 +310,23 @@ impl<R: Read> Decompressor<R> {
                                                 HeapAlloc<HuffmanCode> >
       ::new_with_custom_dictionary(r, buffer, alloc_u8, alloc_u32, alloc_hc, dict))
   }
+  /// Decodes with a custom dictionary that is shared rather than copied,
+  /// such as an Arc<[u8]> used by many decompressors at once.
+  pub fn new_with_shared_dictionary(r: R, buffer_size: usize, dict: SharedCustomDictionary) -> Self {
+    let mut alloc_u8 = HeapAlloc::<u8>::new(0);
+    let buffer = alloc_u8.alloc_cell(if buffer_size == 0 {4096} else {buffer_size});
+    let alloc_u32 = HeapAlloc::<u32>::new(0);
+    let alloc_hc = HeapAlloc::<HuffmanCode>::new(HuffmanCode{
+        bits:0, value: 0,
+    });
+    Decompressor::<R>(DecompressorCustomAlloc::<R,
+                                                <HeapAlloc<u8>
+                                                 as Allocator<u8>>::AllocatedMemory,
+                                                HeapAlloc<u8>,
+                                                HeapAlloc<u32>,
+                                                HeapAlloc<HuffmanCode> >
+      ::new_with_shared_dictionary(r, buffer, alloc_u8, alloc_u32, alloc_hc, dict))
+  }
 
   pub fn get_ref(&self) -> &R {
     self.0.get_ref()
@@ -171,6 +337,27 @@ impl<R: Read> Decompressor<R> {
   pub fn into_inner(self) -> R {
     self.0.into_inner()
   }
+  /// See DecompressorCustomIo::set_work_budget.
+  pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+    self.0.set_work_budget(budget)
+  }
+  /// See DecompressorCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
+  /// See DecompressorCustomIo::attach_dictionary.
+  pub fn attach_dictionary(&mut self, dict_type: BrotliSharedDictionaryType, data: &[u8]) -> bool {
+    self.0.attach_dictionary(dict_type, data)
+  }
+  /// See DecompressorCustomIo::skip.
+  pub fn skip(&mut self, amount: u64) -> Result<u64, Error> {
+    self.0.skip(amount)
+  }
 }
 
 
@@ -181,6 +368,74 @@ impl<R: Read> Read for Decompressor<R> {
   }
 }
 
+#[cfg(feature="std")]
+impl<R: Read> Decompressor<R> {
+  /// Decodes with a static dictionary supplied at runtime, for builds with
+  /// the external-static-dictionary feature.
+  pub fn new_with_static_dictionary(r: R, buffer_size: usize,
+                                    dictionary: &BrotliStaticDictionary) -> Self {
+    let mut reader = Self::new(r, buffer_size);
+    reader.set_static_dictionary(dictionary);
+    reader
+  }
+}
+
+/// See the Seek implementation of DecompressorCustomAlloc.
+#[cfg(feature="std")]
+impl<R: Read + Seek> Seek for Decompressor<R> {
+  fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+    self.0.seek(pos)
+  }
+}
+
+/// A Decompressor whose custom dictionary is borrowed for 'a, such as a
+/// memory map or a per-request buffer, and read in place: it is lent to the
+/// decoder on every read, as with BrotliDecompressStreamWithDictionary.
+#[cfg(feature="std")]
+pub struct DecompressorWithDictionary<'a, R: Read> {
+  decompressor: Decompressor<R>,
+  dictionary: &'a [u8],
+}
+
+#[cfg(feature="std")]
+impl<'a, R: Read> DecompressorWithDictionary<'a, R> {
+  pub fn new(r: R, buffer_size: usize, dictionary: &'a [u8]) -> Self {
+    DecompressorWithDictionary {
+      decompressor: Decompressor::new(r, buffer_size),
+      dictionary,
+    }
+  }
+  pub fn get_ref(&self) -> &R {
+    self.decompressor.get_ref()
+  }
+  pub fn get_mut(&mut self) -> &mut R {
+    self.decompressor.get_mut()
+  }
+  pub fn into_inner(self) -> R {
+    self.decompressor.into_inner()
+  }
+  /// See DecompressorCustomIo::set_work_budget.
+  pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+    self.decompressor.set_work_budget(budget)
+  }
+  /// See DecompressorCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.decompressor.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.decompressor.set_static_dictionary(dictionary)
+  }
+}
+
+#[cfg(feature="std")]
+impl<'a, R: Read> Read for DecompressorWithDictionary<'a, R> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+    (self.decompressor.0).0.read_with_dictionary(buf, self.dictionary)
+  }
+}
+
 pub struct DecompressorCustomIo<ErrType,
                                 R: CustomRead<ErrType>,
                                 BufferType: SliceWrapperMut<u8>,
@@ -195,7 +450,12 @@ pub struct DecompressorCustomIo<ErrType,
   input: R,
   error_if_invalid_data: Option<ErrType>,
   state: BrotliState<AllocU8, AllocU32, AllocHC>,
+  work_budget: BrotliWorkBudget,
   done: bool,
+  // Decoded position of the next read, which a seek may put past the end,
+  // and the number of bytes taken from input, for rewinding it.
+  position: u64,
+  input_read: u64,
 }
 
 impl<ErrType,
@@ -217,18 +477,39 @@ impl<ErrType,
                alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC,
                dict: AllocU8::AllocatedMemory,
                invalid_data_error_type : ErrType) -> Self {
+        let state = BrotliState::new_with_custom_dictionary(alloc_u8,
+                                                           alloc_u32,
+                                                           alloc_hc,
+                                                           dict);
+        Self::new_with_state(r, buffer, state, invalid_data_error_type)
+    }
+    /// Like new_with_custom_dictionary, but reads the dictionary in place
+    /// rather than taking an allocation of its own.
+    pub fn new_with_shared_dictionary(r: R, buffer : BufferType,
+               alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC,
+               dict: SharedCustomDictionary,
+               invalid_data_error_type : ErrType) -> Self {
+        let state = BrotliState::new_with_shared_dictionary(alloc_u8,
+                                                           alloc_u32,
+                                                           alloc_hc,
+                                                           dict);
+        Self::new_with_state(r, buffer, state, invalid_data_error_type)
+    }
+    fn new_with_state(r: R, buffer : BufferType,
+                      state: BrotliState<AllocU8, AllocU32, AllocHC>,
+                      invalid_data_error_type : ErrType) -> Self {
         DecompressorCustomIo::<ErrType, R, BufferType, AllocU8, AllocU32, AllocHC>{
             input_buffer : buffer,
             total_out : 0,
             input_offset : 0,
             input_len : 0,
             input: r,
-            state : BrotliState::new_with_custom_dictionary(alloc_u8,
-                                     alloc_u32,
-                                     alloc_hc,
-                                     dict),
+            state,
             error_if_invalid_data : Some(invalid_data_error_type),
+            work_budget: BrotliWorkBudget::default(),
             done: false,
+            position: 0,
+            input_read: 0,
         }
     }
 
@@ -248,13 +529,106 @@ impl<ErrType,
           input_len: _il,
           error_if_invalid_data:_eiid,
           input,
+          work_budget: _wb,
           done: _done,
+          position: _position,
+          input_read: _input_read,
         } =>{
           input
         }
     }
     }
 
+    /// Bounds the decoding work done by each read(), so that a large buf
+    /// cannot stall the caller. read() may then return fewer bytes than it
+    /// could have, but still only returns Ok(0) at the end of the stream.
+    pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+      self.work_budget = budget;
+    }
+
+    /// Calls progress(opaque, compressed bytes consumed, bytes decompressed)
+    /// as reading goes; see BrotliDecoderSetProgressCallback. Reports come at
+    /// most once per read(), so an interval below the read size acts like 0.
+    pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                                 interval: usize, opaque: usize) {
+      ::decode::BrotliDecoderSetProgressCallback(&mut self.state, progress, interval, opaque);
+    }
+
+    /// Supplies the static dictionary before the first byte is decoded; see
+    /// BrotliDecoderSetStaticDictionary. Needed only with the
+    /// external-static-dictionary feature.
+    pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+      ::decode::BrotliDecoderSetStaticDictionary(&mut self.state, dictionary)
+    }
+
+    /// Attaches a raw or serialized shared dictionary before the first byte
+    /// is decoded; see BrotliDecoderAttachDictionary.
+    pub fn attach_dictionary(&mut self, dict_type: BrotliSharedDictionaryType, data: &[u8]) -> bool {
+      ::decode::BrotliDecoderAttachDictionary(&mut self.state, dict_type, data)
+    }
+
+    /// Decodes and discards the next amount bytes of output, straight from
+    /// the decoder's ring buffer, and returns how many there were: fewer than
+    /// amount only if the stream ended first. Input is read only as far as
+    /// those bytes need.
+    pub fn skip(&mut self, amount: u64) -> Result<u64, ErrType> {
+      let mut skipped = 0u64;
+      while skipped < amount {
+        let mut size = core::cmp::min(amount - skipped, core::usize::MAX as u64) as usize;
+        ::decode::BrotliDecoderTakeOutput(&mut self.state, &mut size);
+        skipped += size as u64;
+        self.position += size as u64;
+        if size != 0 {
+          continue;
+        }
+        let mut avail_in = self.input_len - self.input_offset;
+        let mut avail_out = 0;
+        let mut output_offset = 0;
+        match BrotliDecompressStreamBudgeted(&mut avail_in,
+                                             &mut self.input_offset,
+                                             &self.input_buffer.slice_mut()[..],
+                                             &mut avail_out,
+                                             &mut output_offset,
+                                             &mut [],
+                                             &mut self.total_out,
+                                             &self.work_budget,
+                                             &mut self.state) {
+          BrotliBudgetedResult::NeedsMoreInput => {
+            if ::decode::BrotliDecoderHasMoreOutput(&self.state) {
+              continue;
+            }
+            self.copy_to_front();
+            match self.input.read(&mut self.input_buffer.slice_mut()[self.input_len..]) {
+              Err(e) => return Err(e),
+              Ok(0) => return self.error_if_invalid_data.take().map(|e| Err(e)).unwrap_or(Ok(skipped)),
+              Ok(size) => {
+                self.input_len += size;
+                self.input_read += size as u64;
+              }
+            }
+          }
+          BrotliBudgetedResult::NeedsMoreOutput | BrotliBudgetedResult::Yield => {}
+          BrotliBudgetedResult::ResultSuccess => break,
+          BrotliBudgetedResult::ResultFailure => {
+            return self.error_if_invalid_data.take().map(|e| Err(e)).unwrap_or(Ok(skipped));
+          }
+        }
+      }
+      Ok(skipped)
+    }
+
+    // Starts the stream over, for an input that has been rewound to where
+    // it was when this decompressor was made.
+    fn restart(&mut self) {
+      self.state.reset(false);
+      self.input_offset = 0;
+      self.input_len = 0;
+      self.total_out = 0;
+      self.done = false;
+      self.position = 0;
+      self.input_read = 0;
+    }
+
     pub fn copy_to_front(&mut self) {
         let avail_in = self.input_len - self.input_offset;
         if self.input_offset == self.input_buffer.slice_mut().len() {
@@ -269,6 +643,24 @@ impl<ErrType,
     }
 }
 
+impl<ErrType,
+     R: CustomRead<ErrType>,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > DecompressorCustomIo<ErrType,
+                                                             R,
+                                                             BufferType,
+                                                             AccountingAlloc<AllocU8>,
+                                                             AccountingAlloc<AllocU32>,
+                                                             AccountingAlloc<AllocHC>> {
+    /// Memory the decoder has allocated so far, by category. Once the stream
+    /// has been read to the end the peaks give the memory it needed.
+    pub fn mem_stats(&self) -> MemStats {
+      self.state.mem_stats()
+    }
+}
+
 impl<ErrType,
      R: CustomRead<ErrType>,
      BufferType : SliceWrapperMut<u8>,
@@ -297,19 +689,48 @@ impl<ErrType,
   /// non-brotli bytes in the buffer, then return an InvalidData error.
   /// Also upstream errors from the reader are returned.
   fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrType > {
+    let size = self.decode(buf, None)?;
+    self.position += size as u64;
+    Ok(size)
+  }
+}
+
+impl<ErrType,
+     R: CustomRead<ErrType>,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > DecompressorCustomIo<ErrType,
+                                                             R,
+                                                             BufferType,
+                                                             AllocU8,
+                                                             AllocU32,
+                                                             AllocHC> {
+  // read() for a decoder whose custom dictionary is lent on each call; see
+  // BrotliDecompressStreamWithDictionary.
+  #[cfg(feature="std")]
+  fn read_with_dictionary(&mut self, buf: &mut [u8], dictionary: &[u8]) -> Result<usize, ErrType> {
+    let size = self.decode(buf, Some(dictionary))?;
+    self.position += size as u64;
+    Ok(size)
+  }
+
+  fn decode(&mut self, buf: &mut [u8], dictionary: Option<&[u8]>) -> Result<usize, ErrType > {
     let mut output_offset : usize = 0;
     let mut avail_out = buf.len() - output_offset;
     let mut avail_in = self.input_len - self.input_offset;
     while avail_out == buf.len() {
-      match BrotliDecompressStream(&mut avail_in,
-                                   &mut self.input_offset,
-                                   &self.input_buffer.slice_mut()[..],
-                                   &mut avail_out,
-                                   &mut output_offset,
-                                   buf,
-                                   &mut self.total_out,
-                                   &mut self.state) {
-        BrotliResult::NeedsMoreInput => {
+      match DecompressStreamBudgeted(&mut avail_in,
+                                     &mut self.input_offset,
+                                     &self.input_buffer.slice_mut()[..],
+                                     &mut avail_out,
+                                     &mut output_offset,
+                                     buf,
+                                     &mut self.total_out,
+                                     &self.work_budget,
+                                     dictionary,
+                                     &mut self.state) {
+        BrotliBudgetedResult::NeedsMoreInput => {
           self.copy_to_front();
           if output_offset != 0 {
             // The decompressor successfully decoded some bytes, but still requires more
@@ -325,14 +746,15 @@ impl<ErrType,
               return self.error_if_invalid_data.take().map(|e| Err(e)).unwrap_or(Ok(0));
             }else {
               self.input_len += size;
+              self.input_read += size as u64;
               avail_in = self.input_len - self.input_offset;
             },
           }
         },
-        BrotliResult::NeedsMoreOutput => {
-          break;
-        },
-        BrotliResult::ResultSuccess => {
+        BrotliBudgetedResult::NeedsMoreOutput => break,
+        // after a yield, keeps going only if nothing has been produced yet
+        BrotliBudgetedResult::Yield => {},
+        BrotliBudgetedResult::ResultSuccess => {
             if output_offset == 0 {
                 if !self.done {
                     self.done = true;
@@ -343,13 +765,90 @@ impl<ErrType,
             }
             return Ok(output_offset);
         }
-        BrotliResult::ResultFailure => return self.error_if_invalid_data.take().map(|e| Err(e)).unwrap_or(Ok(0)),
+        BrotliBudgetedResult::ResultFailure => return self.error_if_invalid_data.take().map(|e| Err(e)).unwrap_or(Ok(0)),
       }
     }
     Ok(output_offset)
   }
 }
 
+/// Streaming decompressor over a CustomRead that allocates from the global
+/// heap through the alloc crate, for targets without std.
+#[cfg(feature="alloc")]
+pub struct DecompressorHeap<ErrType, R: CustomRead<ErrType>>(DecompressorCustomIo<ErrType,
+                                                                                  R,
+                                                                                  BoxedSlice<u8>,
+                                                                                  BoxAlloc,
+                                                                                  BoxAlloc,
+                                                                                  BoxAlloc>);
+
+#[cfg(feature="alloc")]
+impl<ErrType, R: CustomRead<ErrType>> DecompressorHeap<ErrType, R> {
+  pub fn new(r: R, buffer_size: usize, invalid_data_error_type: ErrType) -> Self {
+    Self::new_with_custom_dictionary(r, buffer_size, BoxedSlice::<u8>::default(), invalid_data_error_type)
+  }
+  pub fn new_with_custom_dictionary(r: R, buffer_size: usize, dict: BoxedSlice<u8>,
+                                    invalid_data_error_type: ErrType) -> Self {
+    let buffer = <BoxAlloc as Allocator<u8>>::alloc_cell(&mut BoxAlloc, if buffer_size == 0 {4096} else {buffer_size});
+    DecompressorHeap::<ErrType, R>(DecompressorCustomIo::new_with_custom_dictionary(r,
+                                                                                   buffer,
+                                                                                   BoxAlloc,
+                                                                                   BoxAlloc,
+                                                                                   BoxAlloc,
+                                                                                   dict,
+                                                                                   invalid_data_error_type))
+  }
+  pub fn new_with_shared_dictionary(r: R, buffer_size: usize, dict: SharedCustomDictionary,
+                                    invalid_data_error_type: ErrType) -> Self {
+    let buffer = <BoxAlloc as Allocator<u8>>::alloc_cell(&mut BoxAlloc, if buffer_size == 0 {4096} else {buffer_size});
+    DecompressorHeap::<ErrType, R>(DecompressorCustomIo::new_with_shared_dictionary(r,
+                                                                                   buffer,
+                                                                                   BoxAlloc,
+                                                                                   BoxAlloc,
+                                                                                   BoxAlloc,
+                                                                                   dict,
+                                                                                   invalid_data_error_type))
+  }
+
+  pub fn get_ref(&self) -> &R {
+    self.0.get_ref()
+  }
+  pub fn get_mut(&mut self) -> &mut R {
+    self.0.get_mut()
+  }
+  pub fn into_inner(self) -> R {
+    self.0.into_inner()
+  }
+  /// See DecompressorCustomIo::set_work_budget.
+  pub fn set_work_budget(&mut self, budget: BrotliWorkBudget) {
+    self.0.set_work_budget(budget)
+  }
+  /// See DecompressorCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
+  /// See DecompressorCustomIo::attach_dictionary.
+  pub fn attach_dictionary(&mut self, dict_type: BrotliSharedDictionaryType, data: &[u8]) -> bool {
+    self.0.attach_dictionary(dict_type, data)
+  }
+  /// See DecompressorCustomIo::skip.
+  pub fn skip(&mut self, amount: u64) -> Result<u64, ErrType> {
+    self.0.skip(amount)
+  }
+}
+
+#[cfg(feature="alloc")]
+impl<ErrType, R: CustomRead<ErrType>> CustomRead<ErrType> for DecompressorHeap<ErrType, R> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrType> {
+    self.0.read(buf)
+  }
+}
+
 #[cfg(feature="std")]
 #[test]
 fn test_no_vanishing_bytes() {
@@ -0,0 +1,763 @@
+#![cfg(feature="alloc")]
+// Random access into brotli streams. IndexBuilder makes one pass over a
+// stream, recording where its meta-blocks start and what a decoder needs to
+// start there; SeekableDecompressor then serves reads at any position by
+// decoding from the closest recorded point before it. SeekTable describes
+// streams built from independently decodable segments, with their offsets
+// stored in a metadata meta-block at the end of the stream itself.
+use core;
+#[cfg(feature="std")]
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+#[cfg(feature="std")]
+use reader::checked_add_signed;
+use alloc::{Allocator, SliceWrapper, SliceWrapperMut};
+use alloc_crate::vec::Vec;
+use heap_alloc::BoxAlloc;
+use huffman::HuffmanCode;
+use state::{BrotliState, BrotliDecoderErrorCode};
+use decode::{BrotliDecompressStream, BrotliResult, BrotliResumePoint, BrotliDecoderGetResumePoint,
+             BrotliDecoderGetWindow, BrotliDecoderResumeAt, ResumePointIsValid,
+             BrotliDecoderSetStopAtMetaBlockBoundaries,
+             BrotliDecoderStoppedAtMetaBlockBoundary};
+
+type BoxBrotliState = BrotliState<BoxAlloc, BoxAlloc, BoxAlloc>;
+
+fn new_state(custom_dict: &[u8]) -> BoxBrotliState {
+  if custom_dict.is_empty() {
+    return BrotliState::new(BoxAlloc, BoxAlloc, BoxAlloc);
+  }
+  let mut dict = <BoxAlloc as Allocator<u8>>::alloc_cell(&mut BoxAlloc, custom_dict.len());
+  dict.slice_mut().clone_from_slice(custom_dict);
+  BrotliState::new_with_custom_dictionary(BoxAlloc, BoxAlloc, BoxAlloc, dict)
+}
+
+/// A point a decoder can start from, with the window it needs there.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexEntry {
+  pub point: BrotliResumePoint,
+  /// What BrotliDecoderGetWindow returned at the point.
+  pub window: Vec<u8>,
+}
+
+/// Resume points for one brotli stream, in stream order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetaBlockIndex {
+  pub entries: Vec<IndexEntry>,
+  /// Size of the whole compressed stream.
+  pub compressed_size: u64,
+  /// Size of the stream once decoded.
+  pub decoded_size: u64,
+}
+
+const INDEX_MAGIC: &[u8; 4] = b"BRIX";
+const INDEX_VERSION: u8 = 1;
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+  if data.len() < 8 {
+    return None;
+  }
+  let (bytes, rest) = data.split_at(8);
+  *data = rest;
+  let mut value = [0u8; 8];
+  value.clone_from_slice(bytes);
+  Some(u64::from_le_bytes(value))
+}
+
+fn read_u8(data: &mut &[u8]) -> Option<u8> {
+  let (&value, rest) = data.split_first()?;
+  *data = rest;
+  Some(value)
+}
+
+// The number of leading items for which pred holds, given that it holds for
+// a prefix of the slice.
+fn partition_point<T, P: Fn(&T) -> bool>(items: &[T], pred: P) -> usize {
+  let (mut low, mut high) = (0, items.len());
+  while low < high {
+    let mid = low + (high - low) / 2;
+    if pred(&items[mid]) { low = mid + 1; } else { high = mid; }
+  }
+  low
+}
+
+impl MetaBlockIndex {
+  /// The last entry at or before the decoded position, if any.
+  pub fn entry_before(&self, position: u64) -> Option<&IndexEntry> {
+    let count = partition_point(&self.entries, |entry| entry.point.decoded_position <= position);
+    if count == 0 { None } else { Some(&self.entries[count - 1]) }
+  }
+
+  /// Encodes the index for storage. All integers are little endian: the
+  /// magic "BRIX", a version byte, the compressed and decoded sizes and the
+  /// entry count as u64s, then for each entry its bit and decoded positions
+  /// (u64), window bits and large window flag (u8), dist_rb (four u32s),
+  /// dist_rb_idx (u8) and the window length (u64) followed by the window.
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(INDEX_MAGIC);
+    data.push(INDEX_VERSION);
+    data.extend_from_slice(&self.compressed_size.to_le_bytes());
+    data.extend_from_slice(&self.decoded_size.to_le_bytes());
+    data.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+    for entry in self.entries.iter() {
+      let point = &entry.point;
+      data.extend_from_slice(&point.bit_position.to_le_bytes());
+      data.extend_from_slice(&point.decoded_position.to_le_bytes());
+      data.push(point.window_bits as u8);
+      data.push(point.large_window as u8);
+      for distance in point.dist_rb.iter() {
+        data.extend_from_slice(&(*distance as u32).to_le_bytes());
+      }
+      data.push(point.dist_rb_idx as u8);
+      data.extend_from_slice(&(entry.window.len() as u64).to_le_bytes());
+      data.extend_from_slice(&entry.window[..]);
+    }
+    data
+  }
+
+  /// Decodes what serialize produced; None if data is not such an index.
+  pub fn deserialize(mut data: &[u8]) -> Option<MetaBlockIndex> {
+    if data.len() < 5 || &data[..4] != INDEX_MAGIC || data[4] != INDEX_VERSION {
+      return None;
+    }
+    data = &data[5..];
+    let compressed_size = read_u64(&mut data)?;
+    let decoded_size = read_u64(&mut data)?;
+    let count = read_u64(&mut data)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+      let bit_position = read_u64(&mut data)?;
+      let decoded_position = read_u64(&mut data)?;
+      let window_bits = read_u8(&mut data)? as u32;
+      let large_window = match read_u8(&mut data)? {
+        0 => false,
+        1 => true,
+        _ => return None,
+      };
+      let mut dist_rb = [0i32; 4];
+      for distance in dist_rb.iter_mut() {
+        let bytes = data.get(..4)?;
+        *distance = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i32;
+        data = &data[4..];
+      }
+      let dist_rb_idx = read_u8(&mut data)? as i32;
+      let window_len = read_u64(&mut data)?;
+      if window_len > data.len() as u64 || dist_rb_idx > 3 {
+        return None;
+      }
+      let (window, rest) = data.split_at(window_len as usize);
+      data = rest;
+      let point = BrotliResumePoint {
+        bit_position,
+        decoded_position,
+        window_bits,
+        large_window,
+        dist_rb,
+        dist_rb_idx,
+      };
+      // indexes are built without compound dictionaries
+      if !ResumePointIsValid(&point, 0) {
+        return None;
+      }
+      entries.push(IndexEntry {
+        point,
+        window: window.to_vec(),
+      });
+    }
+    if !data.is_empty() {
+      return None;
+    }
+    Some(MetaBlockIndex {
+      entries,
+      compressed_size,
+      decoded_size,
+    })
+  }
+
+  /// Runs a whole stream through an IndexBuilder.
+  #[cfg(feature="std")]
+  pub fn build<R: Read>(input: &mut R, spacing: u64) -> io::Result<MetaBlockIndex> {
+    MetaBlockIndex::build_with_custom_dictionary(input, spacing, &[])
+  }
+
+  #[cfg(feature="std")]
+  pub fn build_with_custom_dictionary<R: Read>(input: &mut R,
+                                               spacing: u64,
+                                               dict: &[u8])
+                                               -> io::Result<MetaBlockIndex> {
+    let mut builder = IndexBuilder::new_with_custom_dictionary(spacing, dict);
+    let mut buffer = alloc_crate::vec![0u8; 65536];
+    while !builder.is_finished() {
+      let size = input.read(&mut buffer[..])?;
+      if size == 0 {
+        break;
+      }
+      builder.write(&buffer[..size])
+             .map_err(|code| Error::new(ErrorKind::InvalidData, format!("{:?}", code)))?;
+    }
+    builder.finish().map_err(|code| match code {
+      BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT => {
+        Error::new(ErrorKind::UnexpectedEof, "Unexpected EOF")
+      }
+      code => Error::new(ErrorKind::InvalidData, format!("{:?}", code)),
+    })
+  }
+}
+
+/// Decodes a stream handed to it in pieces, recording a resume point at
+/// the first meta-block boundary at least spacing decoded bytes past the
+/// previous one (every boundary for 0). Each point keeps a copy of the
+/// window, which can be as large as the stream's window size, so spacing
+/// sets the size of the index.
+pub struct IndexBuilder {
+  state: BoxBrotliState,
+  spacing: u64,
+  scratch: Vec<u8>,
+  index: MetaBlockIndex,
+  done: bool,
+}
+
+impl IndexBuilder {
+  pub fn new(spacing: u64) -> Self {
+    IndexBuilder::new_with_custom_dictionary(spacing, &[])
+  }
+
+  /// For streams compressed with a custom dictionary, which must be passed
+  /// again to SeekableDecompressor.
+  pub fn new_with_custom_dictionary(spacing: u64, dict: &[u8]) -> Self {
+    let mut state = new_state(dict);
+    BrotliDecoderSetStopAtMetaBlockBoundaries(&mut state, true);
+    IndexBuilder {
+      state,
+      spacing,
+      scratch: alloc_crate::vec![0u8; 65536],
+      index: MetaBlockIndex::default(),
+      done: false,
+    }
+  }
+
+  /// Takes the next part of the stream and returns how much of it was used:
+  /// all of it, unless the stream ended part way through.
+  pub fn write(&mut self, input: &[u8]) -> Result<usize, BrotliDecoderErrorCode> {
+    let mut available_in = input.len();
+    let mut input_offset = 0;
+    while !self.done {
+      let mut available_out = self.scratch.len();
+      let mut output_offset = 0;
+      let mut written = 0;
+      match BrotliDecompressStream(&mut available_in, &mut input_offset, input,
+                                   &mut available_out, &mut output_offset,
+                                   &mut self.scratch[..], &mut written, &mut self.state) {
+        BrotliResult::NeedsMoreOutput => if BrotliDecoderStoppedAtMetaBlockBoundary(&self.state) {
+          self.record_boundary();
+        },
+        BrotliResult::NeedsMoreInput => break,
+        BrotliResult::ResultSuccess => {
+          self.done = true;
+          self.index.compressed_size = self.state.progress_total_in as u64;
+          self.index.decoded_size = self.state.partial_pos_out as u64;
+        }
+        BrotliResult::ResultFailure => return Err(self.state.error_code),
+      }
+    }
+    Ok(input_offset)
+  }
+
+  fn record_boundary(&mut self) {
+    let point = match BrotliDecoderGetResumePoint(&self.state) {
+      Some(point) => point,
+      None => return,
+    };
+    let last = self.index.entries.last().map_or(0, |entry| entry.point.decoded_position);
+    if point.decoded_position <= last || point.decoded_position - last < self.spacing {
+      return;
+    }
+    let (window_start, window_end) = BrotliDecoderGetWindow(&self.state);
+    let mut window = Vec::with_capacity(window_start.len() + window_end.len());
+    window.extend_from_slice(window_start);
+    window.extend_from_slice(window_end);
+    self.index.entries.push(IndexEntry { point, window });
+  }
+
+  /// True once the end of the stream has been seen.
+  pub fn is_finished(&self) -> bool {
+    self.done
+  }
+
+  /// The index, or BROTLI_DECODER_NEEDS_MORE_INPUT if the stream is
+  /// incomplete.
+  pub fn finish(self) -> Result<MetaBlockIndex, BrotliDecoderErrorCode> {
+    if !self.done {
+      return Err(BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT);
+    }
+    Ok(self.index)
+  }
+}
+
+/// Read + Seek over the decoded contents of a brotli stream. Each read at a
+/// new position restarts decoding from the closest index entry at or before
+/// it, or continues the current decode if that is closer.
+#[cfg(feature="std")]
+pub struct SeekableDecompressor<R: Read + Seek> {
+  input: R,
+  index: MetaBlockIndex,
+  custom_dict: Vec<u8>,
+  state: Option<BoxBrotliState>,
+  input_buffer: Vec<u8>,
+  input_offset: usize,
+  input_len: usize,
+  // Decoded position the decoder has reached, and the one to read from.
+  decoded: u64,
+  position: u64,
+  done: bool,
+}
+
+#[cfg(feature="std")]
+impl<R: Read + Seek> SeekableDecompressor<R> {
+  pub fn new(input: R, index: MetaBlockIndex) -> Self {
+    SeekableDecompressor::new_with_custom_dictionary(input, index, &[])
+  }
+
+  /// For streams in the seekable format: reads the seek table at the end of
+  /// input and starts each decode at the closest segment.
+  pub fn from_seek_table(mut input: R) -> io::Result<Self> {
+    let table = SeekTable::read(&mut input)?;
+    Ok(SeekableDecompressor::new(input, table.to_index()))
+  }
+
+  pub fn new_with_custom_dictionary(input: R, index: MetaBlockIndex, dict: &[u8]) -> Self {
+    SeekableDecompressor {
+      input,
+      index,
+      custom_dict: dict.to_vec(),
+      state: None,
+      input_buffer: alloc_crate::vec![0u8; 65536],
+      input_offset: 0,
+      input_len: 0,
+      decoded: 0,
+      position: 0,
+      done: false,
+    }
+  }
+
+  pub fn index(&self) -> &MetaBlockIndex {
+    &self.index
+  }
+  pub fn get_ref(&self) -> &R {
+    &self.input
+  }
+  pub fn into_inner(self) -> R {
+    self.input
+  }
+
+  // Starts a new decoder at the entry closest to position, unless the
+  // current one will get there sooner.
+  fn restart_for(&mut self, position: u64) -> io::Result<()> {
+    let entry = self.index.entry_before(position);
+    let entry_position = entry.map_or(0, |entry| entry.point.decoded_position);
+    if self.state.is_some() && self.decoded <= position && self.decoded >= entry_position {
+      return Ok(());
+    }
+    let (state, byte_position) = match entry {
+      Some(entry) => {
+        let mut state = BrotliState::new(BoxAlloc, BoxAlloc, BoxAlloc);
+        if !BrotliDecoderResumeAt(&mut state, &entry.point, &entry.window[..]) {
+          return Err(Error::new(ErrorKind::InvalidData, "Invalid index entry"));
+        }
+        (state, entry.point.bit_position >> 3)
+      }
+      None => (new_state(&self.custom_dict[..]), 0),
+    };
+    self.state = None;
+    self.input.seek(SeekFrom::Start(byte_position))?;
+    self.state = Some(state);
+    self.input_offset = 0;
+    self.input_len = 0;
+    self.decoded = entry_position;
+    self.done = false;
+    Ok(())
+  }
+
+  // Decodes into output, returning 0 only at the end of the stream.
+  fn decode(&mut self, output: &mut [u8]) -> io::Result<usize> {
+    let state = match self.state {
+      Some(ref mut state) => state,
+      None => return Ok(0),
+    };
+    loop {
+      if self.done || output.is_empty() {
+        return Ok(0);
+      }
+      let mut available_in = self.input_len - self.input_offset;
+      let mut available_out = output.len();
+      let mut output_offset = 0;
+      let mut written = 0;
+      let result = BrotliDecompressStream(&mut available_in, &mut self.input_offset,
+                                          &self.input_buffer[..], &mut available_out,
+                                          &mut output_offset, output, &mut written, state);
+      self.decoded += output_offset as u64;
+      match result {
+        BrotliResult::ResultSuccess => self.done = true,
+        BrotliResult::NeedsMoreInput => {
+          self.input_offset = 0;
+          self.input_len = self.input.read(&mut self.input_buffer[..])?;
+          if self.input_len == 0 && output_offset == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected EOF"));
+          }
+        }
+        BrotliResult::NeedsMoreOutput => {}
+        BrotliResult::ResultFailure => {
+          return Err(Error::new(ErrorKind::InvalidData, "Invalid Data"));
+        }
+      }
+      if output_offset != 0 {
+        return Ok(output_offset);
+      }
+    }
+  }
+}
+
+#[cfg(feature="std")]
+impl<R: Read + Seek> Read for SeekableDecompressor<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.state.is_none() || self.decoded != self.position {
+      self.restart_for(self.position)?;
+      let mut scratch = [0u8; 4096];
+      while self.decoded < self.position {
+        let skip = core::cmp::min(self.position - self.decoded, scratch.len() as u64);
+        if self.decode(&mut scratch[..skip as usize])? == 0 {
+          // past the end of the stream
+          return Ok(0);
+        }
+      }
+    }
+    let size = self.decode(buf)?;
+    self.position += size as u64;
+    Ok(size)
+  }
+}
+
+#[cfg(feature="std")]
+impl<R: Read + Seek> Seek for SeekableDecompressor<R> {
+  /// Seeking only moves the read position; decoding restarts, if it needs
+  /// to, on the next read. SeekFrom::End is relative to the decoded size
+  /// recorded in the index.
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(position) => {
+        self.position = position;
+        return Ok(position);
+      }
+      SeekFrom::Current(offset) => (self.position, offset),
+      SeekFrom::End(offset) => (self.index.decoded_size, offset),
+    };
+    match checked_add_signed(base, offset) {
+      Some(position) => {
+        self.position = position;
+        Ok(position)
+      }
+      None => Err(Error::new(ErrorKind::InvalidInput,
+                             "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+/// Where one segment of a seekable stream starts, in the stream and in its
+/// decoded contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Segment {
+  pub compressed_offset: u64,
+  pub decompressed_offset: u64,
+}
+
+/// The segment table of a stream in the seekable format.
+///
+/// A seekable stream is a run of segments, each made of whole meta-blocks
+/// and ending on a byte boundary (after an uncompressed or metadata
+/// meta-block, e.g. an empty metadata block written as a flush), followed
+/// by the trailer: a metadata meta-block holding the table and an empty
+/// last meta-block. Every segment after the first must decode the same
+/// when started with no history: no backward references, distance codes
+/// or literal contexts reaching into earlier segments. Ordinary decoders
+/// skip the trailer, so the whole is still a valid brotli stream.
+///
+/// The metadata block contains, little endian, the compressed and
+/// decompressed offset of each segment as u64s, then the decoded size
+/// (u64), the segment count (u32), the window bits and a flags byte (bit 0:
+/// large window), and the magic "BRSK".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeekTable {
+  pub segments: Vec<Segment>,
+  /// Window bits from the stream header.
+  pub window_bits: u32,
+  pub large_window: bool,
+  /// Size of the segments, which is where the trailer starts.
+  pub compressed_size: u64,
+  pub decoded_size: u64,
+}
+
+const SEEK_TABLE_MAGIC: &[u8; 4] = b"BRSK";
+const SEEK_TABLE_FOOTER_LEN: usize = 18;
+const SEEK_TABLE_ENTRY_LEN: usize = 16;
+// ISLAST and ISLASTEMPTY set: the empty meta-block that ends the stream.
+const LAST_EMPTY_META_BLOCK: u8 = 3;
+// A metadata block's length has at most three bytes.
+const MAX_METADATA_LEN: usize = 1 << 24;
+
+// Header of a metadata meta-block of len bytes: ISLAST 0, MNIBBLES 0 (coded
+// as 3), the reserved bit, MSKIPBYTES and MSKIPLEN - 1, padded to a byte.
+fn metadata_header(len: usize) -> Vec<u8> {
+  let skip_len = len - 1;
+  let mut skip_bytes = 1;
+  while skip_bytes < 3 && skip_len >> (8 * skip_bytes) != 0 {
+    skip_bytes += 1;
+  }
+  let bits = 0b110 | (skip_bytes << 4) as u64 | (skip_len as u64) << 6;
+  bits.to_le_bytes()[..skip_bytes + 1].to_vec()
+}
+
+impl SeekTable {
+  /// The segment containing a decoded position: the last one starting at or
+  /// before it.
+  pub fn segment_for(&self, position: u64) -> usize {
+    let count = partition_point(&self.segments, |segment| segment.decompressed_offset <= position);
+    count.saturating_sub(1)
+  }
+
+  /// The index SeekableDecompressor needs to start at each segment.
+  pub fn to_index(&self) -> MetaBlockIndex {
+    let entries = self.segments.iter().skip(1).map(|segment| IndexEntry {
+      point: BrotliResumePoint {
+        bit_position: segment.compressed_offset << 3,
+        decoded_position: segment.decompressed_offset,
+        window_bits: self.window_bits,
+        large_window: self.large_window,
+        dist_rb: [16, 15, 11, 4],
+        dist_rb_idx: 0,
+      },
+      window: Vec::new(),
+    }).collect();
+    MetaBlockIndex {
+      entries,
+      compressed_size: self.compressed_size,
+      decoded_size: self.decoded_size,
+    }
+  }
+
+  /// The bytes to append to the segments to finish the stream, or None if
+  /// the table is too large for a metadata block (about a million
+  /// segments).
+  pub fn trailer(&self) -> Option<Vec<u8>> {
+    let len = self.segments.len() * SEEK_TABLE_ENTRY_LEN + SEEK_TABLE_FOOTER_LEN;
+    if len > MAX_METADATA_LEN {
+      return None;
+    }
+    let mut trailer = metadata_header(len);
+    for segment in self.segments.iter() {
+      trailer.extend_from_slice(&segment.compressed_offset.to_le_bytes());
+      trailer.extend_from_slice(&segment.decompressed_offset.to_le_bytes());
+    }
+    trailer.extend_from_slice(&self.decoded_size.to_le_bytes());
+    trailer.extend_from_slice(&(self.segments.len() as u32).to_le_bytes());
+    trailer.push(self.window_bits as u8);
+    trailer.push(self.large_window as u8);
+    trailer.extend_from_slice(SEEK_TABLE_MAGIC);
+    trailer.push(LAST_EMPTY_META_BLOCK);
+    Some(trailer)
+  }
+
+  /// Reads the table from a whole seekable stream; None if it does not end
+  /// in a well-formed trailer.
+  pub fn from_stream(data: &[u8]) -> Option<SeekTable> {
+    let tail_len = SEEK_TABLE_FOOTER_LEN + 1;
+    if data.len() < tail_len {
+      return None;
+    }
+    let trailer_len = SeekTable::trailer_len(&data[data.len() - tail_len..])?;
+    if trailer_len > data.len() {
+      return None;
+    }
+    SeekTable::parse_trailer(&data[data.len() - trailer_len..], data.len() as u64)
+  }
+
+  /// Reads the table from the end of a seekable stream.
+  #[cfg(feature="std")]
+  pub fn read<R: Read + Seek>(input: &mut R) -> io::Result<SeekTable> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid seek table");
+    let size = input.seek(SeekFrom::End(0))?;
+    let mut tail = [0u8; SEEK_TABLE_FOOTER_LEN + 1];
+    if size < tail.len() as u64 {
+      return Err(invalid());
+    }
+    input.seek(SeekFrom::End(-(tail.len() as i64)))?;
+    input.read_exact(&mut tail[..])?;
+    let trailer_len = SeekTable::trailer_len(&tail[..]).ok_or_else(invalid)?;
+    if trailer_len as u64 > size {
+      return Err(invalid());
+    }
+    let mut trailer = alloc_crate::vec![0u8; trailer_len];
+    input.seek(SeekFrom::End(-(trailer_len as i64)))?;
+    input.read_exact(&mut trailer[..])?;
+    SeekTable::parse_trailer(&trailer[..], size).ok_or_else(invalid)
+  }
+
+  // Length of the whole trailer, from its last SEEK_TABLE_FOOTER_LEN + 1
+  // bytes.
+  fn trailer_len(tail: &[u8]) -> Option<usize> {
+    if tail[tail.len() - 1] != LAST_EMPTY_META_BLOCK ||
+       &tail[tail.len() - 5..tail.len() - 1] != SEEK_TABLE_MAGIC {
+      return None;
+    }
+    let count = u32::from_le_bytes([tail[8], tail[9], tail[10], tail[11]]) as usize;
+    let len = count.checked_mul(SEEK_TABLE_ENTRY_LEN)? + SEEK_TABLE_FOOTER_LEN;
+    if len > MAX_METADATA_LEN {
+      return None;
+    }
+    Some(metadata_header(len).len() + len + 1)
+  }
+
+  fn parse_trailer(trailer: &[u8], stream_size: u64) -> Option<SeekTable> {
+    let len = trailer.len() - 1;
+    let count = (len - SEEK_TABLE_FOOTER_LEN) / SEEK_TABLE_ENTRY_LEN;
+    let header = metadata_header(count * SEEK_TABLE_ENTRY_LEN + SEEK_TABLE_FOOTER_LEN);
+    if trailer[..header.len()] != header[..] {
+      return None;
+    }
+    let mut data = &trailer[header.len()..len];
+    let mut segments = Vec::with_capacity(count);
+    for _ in 0..count {
+      let compressed_offset = read_u64(&mut data)?;
+      let decompressed_offset = read_u64(&mut data)?;
+      segments.push(Segment { compressed_offset, decompressed_offset });
+    }
+    let decoded_size = read_u64(&mut data)?;
+    let window_bits = data[4] as u32;
+    let large_window = match data[5] {
+      0 => false,
+      1 => true,
+      _ => return None,
+    };
+    let table = SeekTable {
+      segments,
+      window_bits,
+      large_window,
+      compressed_size: stream_size - trailer.len() as u64,
+      decoded_size,
+    };
+    let mut end = Segment { compressed_offset: 0, decompressed_offset: 0 };
+    for (i, segment) in table.segments.iter().enumerate() {
+      if (i == 0 && *segment != end) ||
+         (i != 0 && (segment.compressed_offset <= end.compressed_offset ||
+                     segment.decompressed_offset < end.decompressed_offset)) {
+        return None;
+      }
+      end = *segment;
+    }
+    if table.segments.is_empty() || end.compressed_offset >= table.compressed_size ||
+       end.decompressed_offset > decoded_size {
+      return None;
+    }
+    Some(table)
+  }
+
+  /// Checks that the stream matches the table: every segment must end at a
+  /// meta-block boundary with the recorded decoded size, decode the same on
+  /// its own as it does following the segments before it, and the trailer
+  /// must end the stream. This decodes the whole stream twice.
+  #[cfg(feature="std")]
+  pub fn validate<R: Read + Seek>(&self, input: &mut R) -> io::Result<()> {
+    let mismatch = || Error::new(ErrorKind::InvalidData, "Seek table does not match the stream");
+    let mut sequential = new_state(&[]);
+    BrotliDecoderSetStopAtMetaBlockBoundaries(&mut sequential, true);
+    let index = self.to_index();
+    let mut segment_data = Vec::new();
+    let mut sequential_output = Vec::new();
+    let mut independent_output = Vec::new();
+    for (i, segment) in self.segments.iter().enumerate() {
+      let end = self.segments.get(i + 1).map_or(
+        Segment { compressed_offset: self.compressed_size, decompressed_offset: self.decoded_size },
+        |next| *next);
+      let size = end.compressed_offset.checked_sub(segment.compressed_offset).ok_or_else(mismatch)?;
+      segment_data.resize(size as usize, 0);
+      input.seek(SeekFrom::Start(segment.compressed_offset))?;
+      input.read_exact(&mut segment_data[..])?;
+      sequential_output.clear();
+      if !self.decode_segment(&mut sequential, &segment_data[..], end, &mut sequential_output) {
+        return Err(mismatch());
+      }
+      if i != 0 {
+        let mut independent = BrotliState::new(BoxAlloc, BoxAlloc, BoxAlloc);
+        if !BrotliDecoderResumeAt(&mut independent, &index.entries[i - 1].point, &[]) {
+          return Err(mismatch());
+        }
+        BrotliDecoderSetStopAtMetaBlockBoundaries(&mut independent, true);
+        independent_output.clear();
+        if !self.decode_segment(&mut independent, &segment_data[..], end, &mut independent_output) ||
+           independent_output != sequential_output {
+          return Err(mismatch());
+        }
+      }
+    }
+    segment_data.clear();
+    input.seek(SeekFrom::Start(self.compressed_size))?;
+    input.read_to_end(&mut segment_data)?;
+    BrotliDecoderSetStopAtMetaBlockBoundaries(&mut sequential, false);
+    let mut available_in = segment_data.len();
+    let mut input_offset = 0;
+    let mut available_out = 0;
+    let mut output_offset = 0;
+    let mut written = 0;
+    match BrotliDecompressStream(&mut available_in, &mut input_offset, &segment_data[..],
+                                 &mut available_out, &mut output_offset, &mut [],
+                                 &mut written, &mut sequential) {
+      BrotliResult::ResultSuccess if available_in == 0 => Ok(()),
+      _ => Err(mismatch()),
+    }
+  }
+
+  // Decodes one segment's bytes into output, checking that they end at a
+  // meta-block boundary at end.
+  #[cfg(feature="std")]
+  fn decode_segment(&self,
+                    state: &mut BoxBrotliState,
+                    input: &[u8],
+                    end: Segment,
+                    output: &mut Vec<u8>)
+                    -> bool {
+    let mut scratch = [0u8; 4096];
+    let mut available_in = input.len();
+    let mut input_offset = 0;
+    let mut at_end = false;
+    loop {
+      let mut available_out = scratch.len();
+      let mut output_offset = 0;
+      let mut written = 0;
+      let result = BrotliDecompressStream(&mut available_in, &mut input_offset, input,
+                                          &mut available_out, &mut output_offset,
+                                          &mut scratch[..], &mut written, state);
+      output.extend_from_slice(&scratch[..output_offset]);
+      match result {
+        BrotliResult::NeedsMoreOutput if !BrotliDecoderStoppedAtMetaBlockBoundary(state) => {}
+        BrotliResult::NeedsMoreOutput => {
+          let point = match BrotliDecoderGetResumePoint(state) {
+            Some(point) => point,
+            None => return false,
+          };
+          if point.bit_position == end.compressed_offset << 3 {
+            if point.decoded_position != end.decompressed_offset ||
+               point.window_bits != self.window_bits ||
+               point.large_window != self.large_window {
+              return false;
+            }
+            at_end = true;
+          }
+        }
+        // Output can stay in the ring buffer past a boundary; asking for
+        // more input writes it out, as much as fits each time.
+        BrotliResult::NeedsMoreInput if at_end && available_out == 0 => {}
+        BrotliResult::NeedsMoreInput => return at_end,
+        _ => return false,
+      }
+    }
+  }
+}
@@ -0,0 +1,415 @@
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+
+use dictionary::{kBrotliDictionaryOffsetsByLength,
+                 kBrotliDictionarySizeBitsByLength};
+use state::BrotliDecoderErrorCode;
+use transform::{TransformDictionaryWord, TransformDictionaryWordWith, kNumTransforms,
+                kNumTransformTypes, kShiftAll, kShiftFirst};
+
+// Dictionaries that can be attached to a decoder before the first byte of
+// input, mirroring shared_dictionary.h of the reference implementation.
+
+// Maximum number of LZ77 prefixes ("compound dictionaries") a decoder accepts.
+pub const SHARED_BROTLI_MAX_COMPOUND_DICTS: usize = 15;
+// Number of slots in the context map of a serialized dictionary.
+pub const SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS: usize = 64;
+// Word lengths a custom word list can hold.
+pub const SHARED_BROTLI_MIN_DICTIONARY_WORD_LENGTH: usize = 4;
+pub const SHARED_BROTLI_MAX_DICTIONARY_WORD_LENGTH: usize = 31;
+
+const kSharedDictionaryMagic: [u8; 2] = [0x91, 0x00];
+// The LZ77 prefix of a serialized dictionary is limited to 1 GiB.
+const kMaxPrefixLength: u32 = (1 << 30) - 1;
+// A word list has up to 2^15 words of each length.
+const kMaxWordListSizeBits: u8 = 15;
+// Stringlets of a transform list, counting the empty one that ends them.
+const kMaxStringlets: usize = 256;
+// Entries of the u32 tables filled by FillSharedDictionaryTables for each
+// word list (offsets then size bits, indexed by word length) and each
+// transform list (transforms, their count and parameters or u32::MAX,
+// followed by the offsets of the stringlets).
+const kWordListTableSize: usize = 64;
+const kTransformListTableSize: usize = 3 + kMaxStringlets;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrotliSharedDictionaryType {
+  // Raw LZ77 prefix dictionary.
+  BROTLI_SHARED_DICTIONARY_RAW = 0,
+  // Serialized shared dictionary.
+  BROTLI_SHARED_DICTIONARY_SERIALIZED = 1,
+}
+
+fn ReadByte(encoded: &[u8], pos: &mut usize) -> Option<u8> {
+  let byte = *encoded.get(*pos)?;
+  *pos += 1;
+  Some(byte)
+}
+
+fn ReadUint16(encoded: &[u8], pos: &mut usize) -> Option<u16> {
+  let lo = ReadByte(encoded, pos)?;
+  let hi = ReadByte(encoded, pos)?;
+  Some(u16::from(lo) | (u16::from(hi) << 8))
+}
+
+// Little-endian base-128 varint that fits in 32 bits.
+fn ReadVarint32(encoded: &[u8], pos: &mut usize) -> Option<u32> {
+  let mut result = 0u32;
+  let mut shift = 0u32;
+  loop {
+    let byte = ReadByte(encoded, pos)?;
+    if shift == 28 && byte > 15 {
+      return None;
+    }
+    result |= u32::from(byte & 127) << shift;
+    if byte & 128 == 0 {
+      return Some(result);
+    }
+    shift += 7;
+  }
+}
+
+// How the word lists and transform lists of a serialized dictionary are
+// combined. A word or transform index equal to the number of lists stands
+// for the built-in words or transforms.
+#[derive(Clone, Copy)]
+pub struct SharedDictionaryLayout {
+  pub num_word_lists: u8,
+  pub num_transform_lists: u8,
+  // Zero when no dictionary is attached.
+  pub num_dictionaries: u8,
+  // Word list and transform list of each dictionary.
+  pub dictionaries: [[u8; 2]; SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS],
+  pub context_based: bool,
+  pub context_map: [u8; SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS],
+  // Upper bound of the prefix plus suffix a custom transform adds to a word.
+  pub max_affix_len: usize,
+}
+
+impl Default for SharedDictionaryLayout {
+  fn default() -> Self {
+    SharedDictionaryLayout {
+      num_word_lists: 0,
+      num_transform_lists: 0,
+      num_dictionaries: 0,
+      dictionaries: [[0; 2]; SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS],
+      context_based: false,
+      context_map: [0; SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS],
+      max_affix_len: 0,
+    }
+  }
+}
+
+impl SharedDictionaryLayout {
+  // True when every dictionary is the built-in one, so that static
+  // dictionary references decode as if no dictionary was attached.
+  pub fn IsBuiltIn(&self) -> bool {
+    self.dictionaries[..self.num_dictionaries as usize].iter().all(
+      |d| d[0] == self.num_word_lists && d[1] == self.num_transform_lists)
+  }
+  // Number of u32 entries FillSharedDictionaryTables needs.
+  pub fn TablesSize(&self) -> usize {
+    self.num_word_lists as usize * kWordListTableSize +
+      self.num_transform_lists as usize * kTransformListTableSize
+  }
+}
+
+// A serialized shared dictionary, borrowing from its encoded form.
+pub struct SharedDictionary<'a> {
+  // LZ77 prefix, possibly empty.
+  pub prefix: &'a [u8],
+  // The encoded word lists and transform lists; the offsets in the tables
+  // filled by FillSharedDictionaryTables are relative to its start.
+  pub lists: &'a [u8],
+  pub layout: SharedDictionaryLayout,
+}
+
+// Walks the word lists and transform lists at the start of lists, checking
+// them and filling tables when given. Returns the number of bytes they take.
+fn ParseLists(lists: &[u8],
+              layout: &mut SharedDictionaryLayout,
+              mut tables: Option<&mut [u32]>)
+              -> Option<usize> {
+  let mut pos = 0usize;
+  layout.num_word_lists = ReadByte(lists, &mut pos)?;
+  for list in 0..layout.num_word_lists as usize {
+    let size_bits = lists.get(pos..pos + SHARED_BROTLI_MAX_DICTIONARY_WORD_LENGTH + 1 -
+                                   SHARED_BROTLI_MIN_DICTIONARY_WORD_LENGTH)?;
+    pos += size_bits.len();
+    let base = list * kWordListTableSize;
+    for (bits, len) in size_bits.iter().zip(SHARED_BROTLI_MIN_DICTIONARY_WORD_LENGTH..) {
+      if *bits > kMaxWordListSizeBits {
+        return None;
+      }
+      if let Some(ref mut tables) = tables {
+        tables[base + len] = pos as u32;
+        tables[base + 32 + len] = u32::from(*bits);
+      }
+      if *bits != 0 {
+        pos += len << *bits;
+      }
+    }
+    if pos > lists.len() {
+      return None;
+    }
+  }
+  let word_tables_size = layout.num_word_lists as usize * kWordListTableSize;
+  layout.num_transform_lists = ReadByte(lists, &mut pos)?;
+  let mut max_stringlet_len = 0usize;
+  for list in 0..layout.num_transform_lists as usize {
+    let base = word_tables_size + list * kTransformListTableSize;
+    // Length-prefixed stringlets, the last one empty.
+    let stringlets_len = ReadUint16(lists, &mut pos)? as usize;
+    let stringlets = lists.get(pos..pos + stringlets_len)?;
+    let mut num_stringlets = 0usize;
+    let mut offset = 0usize;
+    loop {
+      let len = *stringlets.get(offset)? as usize;
+      if num_stringlets == kMaxStringlets {
+        return None;
+      }
+      if let Some(ref mut tables) = tables {
+        tables[base + 3 + num_stringlets] = (pos + offset) as u32;
+      }
+      num_stringlets += 1;
+      offset += 1 + len;
+      if len == 0 {
+        break;
+      }
+      if len > max_stringlet_len {
+        max_stringlet_len = len;
+      }
+    }
+    if offset != stringlets_len {
+      return None;
+    }
+    pos += stringlets_len;
+    let num_transforms = ReadByte(lists, &mut pos)? as usize;
+    let transforms = lists.get(pos..pos + 3 * num_transforms)?;
+    let mut has_params = false;
+    for transform in transforms.chunks(3) {
+      if transform[0] as usize >= num_stringlets || transform[1] >= kNumTransformTypes ||
+         transform[2] as usize >= num_stringlets {
+        return None;
+      }
+      has_params |= transform[1] == kShiftFirst || transform[1] == kShiftAll;
+    }
+    let transforms_start = pos;
+    pos += transforms.len();
+    let mut params_start = core::u32::MAX;
+    if has_params {
+      // Only the shift transforms may have non-zero parameters.
+      let params = lists.get(pos..pos + 2 * num_transforms)?;
+      for (transform, param) in transforms.chunks(3).zip(params.chunks(2)) {
+        if transform[1] != kShiftFirst && transform[1] != kShiftAll &&
+           (param[0] != 0 || param[1] != 0) {
+          return None;
+        }
+      }
+      params_start = pos as u32;
+      pos += params.len();
+    }
+    if let Some(ref mut tables) = tables {
+      tables[base] = transforms_start as u32;
+      tables[base + 1] = num_transforms as u32;
+      tables[base + 2] = params_start;
+    }
+  }
+  layout.max_affix_len = 2 * max_stringlet_len;
+  Some(pos)
+}
+
+// Parses a serialized shared dictionary: an LZ77 prefix, custom word lists
+// and transform lists, and the dictionaries made of them together with the
+// context map that picks one for each static dictionary reference.
+pub fn DecodeSharedDictionary<'a>(encoded: &'a [u8]) -> Option<SharedDictionary<'a>> {
+  if encoded.len() < kSharedDictionaryMagic.len() || encoded[..2] != kSharedDictionaryMagic {
+    return None;
+  }
+  let mut pos = kSharedDictionaryMagic.len();
+  let prefix_size = ReadVarint32(encoded, &mut pos)?;
+  if prefix_size > kMaxPrefixLength || prefix_size as usize > encoded.len() - pos {
+    return None;
+  }
+  let prefix = &encoded[pos..pos + prefix_size as usize];
+  pos += prefix_size as usize;
+
+  let mut layout = SharedDictionaryLayout::default();
+  let lists_len = ParseLists(&encoded[pos..], &mut layout, None)?;
+  let lists = &encoded[pos..pos + lists_len];
+  pos += lists_len;
+
+  layout.num_dictionaries = ReadByte(encoded, &mut pos)?;
+  if layout.num_dictionaries == 0 ||
+     layout.num_dictionaries as usize > SHARED_BROTLI_NUM_DICTIONARY_CONTEXTS {
+    return None;
+  }
+  for dictionary in layout.dictionaries[..layout.num_dictionaries as usize].iter_mut() {
+    let words_index = ReadByte(encoded, &mut pos)?;
+    let transforms_index = ReadByte(encoded, &mut pos)?;
+    if words_index > layout.num_word_lists || transforms_index > layout.num_transform_lists {
+      return None;
+    }
+    *dictionary = [words_index, transforms_index];
+  }
+  match ReadByte(encoded, &mut pos)? {
+    0 => {}
+    1 => {
+      layout.context_based = true;
+      for slot in layout.context_map.iter_mut() {
+        *slot = ReadByte(encoded, &mut pos)?;
+        if *slot >= layout.num_dictionaries {
+          return None;
+        }
+      }
+    }
+    _ => return None,
+  }
+  Some(SharedDictionary {
+    prefix,
+    lists,
+    layout,
+  })
+}
+
+// Fills tables, of layout.TablesSize() entries, with the positions of the
+// words, transforms and stringlets in lists, which DecodeSharedDictionary
+// has already checked.
+pub fn FillSharedDictionaryTables(lists: &[u8],
+                                  layout: &SharedDictionaryLayout,
+                                  tables: &mut [u32])
+                                  -> bool {
+  let mut scratch = *layout;
+  ParseLists(lists, &mut scratch, Some(tables)) == Some(lists.len())
+}
+
+// The dictionary a static dictionary reference goes to first, picked by the
+// context of the last two bytes when the dictionary is context based.
+pub fn SharedDictionaryId(layout: &SharedDictionaryLayout,
+                          context_lookup: &[u8; 512],
+                          p1: u8,
+                          p2: u8)
+                          -> usize {
+  if layout.context_based {
+    let context = context_lookup[p1 as usize] | context_lookup[p2 as usize + 256];
+    layout.context_map[context as usize] as usize
+  } else {
+    0
+  }
+}
+
+fn WordListSizeBits(layout: &SharedDictionaryLayout, tables: &[u32], words: u8, len: usize) -> u32 {
+  if words == layout.num_word_lists {
+    u32::from(kBrotliDictionarySizeBitsByLength.get(len).cloned().unwrap_or(0))
+  } else {
+    tables[words as usize * kWordListTableSize + 32 + len]
+  }
+}
+
+fn TransformListSize(layout: &SharedDictionaryLayout, tables: &[u32], transforms: u8) -> u32 {
+  if transforms == layout.num_transform_lists {
+    kNumTransforms as u32
+  } else {
+    tables[TransformListBase(layout, transforms) + 1]
+  }
+}
+
+fn TransformListBase(layout: &SharedDictionaryLayout, transforms: u8) -> usize {
+  layout.num_word_lists as usize * kWordListTableSize +
+    transforms as usize * kTransformListTableSize
+}
+
+fn Stringlet<'a>(lists: &'a [u8], tables: &[u32], base: usize, id: u8) -> &'a [u8] {
+  let start = tables[base + 3 + id as usize] as usize;
+  &lists[start + 1..start + 1 + lists[start] as usize]
+}
+
+// Writes the word that a static dictionary reference of len bytes resolves
+// to in an attached shared dictionary to dst and returns its length. address
+// counts from the first distance past the window and compound dictionaries,
+// dict_id is the one SharedDictionaryId picked. When address is past the
+// words of that dictionary, the following ones with other word lists are
+// tried in turn. static_dict stands in for the built-in word list.
+#[allow(clippy::too_many_arguments)]
+pub fn TransformSharedDictionaryWord(dst: &mut [u8],
+                                     layout: &SharedDictionaryLayout,
+                                     static_dict: &[u8],
+                                     lists: &[u8],
+                                     tables: &[u32],
+                                     dict_id: usize,
+                                     len: i32,
+                                     address: i32)
+                                     -> Result<i32, BrotliDecoderErrorCode> {
+  let word_len = len as usize;
+  let [mut words, mut transforms] = layout.dictionaries[dict_id];
+  let mut shift = WordListSizeBits(layout, tables, words, word_len);
+  let mut num_transforms = TransformListSize(layout, tables, transforms);
+  let mut address = address as u32;
+  let size = if shift == 0 { 0 } else { num_transforms << shift };
+  if address >= size {
+    let mut remaining = address - size;
+    for next in layout.dictionaries[dict_id + 1..layout.num_dictionaries as usize].iter() {
+      if next[0] == words {
+        continue;
+      }
+      // Like the reference decoder, later dictionaries are sized with the
+      // first one's transform count, not their own.
+      let next_shift = WordListSizeBits(layout, tables, next[0], word_len);
+      let next_size = if next_shift == 0 { 0 } else { num_transforms << next_shift };
+      if remaining < next_size {
+        words = next[0];
+        transforms = next[1];
+        shift = next_shift;
+        num_transforms = TransformListSize(layout, tables, transforms);
+        address = remaining;
+        break;
+      }
+      remaining -= next_size;
+    }
+  }
+  if shift == 0 {
+    return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_DICTIONARY);
+  }
+  let word_idx = (address & ((1u32 << shift) - 1)) as usize;
+  let transform_idx = address >> shift;
+  if transform_idx >= num_transforms {
+    return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_TRANSFORM);
+  }
+  let word = if words == layout.num_word_lists {
+    if static_dict.is_empty() {
+      return Err(BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET);
+    }
+    let offset = kBrotliDictionaryOffsetsByLength[word_len] as usize + word_idx * word_len;
+    &static_dict[offset..offset + word_len]
+  } else {
+    let offset = tables[words as usize * kWordListTableSize + word_len] as usize +
+                 word_idx * word_len;
+    &lists[offset..offset + word_len]
+  };
+  if transforms == layout.num_transform_lists {
+    if transform_idx == 0 {
+      dst[..word_len].clone_from_slice(word);
+      return Ok(len);
+    }
+    return Ok(TransformDictionaryWord(dst, word, len, transform_idx as i32));
+  }
+  let base = TransformListBase(layout, transforms);
+  let transform_start = tables[base] as usize + 3 * transform_idx as usize;
+  let transform = &lists[transform_start..transform_start + 3];
+  let params_start = tables[base + 2];
+  let param = if params_start == core::u32::MAX {
+    0
+  } else {
+    let param_start = params_start as usize + 2 * transform_idx as usize;
+    u16::from(lists[param_start]) | (u16::from(lists[param_start + 1]) << 8)
+  };
+  Ok(TransformDictionaryWordWith(dst,
+                                 word,
+                                 len,
+                                 Stringlet(lists, tables, base, transform[0]),
+                                 transform[1],
+                                 Stringlet(lists, tables, base, transform[2]),
+                                 param))
+}
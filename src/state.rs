@@ -10,6 +10,36 @@ use bit_reader::{BrotliBitReader, BrotliGetAvailableBits, BrotliInitBitReader};
 use huffman::{BROTLI_HUFFMAN_MAX_CODE_LENGTH, BROTLI_HUFFMAN_MAX_CODE_LENGTHS_SIZE,
               BROTLI_HUFFMAN_MAX_TABLE_SIZE, HuffmanCode, HuffmanTreeGroup};
 use alloc::SliceWrapper;
+use shared_dictionary::SharedDictionaryLayout;
+use dictionary::BrotliBuiltInStaticDictionary;
+#[cfg(feature="alloc")]
+use alloc_crate::sync::Arc;
+
+// Called when a metadata meta-block starts, with its size in bytes.
+pub type BrotliDecoderMetadataStartFunc = fn(opaque: usize, size: usize);
+// Called with consecutive pieces of metadata meta-block contents.
+pub type BrotliDecoderMetadataChunkFunc = fn(opaque: usize, data: &[u8]);
+// Called with the compressed bytes consumed and the bytes decompressed so far.
+pub type BrotliDecoderProgressFunc = fn(opaque: usize, total_in: usize, total_out: usize);
+
+// A custom dictionary the decoder reads in place instead of keeping its own
+// copy, so any number of decoders can share one, e.g. a memory-mapped file.
+#[derive(Clone, Debug)]
+pub enum SharedCustomDictionary {
+  Static(&'static [u8]),
+  #[cfg(feature="alloc")]
+  Arc(Arc<[u8]>),
+}
+
+impl SliceWrapper<u8> for SharedCustomDictionary {
+  fn slice(&self) -> &[u8] {
+    match *self {
+      SharedCustomDictionary::Static(dict) => dict,
+      #[cfg(feature="alloc")]
+      SharedCustomDictionary::Arc(ref dict) => &dict[..],
+    }
+  }
+}
 
 #[allow(dead_code)]
 pub enum WhichTreeGroup {
@@ -44,7 +74,9 @@ pub enum BrotliDecoderErrorCode{
   BROTLI_DECODER_ERROR_FORMAT_PADDING_2 = -15,
   BROTLI_DECODER_ERROR_FORMAT_DISTANCE = -16,
 
-  /* -17..-18 codes are reserved */
+  /* -17 code is reserved */
+
+  BROTLI_DECODER_ERROR_COMPOUND_DICTIONARY = -18,
 
   BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET = -19,
   BROTLI_DECODER_ERROR_INVALID_ARGUMENTS = -20,
@@ -208,8 +240,13 @@ pub struct BrotliState<AllocU8: alloc::Allocator<u8>,
   pub dist_htree_index: u8,
   pub large_window: bool,
   pub(crate) canny_ringbuffer_allocation: bool,
+  // Streams declaring a larger window are rejected; kept across reset.
+  pub(crate) max_window_bits: u32,
   pub should_wrap_ringbuffer: bool,
   pub error_code: BrotliDecoderErrorCode,
+  // Bits already taken from the caller but left undecoded when error_code
+  // turned fatal; locates the failure in the compressed stream.
+  pub error_unread_bits: u32,
   pub repeat_code_len: u32,
   pub prev_code_len: u32,
 
@@ -256,6 +293,53 @@ pub struct BrotliState<AllocU8: alloc::Allocator<u8>,
   pub custom_dict: AllocU8::AllocatedMemory,
   pub custom_dict_size: isize,
   pub custom_dict_avoid_context_seed: bool,
+  // Used instead of custom_dict when set.
+  pub shared_custom_dict: Option<SharedCustomDictionary>,
+  // Length of the dictionary lent to BrotliDecompressStreamWithDictionary when
+  // the stream started; every later call must lend that many bytes.
+  pub lent_dict_size: Option<usize>,
+  // The RFC 7932 static dictionary, empty if it was compiled out and has not
+  // been supplied with BrotliDecoderSetStaticDictionary.
+  pub static_dict: SharedCustomDictionary,
+  // LZ77 prefixes attached with BrotliDecoderAttachDictionary, stored back to
+  // back; distances just past the window address this buffer from its end.
+  pub compound_dict: AllocU8::AllocatedMemory,
+  pub num_compound_dicts: usize,
+  // Word lists and transform lists of an attached serialized dictionary, the
+  // tables locating words and transforms in them, and how they are combined;
+  // shared_dict.num_dictionaries is zero when there is none.
+  pub shared_dict_lists: AllocU8::AllocatedMemory,
+  pub shared_dict_tables: AllocU32::AllocatedMemory,
+  pub shared_dict: SharedDictionaryLayout,
+  // Progress of a compound dictionary copy that hit the ringbuffer end.
+  pub compound_copy_offset: i32,
+  pub compound_copy_remaining: i32,
+  // Work the current BrotliDecompressStreamBudgeted call may still do: the
+  // commands left and the decoded position at which to stop. Both are
+  // usize::MAX outside such calls.
+  pub(crate) budget_commands_left: usize,
+  pub(crate) budget_decoded_limit: usize,
+  pub(crate) budget_exhausted: bool,
+
+  pub metadata_start_func: Option<BrotliDecoderMetadataStartFunc>,
+  pub metadata_chunk_func: Option<BrotliDecoderMetadataChunkFunc>,
+  pub metadata_callback_opaque: usize,
+  pub progress_func: Option<BrotliDecoderProgressFunc>,
+  pub progress_opaque: usize,
+  pub progress_interval: usize,
+  // Compressed bytes taken from the caller so far, and the totals passed to
+  // the last progress report.
+  pub progress_total_in: usize,
+  pub(crate) progress_reported_in: usize,
+  pub(crate) progress_reported_out: usize,
+  // Set by BrotliDecoderSetStopAtMetaBlockBoundaries; stopped_at_boundary
+  // records that the last call returned at one.
+  pub(crate) stop_at_boundaries: bool,
+  pub(crate) stopped_at_boundary: bool,
+  // Set by BrotliDecoderResumeAt: the bits of the first input byte that
+  // belong to the previous meta-block, and the decoded position resumed from.
+  pub(crate) resume_skip_bits: u32,
+  pub(crate) resume_decoded_base: u64,
   // less used attributes are in the end of this struct */
   // States inside function calls
   pub substate_metablock_header: BrotliRunningMetablockHeaderState,
@@ -270,6 +354,9 @@ pub struct BrotliState<AllocU8: alloc::Allocator<u8>,
   pub is_metadata: u8,
   pub size_nibbles: u8,
   pub window_bits: u32,
+  // Number of meta-block headers decoded so far, including metadata and
+  // empty meta-blocks.
+  pub meta_block_count: usize,
 
   pub num_literal_htrees: u32,
   pub context_map: AllocU8::AllocatedMemory,
@@ -358,6 +445,32 @@ macro_rules! make_brotli_state {
            custom_dict : $custom_dict,
            custom_dict_size : $custom_dict_len as isize,
            custom_dict_avoid_context_seed: $custom_dict_len != 0,
+           shared_custom_dict : None,
+           lent_dict_size : None,
+           static_dict : SharedCustomDictionary::Static(BrotliBuiltInStaticDictionary()),
+           compound_dict : AllocU8::AllocatedMemory::default(),
+           num_compound_dicts : 0,
+           shared_dict_lists : AllocU8::AllocatedMemory::default(),
+           shared_dict_tables : AllocU32::AllocatedMemory::default(),
+           shared_dict : SharedDictionaryLayout::default(),
+           compound_copy_offset : 0,
+           compound_copy_remaining : 0,
+           budget_commands_left : core::usize::MAX,
+           budget_decoded_limit : core::usize::MAX,
+           budget_exhausted : false,
+           metadata_start_func : None,
+           metadata_chunk_func : None,
+           metadata_callback_opaque : 0,
+           progress_func : None,
+           progress_opaque : 0,
+           progress_interval : 0,
+           progress_total_in : 0,
+           progress_reported_in : 0,
+           progress_reported_out : 0,
+           stop_at_boundaries : false,
+           stopped_at_boundary : false,
+           resume_skip_bits : 0,
+           resume_decoded_base : 0,
            /* less used attributes are in the end of this struct */
            /* States inside function calls */
            substate_metablock_header : BrotliRunningMetablockHeaderState::BROTLI_STATE_METABLOCK_HEADER_NONE,
@@ -372,10 +485,13 @@ macro_rules! make_brotli_state {
            is_metadata : 0,
            size_nibbles : 0,
            window_bits : 0,
+           meta_block_count : 0,
            large_window: false,
            canny_ringbuffer_allocation: true,
+           max_window_bits: 30,
            should_wrap_ringbuffer: false,
            error_code: BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS,
+           error_unread_bits: 0,
            num_literal_htrees : 0,
            context_map : AllocU8::AllocatedMemory::default(),
            context_modes : AllocU8::AllocatedMemory::default(),
@@ -409,6 +525,23 @@ impl <'brotli_state,
         BrotliInitBitReader(&mut retval.br);
         retval
     }
+    pub fn new_with_shared_dictionary(alloc_u8 : AllocU8,
+           alloc_u32 : AllocU32,
+           alloc_hc : AllocHC,
+           custom_dict: SharedCustomDictionary) -> Self{
+        let mut retval = Self::new(alloc_u8, alloc_u32, alloc_hc);
+        retval.custom_dict_size = custom_dict.slice().len() as isize;
+        retval.custom_dict_avoid_context_seed = retval.custom_dict_size != 0;
+        retval.shared_custom_dict = Some(custom_dict);
+        retval
+    }
+    // The custom dictionary in use, shared or owned.
+    pub fn custom_dictionary(&self) -> &[u8] {
+        match self.shared_custom_dict {
+            Some(ref dict) => dict.slice(),
+            None => self.custom_dict.slice(),
+        }
+    }
     pub fn new_strict(alloc_u8 : AllocU8,
            alloc_u32 : AllocU32,
            alloc_hc : AllocHC) -> Self{
@@ -419,6 +552,108 @@ impl <'brotli_state,
         BrotliInitBitReader(&mut retval.br);
         retval
     }
+    // Returns the decoder to the start-of-stream state so it can decode
+    // another stream without being rebuilt. The allocators, a shared custom
+    // dictionary, the attached dictionaries, the metadata and progress
+    // callbacks, whether to stop at meta-block boundaries and the context map
+    // table are kept; every buffer sized by the previous stream is handed back
+    // to the allocators. An owned custom dictionary is freed once it has
+    // seeded the ring buffer, so the next stream only gets it if the previous
+    // one never got that far.
+    // large_window has the same meaning as in the constructors.
+    pub fn reset(&mut self, large_window : bool) {
+        self.BrotliStateCleanupAfterMetablock();
+        self.alloc_u8.free_cell(core::mem::take(&mut self.ringbuffer));
+        self.alloc_hc.free_cell(core::mem::take(&mut self.block_type_length_state.block_type_trees));
+        self.alloc_hc.free_cell(core::mem::take(&mut self.block_type_length_state.block_len_trees));
+        self.state = BrotliRunningState::BROTLI_STATE_UNINITED;
+        self.loop_counter = 0;
+        self.br = BrotliBitReader::default();
+        BrotliInitBitReader(&mut self.br);
+        self.buffer = [0u8; 8];
+        self.buffer_length = 0;
+        self.pos = 0;
+        self.max_backward_distance = 0;
+        self.max_backward_distance_minus_custom_dict_size = 0;
+        self.max_distance = 0;
+        self.ringbuffer_size = 0;
+        self.ringbuffer_mask = 0;
+        self.dist_rb_idx = 0;
+        self.dist_rb = [16, 15, 11, 4];
+        self.htree_command_index = 0;
+        self.context_lookup = &kContextLookup[0];
+        self.context_map_slice_index = 0;
+        self.dist_context_map_slice_index = 0;
+        self.sub_loop_counter = 0;
+        self.trivial_literal_context = 0;
+        self.distance_context = 0;
+        self.meta_block_remaining_len = 0;
+        self.block_type_length_state.block_length_index = 0;
+        self.block_type_length_state.block_length = [0; 3];
+        self.block_type_length_state.num_block_types = [0; 3];
+        self.block_type_length_state.block_type_rb = [0; 6];
+        self.block_type_length_state.substate_read_block_length =
+            BrotliRunningReadBlockLengthState::BROTLI_STATE_READ_BLOCK_LENGTH_NONE;
+        self.distance_postfix_bits = 0;
+        self.num_direct_distance_codes = 0;
+        self.distance_postfix_mask = 0;
+        self.num_dist_htrees = 0;
+        self.literal_htree_index = 0;
+        self.dist_htree_index = 0;
+        self.repeat_code_len = 0;
+        self.prev_code_len = 0;
+        self.copy_length = 0;
+        self.distance_code = 0;
+        self.rb_roundtrips = 0;
+        self.partial_pos_out = 0;
+        self.symbol = 0;
+        self.repeat = 0;
+        self.space = 0;
+        self.table = [HuffmanCode::default(); 32];
+        self.symbol_lists_index = BROTLI_HUFFMAN_MAX_CODE_LENGTH + 1;
+        self.symbols_lists_array = [0; BROTLI_HUFFMAN_MAX_CODE_LENGTH + 1 +
+                                    BROTLI_HUFFMAN_MAX_CODE_LENGTHS_SIZE];
+        self.next_symbol = [0; 32];
+        self.code_length_code_lengths = [0; 18];
+        self.code_length_histo = [0; 16];
+        self.htree_index = 0;
+        self.htree_next_offset = 0;
+        self.context_index = 0;
+        self.max_run_length_prefix = 0;
+        self.code = 0;
+        self.mtf_upper_bound = 255;
+        self.mtf_or_error_string = Ok([0; 256]);
+        self.custom_dict_size = self.custom_dictionary().len() as isize;
+        self.custom_dict_avoid_context_seed = self.custom_dict_size != 0;
+        self.compound_copy_offset = 0;
+        self.compound_copy_remaining = 0;
+        self.substate_metablock_header =
+            BrotliRunningMetablockHeaderState::BROTLI_STATE_METABLOCK_HEADER_NONE;
+        self.substate_tree_group = BrotliRunningTreeGroupState::BROTLI_STATE_TREE_GROUP_NONE;
+        self.substate_context_map = BrotliRunningContextMapState::BROTLI_STATE_CONTEXT_MAP_NONE;
+        self.substate_uncompressed =
+            BrotliRunningUncompressedState::BROTLI_STATE_UNCOMPRESSED_NONE;
+        self.substate_huffman = BrotliRunningHuffmanState::BROTLI_STATE_HUFFMAN_NONE;
+        self.substate_decode_uint8 = BrotliRunningDecodeUint8State::BROTLI_STATE_DECODE_UINT8_NONE;
+        self.is_last_metablock = 0;
+        self.is_uncompressed = 0;
+        self.is_metadata = 0;
+        self.size_nibbles = 0;
+        self.window_bits = 0;
+        self.meta_block_count = 0;
+        self.progress_total_in = 0;
+        self.progress_reported_in = 0;
+        self.progress_reported_out = 0;
+        self.stopped_at_boundary = false;
+        self.resume_skip_bits = 0;
+        self.resume_decoded_base = 0;
+        self.large_window = large_window;
+        self.should_wrap_ringbuffer = false;
+        self.error_code = BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS;
+        self.error_unread_bits = 0;
+        self.num_literal_htrees = 0;
+        self.trivial_literal_contexts = [0u32; 8];
+    }
     pub fn BrotliStateMetablockBegin(self : &mut Self) {
         self.meta_block_remaining_len = 0;
         self.block_type_length_state.block_length[0] = 1u32 << 24;
@@ -474,6 +709,10 @@ impl <'brotli_state,
                               AllocHC::AllocatedMemory::default()));
       self.alloc_u8.free_cell(core::mem::replace(&mut self.custom_dict,
                               AllocU8::AllocatedMemory::default()));
+      self.shared_custom_dict = None;
+      self.alloc_u8.free_cell(core::mem::take(&mut self.compound_dict));
+      self.alloc_u8.free_cell(core::mem::take(&mut self.shared_dict_lists));
+      self.alloc_u32.free_cell(core::mem::take(&mut self.shared_dict_tables));
 
       //FIXME??  BROTLI_FREE(s, s->legacy_input_buffer);
       //FIXME??  BROTLI_FREE(s, s->legacy_output_buffer);
@@ -556,7 +795,9 @@ pub fn BrotliDecoderErrorStr(c: BrotliDecoderErrorCode) -> &'static str {
   BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_PADDING_2 =>"ERROR_FORMAT_PADDING_2\0",
   BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_DISTANCE =>"ERROR_FORMAT_DISTANCE\0",
 
-  /* -17..-18 codes are reserved */
+  /* -17 code is reserved */
+
+  BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_COMPOUND_DICTIONARY => "ERROR_COMPOUND_DICTIONARY\0",
 
   BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET => "ERROR_DICTIONARY_NOT_SET\0",
   BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS => "ERROR_INVALID_ARGUMENTS\0",
@@ -576,3 +817,33 @@ pub fn BrotliDecoderErrorStr(c: BrotliDecoderErrorCode) -> &'static str {
   BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_UNREACHABLE => "ERROR_UNREACHABLE\0",
   }
 }
+
+pub fn BrotliRunningStateStr(state: &BrotliRunningState) -> &'static str {
+  match *state {
+  BrotliRunningState::BROTLI_STATE_UNINITED => "STATE_UNINITED\0",
+  BrotliRunningState::BROTLI_STATE_LARGE_WINDOW_BITS => "STATE_LARGE_WINDOW_BITS\0",
+  BrotliRunningState::BROTLI_STATE_INITIALIZE => "STATE_INITIALIZE\0",
+  BrotliRunningState::BROTLI_STATE_METABLOCK_BEGIN => "STATE_METABLOCK_BEGIN\0",
+  BrotliRunningState::BROTLI_STATE_METABLOCK_HEADER => "STATE_METABLOCK_HEADER\0",
+  BrotliRunningState::BROTLI_STATE_METABLOCK_HEADER_2 => "STATE_METABLOCK_HEADER_2\0",
+  BrotliRunningState::BROTLI_STATE_CONTEXT_MODES => "STATE_CONTEXT_MODES\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_BEGIN => "STATE_COMMAND_BEGIN\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_INNER => "STATE_COMMAND_INNER\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_POST_DECODE_LITERALS => "STATE_COMMAND_POST_DECODE_LITERALS\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRAP_COPY => "STATE_COMMAND_POST_WRAP_COPY\0",
+  BrotliRunningState::BROTLI_STATE_UNCOMPRESSED => "STATE_UNCOMPRESSED\0",
+  BrotliRunningState::BROTLI_STATE_METADATA => "STATE_METADATA\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_INNER_WRITE => "STATE_COMMAND_INNER_WRITE\0",
+  BrotliRunningState::BROTLI_STATE_METABLOCK_DONE => "STATE_METABLOCK_DONE\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRITE_1 => "STATE_COMMAND_POST_WRITE_1\0",
+  BrotliRunningState::BROTLI_STATE_COMMAND_POST_WRITE_2 => "STATE_COMMAND_POST_WRITE_2\0",
+  BrotliRunningState::BROTLI_STATE_HUFFMAN_CODE_0 => "STATE_HUFFMAN_CODE_0\0",
+  BrotliRunningState::BROTLI_STATE_HUFFMAN_CODE_1 => "STATE_HUFFMAN_CODE_1\0",
+  BrotliRunningState::BROTLI_STATE_HUFFMAN_CODE_2 => "STATE_HUFFMAN_CODE_2\0",
+  BrotliRunningState::BROTLI_STATE_HUFFMAN_CODE_3 => "STATE_HUFFMAN_CODE_3\0",
+  BrotliRunningState::BROTLI_STATE_CONTEXT_MAP_1 => "STATE_CONTEXT_MAP_1\0",
+  BrotliRunningState::BROTLI_STATE_CONTEXT_MAP_2 => "STATE_CONTEXT_MAP_2\0",
+  BrotliRunningState::BROTLI_STATE_TREE_GROUP => "STATE_TREE_GROUP\0",
+  BrotliRunningState::BROTLI_STATE_DONE => "STATE_DONE\0",
+  }
+}
@@ -0,0 +1,297 @@
+// Decoder whose memory is a fixed block sized at compile time, for targets
+// that can neither allocate nor afford brotli_decode's stack pools. The slot
+// allocator is also what brotli_decode_prealloc runs on; StaticDecoder
+// itself is const generic, so it needs the static-decoder feature.
+use core;
+use alloc::{AllocatedStackMemory, Allocator};
+use decode::kRingBufferSlack;
+#[cfg(feature="static-decoder")]
+use decode::{BrotliDecompressStream, BrotliResult};
+use huffman::{HuffmanCode, BROTLI_HUFFMAN_MAX_TABLE_SIZE};
+use state::BrotliState;
+#[cfg(feature="static-decoder")]
+use BrotliDecoderReturnInfo;
+
+// Worst cases permitted by the format: at most 256 block types and 256
+// Huffman trees per category, and each tree group reserves
+// BROTLI_HUFFMAN_MAX_TABLE_SIZE codes per tree.
+const MAX_HTREES: usize = 256;
+const LITERAL_CONTEXT_MAP_SIZE: usize = MAX_HTREES << 6;
+const SMALL_U8_SLOT_SIZE: usize = MAX_HTREES << 2;
+const HTREE_GROUP_SIZE: usize = MAX_HTREES * BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize;
+const BLOCK_TREES_SIZE: usize = 3 * BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize;
+
+// Slot layout brotli_decode_prealloc carves out of its scratch areas: the
+// ring buffer gets whatever u8 scratch is left after the context map and
+// context mode slots, and is never smaller than the literal context map so
+// that best fit cannot run out whatever order the buffers are requested in.
+pub(crate) const SCRATCH_U8_SLOT_SIZES: [usize; 3] = [LITERAL_CONTEXT_MAP_SIZE, SMALL_U8_SLOT_SIZE, MAX_HTREES];
+pub(crate) const SCRATCH_U32_SLOT_SIZES: [usize; 3] = [MAX_HTREES; 3];
+pub(crate) const SCRATCH_HC_SLOT_SIZES: [usize; 6] = [BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize,
+                                                      BLOCK_TREES_SIZE, BLOCK_TREES_SIZE,
+                                                      HTREE_GROUP_SIZE, HTREE_GROUP_SIZE, HTREE_GROUP_SIZE];
+pub(crate) const MIN_RING_BUFFER_SLOT_SIZE: usize = LITERAL_CONTEXT_MAP_SIZE;
+// Enough for the largest layout plus a slot for left over scratch.
+const MAX_SLOTS: usize = 7;
+
+/// Bytes of ring buffer storage a stream with the given window needs.
+pub const fn ring_buffer_size(window_bits: usize) -> usize {
+  (1 << window_bits) + kRingBufferSlack
+}
+
+/// Names a window size for StaticDecoder.
+#[cfg(feature="static-decoder")]
+pub struct WindowBits<const BITS: usize>;
+
+/// Ring buffer storage for each window size the format allows (10 to 24 bits).
+#[cfg(feature="static-decoder")]
+pub trait RingBufferStorage {
+  type Buffer: AsMut<[u8]>;
+  const ZEROED: Self::Buffer;
+}
+
+#[cfg(feature="static-decoder")]
+macro_rules! ring_buffer_storage {
+  ($($bits:expr),*) => {
+    $(
+      impl RingBufferStorage for WindowBits<$bits> {
+        type Buffer = [u8; ring_buffer_size($bits)];
+        const ZEROED: Self::Buffer = [0; ring_buffer_size($bits)];
+      }
+    )*
+  };
+}
+#[cfg(feature="static-decoder")]
+ring_buffer_storage!(10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24);
+
+/// Hands out whole preallocated slots: each request gets the smallest free
+/// slot that can hold it, and a freed cell becomes a slot again. The decoder
+/// holds few enough buffers at once that StaticDecoder can give every one of
+/// them a slot, so nothing is ever split or coalesced.
+pub struct SlotAllocator<'a, T: 'a> {
+  slots: [&'a mut [T]; MAX_SLOTS],
+}
+
+impl<'a, T: 'a> SlotAllocator<'a, T> {
+  // Takes up to MAX_SLOTS slots; the rest stay empty.
+  #[cfg(feature="static-decoder")]
+  fn new<I: IntoIterator<Item = &'a mut [T]>>(slots: I) -> Self {
+    let mut allocator = SlotAllocator { slots: Default::default() };
+    for (slot, mem) in allocator.slots.iter_mut().zip(slots) {
+      *slot = mem;
+    }
+    allocator
+  }
+}
+
+impl<'a, T: Clone + Default + 'a> Allocator<T> for SlotAllocator<'a, T> {
+  type AllocatedMemory = AllocatedStackMemory<'a, T>;
+  fn alloc_cell(&mut self, len: usize) -> AllocatedStackMemory<'a, T> {
+    if len == 0 {
+      return AllocatedStackMemory::<'a, T>::default();
+    }
+    let mut best: Option<usize> = None;
+    for (index, slot) in self.slots.iter().enumerate() {
+      let smaller = match best {
+        None => true,
+        Some(b) => slot.len() < self.slots[b].len(),
+      };
+      if slot.len() >= len && smaller {
+        best = Some(index);
+      }
+    }
+    match best {
+      // Out of slots: the decoder reports the matching ALLOC error.
+      None => AllocatedStackMemory::<'a, T>::default(),
+      Some(index) => {
+        let mem = core::mem::take(&mut self.slots[index]);
+        for item in mem[..len].iter_mut() {
+          *item = T::default();
+        }
+        AllocatedStackMemory::<'a, T>{mem}
+      }
+    }
+  }
+  fn free_cell(&mut self, data: AllocatedStackMemory<'a, T>) {
+    if data.mem.is_empty() {
+      return;
+    }
+    if let Some(slot) = self.slots.iter_mut().find(|slot| slot.is_empty()) {
+      *slot = data.mem;
+    }
+  }
+}
+
+// Splits slots of the given sizes off the end of scratch, and makes
+// whatever is left over one more slot. The caller checks that scratch is
+// large enough.
+fn carve_slots<'a, T>(mut scratch: &'a mut [T], sizes: &[usize]) -> SlotAllocator<'a, T> {
+  let mut allocator = SlotAllocator { slots: Default::default() };
+  for (slot, size) in allocator.slots.iter_mut().zip(sizes.iter()) {
+    let split = scratch.len() - size;
+    let (rest, tail) = core::mem::take(&mut scratch).split_at_mut(split);
+    *slot = tail;
+    scratch = rest;
+  }
+  allocator.slots[sizes.len()] = scratch;
+  allocator
+}
+
+/// BrotliState running on the storage of a StaticDecoder.
+pub type StaticBrotliState<'a> = BrotliState<SlotAllocator<'a, u8>,
+                                             SlotAllocator<'a, u32>,
+                                             SlotAllocator<'a, HuffmanCode>>;
+
+// State for brotli_decode_prealloc whose scratch holds the whole slot
+// layout, so decoding cannot run out of memory.
+pub(crate) fn scratch_state<'a>(scratch_u8: &'a mut [u8],
+                                scratch_u32: &'a mut [u32],
+                                scratch_hc: &'a mut [HuffmanCode]) -> StaticBrotliState<'a> {
+  BrotliState::new(carve_slots(scratch_u8, &SCRATCH_U8_SLOT_SIZES[..]),
+                   carve_slots(scratch_u32, &SCRATCH_U32_SLOT_SIZES[..]),
+                   carve_slots(scratch_hc, &SCRATCH_HC_SLOT_SIZES[..]))
+}
+
+/// All the memory needed to decode any stream whose window is at most
+/// 2^WINDOW_BITS bytes, with no allocator or stack pools involved.
+/// new() is a const fn, so the decoder can be placed in a static; it takes
+/// about (1 << WINDOW_BITS) + 3.3 MiB, most of it the worst-case Huffman
+/// tables the format permits. Streams declaring a larger window fail with
+/// BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS.
+#[cfg(feature="static-decoder")]
+pub struct StaticDecoder<const WINDOW_BITS: usize> where WindowBits<WINDOW_BITS>: RingBufferStorage {
+  ring_buffer: <WindowBits<WINDOW_BITS> as RingBufferStorage>::Buffer,
+  literal_context_map: [u8; LITERAL_CONTEXT_MAP_SIZE],
+  small_u8: [[u8; SMALL_U8_SLOT_SIZE]; 2],
+  htrees: [[u32; MAX_HTREES]; 3],
+  context_map_table: [HuffmanCode; BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize],
+  block_trees: [[HuffmanCode; BLOCK_TREES_SIZE]; 2],
+  htree_groups: [[HuffmanCode; HTREE_GROUP_SIZE]; 3],
+}
+
+#[cfg(feature="static-decoder")]
+impl<const WINDOW_BITS: usize> Default for StaticDecoder<WINDOW_BITS> where WindowBits<WINDOW_BITS>: RingBufferStorage {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(feature="static-decoder")]
+impl<const WINDOW_BITS: usize> StaticDecoder<WINDOW_BITS> where WindowBits<WINDOW_BITS>: RingBufferStorage {
+  pub const fn new() -> Self {
+    const EMPTY_CODE: HuffmanCode = HuffmanCode { value: 0, bits: 0 };
+    StaticDecoder {
+      ring_buffer: <WindowBits<WINDOW_BITS> as RingBufferStorage>::ZEROED,
+      literal_context_map: [0; LITERAL_CONTEXT_MAP_SIZE],
+      small_u8: [[0; SMALL_U8_SLOT_SIZE]; 2],
+      htrees: [[0; MAX_HTREES]; 3],
+      context_map_table: [EMPTY_CODE; BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize],
+      block_trees: [[EMPTY_CODE; BLOCK_TREES_SIZE]; 2],
+      htree_groups: [[EMPTY_CODE; HTREE_GROUP_SIZE]; 3],
+    }
+  }
+
+  /// Starts a new stream. The returned state borrows this decoder's memory;
+  /// drive it with BrotliDecompressStream.
+  pub fn state(&mut self) -> StaticBrotliState<'_> {
+    let [small_a, small_b] = &mut self.small_u8;
+    let [htrees_a, htrees_b, htrees_c] = &mut self.htrees;
+    let [block_a, block_b] = &mut self.block_trees;
+    let [group_a, group_b, group_c] = &mut self.htree_groups;
+    let mut state = BrotliState::new_strict(
+      SlotAllocator::new([self.ring_buffer.as_mut(), &mut self.literal_context_map[..],
+                          &mut small_a[..], &mut small_b[..]]),
+      SlotAllocator::new([&mut htrees_a[..], &mut htrees_b[..], &mut htrees_c[..]]),
+      SlotAllocator::new([&mut self.context_map_table[..], &mut block_a[..], &mut block_b[..],
+                          &mut group_a[..], &mut group_b[..], &mut group_c[..]]));
+    state.max_window_bits = WINDOW_BITS as u32;
+    state
+  }
+
+  /// Decodes a complete stream from input into output in one call.
+  pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> BrotliDecoderReturnInfo {
+    let mut state = self.state();
+    let mut available_in = input.len();
+    let mut input_offset: usize = 0;
+    let mut available_out = output.len();
+    let mut output_offset: usize = 0;
+    let mut written: usize = 0;
+    let result: BrotliResult = BrotliDecompressStream(&mut available_in,
+                                                      &mut input_offset,
+                                                      input,
+                                                      &mut available_out,
+                                                      &mut output_offset,
+                                                      output,
+                                                      &mut written,
+                                                      &mut state);
+    BrotliDecoderReturnInfo::new(&state, result, output_offset)
+  }
+}
+
+// Every test here decodes streams that use the built-in static dictionary.
+#[cfg(all(test, feature="std", feature="static-decoder",
+          not(feature="external-static-dictionary")))]
+mod tests {
+  use super::*;
+  use std::sync::Mutex;
+  use std::vec::Vec;
+  use state::BrotliDecoderErrorCode;
+
+  // Statics keep the multi-megabyte storage off the test thread's stack.
+  static SMALL_WINDOW: Mutex<StaticDecoder<10>> = Mutex::new(StaticDecoder::new());
+  static LARGE_WINDOW: Mutex<StaticDecoder<22>> = Mutex::new(StaticDecoder::new());
+
+  static METABLOCK_RESET_BR: &[u8] = include_bytes!("../testdata/metablock_reset.compressed");
+  static METABLOCK_RESET: &[u8] = include_bytes!("../testdata/metablock_reset");
+  static ALICE_BR: &[u8] = include_bytes!("../testdata/alice29.txt.compressed");
+  static ALICE: &[u8] = include_bytes!("../testdata/alice29.txt");
+
+  fn decompress_in_chunks(state: &mut StaticBrotliState, input: &[u8], chunk_size: usize) -> (BrotliResult, Vec<u8>) {
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let mut result = BrotliResult::NeedsMoreInput;
+    let mut total_out = 0;
+    for chunk in input.chunks(chunk_size) {
+      let mut available_in = chunk.len();
+      let mut input_offset = 0;
+      loop {
+        let mut available_out = buffer.len();
+        let mut output_offset = 0;
+        result = BrotliDecompressStream(&mut available_in, &mut input_offset, chunk,
+                                        &mut available_out, &mut output_offset, &mut buffer[..],
+                                        &mut total_out, state);
+        output.extend_from_slice(&buffer[..output_offset]);
+        match result {
+          BrotliResult::NeedsMoreOutput => {},
+          _ => break,
+        }
+      }
+    }
+    (result, output)
+  }
+
+  #[test]
+  fn decodes_many_metablocks_in_fixed_memory() {
+    let mut decoder = SMALL_WINDOW.lock().unwrap();
+    // twice, so the second stream runs on slots handed back by the first
+    for chunk_size in [METABLOCK_RESET_BR.len(), 1000] {
+      let (result, output) = decompress_in_chunks(&mut decoder.state(), METABLOCK_RESET_BR, chunk_size);
+      assert!(matches!(result, BrotliResult::ResultSuccess));
+      assert!(output == METABLOCK_RESET);
+    }
+  }
+
+  #[test]
+  fn rejects_larger_windows() {
+    let mut output = [0u8; 16];
+    let info = SMALL_WINDOW.lock().unwrap().decompress(ALICE_BR, &mut output[..]);
+    assert!(matches!(info.result, BrotliResult::ResultFailure));
+    assert!(matches!(info.error_code, BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS));
+
+    let mut output = std::vec![0u8; ALICE.len()];
+    let info = LARGE_WINDOW.lock().unwrap().decompress(ALICE_BR, &mut output[..]);
+    assert!(matches!(info.result, BrotliResult::ResultSuccess));
+    assert_eq!(info.decoded_size, ALICE.len());
+    assert!(output == ALICE);
+  }
+}
@@ -0,0 +1,204 @@
+// Safe lookups into the RFC 7932 static dictionary and its 121 transforms,
+// for tools that analyze streams rather than decode them. With the
+// external-static-dictionary feature the words are not compiled in, so the
+// word lookups return None and the entry iterator is empty; the transform
+// descriptions are always available.
+use core;
+use dictionary::{BrotliBuiltInStaticDictionary, kBrotliDictionaryOffsetsByLength,
+                 kBrotliDictionarySizeBitsByLength, kBrotliMaxDictionaryWordLength,
+                 kBrotliMinDictionaryWordLength};
+use transform::{TransformDictionaryWord, kIdentity, kNumTransforms, kOmitFirst1, kOmitFirst9,
+                kOmitLast9, kPrefixSuffix, kTransforms, kUppercaseAll, kUppercaseFirst};
+
+/// Shortest word in the dictionary.
+pub const MIN_WORD_LENGTH: usize = kBrotliMinDictionaryWordLength as usize;
+/// Longest word in the dictionary.
+pub const MAX_WORD_LENGTH: usize = kBrotliMaxDictionaryWordLength as usize;
+/// Number of transforms, numbered from 0.
+pub const NUM_TRANSFORMS: usize = kNumTransforms as usize;
+/// Longest result of applying a transform to a word.
+pub const MAX_TRANSFORMED_WORD_LENGTH: usize = 37;
+
+// Uppercasing a truncated utf-8 sequence may touch up to two bytes past it.
+const TRANSFORM_SLACK: usize = 2;
+
+/// What a transform does to the word between its prefix and suffix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransformOp {
+  Identity,
+  /// Drops this many bytes, 1 to 9, from the end of the word.
+  OmitLast(u8),
+  UppercaseFirst,
+  UppercaseAll,
+  /// Drops this many bytes, 1 to 9, from the start of the word.
+  OmitFirst(u8),
+}
+
+/// One of the transforms that static dictionary references select.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransformDescription {
+  pub prefix: &'static [u8],
+  pub op: TransformOp,
+  pub suffix: &'static [u8],
+}
+
+fn Affix(id: u8) -> &'static [u8] {
+  let affix = &kPrefixSuffix[id as usize..];
+  let len = affix.iter().position(|&b| b == 0).unwrap_or(affix.len());
+  &affix[..len]
+}
+
+/// Describes transform, or returns None if it is not below NUM_TRANSFORMS.
+pub fn transform_description(transform: usize) -> Option<TransformDescription> {
+  let t = kTransforms.get(transform)?;
+  let op = match t.transform {
+    kIdentity => TransformOp::Identity,
+    n if n <= kOmitLast9 => TransformOp::OmitLast(n),
+    kUppercaseFirst => TransformOp::UppercaseFirst,
+    kUppercaseAll => TransformOp::UppercaseAll,
+    n if (kOmitFirst1..=kOmitFirst9).contains(&n) => TransformOp::OmitFirst(n - kOmitFirst1 + 1),
+    _ => return None,
+  };
+  Some(TransformDescription {
+    prefix: Affix(t.prefix_id),
+    op,
+    suffix: Affix(t.suffix_id),
+  })
+}
+
+/// All transforms, in order of their ids.
+pub fn transform_descriptions() -> impl Iterator<Item = TransformDescription> {
+  (0..NUM_TRANSFORMS).filter_map(transform_description)
+}
+
+/// Number of words of len bytes in the dictionary; 0 for lengths it has
+/// none of.
+pub fn word_count(len: usize) -> usize {
+  if !(MIN_WORD_LENGTH..=MAX_WORD_LENGTH).contains(&len) {
+    return 0;
+  }
+  1 << kBrotliDictionarySizeBitsByLength[len]
+}
+
+/// The word at index among the dictionary's words of len bytes.
+pub fn word(len: usize, index: usize) -> Option<&'static [u8]> {
+  let dictionary = BrotliBuiltInStaticDictionary();
+  if index >= word_count(len) || dictionary.is_empty() {
+    return None;
+  }
+  let offset = kBrotliDictionaryOffsetsByLength[len] as usize + index * len;
+  Some(&dictionary[offset..offset + len])
+}
+
+/// A transformed dictionary word, held inline.
+#[derive(Clone, Copy)]
+pub struct TransformedWord {
+  bytes: [u8; MAX_TRANSFORMED_WORD_LENGTH + TRANSFORM_SLACK],
+  len: u8,
+}
+
+impl TransformedWord {
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.bytes[..self.len as usize]
+  }
+}
+
+impl core::ops::Deref for TransformedWord {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    self.as_bytes()
+  }
+}
+
+impl core::fmt::Debug for TransformedWord {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    self.as_bytes().fmt(f)
+  }
+}
+
+impl PartialEq for TransformedWord {
+  fn eq(&self, other: &Self) -> bool {
+    self.as_bytes() == other.as_bytes()
+  }
+}
+
+impl Eq for TransformedWord {}
+
+/// The bytes a static dictionary reference to word index of len bytes with
+/// transform produces, or None if there is no such word or transform.
+pub fn transform_word(len: usize, index: usize, transform: usize) -> Option<TransformedWord> {
+  let word = word(len, index)?;
+  if transform >= NUM_TRANSFORMS {
+    return None;
+  }
+  let mut result = TransformedWord {
+    bytes: [0; MAX_TRANSFORMED_WORD_LENGTH + TRANSFORM_SLACK],
+    len: 0,
+  };
+  result.len = TransformDictionaryWord(&mut result.bytes, word, len as i32, transform as i32) as u8;
+  Some(result)
+}
+
+/// A word of the dictionary with one of the transforms applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DictionaryEntry {
+  pub len: usize,
+  pub index: usize,
+  pub transform: usize,
+  pub word: TransformedWord,
+}
+
+impl DictionaryEntry {
+  /// How a stream refers to the entry: the distance of a copy of len bytes,
+  /// less 1 and the largest distance into the window and any attached
+  /// dictionaries.
+  pub fn word_id(&self) -> usize {
+    self.index | self.transform << kBrotliDictionarySizeBitsByLength[self.len]
+  }
+}
+
+/// Iterator over every word of the dictionary under every transform, by
+/// length, then transform, then word index, which is word_id order within
+/// each length.
+#[derive(Clone, Debug)]
+pub struct DictionaryEntries {
+  len: usize,
+  index: usize,
+  transform: usize,
+}
+
+/// Every entry a static dictionary reference can produce.
+pub fn entries() -> DictionaryEntries {
+  let len = if BrotliBuiltInStaticDictionary().is_empty() {
+    MAX_WORD_LENGTH + 1
+  } else {
+    MIN_WORD_LENGTH
+  };
+  DictionaryEntries { len, index: 0, transform: 0 }
+}
+
+impl Iterator for DictionaryEntries {
+  type Item = DictionaryEntry;
+  fn next(&mut self) -> Option<DictionaryEntry> {
+    while self.len <= MAX_WORD_LENGTH {
+      if self.index == word_count(self.len) {
+        self.index = 0;
+        self.transform += 1;
+      }
+      if self.transform == NUM_TRANSFORMS {
+        self.transform = 0;
+        self.len += 1;
+        continue;
+      }
+      let entry = DictionaryEntry {
+        len: self.len,
+        index: self.index,
+        transform: self.transform,
+        word: transform_word(self.len, self.index, self.transform)?,
+      };
+      self.index += 1;
+      return Some(entry);
+    }
+    None
+  }
+}
@@ -1,4 +1,6 @@
 #![cfg(test)]
+// Some helpers are only used by tests that need the built-in static dictionary.
+#![cfg_attr(feature="external-static-dictionary", allow(dead_code))]
 
 extern crate alloc_no_stdlib as alloc;
 use alloc::{AllocatedStackMemory, Allocator, SliceWrapper, SliceWrapperMut, StackAllocator, bzero};
@@ -116,6 +118,154 @@ fn test_block_len_trees_allocation_failure() {
   }
 }
 
+// Fails the allocation at which the shared countdown runs out, whichever
+// of the three allocators it comes from, and lets every other one through.
+struct FailNthAllocation<'a, Alloc> {
+  alloc: Alloc,
+  countdown: &'a core::cell::Cell<usize>,
+}
+
+impl<'a, T, Alloc: Allocator<T>> Allocator<T> for FailNthAllocation<'a, Alloc> {
+  type AllocatedMemory = Alloc::AllocatedMemory;
+
+  fn alloc_cell(&mut self, len: usize) -> Self::AllocatedMemory {
+    let remaining = self.countdown.get();
+    self.countdown.set(remaining.wrapping_sub(1));
+    if remaining == 0 {
+      return Self::AllocatedMemory::default();
+    }
+    self.alloc.alloc_cell(len)
+  }
+
+  fn free_cell(&mut self, data: Self::AllocatedMemory) {
+    self.alloc.free_cell(data);
+  }
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_every_allocation_failure_is_reported() {
+  use super::state::BrotliDecoderErrorCode;
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let mut output = vec![0u8; alice.len()];
+  let mut codes = Vec::new();
+  for fail_at in 0.. {
+    let countdown = core::cell::Cell::new(fail_at);
+    let mut state = BrotliState::new(FailNthAllocation{alloc: super::StandardAlloc::default(), countdown: &countdown},
+                                     FailNthAllocation{alloc: super::StandardAlloc::default(), countdown: &countdown},
+                                     FailNthAllocation{alloc: super::StandardAlloc::default(), countdown: &countdown});
+    let result = decode_with_state(&mut state, &alice_br[..], &mut output[..]);
+    let failed_once = countdown.get() > fail_at;
+    match result {
+      (BrotliResult::ResultFailure, _) => {
+        assert!(failed_once);
+        codes.push(state.error_code as i32);
+      }
+      // the table allocated by the constructor is retried when decoding starts
+      (BrotliResult::ResultSuccess, decoded_size) => {
+        assert!(output[..decoded_size] == alice[..]);
+        if !failed_once {
+          break;
+        }
+      }
+      _ => panic!("allocation failure must fail decoding"),
+    }
+  }
+  for code in codes.iter() {
+    assert!((BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES as i32..=
+             BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MODES as i32).contains(code));
+  }
+  for site in [BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MODES,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_CONTEXT_MAP,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_2,
+               BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_BLOCK_TYPE_TREES] {
+    assert!(codes.contains(&(site as i32)));
+  }
+}
+
+// Decodes input fed chunk_size bytes at a time into one large output buffer,
+// checking that every yield hands over fresh output and that no call writes
+// more than the budget allows. Returns the output size and the yield count.
+#[cfg(feature="std")]
+fn decode_budgeted(input: &[u8], output: &mut [u8], chunk_size: usize,
+                   budget: &super::BrotliWorkBudget) -> (usize, usize) {
+  use super::BrotliBudgetedResult;
+  let mut state = BrotliState::new(super::StandardAlloc::default(),
+                                   super::StandardAlloc::default(),
+                                   super::StandardAlloc::default());
+  let mut output_offset = 0;
+  let mut total_out = 0;
+  let mut yields = 0;
+  for chunk in input.chunks(chunk_size) {
+    let mut available_in = chunk.len();
+    let mut input_offset = 0;
+    loop {
+      let mut available_out = output.len() - output_offset;
+      let start = output_offset;
+      let result = super::BrotliDecompressStreamBudgeted(&mut available_in, &mut input_offset, chunk,
+                                                         &mut available_out, &mut output_offset,
+                                                         output, &mut total_out, budget,
+                                                         &mut state);
+      if budget.max_output != 0 {
+        assert!(output_offset - start <= budget.max_output);
+      }
+      match result {
+        BrotliBudgetedResult::Yield => {
+          assert!(output_offset > start);
+          yields += 1;
+        }
+        BrotliBudgetedResult::NeedsMoreInput => break,
+        BrotliBudgetedResult::ResultSuccess => return (output_offset, yields),
+        _ => panic!("unexpected {:?}", result),
+      }
+    }
+  }
+  panic!("stream did not finish");
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_budgeted_decompress_stream() {
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let mut output = vec![0u8; alice.len()];
+  // the budget applies per call, so small input chunks rarely use it up
+  for &(chunk_size, min_yields) in [(alice_br.len(), 1), (7, 0)].iter() {
+    let budget = super::BrotliWorkBudget{max_commands: 64, max_output: 0};
+    let (size, yields) = decode_budgeted(&alice_br[..], &mut output[..], chunk_size, &budget);
+    assert!(output[..size] == alice[..]);
+    assert!(yields >= min_yields * 100);
+    let budget = super::BrotliWorkBudget{max_commands: 0, max_output: 4096};
+    let (size, yields) = decode_budgeted(&alice_br[..], &mut output[..], chunk_size, &budget);
+    assert!(output[..size] == alice[..]);
+    assert!(yields >= min_yields * (alice.len() / 4096 - 1));
+    // no budget never yields
+    let (size, yields) = decode_budgeted(&alice_br[..], &mut output[..], chunk_size,
+                                         &super::BrotliWorkBudget::default());
+    assert!(output[..size] == alice[..]);
+    assert_eq!(yields, 0);
+  }
+  let mut reader = super::Decompressor::new(&alice_br[..], 4096);
+  reader.set_work_budget(super::BrotliWorkBudget{max_commands: 16, max_output: 1000});
+  let mut decoded = Vec::new();
+  let mut buf = vec![0u8; alice.len()];
+  let mut reads = 0;
+  loop {
+    match io::Read::read(&mut reader, &mut buf[..]).unwrap() {
+      0 => break,
+      size => {
+        assert!(size <= 1000);
+        decoded.extend_from_slice(&buf[..size]);
+        reads += 1;
+      }
+    }
+  }
+  assert!(decoded[..] == alice[..]);
+  assert!(reads > alice.len() / 1000);
+}
+
 // no-std variant of oneshot that seeds the decoder with a custom dictionary.
 fn oneshot_dict(input: &mut [u8], dict: &[u8], mut output: &mut [u8]) -> (BrotliResult, usize, usize) {
   let mut available_out: usize = output.len();
@@ -240,7 +390,7 @@ const QF_BUFFER_SIZE: usize = 180 * 1024;
 
 /// The decompressor needs a 256K ring buffer so should use std heap allocation.
 #[test]
-#[cfg(feature="std")]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
 fn test_quickfox_repeated_custom() {
   let mut input: [u8; 58] =
     [0x5B, 0xFF, 0xAF, 0x02, 0xC0, 0x22, 0x79, 0x5C, 0xFB, 0x5A, 0x8C, 0x42, 0x3B, 0xF4, 0x25,
@@ -271,7 +421,7 @@ fn test_quickfox_repeated_custom() {
 }
 
 #[test]
-#[cfg(feature="std")]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
 fn test_quickfox_repeated_exported() {
   let input: [u8; 58] =
     [0x5B, 0xFF, 0xAF, 0x02, 0xC0, 0x22, 0x79, 0x5C, 0xFB, 0x5A, 0x8C, 0x42, 0x3B, 0xF4, 0x25,
@@ -300,7 +450,7 @@ fn test_quickfox_repeated_exported() {
 }
 
 #[test]
-#[cfg(feature="std")]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
 fn test_quickfox_repeated_exported_prealloc() {
   let input: [u8; 58] =
     [0x5B, 0xFF, 0xAF, 0x02, 0xC0, 0x22, 0x79, 0x5C, 0xFB, 0x5A, 0x8C, 0x42, 0x3B, 0xF4, 0x25,
@@ -332,6 +482,60 @@ fn test_quickfox_repeated_exported_prealloc() {
 }
 
 
+#[cfg(feature="std")]
+fn prealloc_with_sizes(input: &[u8], output: &mut [u8], sizes: super::BrotliDecoderScratchSizes)
+                       -> (super::BrotliDecoderReturnInfo, super::BrotliDecoderScratchArea) {
+  let mut scratch_u8 = vec![0u8; sizes.u8_size];
+  let mut scratch_u32 = vec![0u32; sizes.u32_size];
+  let mut scratch_hc = vec![HuffmanCode::default(); sizes.hc_size];
+  let mut exhausted = super::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE;
+  let res = ::brotli_decode_prealloc_with_area(input, output, &mut scratch_u8[..], &mut scratch_u32[..],
+                                               &mut scratch_hc[..], &mut exhausted);
+  (res, exhausted)
+}
+
+#[test]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+fn test_prealloc_scratch_sizes() {
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let sizes = ::brotli_decode_prealloc_scratch_sizes_for_stream(&alice_br[..]).unwrap();
+  // a 2^22 byte window, but the single meta-block fits in 2^18 bytes
+  assert_eq!(sizes, ::brotli_decode_prealloc_scratch_sizes(18));
+  let mut output = vec![0u8; alice.len()];
+  let (res, exhausted) = prealloc_with_sizes(&alice_br[..], &mut output[..], sizes);
+  assert!(matches!(res.result, BrotliResult::ResultSuccess));
+  assert_eq!(exhausted, super::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_NONE);
+  assert!(output[..res.decoded_size] == alice[..]);
+
+  // slots are reused by every meta-block
+  let reset_br = include_bytes!("../testdata/metablock_reset.compressed");
+  let reset = include_bytes!("../testdata/metablock_reset");
+  let mut reset_output = vec![0u8; reset.len()];
+  let reset_sizes = ::brotli_decode_prealloc_scratch_sizes_for_stream(&reset_br[..]).unwrap();
+  let (res, _) = prealloc_with_sizes(&reset_br[..], &mut reset_output[..], reset_sizes);
+  assert!(matches!(res.result, BrotliResult::ResultSuccess));
+  assert!(reset_output[..res.decoded_size] == reset[..]);
+
+  // the failed allocation keeps its usual error code, and the pool that
+  // was too small is reported beside it
+  let too_small = [
+    (super::BrotliDecoderScratchSizes{u8_size: sizes.u8_size / 2, ..sizes},
+     super::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_U8, super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_RING_BUFFER_2),
+    (super::BrotliDecoderScratchSizes{u32_size: 2, ..sizes},
+     super::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_U32, super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS),
+    // room for the tables the decoder always keeps, but no tree groups
+    (super::BrotliDecoderScratchSizes{hc_size: 7 * super::huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE as usize, ..sizes},
+     super::BrotliDecoderScratchArea::BROTLI_DECODER_SCRATCH_HC, super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_ALLOC_TREE_GROUPS),
+  ];
+  for (small_sizes, area, code) in too_small.iter() {
+    let (res, exhausted) = prealloc_with_sizes(&alice_br[..], &mut output[..], *small_sizes);
+    assert!(matches!(res.result, BrotliResult::ResultFailure));
+    assert_eq!(exhausted, *area);
+    assert_eq!(res.error_code as i32, *code as i32);
+  }
+}
+
 #[cfg(feature="std")]
 struct Buffer {
   data: Vec<u8>,
@@ -373,7 +577,7 @@ impl io::Write for Buffer {
 
 
 #[test]
-#[cfg(feature="std")]
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
 fn test_reader_quickfox_repeated() {
   let in_buf: [u8; 58] = [0x5B, 0xFF, 0xAF, 0x02, 0xC0, 0x22, 0x79, 0x5C, 0xFB, 0x5A, 0x8C, 0x42,
                           0x3B, 0xF4, 0x25, 0x55, 0x19, 0x5A, 0x92, 0x99, 0xB1, 0x35, 0xC8, 0x19,
@@ -506,3 +710,1377 @@ fn test_dict_medium() {
   assert_eq!(output_offset, expected.len());
   assert_eq!(&output[..output_offset], expected);
 }
+
+#[test]
+fn test_inspect_header_empty() {
+  let info = super::inspect_header(&[0x06]).unwrap();
+  assert_eq!(info.window_bits, 16);
+  assert!(!info.large_window);
+  assert!(info.is_empty_stream);
+  assert_eq!(info.ringbuffer_size, 0);
+  let mb = info.first_metablock.unwrap();
+  assert!(mb.is_last);
+  assert_eq!(mb.mlen, 0);
+}
+
+#[test]
+fn test_inspect_header_first_metablock() {
+  let input: [u8; 12] = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+  let info = super::inspect_header(&input[..]).unwrap();
+  assert_eq!(info.window_bits, 22);
+  assert!(!info.is_empty_stream);
+  assert_eq!(info.first_metablock,
+             Some(super::BrotliMetaBlockHeaderInfo {
+               mlen: 20,
+               is_last: true,
+               is_uncompressed: false,
+               is_metadata: false,
+             }));
+  // A last meta-block of 20 bytes shrinks the ring buffer down to 64 bytes plus slack.
+  assert_eq!(info.ringbuffer_size, 64 + 42 + 24);
+  // The single window-bits byte is enough to report the window.
+  let info = super::inspect_header(&input[..1]).unwrap();
+  assert_eq!(info.window_bits, 22);
+  assert_eq!(info.first_metablock, None);
+  assert_eq!(info.ringbuffer_size, (1 << 22) + 42 + 24);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_inspect_header_matches_allocation() {
+  // the reported size is what a default decoder allocates for the stream
+  let input: [u8; 12] = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+  let info = super::inspect_header(&input[..]).unwrap();
+  let mut state = new_standard_state();
+  let mut output = [0u8; 32];
+  let _ = decode_with_state(&mut state, &input[..], &mut output[..]);
+  assert_eq!(state.ringbuffer.slice().len(), info.ringbuffer_size);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_inspect_header_with_dictionary_matches_allocation() {
+  // the ring buffer has to hold the custom dictionary too
+  let input: [u8; 12] = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+  let dict = [b'x'; 100];
+  let info = super::inspect_header_with_dictionary(&input[..], dict.len()).unwrap();
+  assert_eq!(info.ringbuffer_size, 256 + 42 + 24);
+  let mut alloc_u8 = super::StandardAlloc::default();
+  let mut custom_dict = alloc_u8.alloc_cell(dict.len());
+  custom_dict.slice_mut().clone_from_slice(&dict[..]);
+  let mut state = BrotliState::new_with_custom_dictionary(alloc_u8,
+                                                          super::StandardAlloc::default(),
+                                                          super::StandardAlloc::default(),
+                                                          custom_dict);
+  let mut output = [0u8; 32];
+  let _ = decode_with_state(&mut state, &input[..], &mut output[..]);
+  assert_eq!(state.ringbuffer.slice().len(), info.ringbuffer_size);
+
+  // an uncompressed meta-block followed by an empty last one counts as last
+  let input: [u8; 9] = [0x40, 0x00, 0x10, b'h', b'e', b'l', b'l', b'o', 0x03];
+  let info = super::inspect_header(&input[..]).unwrap();
+  assert_eq!(info.ringbuffer_size, 32 + 42 + 24);
+  let mut state = new_standard_state();
+  let mut output = [0u8; 32];
+  match decode_with_state(&mut state, &input[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 5) => assert_eq!(&output[..5], b"hello"),
+    _ => panic!("expected the uncompressed meta-block to decode"),
+  }
+  assert_eq!(state.ringbuffer.slice().len(), info.ringbuffer_size);
+}
+
+#[test]
+fn test_inspect_header_large_window() {
+  let info = super::inspect_header(&[0x11, 0x1e]).unwrap();
+  assert_eq!(info.window_bits, 30);
+  assert!(info.large_window);
+  assert_eq!(info.first_metablock, None);
+  match super::inspect_header(&[0x11]) {
+    Err(super::state::BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT) => {}
+    _ => panic!("expected truncated large window header to need more input"),
+  }
+  match super::inspect_header(&[0x11, 0x3f]) {
+    Err(super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_WINDOW_BITS) => {}
+    _ => panic!("expected out of range large window bits to be rejected"),
+  }
+}
+
+#[cfg(feature="std")]
+fn decode_with_state<AllocU8: Allocator<u8>, AllocU32: Allocator<u32>, AllocHC: Allocator<HuffmanCode>>(
+                     state: &mut BrotliState<AllocU8, AllocU32, AllocHC>,
+                     input: &[u8],
+                     output: &mut [u8])
+                     -> (BrotliResult, usize) {
+  let mut available_in = input.len();
+  let mut input_offset = 0;
+  let mut available_out = output.len();
+  let mut output_offset = 0;
+  let mut written = 0;
+  let result = BrotliDecompressStream(&mut available_in,
+                                      &mut input_offset,
+                                      input,
+                                      &mut available_out,
+                                      &mut output_offset,
+                                      output,
+                                      &mut written,
+                                      state);
+  (result, output_offset)
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_state_reset() {
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let x_y: [u8; 12] = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+  let mut output = vec![0u8; alice.len()];
+  let mut state = BrotliState::new(super::StandardAlloc::default(),
+                                   super::StandardAlloc::default(),
+                                   super::StandardAlloc::default());
+  match decode_with_state(&mut state, &alice_br[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, n) => assert_eq!(&output[..n], &alice[..]),
+    _ => panic!("alice must decode"),
+  }
+  // a stream abandoned half way through, then one that fails outright
+  state.reset(true);
+  decode_with_state(&mut state, &alice_br[..alice_br.len() / 2], &mut output[..]);
+  state.reset(true);
+  match decode_with_state(&mut state, &[0x11, 0x3f], &mut output[..]) {
+    (BrotliResult::ResultFailure, _) => {}
+    _ => panic!("invalid window bits must fail"),
+  }
+  state.reset(false);
+  assert!(!state.large_window);
+  match decode_with_state(&mut state, &x_y[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 20) => {}
+    _ => panic!("10x10y must decode after a failed stream"),
+  }
+  assert_eq!(&output[..20], b"XXXXXXXXXXYYYYYYYYYY");
+  assert_eq!(state.meta_block_count, 1);
+  state.reset(true);
+  match decode_with_state(&mut state, &alice_br[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, n) => assert_eq!(&output[..n], &alice[..]),
+    _ => panic!("alice must decode after reset"),
+  }
+}
+
+// One meta-block holding a single 9-byte copy at distance 9, i.e. just past
+// the (empty) window: static dictionary word 8 unless a dictionary is attached.
+#[cfg(feature="std")]
+static COMPOUND_REFERENCE_BR: [u8; 9] = [0x02, 0x01, 0x00, 0x00, 0x04, 0x40, 0x1c, 0x12, 0x13];
+// 10-bit window, one 2118-byte copy at distance 2500: crosses the 1 KiB
+// ringbuffer end twice while copying from a compound dictionary.
+#[cfg(feature="std")]
+static COMPOUND_WRAP_BR: [u8; 14] = [0xa1, 0x28, 0x42, 0x00, 0x00, 0x01, 0x10, 0x87, 0x85, 0x08,
+                                     0x00, 0x00, 0x70, 0x1c];
+
+#[cfg(feature="std")]
+fn new_standard_state() -> BrotliState<super::StandardAlloc,
+                                       super::StandardAlloc,
+                                       super::StandardAlloc> {
+  BrotliState::new(super::StandardAlloc::default(),
+                   super::StandardAlloc::default(),
+                   super::StandardAlloc::default())
+}
+
+#[cfg(feature="std")]
+fn static_dictionary_word(len: usize, index: usize) -> &'static [u8] {
+  let offset = super::dictionary::kBrotliDictionaryOffsetsByLength[len] as usize + index * len;
+  &super::dictionary::BrotliBuiltInStaticDictionary()[offset..offset + len]
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_attach_raw_dictionary() {
+  use super::BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_RAW;
+  let mut output = [0u8; 4096];
+  let mut state = new_standard_state();
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], static_dictionary_word(9, 8)),
+    _ => panic!("expected a static dictionary word"),
+  }
+
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW,
+                                               b"0123456789"));
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW,
+                                               b"abcdefghij"));
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], b"bcdefghij"),
+    _ => panic!("expected the tail of the compound dictionary"),
+  }
+  // too late to attach anything once decoding started
+  assert!(!super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW, b"x"));
+
+  // distances past a short compound dictionary still reach the static one
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW,
+                                               b"01234"));
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], static_dictionary_word(9, 3)),
+    _ => panic!("expected a static dictionary word"),
+  }
+
+  let mut state = new_standard_state();
+  for _ in 0..super::shared_dictionary::SHARED_BROTLI_MAX_COMPOUND_DICTS {
+    assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW, b""));
+  }
+  assert!(!super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW, b""));
+
+  let dict: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW, &dict));
+  match decode_with_state(&mut state, &COMPOUND_WRAP_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 2118) => assert_eq!(&output[..2118], &dict[500..2618]),
+    _ => panic!("expected a copy across the ringbuffer end"),
+  }
+
+  // a 9-byte copy at distance 5 would run past the end of the dictionary
+  let mut overrun = COMPOUND_REFERENCE_BR;
+  overrun[8] = 0x12;
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_RAW,
+                                               b"0123456789abcdefghij"));
+  match decode_with_state(&mut state, &overrun[..], &mut output[..]) {
+    (BrotliResult::ResultFailure, _) => {}
+    _ => panic!("expected a compound dictionary error"),
+  }
+  match super::BrotliDecoderGetErrorCode(&state) {
+    super::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_COMPOUND_DICTIONARY => {}
+    e => panic!("unexpected error {:?}", e),
+  }
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_attach_serialized_dictionary() {
+  use super::BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_SERIALIZED;
+  let mut serialized = vec![0x91, 0x00, 20];
+  serialized.extend_from_slice(b"0123456789abcdefghij");
+  // no word or transform lists, one dictionary using the built-in ones,
+  // not context based
+  serialized.extend_from_slice(&[0, 0, 1, 0, 0, 0]);
+  let mut output = [0u8; 64];
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                               &serialized));
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], b"bcdefghij"),
+    _ => panic!("expected the tail of the serialized dictionary prefix"),
+  }
+
+  let mut context_based = serialized.clone();
+  let last = context_based.len() - 1;
+  context_based[last] = 1;
+  context_based.extend_from_slice(&[0u8; 64]);
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                               &context_based));
+
+  let mut bad_magic = serialized.clone();
+  bad_magic[0] = 0x92;
+  let mut bad_words_index = serialized.clone();
+  bad_words_index[3 + 20 + 3] = 1;
+  let mut custom_words = serialized.clone();
+  custom_words[3 + 20] = 1;
+  for invalid in [&bad_magic[..], &bad_words_index[..], &custom_words[..],
+                  &serialized[..serialized.len() - 1], &context_based[..context_based.len() - 1],
+                  &[0x91, 0x00, 0xff, 0xff, 0xff, 0xff, 0x1f]] {
+    let mut state = new_standard_state();
+    assert!(!super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                                  invalid));
+  }
+}
+
+// COMPOUND_REFERENCE_BR with a meta-block length of mlen (9 to 16), for
+// transforms that change the length of the word.
+#[cfg(feature="std")]
+fn compound_reference_br(mlen: usize) -> [u8; 9] {
+  let mut input = COMPOUND_REFERENCE_BR;
+  input[0] |= ((mlen - 9) << 5) as u8;
+  input
+}
+
+// Stringlets, transforms and shift parameters of a transform list.
+#[cfg(feature="std")]
+type TransformList<'a> = (&'a [&'a [u8]], &'a [[u8; 3]], &'a [u16]);
+
+// A serialized shared dictionary without a prefix. Every word list holds a
+// power of two words of one length, every transform list is given as its
+// stringlets (the empty one that ends them gets the next id), transforms
+// and, for shift transforms, parameters.
+#[cfg(feature="std")]
+fn serialized_shared_dictionary(word_lists: &[&[&[u8]]],
+                                transform_lists: &[TransformList],
+                                dictionaries: &[[u8; 2]],
+                                context_map: Option<[u8; 64]>) -> Vec<u8> {
+  let mut serialized = vec![0x91, 0x00, 0, word_lists.len() as u8];
+  for words in word_lists {
+    let mut size_bits = [0u8; 28];
+    size_bits[words[0].len() - 4] = words.len().trailing_zeros() as u8;
+    serialized.extend_from_slice(&size_bits);
+    for word in words.iter() {
+      serialized.extend_from_slice(word);
+    }
+  }
+  serialized.push(transform_lists.len() as u8);
+  for &(stringlets, transforms, params) in transform_lists {
+    let mut prefix_suffix = Vec::new();
+    for stringlet in stringlets {
+      prefix_suffix.push(stringlet.len() as u8);
+      prefix_suffix.extend_from_slice(stringlet);
+    }
+    prefix_suffix.push(0);
+    serialized.extend_from_slice(&(prefix_suffix.len() as u16).to_le_bytes());
+    serialized.extend_from_slice(&prefix_suffix);
+    serialized.push(transforms.len() as u8);
+    for transform in transforms {
+      serialized.extend_from_slice(transform);
+    }
+    for param in params {
+      serialized.extend_from_slice(&param.to_le_bytes());
+    }
+  }
+  serialized.push(dictionaries.len() as u8);
+  for dictionary in dictionaries {
+    serialized.extend_from_slice(dictionary);
+  }
+  match context_map {
+    Some(context_map) => {
+      serialized.push(1);
+      serialized.extend_from_slice(&context_map);
+    }
+    None => serialized.push(0),
+  }
+  serialized
+}
+
+#[cfg(feature="std")]
+fn decode_with_shared_dictionary(serialized: &[u8], input: &[u8]) -> Result<Vec<u8>, super::BrotliDecoderErrorCode> {
+  use super::BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_SERIALIZED;
+  let mut output = [0u8; 64];
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                               serialized));
+  match decode_with_state(&mut state, input, &mut output[..]) {
+    (BrotliResult::ResultSuccess, len) => Ok(output[..len].to_vec()),
+    _ => Err(super::BrotliDecoderGetErrorCode(&state)),
+  }
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_shared_dictionary_word_and_transform_lists() {
+  use super::BrotliSharedDictionaryType::BROTLI_SHARED_DICTIONARY_SERIALIZED;
+  let words: &[&[u8]] = &[b"alphabets", b"backwards", b"crossword", b"dangerous",
+                          b"education", b"fantastic", b"gardening", b"happiness"];
+  // Uppercase all, omit last 2 between brackets and shift all by one; the
+  // empty stringlet that ends the list is stringlet 2.
+  let transforms: TransformList =
+    (&[b"<", b">"], &[[2, 11, 2], [0, 2, 1], [2, 22, 2]], &[0, 0, 1]);
+
+  // address 8 is word 0 with the second built-in transform, which adds a space
+  let custom_words = serialized_shared_dictionary(&[words], &[], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&custom_words, &compound_reference_br(10)).unwrap(),
+             b"alphabets ");
+  // with 16 words it is word 8 with the identity transform
+  let mut sixteen_words = words.to_vec();
+  sixteen_words.extend_from_slice(&[b"invisible", b"jellyfish", b"knowledge", b"lightning",
+                                    b"mountains", b"nightfall", b"orchestra", b"pineapple"]);
+  let custom_words = serialized_shared_dictionary(&[&sixteen_words], &[], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&custom_words, &COMPOUND_REFERENCE_BR).unwrap(),
+             b"invisible");
+
+  // built-in word 8 with the custom transforms
+  let word = static_dictionary_word(9, 8);
+  let custom_transforms = serialized_shared_dictionary(&[], &[transforms], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&custom_transforms, &COMPOUND_REFERENCE_BR).unwrap(),
+             word.to_ascii_uppercase());
+  // 2 words per transform: address 8 is word 0 with transform 4, past the end
+  let both = serialized_shared_dictionary(&[&words[..2]], &[transforms], &[[0, 0]], None);
+  match decode_with_shared_dictionary(&both, &COMPOUND_REFERENCE_BR) {
+    Err(super::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_TRANSFORM) => {}
+    other => panic!("expected a transform error, got {:?}", other),
+  }
+  // 4 words per transform: word 0 with transform 2
+  let both = serialized_shared_dictionary(&[&words[..4]], &[transforms], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&both, &COMPOUND_REFERENCE_BR).unwrap(),
+             b"bmqibcfut");
+  // 8 words per transform: word 0 with transform 1
+  let both = serialized_shared_dictionary(&[words], &[transforms], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&both, &COMPOUND_REFERENCE_BR).unwrap(),
+             b"<alphabe>");
+  // shift first by -1 turns the leading e with an acute accent into a grave one
+  let shift_back: TransformList =
+    (&[], &[[0, 0, 0], [0, 0, 0], [0, 21, 0]], &[0, 0, 0xffff]);
+  let accented: &[&[u8]] = &[b"\xc3\xa9ducatio", b"alphabets", b"backwards", b"crossword"];
+  let both = serialized_shared_dictionary(&[accented], &[shift_back], &[[0, 0]], None);
+  assert_eq!(decode_with_shared_dictionary(&both, &COMPOUND_REFERENCE_BR).unwrap(),
+             b"\xc3\xa8ducatio");
+
+  // words that are not in a custom list
+  let short_words: &[&[u8]] = &[b"abcd", b"efgh"];
+  let no_nine = serialized_shared_dictionary(&[short_words], &[], &[[0, 0]], None);
+  match decode_with_shared_dictionary(&no_nine, &COMPOUND_REFERENCE_BR) {
+    Err(super::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_FORMAT_DICTIONARY) => {}
+    other => panic!("expected a dictionary error, got {:?}", other),
+  }
+
+  // only one dictionary may replace the built-in words and transforms
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                               &custom_words));
+  assert!(!super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                                &custom_transforms));
+
+  let mut bad_size_bits = custom_words.clone();
+  bad_size_bits[4 + 5] = 16;
+  let bad_type = serialized_shared_dictionary(&[], &[(&[], &[[0, 23, 0]], &[])], &[[0, 0]], None);
+  let bad_stringlet = serialized_shared_dictionary(&[], &[(&[], &[[1, 0, 0]], &[])], &[[0, 0]],
+                                                   None);
+  let bad_param = serialized_shared_dictionary(&[], &[(&[], &[[0, 0, 0], [0, 21, 0]], &[1, 1])],
+                                               &[[0, 0]], None);
+  for invalid in [&bad_size_bits[..], &custom_words[..custom_words.len() - 40], &bad_type[..],
+                  &bad_stringlet[..], &bad_param[..], &custom_transforms[..10]] {
+    let mut state = new_standard_state();
+    assert!(!super::BrotliDecoderAttachDictionary(&mut state, BROTLI_SHARED_DICTIONARY_SERIALIZED,
+                                                  invalid));
+  }
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_shared_dictionary_selection() {
+  let words: &[&[u8]] = &[b"alphabets", b"backwards"];
+  let identity: TransformList = (&[], &[[0, 0, 0]], &[]);
+  // the context of the first copy picks dictionary 1, the custom words
+  let custom = serialized_shared_dictionary(&[&[b"alphabets", b"backwards", b"crossword",
+                                                b"dangerous", b"education", b"fantastic",
+                                                b"gardening", b"happiness", b"invisible",
+                                                b"jellyfish", b"knowledge", b"lightning",
+                                                b"mountains", b"nightfall", b"orchestra",
+                                                b"pineapple"]],
+                                            &[], &[[1, 0], [0, 0]], Some([1; 64]));
+  assert_eq!(decode_with_shared_dictionary(&custom, &COMPOUND_REFERENCE_BR).unwrap(),
+             b"invisible");
+  let mut built_in = custom.clone();
+  let last = built_in.len() - 64;
+  for slot in built_in[last..].iter_mut() {
+    *slot = 0;
+  }
+  assert_eq!(decode_with_shared_dictionary(&built_in, &COMPOUND_REFERENCE_BR).unwrap(),
+             static_dictionary_word(9, 8));
+
+  // past the 2 words of dictionary 0, addresses continue in dictionary 1
+  let fallback = serialized_shared_dictionary(&[words], &[identity], &[[0, 0], [1, 1]], None);
+  assert_eq!(decode_with_shared_dictionary(&fallback, &COMPOUND_REFERENCE_BR).unwrap(),
+             static_dictionary_word(9, 6));
+  // dictionaries with the same word list are skipped
+  let skipped = serialized_shared_dictionary(&[words], &[identity], &[[0, 0], [0, 1], [1, 1]],
+                                             None);
+  assert_eq!(decode_with_shared_dictionary(&skipped, &COMPOUND_REFERENCE_BR).unwrap(),
+             static_dictionary_word(9, 6));
+  // later dictionaries are sized with the first one's transform count: of
+  // the 6 addresses left after dictionary 0, dictionary 1 takes 4 words times
+  // 1 transform, not times its own 2, so the last 2 reach the built-in words
+  let uppercase: TransformList = (&[], &[[0, 0, 0], [0, 11, 0]], &[]);
+  let four_words: &[&[u8]] = &[b"crossword", b"dangerous", b"education", b"fantastic"];
+  let differing = serialized_shared_dictionary(&[words, four_words], &[identity, uppercase],
+                                               &[[0, 0], [1, 1], [2, 2]], None);
+  assert_eq!(decode_with_shared_dictionary(&differing, &COMPOUND_REFERENCE_BR).unwrap(),
+             static_dictionary_word(9, 2));
+}
+
+#[cfg(feature="std")]
+std::thread_local!(static METADATA_EVENTS: core::cell::RefCell<Vec<Vec<u8>>> =
+  const { core::cell::RefCell::new(Vec::new()) });
+
+#[cfg(feature="std")]
+fn record_metadata_start(opaque: usize, size: usize) {
+  assert_eq!(opaque, 0x5eed);
+  METADATA_EVENTS.with(|events| events.borrow_mut().push(format!("start {}", size).into_bytes()));
+}
+
+#[cfg(feature="std")]
+fn record_metadata_chunk(opaque: usize, data: &[u8]) {
+  assert_eq!(opaque, 0x5eed);
+  METADATA_EVENTS.with(|events| events.borrow_mut().push(data.to_vec()));
+}
+
+#[cfg(feature="std")]
+fn take_metadata_events() -> Vec<Vec<u8>> {
+  METADATA_EVENTS.with(|events| core::mem::take(&mut *events.borrow_mut()))
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_metadata_callbacks() {
+  // a 15-byte metadata meta-block followed by an empty last one
+  let input = [0x2c, 0x07, b'h', b'e', b'l', b'l', b'o', b',', b' ', b'm', b'e', b't', b'a',
+               b'd', b'a', b't', b'a', 0x03];
+  let mut output = [0u8; 16];
+  let mut state = new_standard_state();
+  super::BrotliDecoderSetMetadataCallbacks(&mut state,
+                                           Some(record_metadata_start),
+                                           Some(record_metadata_chunk),
+                                           0x5eed);
+  match decode_with_state(&mut state, &input[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 0) => {}
+    _ => panic!("metadata-only stream must decode"),
+  }
+  let events = take_metadata_events();
+  assert_eq!(events[0], b"start 15");
+  assert_eq!(events[1..].concat(), b"hello, metadata");
+
+  // byte at a time, through the decoder's internal buffer
+  state.reset(true);
+  let mut available_in = 0;
+  let mut input_offset = 0;
+  let mut available_out = output.len();
+  let mut output_offset = 0;
+  let mut written = 0;
+  let mut result = BrotliResult::NeedsMoreInput;
+  while input_offset < input.len() {
+    available_in = 1;
+    result = BrotliDecompressStream(&mut available_in, &mut input_offset, &input[..],
+                                    &mut available_out, &mut output_offset, &mut output[..],
+                                    &mut written, &mut state);
+  }
+  assert_eq!(available_in, 0);
+  match result {
+    BrotliResult::ResultSuccess => {}
+    _ => panic!("metadata-only stream must decode byte by byte"),
+  }
+  let events = take_metadata_events();
+  assert_eq!(events[0], b"start 15");
+  assert_eq!(events[1..].concat(), b"hello, metadata");
+}
+
+#[cfg(feature="std")]
+std::thread_local!(static PROGRESS_EVENTS: core::cell::RefCell<Vec<(usize, usize)>> =
+  const { core::cell::RefCell::new(Vec::new()) });
+
+#[cfg(feature="std")]
+fn record_progress(opaque: usize, total_in: usize, total_out: usize) {
+  assert_eq!(opaque, 0x5eed);
+  PROGRESS_EVENTS.with(|events| events.borrow_mut().push((total_in, total_out)));
+}
+
+// Returns the progress reports made since the last call, checking that they
+// move forward, at least interval output bytes at a time, and end with the
+// totals for the whole of input and expected.
+#[cfg(feature="std")]
+fn take_progress_events(input: &[u8], expected: &[u8], interval: usize) -> Vec<(usize, usize)> {
+  let events = PROGRESS_EVENTS.with(|events| core::mem::take(&mut *events.borrow_mut()));
+  assert_eq!(events.last(), Some(&(input.len(), expected.len())));
+  for pair in events.windows(2) {
+    assert!(pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1);
+    assert!(pair[0] != pair[1]);
+  }
+  if events.len() > 2 {
+    for pair in events[..events.len() - 1].windows(2) {
+      assert!(pair[1].1 - pair[0].1 >= interval);
+    }
+  }
+  events
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_progress_callback() {
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let mut output = vec![0u8; alice.len() * 2];
+  let info = super::brotli_decode_with_progress(&alice_br[..], &mut output[..],
+                                                Some(record_progress), 10000, 0x5eed);
+  assert!(output[..info.decoded_size] == alice[..]);
+  let events = take_progress_events(&alice_br[..], &alice[..], 10000);
+  assert!(events.len() > alice.len() / 20000);
+
+  let mut reader = super::Decompressor::new(&alice_br[..], 4096);
+  reader.set_progress_callback(Some(record_progress), 0, 0x5eed);
+  let mut decoded = Vec::new();
+  io::Read::read_to_end(&mut reader, &mut decoded).unwrap();
+  assert!(decoded[..] == alice[..]);
+  let events = take_progress_events(&alice_br[..], &alice[..], 0);
+  assert!(events.len() > alice_br.len() / 4096);
+
+  let mut writer = super::DecompressorWriter::new(Vec::new(), 4096);
+  writer.set_progress_callback(Some(record_progress), 50000, 0x5eed);
+  for chunk in alice_br.chunks(1000) {
+    io::Write::write_all(&mut writer, chunk).unwrap();
+  }
+  assert!(writer.into_inner().unwrap()[..] == alice[..]);
+  let events = take_progress_events(&alice_br[..], &alice[..], 50000);
+  assert!(events.len() >= 2 && events.len() <= alice.len() / 50000 + 1);
+}
+
+// Decodes all of input with a fresh decoder resumed at entry.
+#[cfg(feature="std")]
+fn decode_from_entry(input: &[u8], entry: &super::IndexEntry) -> Vec<u8> {
+  let mut state = new_standard_state();
+  assert!(super::BrotliDecoderResumeAt(&mut state, &entry.point, &entry.window[..]));
+  let mut output = vec![0u8; 1 << 20];
+  let start = (entry.point.bit_position >> 3) as usize;
+  match decode_with_state(&mut state, &input[start..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, size) => output.truncate(size),
+    _ => panic!("decoding from an index entry failed"),
+  }
+  output
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_metablock_index() {
+  for &(compressed, expected) in [
+    (&include_bytes!("../testdata/reducetostream.map.compressed")[..], &include_bytes!("../testdata/reducetostream.map")[..]),
+    (&include_bytes!("../testdata/metablock_reset.compressed")[..], &include_bytes!("../testdata/metablock_reset")[..]),
+  ].iter() {
+    let index = super::MetaBlockIndex::build(&mut &compressed[..], 0).unwrap();
+    assert_eq!(index.compressed_size, compressed.len() as u64);
+    assert_eq!(index.decoded_size, expected.len() as u64);
+    assert!(index.entries.len() > 2);
+    for entry in index.entries.iter() {
+      let start = entry.point.decoded_position as usize;
+      assert!(decode_from_entry(compressed, entry)[..] == expected[start..]);
+    }
+    assert_eq!(super::MetaBlockIndex::deserialize(&index.serialize()[..]), Some(index.clone()));
+    // distances a stream with the window could not have used are rejected
+    let max_distance = (1i32 << index.entries[0].point.window_bits) - 16;
+    for &distance in [0, -1, max_distance + 1, i32::MAX].iter() {
+      let mut corrupt = index.clone();
+      corrupt.entries[0].point.dist_rb[1] = distance;
+      assert_eq!(super::MetaBlockIndex::deserialize(&corrupt.serialize()[..]), None);
+      let mut state = new_standard_state();
+      assert!(!super::BrotliDecoderResumeAt(&mut state, &corrupt.entries[0].point,
+                                            &corrupt.entries[0].window[..]));
+    }
+    let mut furthest = index.clone();
+    furthest.entries[0].point.dist_rb[1] = max_distance;
+    assert!(super::MetaBlockIndex::deserialize(&furthest.serialize()[..]).is_some());
+
+    // fed a byte at a time, the builder records the same entries, give or
+    // take boundaries met while finishing a read from the internal buffer
+    let mut builder = super::IndexBuilder::new(0);
+    for byte in compressed.chunks(1) {
+      assert!(matches!(builder.write(byte), Ok(1)));
+    }
+    let bytewise = builder.finish().unwrap();
+    assert!(!bytewise.entries.is_empty());
+    assert!(bytewise.entries.iter().all(|entry| index.entries.contains(entry)));
+
+    let spaced = super::MetaBlockIndex::build(&mut &compressed[..], 300000).unwrap();
+    assert!(!spaced.entries.is_empty() && spaced.entries.len() < index.entries.len());
+    assert!(spaced.entries[0].point.decoded_position >= 300000);
+    for pair in spaced.entries.windows(2) {
+      assert!(pair[1].point.decoded_position - pair[0].point.decoded_position >= 300000);
+    }
+  }
+  let mut truncated = super::IndexBuilder::new(0);
+  let lcet10 = include_bytes!("../testdata/lcet10.txt.compressed");
+  assert!(matches!(truncated.write(&lcet10[..1000]), Ok(1000)));
+  assert!(matches!(truncated.finish(),
+                   Err(super::BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT)));
+  assert_eq!(super::MetaBlockIndex::deserialize(b"BRIX\x01"), None);
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_seekable_decompressor() {
+  use std::io::{Read, Seek, SeekFrom};
+  let compressed = include_bytes!("../testdata/metablock_reset.compressed");
+  let expected = include_bytes!("../testdata/metablock_reset");
+  let index = super::MetaBlockIndex::build(&mut &compressed[..], 65536).unwrap();
+  let mut reader = super::SeekableDecompressor::new(io::Cursor::new(&compressed[..]), index);
+  let mut buf = vec![0u8; 5000];
+  for &position in [700000u64, 10, 300000, 305000, 0, 912000].iter() {
+    assert_eq!(reader.seek(SeekFrom::Start(position)).unwrap(), position);
+    let end = core::cmp::min(position as usize + buf.len(), expected.len());
+    reader.read_exact(&mut buf[..end - position as usize]).unwrap();
+    assert!(buf[..end - position as usize] == expected[position as usize..end]);
+  }
+  assert_eq!(reader.seek(SeekFrom::End(-100)).unwrap(), expected.len() as u64 - 100);
+  let mut tail = Vec::new();
+  reader.read_to_end(&mut tail).unwrap();
+  assert!(tail[..] == expected[expected.len() - 100..]);
+  assert_eq!(reader.seek(SeekFrom::Current(10)).unwrap(), expected.len() as u64 + 10);
+  assert_eq!(reader.read(&mut buf[..]).unwrap(), 0);
+  assert!(reader.seek(SeekFrom::Current(-(expected.len() as i64) - 11)).is_err());
+}
+
+// An uncompressed meta-block, after the stream header (window bits 16, a
+// single 0 bit) if first.
+#[cfg(feature="std")]
+fn uncompressed_meta_block(first: bool, data: &[u8]) -> Vec<u8> {
+  let bits = (((data.len() - 1) as u32) << 3 | 1 << 19) << (first as u32);
+  let mut block = bits.to_le_bytes()[..3].to_vec();
+  block.extend_from_slice(data);
+  block
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_seek_table() {
+  use std::io::{Read, Seek, SeekFrom};
+  let expected = include_bytes!("../testdata/alice29.txt");
+  let mut table = super::SeekTable {
+    window_bits: 16,
+    ..super::SeekTable::default()
+  };
+  let mut stream = Vec::new();
+  for (i, block) in expected.chunks(10000).enumerate() {
+    if i % 3 == 0 {
+      table.segments.push(super::Segment {
+        compressed_offset: stream.len() as u64,
+        decompressed_offset: (i * 10000) as u64,
+      });
+    }
+    stream.extend(uncompressed_meta_block(i == 0, block));
+  }
+  table.compressed_size = stream.len() as u64;
+  table.decoded_size = expected.len() as u64;
+  stream.extend(table.trailer().unwrap());
+
+  let mut decoded = Vec::new();
+  super::BrotliDecompress(&mut &stream[..], &mut decoded).unwrap();
+  assert!(decoded[..] == expected[..]);
+  assert_eq!(super::SeekTable::from_stream(&stream[..]), Some(table.clone()));
+  assert_eq!(super::SeekTable::read(&mut io::Cursor::new(&stream[..])).unwrap(), table);
+  table.validate(&mut io::Cursor::new(&stream[..])).unwrap();
+  assert_eq!(table.segment_for(0), 0);
+  assert_eq!(table.segment_for(29999), 0);
+  assert_eq!(table.segment_for(30000), 1);
+  assert_eq!(table.segment_for(expected.len() as u64), table.segments.len() - 1);
+
+  let mut reader = super::SeekableDecompressor::from_seek_table(io::Cursor::new(&stream[..])).unwrap();
+  let mut buf = vec![0u8; 5000];
+  for &position in [100000u64, 29998, 0, 60000, 150000].iter() {
+    assert_eq!(reader.seek(SeekFrom::Start(position)).unwrap(), position);
+    let end = core::cmp::min(position as usize + buf.len(), expected.len());
+    reader.read_exact(&mut buf[..end - position as usize]).unwrap();
+    assert!(buf[..end - position as usize] == expected[position as usize..end]);
+  }
+
+  let mut wrong_size = table.clone();
+  wrong_size.segments[2].decompressed_offset += 1;
+  assert!(wrong_size.validate(&mut io::Cursor::new(&stream[..])).is_err());
+  let mut mid_block = table.clone();
+  mid_block.segments[2].compressed_offset += 10;
+  assert!(mid_block.validate(&mut io::Cursor::new(&stream[..])).is_err());
+  assert_eq!(super::SeekTable::from_stream(&include_bytes!("../testdata/alice29.txt.compressed")[..]),
+             None);
+  assert_eq!(super::SeekTable::from_stream(&stream[..stream.len() - 1]), None);
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_decompressor_seek() {
+  use std::io::{Read, Seek, SeekFrom};
+  let compressed = include_bytes!("../testdata/metablock_reset.compressed");
+  let expected = include_bytes!("../testdata/metablock_reset");
+  let mut reader = super::Decompressor::new(io::Cursor::new(&compressed[..]), 4096);
+  let mut buf = vec![0u8; 5000];
+  for &(pos, position) in [(SeekFrom::Start(700000), 700000u64),
+                           (SeekFrom::Current(-400000), 305000),
+                           (SeekFrom::Current(1000), 311000),
+                           (SeekFrom::Start(10), 10),
+                           (SeekFrom::End(-5000), expected.len() as u64 - 5000)].iter() {
+    assert_eq!(reader.seek(pos).unwrap(), position);
+    reader.read_exact(&mut buf[..]).unwrap();
+    assert!(buf[..] == expected[position as usize..position as usize + buf.len()]);
+  }
+  assert_eq!(reader.seek(SeekFrom::End(10)).unwrap(), expected.len() as u64 + 10);
+  assert_eq!(reader.read(&mut buf[..]).unwrap(), 0);
+  assert_eq!(reader.stream_position().unwrap(), expected.len() as u64 + 10);
+  assert!(reader.seek(SeekFrom::Current(-(expected.len() as i64) - 11)).is_err());
+
+  // the stream need not start at the beginning of the inner reader
+  let mut padded = vec![0xffu8; 100];
+  padded.extend_from_slice(&compressed[..]);
+  let mut cursor = io::Cursor::new(&padded[..]);
+  cursor.seek(SeekFrom::Start(100)).unwrap();
+  let mut reader = super::Decompressor::new(cursor, 4096);
+  reader.seek(SeekFrom::Start(500000)).unwrap();
+  reader.seek(SeekFrom::Start(1)).unwrap();
+  reader.read_exact(&mut buf[..]).unwrap();
+  assert!(buf[..] == expected[1..1 + buf.len()]);
+
+  let mut reader = super::Decompressor::new(&compressed[..], 4096);
+  assert_eq!(reader.skip(1000).unwrap(), 1000);
+  assert_eq!(reader.skip(u64::MAX).unwrap(), expected.len() as u64 - 1000);
+  assert_eq!(reader.read(&mut buf[..]).unwrap(), 0);
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_decompress_range() {
+  use std::io::Read;
+  let compressed = include_bytes!("../testdata/alice29.txt.compressed");
+  let expected = include_bytes!("../testdata/alice29.txt");
+  let truncated = &compressed[..compressed.len() / 2];
+  assert!(super::Decompressor::new(truncated, 4096).read_to_end(&mut Vec::new()).is_err());
+  assert!(super::decompress_range(truncated, 1000..21000).unwrap()[..] == expected[1000..21000]);
+  assert!(super::decompress_range(&compressed[..], 0..10).unwrap()[..] == expected[..10]);
+  let end = expected.len() as u64;
+  assert!(super::decompress_range(&compressed[..], end - 10..end + 10).unwrap()[..] ==
+          expected[expected.len() - 10..]);
+  assert!(super::decompress_range(&compressed[..], end + 1..end + 10).unwrap().is_empty());
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_dictionary_hash() {
+  fn hex(digest: [u8; 32]) -> std::string::String {
+    digest.iter().map(|b| std::format!("{:02x}", b)).collect()
+  }
+  assert_eq!(hex(super::dcb::dictionary_hash(b"")),
+             "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+  assert_eq!(hex(super::dcb::dictionary_hash(b"abc")),
+             "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  // 56 bytes: the length goes in a second padding block
+  assert_eq!(hex(super::dcb::dictionary_hash(
+               b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+             "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+  assert_eq!(hex(super::dcb::dictionary_hash(&[b'a'; 1000000][..])),
+             "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_dcb_decompressor() {
+  use std::collections::HashMap;
+  use std::io::{ErrorKind, Read};
+  use super::dcb::{dictionary_hash, find_dcb_dictionary, DCB_MAGIC};
+  use super::{DcbDecompressor, DcbError};
+  let dictionary = b"0123456789".to_vec();
+  // the copy at distance 9 reaches into the custom dictionary
+  let mut stream = DCB_MAGIC.to_vec();
+  stream.extend_from_slice(&dictionary_hash(&dictionary));
+  stream.extend_from_slice(&COMPOUND_REFERENCE_BR);
+
+  let mut registry = HashMap::new();
+  registry.insert(dictionary_hash(b"unrelated"), b"unrelated".to_vec());
+  registry.insert(dictionary_hash(&dictionary), dictionary.clone());
+  let mut output = Vec::new();
+  DcbDecompressor::new(&stream[..], 4096, &registry).unwrap().read_to_end(&mut output).unwrap();
+  assert_eq!(output, b"123456789");
+
+  let pairs = [(dictionary_hash(&dictionary), &dictionary[..])];
+  assert_eq!(find_dcb_dictionary(&stream, &pairs[..]), Ok(&dictionary[..]));
+  assert_eq!(find_dcb_dictionary(&stream, &pairs[..0]), Err(DcbError::UnknownDictionary));
+  // a registry entry whose contents do not match its hash
+  let wrong = [(dictionary_hash(&dictionary), &b"9876543210"[..])];
+  assert_eq!(find_dcb_dictionary(&stream, &wrong[..]), Err(DcbError::UnknownDictionary));
+
+  let dcb_error = |input: &[u8], registry: &HashMap<[u8; 32], Vec<u8>>| {
+    match DcbDecompressor::new(input, 4096, registry) {
+      Ok(_) => panic!("expected an error"),
+      Err(e) => (e.kind(), *e.get_ref().unwrap().downcast_ref::<DcbError>().unwrap()),
+    }
+  };
+  assert_eq!(dcb_error(&stream, &HashMap::new()),
+             (ErrorKind::NotFound, DcbError::UnknownDictionary));
+  let mut bad_magic = stream.clone();
+  bad_magic[1] = b'd';
+  assert_eq!(dcb_error(&bad_magic, &registry), (ErrorKind::InvalidData, DcbError::BadHeader));
+  assert_eq!(dcb_error(&stream[..20], &registry), (ErrorKind::InvalidData, DcbError::BadHeader));
+
+  // a 2^10 byte window, 1100 bytes of output and then a 9-byte copy at
+  // distance 1021, which lands 12 bytes before the end of a 2000-byte
+  // dictionary although neither fits in the window
+  let dictionary: Vec<u8> = (0..2000u32).map(|i| (i * 7) as u8).collect();
+  let mut stream = DCB_MAGIC.to_vec();
+  stream.extend_from_slice(&dictionary_hash(&dictionary));
+  stream.extend_from_slice(&[0x21, 0x2c, 0x11, 0x04]);
+  stream.extend_from_slice(&[b'x'; 1100][..]);
+  stream.extend_from_slice(&[0x81, 0x00, 0x00, 0x00, 0x02, 0x20, 0x0e, 0x09, 0x10, 0x00]);
+  registry.insert(dictionary_hash(&dictionary), dictionary.clone());
+  let mut output = Vec::new();
+  DcbDecompressor::new(&stream[..], 4096, &registry).unwrap().read_to_end(&mut output).unwrap();
+  assert_eq!(output.len(), 1109);
+  assert!(output[..1100].iter().all(|&b| b == b'x'));
+  assert_eq!(&output[1100..], &dictionary[1987..1996]);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_state_reset_keeps_custom_dictionary() {
+  let mut output = [0u8; 16];
+  let mut state = BrotliState::new_with_shared_dictionary(
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::SharedCustomDictionary::Static(b"0123456789"));
+  for _ in 0..2 {
+    // distance 9 reaches back into the custom dictionary
+    match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+      (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], b"123456789"),
+      _ => panic!("expected a copy from the custom dictionary"),
+    }
+    state.reset(true);
+  }
+
+  // an owned dictionary is freed once it seeds the window
+  let mut state = BrotliState::new_with_custom_dictionary(
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default().alloc_cell(10));
+  state.custom_dict.slice_mut().clone_from_slice(b"0123456789");
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], b"123456789"),
+    _ => panic!("expected a copy from the custom dictionary"),
+  }
+  assert!(state.custom_dict.slice().is_empty());
+  state.reset(true);
+  assert_eq!(state.custom_dict_size, 0);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_shared_custom_dictionary() {
+  use super::SharedCustomDictionary;
+  use std::io::Read;
+  use std::sync::Arc;
+  let shared: Arc<[u8]> = Arc::from(&b"0123456789"[..]);
+  let dicts = [SharedCustomDictionary::Static(b"0123456789"),
+               SharedCustomDictionary::Arc(shared.clone())];
+  for dict in dicts.iter() {
+    let mut output = [0u8; 16];
+    let mut state = BrotliState::new_with_shared_dictionary(
+      super::StandardAlloc::default(),
+      super::StandardAlloc::default(),
+      super::StandardAlloc::default(),
+      dict.clone());
+    for _ in 0..2 {
+      match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+        (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], b"123456789"),
+        _ => panic!("expected a copy from the shared dictionary"),
+      }
+      state.reset(true);
+    }
+    let mut decoded = Vec::new();
+    super::Decompressor::new_with_shared_dictionary(&COMPOUND_REFERENCE_BR[..], 4096, dict.clone())
+      .read_to_end(&mut decoded).unwrap();
+    assert_eq!(&decoded[..], b"123456789");
+  }
+  drop(dicts);
+  assert_eq!(Arc::strong_count(&shared), 1);
+}
+
+#[cfg(feature="std")]
+#[test]
+fn test_borrowed_custom_dictionary() {
+  use super::BrotliDecompressStreamWithDictionary;
+  use std::io::Read;
+  // lives only as long as this function, like a per-request buffer
+  let dictionary = b"0123456789".to_vec();
+  let mut state = new_standard_state();
+  for _ in 0..2 {
+    // a byte at a time, so the dictionary is lent over several calls
+    let mut output = [0u8; 16];
+    let mut output_offset = 0;
+    let mut written = 0;
+    let mut result = BrotliResult::NeedsMoreInput;
+    for input_offset in 0..COMPOUND_REFERENCE_BR.len() {
+      let mut available_in = 1;
+      let mut offset = input_offset;
+      let mut available_out = output.len() - output_offset;
+      result = BrotliDecompressStreamWithDictionary(&mut available_in, &mut offset,
+                                                    &COMPOUND_REFERENCE_BR[..],
+                                                    &mut available_out, &mut output_offset,
+                                                    &mut output[..], &mut written,
+                                                    &dictionary[..], &mut state);
+    }
+    assert!(matches!(result, BrotliResult::ResultSuccess));
+    assert_eq!(&output[..output_offset], b"123456789");
+    state.reset(false);
+  }
+
+  let mut decoded = Vec::new();
+  super::DecompressorWithDictionary::new(&COMPOUND_REFERENCE_BR[..], 4096, &dictionary[..])
+    .read_to_end(&mut decoded).unwrap();
+  assert_eq!(&decoded[..], b"123456789");
+
+  // the size is fixed once the stream has started
+  let mut state = new_standard_state();
+  let mut output = [0u8; 16];
+  let (mut available_in, mut input_offset) = (1, 0);
+  let (mut available_out, mut output_offset, mut written) = (output.len(), 0, 0);
+  assert!(matches!(BrotliDecompressStreamWithDictionary(&mut available_in, &mut input_offset,
+                                                        &COMPOUND_REFERENCE_BR[..],
+                                                        &mut available_out, &mut output_offset,
+                                                        &mut output[..], &mut written,
+                                                        &dictionary[..], &mut state),
+                   BrotliResult::NeedsMoreInput));
+  available_in = COMPOUND_REFERENCE_BR.len() - input_offset;
+  assert!(matches!(BrotliDecompressStreamWithDictionary(&mut available_in, &mut input_offset,
+                                                        &COMPOUND_REFERENCE_BR[..],
+                                                        &mut available_out, &mut output_offset,
+                                                        &mut output[..], &mut written,
+                                                        &dictionary[1..], &mut state),
+                   BrotliResult::ResultFailure));
+  assert!(matches!(state.error_code, super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS));
+
+  // and stays fixed after the ring buffer has been seeded from it
+  for &next in [Some(&dictionary[1..]), None].iter() {
+    let mut state = new_standard_state();
+    let (mut available_out, mut output_offset, mut written) = (output.len(), 0, 0);
+    let mut input_offset = 0;
+    while state.ringbuffer.slice().is_empty() {
+      let mut available_in = 1;
+      assert!(matches!(BrotliDecompressStreamWithDictionary(&mut available_in, &mut input_offset,
+                                                            &COMPOUND_REFERENCE_BR[..],
+                                                            &mut available_out, &mut output_offset,
+                                                            &mut output[..], &mut written,
+                                                            &dictionary[..], &mut state),
+                       BrotliResult::NeedsMoreInput));
+    }
+    let mut available_in = COMPOUND_REFERENCE_BR.len() - input_offset;
+    let result = match next {
+      Some(next) => BrotliDecompressStreamWithDictionary(&mut available_in, &mut input_offset,
+                                                         &COMPOUND_REFERENCE_BR[..],
+                                                         &mut available_out, &mut output_offset,
+                                                         &mut output[..], &mut written,
+                                                         next, &mut state),
+      None => BrotliDecompressStream(&mut available_in, &mut input_offset,
+                                     &COMPOUND_REFERENCE_BR[..],
+                                     &mut available_out, &mut output_offset,
+                                     &mut output[..], &mut written, &mut state),
+    };
+    assert!(matches!(result, BrotliResult::ResultFailure));
+    assert!(matches!(state.error_code, super::state::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_INVALID_ARGUMENTS));
+  }
+
+  // and cannot stand in for a dictionary the decoder already has
+  let mut state = BrotliState::new_with_shared_dictionary(
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::StandardAlloc::default(),
+    super::SharedCustomDictionary::Static(b"0123456789"));
+  let (mut available_in, mut input_offset) = (COMPOUND_REFERENCE_BR.len(), 0);
+  let (mut available_out, mut output_offset, mut written) = (output.len(), 0, 0);
+  assert!(matches!(BrotliDecompressStreamWithDictionary(&mut available_in, &mut input_offset,
+                                                        &COMPOUND_REFERENCE_BR[..],
+                                                        &mut available_out, &mut output_offset,
+                                                        &mut output[..], &mut written,
+                                                        &dictionary[..], &mut state),
+                   BrotliResult::ResultFailure));
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_set_static_dictionary() {
+  use super::{BrotliDecoderSetStaticDictionary, BrotliStaticDictionary};
+  use super::SharedCustomDictionary;
+  use super::dictionary::{kBrotliDictionary, BrotliIsStaticDictionary};
+  assert!(BrotliIsStaticDictionary(&kBrotliDictionary[..]));
+  let mut output = [0u8; 16];
+  let mut state = new_standard_state();
+  // as in a build that leaves the dictionary out
+  state.static_dict = SharedCustomDictionary::Static(&[]);
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultFailure, _) => assert!(matches!(
+      state.error_code, super::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET)),
+    _ => panic!("expected the missing dictionary to be reported"),
+  }
+
+  let mut state = new_standard_state();
+  state.static_dict = SharedCustomDictionary::Static(&[]);
+  let mut corrupt = kBrotliDictionary.to_vec();
+  corrupt[1000] ^= 1;
+  let corrupt: std::sync::Arc<[u8]> = std::sync::Arc::from(corrupt);
+  assert!(BrotliStaticDictionary::new(SharedCustomDictionary::Arc(corrupt)).is_none());
+  assert!(BrotliStaticDictionary::new(SharedCustomDictionary::Static(&kBrotliDictionary[1..]))
+          .is_none());
+  let dictionary =
+    BrotliStaticDictionary::new(SharedCustomDictionary::Static(&kBrotliDictionary[..])).unwrap();
+  assert!(BrotliDecoderSetStaticDictionary(&mut state, &dictionary));
+  match decode_with_state(&mut state, &COMPOUND_REFERENCE_BR[..], &mut output[..]) {
+    (BrotliResult::ResultSuccess, 9) => assert_eq!(&output[..9], static_dictionary_word(9, 8)),
+    _ => panic!("expected a copy from the static dictionary"),
+  }
+  // too late once decoding has started
+  assert!(!BrotliDecoderSetStaticDictionary(&mut state, &dictionary));
+}
+
+#[cfg(all(feature="std", not(feature="external-static-dictionary")))]
+#[test]
+fn test_static_dictionary_lookup() {
+  use super::static_dictionary::{entries, transform_description, transform_descriptions,
+                                 transform_word, word, word_count, TransformDescription,
+                                 TransformOp, MAX_TRANSFORMED_WORD_LENGTH, NUM_TRANSFORMS};
+  let time = word(4, 0).unwrap();
+  assert_eq!(time, b"time");
+  assert_eq!(word(9, 8).unwrap(), static_dictionary_word(9, 8));
+  assert_eq!(transform_word(4, 0, 0).unwrap().as_bytes(), b"time");
+  assert_eq!(&transform_word(4, 0, 4).unwrap()[..], b"Time ");
+  assert_eq!(&transform_word(4, 0, 5).unwrap()[..], b"time the ");
+  assert_eq!(&transform_word(4, 0, 11).unwrap()[..], b"me");
+  assert!(word(3, 0).is_none());
+  assert!(word(4, word_count(4)).is_none());
+  assert!(transform_word(4, 0, NUM_TRANSFORMS).is_none());
+
+  assert_eq!(transform_description(5), Some(TransformDescription {
+    prefix: b"", op: TransformOp::Identity, suffix: b" the ",
+  }));
+  assert_eq!(transform_description(3).unwrap().op, TransformOp::OmitFirst(1));
+  assert!(transform_description(NUM_TRANSFORMS).is_none());
+  assert_eq!(transform_descriptions().count(), NUM_TRANSFORMS);
+
+  let mut count = 0;
+  let mut longest = 0;
+  let mut last_len = 0;
+  let mut next_id = 0;
+  for entry in entries() {
+    if entry.len != last_len {
+      last_len = entry.len;
+      next_id = 0;
+    }
+    assert_eq!(entry.word_id(), next_id);
+    next_id += 1;
+    longest = ::core::cmp::max(longest, entry.word.len());
+    count += 1;
+  }
+  let words: usize = (0..25).map(word_count).sum();
+  assert_eq!(words, 13504);
+  assert_eq!(count, words * NUM_TRANSFORMS);
+  assert_eq!(longest, MAX_TRANSFORMED_WORD_LENGTH);
+}
+
+#[cfg(feature="alloc")]
+const QUICKFOX_REPEATED_BR: [u8; 58] = [0x5B, 0xFF, 0xAF, 0x02, 0xC0, 0x22, 0x79, 0x5C, 0xFB, 0x5A, 0x8C, 0x42,
+                                        0x3B, 0xF4, 0x25, 0x55, 0x19, 0x5A, 0x92, 0x99, 0xB1, 0x35, 0xC8, 0x19,
+                                        0x9E, 0x9E, 0x0A, 0x7B, 0x4B, 0x90, 0xB9, 0x3C, 0x98, 0xC8, 0x09, 0x40,
+                                        0xF3, 0xE6, 0xD9, 0x4D, 0xE4, 0x6D, 0x65, 0x1B, 0x27, 0x87, 0x13, 0x5F,
+                                        0xA6, 0xE9, 0x30, 0x96, 0x7B, 0x3C, 0x15, 0xD8, 0x53, 0x1C];
+
+#[cfg(feature="alloc")]
+fn assert_quickfox_repeated(data: &[u8]) {
+  let fox = b"The quick brown fox jumps over the lazy dog";
+  assert_eq!(data.len(), 176128);
+  for (index, item) in data.iter().enumerate() {
+    assert_eq!(*item, fox[index % fox.len()]);
+  }
+}
+
+// CustomRead/CustomWrite endpoints that need nothing beyond the alloc crate.
+#[cfg(feature="alloc")]
+struct SliceReader<'a>(&'a [u8]);
+#[cfg(feature="alloc")]
+impl<'a> super::CustomRead<()> for SliceReader<'a> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+    let to_copy = ::core::cmp::min(buf.len(), self.0.len());
+    buf[..to_copy].clone_from_slice(&self.0[..to_copy]);
+    self.0 = &self.0[to_copy..];
+    Ok(to_copy)
+  }
+}
+#[cfg(feature="alloc")]
+struct VecWriter(::alloc_crate::vec::Vec<u8>);
+#[cfg(feature="alloc")]
+impl super::CustomWrite<()> for VecWriter {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+    self.0.extend_from_slice(buf);
+    Ok(buf.len())
+  }
+  fn flush(&mut self) -> Result<(), ()> {
+    Ok(())
+  }
+}
+
+#[cfg(all(feature="alloc", not(feature="external-static-dictionary")))]
+#[test]
+fn test_decompress_to_vec() {
+  assert_quickfox_repeated(&super::decompress_to_vec(&QUICKFOX_REPEATED_BR[..]).unwrap());
+  assert!(matches!(super::decompress_to_vec(&QUICKFOX_REPEATED_BR[..30]),
+                   Err(super::BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT)));
+  let mut corrupt = QUICKFOX_REPEATED_BR;
+  corrupt[0] = 0xff;
+  assert!(super::decompress_to_vec(&corrupt[..]).is_err());
+}
+
+#[cfg(all(feature="alloc", not(feature="external-static-dictionary")))]
+#[test]
+fn test_heap_reader_and_writer() {
+  let mut reader = super::DecompressorHeap::new(SliceReader(&QUICKFOX_REPEATED_BR[..]), 16, ());
+  let mut output = VecWriter(::alloc_crate::vec::Vec::new());
+  let mut buf = [0u8; 1000];
+  loop {
+    match super::CustomRead::read(&mut reader, &mut buf[..]) {
+      Ok(0) => break,
+      Ok(size) => output.0.extend_from_slice(&buf[..size]),
+      Err(()) => panic!("heap reader failed"),
+    }
+  }
+  assert_quickfox_repeated(&output.0);
+
+  let mut writer = super::DecompressorWriterHeap::new(VecWriter(::alloc_crate::vec::Vec::new()), 0, ());
+  for chunk in QUICKFOX_REPEATED_BR.chunks(7) {
+    super::io_wrappers::write_all(&mut writer, chunk).unwrap();
+  }
+  assert_quickfox_repeated(&writer.into_inner().unwrap_or_else(|_| panic!("truncated")).0);
+
+  let mut writer = super::DecompressorWriterHeap::new(VecWriter(::alloc_crate::vec::Vec::new()), 0, ());
+  super::io_wrappers::write_all(&mut writer, &QUICKFOX_REPEATED_BR[..30]).unwrap();
+  assert_eq!(writer.close(), Err(()));
+}
+
+#[cfg(all(feature="alloc", not(feature="external-static-dictionary")))]
+#[test]
+fn test_mem_stats() {
+  use super::{AccountingAlloc, BoxAlloc, BoxedSlice, MemStats};
+  let mut reader = super::DecompressorCustomIo::new(SliceReader(&QUICKFOX_REPEATED_BR[..]),
+                                                    BoxedSlice::from(::alloc_crate::vec![0u8; 16]),
+                                                    AccountingAlloc::new(BoxAlloc),
+                                                    AccountingAlloc::new(BoxAlloc),
+                                                    AccountingAlloc::new(BoxAlloc),
+                                                    ());
+  // only the context map table is allocated up front
+  assert_eq!(reader.mem_stats().u8, MemStats::default().u8);
+  let mut output = VecWriter(::alloc_crate::vec::Vec::new());
+  let mut buf = [0u8; 1000];
+  loop {
+    match super::CustomRead::read(&mut reader, &mut buf[..]) {
+      Ok(0) => break,
+      Ok(size) => output.0.extend_from_slice(&buf[..size]),
+      Err(()) => panic!("accounting reader failed"),
+    }
+  }
+  assert_quickfox_repeated(&output.0);
+  let stats = reader.mem_stats();
+  // the whole output fits in the ring buffer
+  assert!(stats.u8.peak_bytes >= output.0.len());
+  assert!(stats.u8.current_bytes <= stats.u8.peak_bytes);
+  assert!(stats.u32.allocations != 0 && stats.hc.allocations != 0);
+  assert_eq!(stats.hc.peak_bytes % ::core::mem::size_of::<HuffmanCode>(), 0);
+  assert_eq!(stats.peak_bytes(), stats.u8.peak_bytes + stats.u32.peak_bytes + stats.hc.peak_bytes);
+
+  let mut writer = super::DecompressorWriterCustomIo::new(VecWriter(::alloc_crate::vec::Vec::new()),
+                                                          BoxedSlice::from(::alloc_crate::vec![0u8; 4096]),
+                                                          AccountingAlloc::new(BoxAlloc),
+                                                          AccountingAlloc::new(BoxAlloc),
+                                                          AccountingAlloc::new(BoxAlloc),
+                                                          ());
+  for chunk in QUICKFOX_REPEATED_BR.chunks(7) {
+    super::io_wrappers::write_all(&mut writer, chunk).unwrap();
+  }
+  writer.close().unwrap();
+  let writer_stats = writer.mem_stats();
+  assert_eq!(writer_stats.u8.peak_bytes, stats.u8.peak_bytes);
+  assert_eq!(writer_stats.hc.peak_bytes, stats.hc.peak_bytes);
+  assert_eq!(writer_stats.u32.allocations, stats.u32.allocations);
+}
+
+// Hands out input a few bytes at a time, interrupting every other call.
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+struct InterruptingReader<'a> {
+  data: &'a [u8],
+  interrupt: bool,
+}
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+impl<'a> ::embedded_io::ErrorType for InterruptingReader<'a> {
+  type Error = ::embedded_io::ErrorKind;
+}
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+impl<'a> ::embedded_io::Read for InterruptingReader<'a> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, ::embedded_io::ErrorKind> {
+    self.interrupt = !self.interrupt;
+    if self.interrupt {
+      return Err(::embedded_io::ErrorKind::Interrupted);
+    }
+    let to_copy = ::core::cmp::min(::core::cmp::min(buf.len(), self.data.len()), 5);
+    buf[..to_copy].clone_from_slice(&self.data[..to_copy]);
+    self.data = &self.data[to_copy..];
+    Ok(to_copy)
+  }
+}
+
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+fn read_all_embedded<R: super::CustomRead<super::EmbeddedIoError<E>>, E>(reader: R) -> Result<::alloc_crate::vec::Vec<u8>, super::EmbeddedIoError<E>> {
+  let mut decompressor = super::DecompressorCustomIo::new(reader,
+                                                          super::BoxedSlice::from(::alloc_crate::vec::Vec::from(&[0u8; 64][..])),
+                                                          super::BoxAlloc,
+                                                          super::BoxAlloc,
+                                                          super::BoxAlloc,
+                                                          super::EmbeddedIoError::InvalidData);
+  let mut output = ::alloc_crate::vec::Vec::new();
+  let mut buf = [0u8; 1000];
+  loop {
+    match super::CustomRead::read(&mut decompressor, &mut buf[..])? {
+      0 => return Ok(output),
+      size => output.extend_from_slice(&buf[..size]),
+    }
+  }
+}
+
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+#[test]
+fn test_embedded_io_reader() {
+  let reader = InterruptingReader { data: &QUICKFOX_REPEATED_BR[..], interrupt: false };
+  assert_quickfox_repeated(&read_all_embedded(super::EmbeddedIoReader(reader)).unwrap());
+  assert_eq!(read_all_embedded(super::EmbeddedIoReader(&QUICKFOX_REPEATED_BR[..30])).unwrap_err(),
+             super::EmbeddedIoError::InvalidData);
+}
+
+#[cfg(all(feature="embedded-io", feature="alloc"))]
+#[test]
+fn test_embedded_io_writer() {
+  let mut output = ::alloc_crate::vec![0u8; 176128 + 1];
+  {
+    let mut writer = super::DecompressorWriterCustomIo::new(super::EmbeddedIoWriter(&mut output[..]),
+                                                            super::BoxedSlice::from(::alloc_crate::vec::Vec::from(&[0u8; 64][..])),
+                                                            super::BoxAlloc,
+                                                            super::BoxAlloc,
+                                                            super::BoxAlloc,
+                                                            super::EmbeddedIoError::InvalidData);
+    super::io_wrappers::write_all(&mut writer, &QUICKFOX_REPEATED_BR[..]).unwrap();
+    assert_eq!(writer.close(), Ok(()));
+    assert_eq!(writer.get_ref().0.len(), 1);
+  }
+  assert_quickfox_repeated(&output[..176128]);
+
+  // a full flash region surfaces the peripheral's own error
+  let mut writer = super::DecompressorWriterCustomIo::new(super::EmbeddedIoWriter(&mut output[..1000]),
+                                                          super::BoxedSlice::from(::alloc_crate::vec::Vec::from(&[0u8; 64][..])),
+                                                          super::BoxAlloc,
+                                                          super::BoxAlloc,
+                                                          super::BoxAlloc,
+                                                          super::EmbeddedIoError::InvalidData);
+  assert_eq!(super::io_wrappers::write_all(&mut writer, &QUICKFOX_REPEATED_BR[..]),
+             Err(super::EmbeddedIoError::Io(::embedded_io::SliceWriteError::Full)));
+}
+
+#[cfg(all(feature="std", feature="external-static-dictionary"))]
+#[test]
+fn test_external_static_dictionary_entry_points() {
+  use std::io::{Read, Write};
+  let alice_br = include_bytes!("../testdata/alice29.txt.compressed");
+  let alice = include_bytes!("../testdata/alice29.txt");
+  let dictionary = super::BrotliStaticDictionary::new(
+    super::SharedCustomDictionary::Static(include_bytes!("../testdata/dictionary.bin"))).unwrap();
+
+  let mut output = vec![0u8; alice.len()];
+  let info = super::brotli_decode(&alice_br[..], &mut output[..]);
+  assert!(matches!(info.error_code, super::BrotliDecoderErrorCode::BROTLI_DECODER_ERROR_DICTIONARY_NOT_SET));
+  let info = super::brotli_decode_with_static_dictionary(&alice_br[..], &mut output[..], &dictionary);
+  assert!(matches!(info.result, BrotliResult::ResultSuccess));
+  assert_eq!(info.decoded_size, alice.len());
+  assert!(output[..] == alice[..]);
+
+  assert!(super::decompress_to_vec(&alice_br[..]).is_err());
+  assert!(super::decompress_to_vec_with_static_dictionary(&alice_br[..], &dictionary).unwrap()[..] == alice[..]);
+
+  let mut output = Vec::new();
+  super::Decompressor::new_with_static_dictionary(&alice_br[..], 4096, &dictionary)
+    .read_to_end(&mut output).unwrap();
+  assert!(output[..] == alice[..]);
+
+  let mut output = Vec::new();
+  {
+    let mut writer = super::DecompressorWriter::new_with_static_dictionary(&mut output, 4096, &dictionary);
+    writer.write_all(&alice_br[..]).unwrap();
+    writer.close().unwrap();
+  }
+  assert!(output[..] == alice[..]);
+}
@@ -1,6 +1,6 @@
 #![allow(non_upper_case_globals)]
 
-const kIdentity: u8 = 0;
+pub(crate) const kIdentity: u8 = 0;
 const kOmitLast1: u8 = 1;
 const kOmitLast2: u8 = 2;
 const kOmitLast3: u8 = 3;
@@ -9,10 +9,10 @@ const kOmitLast5: u8 = 5;
 const kOmitLast6: u8 = 6;
 const kOmitLast7: u8 = 7;
 const kOmitLast8: u8 = 8;
-const kOmitLast9: u8 = 9;
-const kUppercaseFirst: u8 = 10;
-const kUppercaseAll: u8 = 11;
-const kOmitFirst1: u8 = 12;
+pub(crate) const kOmitLast9: u8 = 9;
+pub(crate) const kUppercaseFirst: u8 = 10;
+pub(crate) const kUppercaseAll: u8 = 11;
+pub(crate) const kOmitFirst1: u8 = 12;
 const kOmitFirst2: u8 = 13;
 const kOmitFirst3: u8 = 14;
 const kOmitFirst4: u8 = 15;
@@ -20,7 +20,12 @@ const kOmitFirst5: u8 = 16;
 const kOmitFirst6: u8 = 17;
 const kOmitFirst7: u8 = 18;
 // const  kOmitFirst8     : u8 = 19; // <-- unused (reserved)
-const kOmitFirst9: u8 = 20;
+pub(crate) const kOmitFirst9: u8 = 20;
+// Only used by the transform lists of shared dictionaries; their parameter
+// is a 16 bit signed offset added to the unicode scalar values of the word.
+pub(crate) const kShiftFirst: u8 = 21;
+pub(crate) const kShiftAll: u8 = 22;
+pub(crate) const kNumTransformTypes: u8 = 23;
 
 
 pub struct Transform {
@@ -29,7 +34,7 @@ pub struct Transform {
   pub suffix_id: u8,
 }
 
-const kPrefixSuffix: [u8; 208] =
+pub(crate) const kPrefixSuffix: [u8; 208] =
   [0x00, 0x20, 0x00, 0x2c, 0x20, 0x00, 0x20, 0x6f, 0x66, 0x20, 0x74, 0x68, 0x65, 0x20, 0x00, 0x20,
    0x6f, 0x66, 0x20, 0x00, 0x73, 0x20, 0x00, 0x2e, 0x00, 0x20, 0x61, 0x6e, 0x64, 0x20, 0x00, 0x20,
    0x69, 0x6e, 0x20, 0x00, 0x22, 0x00, 0x20, 0x74, 0x6f, 0x20, 0x00, 0x22, 0x3e, 0x00, 0x0a, 0x00,
@@ -734,6 +739,115 @@ fn ToUpperCase(p: &mut [u8]) -> i32 {
   3
 }
 
+// Adds the sign-extended parameter to the scalar value of the utf-8
+// character at the start of word and returns its length in bytes.
+// Continuation bytes and truncated characters are left as they are.
+fn Shift(word: &mut [u8], word_len: i32, parameter: u16) -> i32 {
+  // Limited sign extension: scalar < (1 << 24).
+  let mut scalar: u32 = (u32::from(parameter) & 0x7fff)
+    .wrapping_add(0x1000000 - (u32::from(parameter) & 0x8000));
+  if (fast!((word)[0]) < 0x80) {
+    // 1-byte rune / 0sssssss / 7 bit scalar (ASCII).
+    scalar = scalar.wrapping_add(u32::from(fast!((word)[0])));
+    fast_mut!((word)[0]) = (scalar & 0x7f) as u8;
+    return 1;
+  } else if (fast!((word)[0]) < 0xc0) {
+    // Continuation / 10AAAAAA.
+    return 1;
+  } else if (fast!((word)[0]) < 0xe0) {
+    // 2-byte rune / 110sssss AAssssss / 11 bit scalar.
+    if (word_len < 2) {
+      return 1;
+    }
+    scalar = scalar.wrapping_add(u32::from(fast!((word)[1]) & 0x3f) |
+                                 (u32::from(fast!((word)[0]) & 0x1f) << 6));
+    fast_mut!((word)[0]) = (0xc0 | ((scalar >> 6) & 0x1f)) as u8;
+    fast_mut!((word)[1]) = ((fast!((word)[1]) & 0xc0) as u32 | (scalar & 0x3f)) as u8;
+    return 2;
+  } else if (fast!((word)[0]) < 0xf0) {
+    // 3-byte rune / 1110ssss AAssssss BBssssss / 16 bit scalar.
+    if (word_len < 3) {
+      return word_len;
+    }
+    scalar = scalar.wrapping_add(u32::from(fast!((word)[2]) & 0x3f) |
+                                 (u32::from(fast!((word)[1]) & 0x3f) << 6) |
+                                 (u32::from(fast!((word)[0]) & 0x0f) << 12));
+    fast_mut!((word)[0]) = (0xe0 | ((scalar >> 12) & 0x0f)) as u8;
+    fast_mut!((word)[1]) = ((fast!((word)[1]) & 0xc0) as u32 | ((scalar >> 6) & 0x3f)) as u8;
+    fast_mut!((word)[2]) = ((fast!((word)[2]) & 0xc0) as u32 | (scalar & 0x3f)) as u8;
+    return 3;
+  } else if (fast!((word)[0]) < 0xf8) {
+    // 4-byte rune / 11110sss AAssssss BBssssss CCssssss / 21 bit scalar.
+    if (word_len < 4) {
+      return word_len;
+    }
+    scalar = scalar.wrapping_add(u32::from(fast!((word)[3]) & 0x3f) |
+                                 (u32::from(fast!((word)[2]) & 0x3f) << 6) |
+                                 (u32::from(fast!((word)[1]) & 0x3f) << 12) |
+                                 (u32::from(fast!((word)[0]) & 0x07) << 18));
+    fast_mut!((word)[0]) = (0xf0 | ((scalar >> 18) & 0x07)) as u8;
+    fast_mut!((word)[1]) = ((fast!((word)[1]) & 0xc0) as u32 | ((scalar >> 12) & 0x3f)) as u8;
+    fast_mut!((word)[2]) = ((fast!((word)[2]) & 0xc0) as u32 | ((scalar >> 6) & 0x3f)) as u8;
+    fast_mut!((word)[3]) = ((fast!((word)[3]) & 0xc0) as u32 | (scalar & 0x3f)) as u8;
+    return 4;
+  }
+  1
+}
+
+// Applies a transform from a shared dictionary transform list: prefix and
+// suffix are the stringlets it refers to, param is only used by the shift
+// transforms. Returns the number of bytes written to dst.
+pub fn TransformDictionaryWordWith(dst: &mut [u8],
+                                   mut word: &[u8],
+                                   mut len: i32,
+                                   prefix: &[u8],
+                                   t: u8,
+                                   suffix: &[u8],
+                                   param: u16)
+                                   -> i32 {
+  let mut idx: i32 = prefix.len() as i32;
+  fast_mut!((dst)[0 ; prefix.len()]).clone_from_slice(prefix);
+  if (t <= kOmitLast9) {
+    len -= t as i32;
+  } else if (kOmitFirst1..=kOmitFirst9).contains(&t) {
+    let mut skip = t as i32 - (kOmitFirst1 - 1) as i32;
+    if (skip > len) {
+      skip = len;
+    }
+    word = fast!((word)[skip as usize;]);
+    len -= skip;
+  }
+  if (len < 0) {
+    len = 0;
+  }
+  fast_mut!((dst)[idx as usize ; (idx + len) as usize])
+    .clone_from_slice(fast!((word)[0 ; len as usize]));
+  idx += len;
+  {
+    let transformed = fast_mut!((dst)[(idx - len) as usize ;]);
+    let mut offset: usize = 0;
+    if (t == kUppercaseFirst) {
+      ToUpperCase(transformed);
+    } else if (t == kUppercaseAll) {
+      while (len > 0) {
+        let step = ToUpperCase(fast_mut!((transformed)[offset;]));
+        offset += step as usize;
+        len -= step;
+      }
+    } else if (t == kShiftFirst) {
+      Shift(transformed, len, param);
+    } else if (t == kShiftAll) {
+      while (len > 0) {
+        let step = Shift(fast_mut!((transformed)[offset;]), len, param);
+        offset += step as usize;
+        len -= step;
+      }
+    }
+  }
+  fast_mut!((dst)[idx as usize ; idx as usize + suffix.len()]).clone_from_slice(suffix);
+  idx + suffix.len() as i32
+}
+
 pub fn TransformDictionaryWord(dst: &mut [u8],
                                mut word: &[u8],
                                mut len: i32,
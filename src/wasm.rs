@@ -0,0 +1,190 @@
+// wasm-bindgen front end for browsers and edge runtimes, where
+// DecompressionStream does not understand "br". The README describes how to
+// build the module and run its tests under node.
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::vec::Vec;
+use wasm_bindgen::prelude::*;
+use alloc_stdlib::StandardAlloc;
+use ::{BrotliDecompressStream, BrotliDecoderErrorCode, BrotliDecoderErrorStr, BrotliResult, BrotliState,
+       BrotliDecoderSetStaticDictionary, BrotliStaticDictionary, SharedCustomDictionary};
+
+const OUTPUT_CHUNK_SIZE: usize = 65536;
+
+thread_local! {
+  static STATIC_DICTIONARY: RefCell<Option<BrotliStaticDictionary>> = const { RefCell::new(None) };
+}
+
+/// Supplies the static dictionary to decoders created afterwards, for modules
+/// built with the external-static-dictionary feature. Fails if the bytes are
+/// not the RFC 7932 dictionary.
+#[wasm_bindgen]
+pub fn set_static_dictionary(dictionary: Vec<u8>) -> Result<(), JsError> {
+  let dictionary = match BrotliStaticDictionary::new(SharedCustomDictionary::Arc(Arc::from(dictionary))) {
+    Some(dictionary) => dictionary,
+    None => return Err(JsError::new("not the brotli static dictionary")),
+  };
+  STATIC_DICTIONARY.with(|shared| *shared.borrow_mut() = Some(dictionary));
+  Ok(())
+}
+
+#[derive(Debug)]
+enum StreamError {
+  Corrupt(BrotliDecoderErrorCode),
+  Truncated,
+  TrailingData,
+}
+
+impl From<StreamError> for JsError {
+  fn from(e: StreamError) -> JsError {
+    match e {
+      StreamError::Corrupt(code) => JsError::new(BrotliDecoderErrorStr(code).trim_end_matches('\0')),
+      StreamError::Truncated => JsError::new("brotli stream is truncated"),
+      StreamError::TrailingData => JsError::new("data after the end of the brotli stream"),
+    }
+  }
+}
+
+/// Decompresses a complete brotli stream in one call.
+#[wasm_bindgen]
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, JsError> {
+  let mut decoder = StreamDecoder::new();
+  let output = decoder.push(input)?;
+  decoder.finish()?;
+  Ok(output)
+}
+
+/// Incremental decoder: feed compressed chunks with push() as they arrive and
+/// call finish() once the input has ended.
+#[wasm_bindgen]
+pub struct StreamDecoder {
+  state: BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>,
+  total_out: usize,
+  done: bool,
+}
+
+impl Default for StreamDecoder {
+  fn default() -> Self {
+    StreamDecoder::new()
+  }
+}
+
+#[wasm_bindgen]
+impl StreamDecoder {
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> StreamDecoder {
+    let mut state = BrotliState::new(StandardAlloc::default(),
+                                     StandardAlloc::default(),
+                                     StandardAlloc::default());
+    STATIC_DICTIONARY.with(|shared| if let Some(ref dictionary) = *shared.borrow() {
+      BrotliDecoderSetStaticDictionary(&mut state, dictionary);
+    });
+    StreamDecoder {
+      state,
+      total_out: 0,
+      done: false,
+    }
+  }
+
+  /// Decodes chunk and returns the bytes that became available.
+  pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(self.push_chunk(chunk)?)
+  }
+
+  /// Fails unless the stream has been decoded completely.
+  pub fn finish(&mut self) -> Result<(), JsError> {
+    Ok(self.check_finished()?)
+  }
+}
+
+impl StreamDecoder {
+  fn push_chunk(&mut self, chunk: &[u8]) -> Result<Vec<u8>, StreamError> {
+    let mut output = Vec::new();
+    let mut available_in = chunk.len();
+    let mut input_offset = 0;
+    loop {
+      if self.done {
+        if available_in != 0 {
+          return Err(StreamError::TrailingData);
+        }
+        return Ok(output);
+      }
+      let mut output_offset = output.len();
+      output.resize(output_offset + OUTPUT_CHUNK_SIZE, 0);
+      let mut available_out = OUTPUT_CHUNK_SIZE;
+      let result = BrotliDecompressStream(&mut available_in,
+                                          &mut input_offset,
+                                          chunk,
+                                          &mut available_out,
+                                          &mut output_offset,
+                                          &mut output,
+                                          &mut self.total_out,
+                                          &mut self.state);
+      output.truncate(output_offset);
+      match result {
+        BrotliResult::NeedsMoreOutput => {},
+        BrotliResult::NeedsMoreInput => return Ok(output),
+        BrotliResult::ResultSuccess => self.done = true,
+        BrotliResult::ResultFailure => return Err(StreamError::Corrupt(self.state.error_code)),
+      }
+    }
+  }
+
+  fn check_finished(&self) -> Result<(), StreamError> {
+    match self.state.error_code {
+      _ if self.done => Ok(()),
+      BrotliDecoderErrorCode::BROTLI_DECODER_NEEDS_MORE_INPUT |
+      BrotliDecoderErrorCode::BROTLI_DECODER_SUCCESS => Err(StreamError::Truncated),
+      code => Err(StreamError::Corrupt(code)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(target_arch="wasm32")]
+  use wasm_bindgen_test::wasm_bindgen_test as test;
+
+  static ALICE_BR: &[u8] = include_bytes!("../testdata/alice29.txt.compressed");
+  static ALICE: &[u8] = include_bytes!("../testdata/alice29.txt");
+
+  #[test]
+  fn push_in_chunks() {
+    let mut decoder = StreamDecoder::new();
+    let mut output = Vec::new();
+    for chunk in ALICE_BR.chunks(1000) {
+      output.extend(decoder.push_chunk(chunk).unwrap());
+    }
+    assert!(decoder.check_finished().is_ok());
+    assert_eq!(&output[..], ALICE);
+  }
+
+  #[test]
+  fn truncated_and_trailing_input() {
+    let mut decoder = StreamDecoder::new();
+    decoder.push_chunk(&ALICE_BR[..ALICE_BR.len() - 1]).unwrap();
+    assert!(matches!(decoder.check_finished(), Err(StreamError::Truncated)));
+
+    let mut decoder = StreamDecoder::new();
+    let mut input = ALICE_BR.to_vec();
+    input.push(0);
+    assert!(matches!(decoder.push_chunk(&input), Err(StreamError::TrailingData)));
+  }
+
+  #[test]
+  fn corrupt_input() {
+    let mut decoder = StreamDecoder::new();
+    let mut input = ALICE_BR.to_vec();
+    input[9] ^= 0xff;
+    assert!(matches!(decoder.push_chunk(&input), Err(StreamError::Corrupt(_))));
+    assert!(matches!(decoder.check_finished(), Err(StreamError::Corrupt(_))));
+  }
+
+  #[cfg(target_arch="wasm32")]
+  #[test]
+  fn decompress_one_shot() {
+    assert_eq!(&decompress(ALICE_BR).unwrap()[..], ALICE);
+    assert!(decompress(&ALICE_BR[..10]).is_err());
+  }
+}
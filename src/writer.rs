@@ -6,13 +6,17 @@ pub use alloc_stdlib::StandardAlloc;
 #[cfg(all(feature="unsafe",feature="std"))]
 pub use alloc_stdlib::HeapAlloc;
 pub use huffman::{HuffmanCode, HuffmanTreeGroup};
-pub use state::BrotliState;
+pub use state::{BrotliState, BrotliDecoderProgressFunc, SharedCustomDictionary};
 // use io_wrappers::write_all;
 pub use io_wrappers::{CustomWrite};
 #[cfg(feature="std")]
 pub use io_wrappers::{IntoIoWriter, IoWriterWrapper};
 pub use super::decode::{BrotliDecompressStream, BrotliResult};
+use decode::BrotliStaticDictionary;
 pub use alloc::{AllocatedStackMemory, Allocator, SliceWrapper, SliceWrapperMut, StackAllocator};
+#[cfg(feature="alloc")]
+pub use heap_alloc::{BoxAlloc, BoxedSlice};
+pub use mem_stats::{AccountingAlloc, MemStats};
 
 #[cfg(feature="std")]
 pub struct DecompressorWriterCustomAlloc<W: Write,
@@ -52,6 +56,19 @@ impl<W: Write,
                                                                   Error::new(ErrorKind::InvalidData,
                                                                              "Invalid Data")))
     }
+    pub fn new_with_shared_dictionary(w: W, buffer : BufferType,
+               alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC, dict: SharedCustomDictionary) -> Self {
+        DecompressorWriterCustomAlloc::<W, BufferType, AllocU8, AllocU32, AllocHC>(
+          DecompressorWriterCustomIo::<Error,
+                                 IntoIoWriter<W>,
+                                 BufferType,
+                                 AllocU8, AllocU32, AllocHC>::new_with_shared_dictionary(IntoIoWriter::<W>(w),
+                                                                  buffer,
+                                                                  alloc_u8, alloc_u32, alloc_hc,
+                                                                  dict,
+                                                                  Error::new(ErrorKind::InvalidData,
+                                                                             "Invalid Data")))
+    }
 
     pub fn get_ref(&self) -> &W {
         &self.0.get_ref().0
@@ -65,6 +82,13 @@ impl<W: Write,
             Err(w) => Err(w.0),
         }
     }
+    pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                                 interval: usize, opaque: usize) {
+        self.0.set_progress_callback(progress, interval, opaque)
+    }
+    pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+        self.0.set_static_dictionary(dictionary)
+    }
 }
 #[cfg(feature="std")]
 impl<W: Write,
@@ -99,6 +123,22 @@ impl<W: Write,
     }
 }
 
+#[cfg(feature="std")]
+impl<W: Write,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > DecompressorWriterCustomAlloc<W,
+                                                                      BufferType,
+                                                                      AccountingAlloc<AllocU8>,
+                                                                      AccountingAlloc<AllocU32>,
+                                                                      AccountingAlloc<AllocHC>> {
+    /// Memory the decoder has allocated so far, by category.
+    pub fn mem_stats(&self) -> MemStats {
+        self.0.mem_stats()
+    }
+}
+
 
 #[cfg(not(any(feature="unsafe", not(feature="std"))))]
 pub struct DecompressorWriter<W: Write>(DecompressorWriterCustomAlloc<W,
@@ -129,6 +169,23 @@ impl<W: Write> DecompressorWriter<W> {
                                                                               StandardAlloc::default(),
                                                                               dict))
   }
+  /// Decodes with a custom dictionary that is shared rather than copied,
+  /// such as an Arc<[u8]> used by many decompressors at once.
+  pub fn new_with_shared_dictionary(w: W, buffer_size: usize, dict: SharedCustomDictionary) -> Self {
+    let mut alloc = StandardAlloc::default();
+    let buffer = <StandardAlloc as Allocator<u8>>::alloc_cell(&mut alloc, if buffer_size == 0 {4096} else {buffer_size});
+    DecompressorWriter::<W>(DecompressorWriterCustomAlloc::<W,
+                                                <StandardAlloc
+                                                 as Allocator<u8>>::AllocatedMemory,
+                                                StandardAlloc,
+                                                StandardAlloc,
+                                                StandardAlloc>::new_with_shared_dictionary(w,
+                                                                              buffer,
+                                                                              alloc,
+                                                                              StandardAlloc::default(),
+                                                                              StandardAlloc::default(),
+                                                                              dict))
+  }
 
   pub fn get_ref(&self) -> &W {
       self.0.get_ref()
@@ -139,6 +196,15 @@ impl<W: Write> DecompressorWriter<W> {
   pub fn into_inner(self) -> Result<W, W> {
     self.0.into_inner()
   }
+  /// See DecompressorWriterCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorWriterCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
 }
 
 
@@ -170,6 +236,21 @@ impl<W: Write> DecompressorWriter<W> {
                                                 HeapAlloc<HuffmanCode> >
       ::new_with_custom_dictionary(w, buffer, alloc_u8, alloc_u32, alloc_hc, dict))
   }
+  /// Decodes with a custom dictionary that is shared rather than copied,
+  /// such as an Arc<[u8]> used by many decompressors at once.
+  pub fn new_with_shared_dictionary(w: W, buffer_size: usize, dict: SharedCustomDictionary) -> Self {
+    let mut alloc_u8 = HeapAlloc::<u8>::new(0);
+    let buffer = alloc_u8.alloc_cell(if buffer_size == 0 {4096} else {buffer_size});
+    let alloc_u32 = HeapAlloc::<u32>::new(0);
+    let alloc_hc = HeapAlloc::<HuffmanCode>::new(HuffmanCode{bits:2, value: 1});
+    DecompressorWriter::<W>(DecompressorWriterCustomAlloc::<W,
+                                                <HeapAlloc<u8>
+                                                 as Allocator<u8>>::AllocatedMemory,
+                                                HeapAlloc<u8>,
+                                                HeapAlloc<u32>,
+                                                HeapAlloc<HuffmanCode> >
+      ::new_with_shared_dictionary(w, buffer, alloc_u8, alloc_u32, alloc_hc, dict))
+  }
 
   pub fn get_ref(&self) -> &W {
       self.0.get_ref()
@@ -180,10 +261,27 @@ impl<W: Write> DecompressorWriter<W> {
   pub fn into_inner(self) -> Result<W, W> {
     self.0.into_inner()
   }
+  /// See DecompressorWriterCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorWriterCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
 }
 
 #[cfg(feature="std")]
 impl<W: Write> DecompressorWriter<W> {
+    /// Decodes with a static dictionary supplied at runtime, for builds with
+    /// the external-static-dictionary feature.
+    pub fn new_with_static_dictionary(w: W, buffer_size: usize,
+                                      dictionary: &BrotliStaticDictionary) -> Self {
+        let mut writer = Self::new(w, buffer_size);
+        writer.set_static_dictionary(dictionary);
+        writer
+    }
     pub fn close(&mut self) -> Result<(), Error>{
         self.0.close()
     }
@@ -243,14 +341,32 @@ impl<ErrType,
                alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC,
                dict: AllocU8::AllocatedMemory,
                invalid_data_error_type : ErrType) -> Self {
+        let state = BrotliState::new_with_custom_dictionary(alloc_u8,
+                                                           alloc_u32,
+                                                           alloc_hc,
+                                                           dict);
+        Self::new_with_state(w, buffer, state, invalid_data_error_type)
+    }
+    /// Like new_with_custom_dictionary, but reads the dictionary in place
+    /// rather than taking an allocation of its own.
+    pub fn new_with_shared_dictionary(w: W, buffer : BufferType,
+               alloc_u8 : AllocU8, alloc_u32 : AllocU32, alloc_hc : AllocHC,
+               dict: SharedCustomDictionary,
+               invalid_data_error_type : ErrType) -> Self {
+        let state = BrotliState::new_with_shared_dictionary(alloc_u8,
+                                                           alloc_u32,
+                                                           alloc_hc,
+                                                           dict);
+        Self::new_with_state(w, buffer, state, invalid_data_error_type)
+    }
+    fn new_with_state(w: W, buffer : BufferType,
+                      state: BrotliState<AllocU8, AllocU32, AllocHC>,
+                      invalid_data_error_type : ErrType) -> Self {
         DecompressorWriterCustomIo::<ErrType, W, BufferType, AllocU8, AllocU32, AllocHC>{
             output_buffer : buffer,
             total_out : 0,
             output: Some(w),
-            state : BrotliState::new_with_custom_dictionary(alloc_u8,
-                                                                 alloc_u32,
-                                                                 alloc_hc,
-                                                                 dict),
+            state,
             error_if_invalid_data : Some(invalid_data_error_type),
         }
     }
@@ -300,6 +416,38 @@ impl<ErrType,
             Err(_) => Err((core::mem::replace(&mut self.output, None).unwrap())),
         }
     }
+
+    /// Calls progress(opaque, compressed bytes consumed, bytes decompressed)
+    /// as writing goes; see BrotliDecoderSetProgressCallback.
+    pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                                 interval: usize, opaque: usize) {
+        ::decode::BrotliDecoderSetProgressCallback(&mut self.state, progress, interval, opaque);
+    }
+
+    /// Supplies the static dictionary before the first byte is decoded; see
+    /// BrotliDecoderSetStaticDictionary. Needed only with the
+    /// external-static-dictionary feature.
+    pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+        ::decode::BrotliDecoderSetStaticDictionary(&mut self.state, dictionary)
+    }
+}
+
+impl<ErrType,
+     W: CustomWrite<ErrType>,
+     BufferType : SliceWrapperMut<u8>,
+     AllocU8 : Allocator<u8>,
+     AllocU32 : Allocator<u32>,
+     AllocHC : Allocator<HuffmanCode> > DecompressorWriterCustomIo<ErrType,
+                                                                   W,
+                                                                   BufferType,
+                                                                   AccountingAlloc<AllocU8>,
+                                                                   AccountingAlloc<AllocU32>,
+                                                                   AccountingAlloc<AllocHC>> {
+    /// Memory the decoder has allocated so far, by category. After close()
+    /// the peaks give the memory the stream needed.
+    pub fn mem_stats(&self) -> MemStats {
+        self.state.mem_stats()
+    }
 }
 
 impl<ErrType,
@@ -371,6 +519,77 @@ impl<ErrType,
     }
 }
 
+/// Streaming decompressor onto a CustomWrite that allocates from the global
+/// heap through the alloc crate, for targets without std.
+#[cfg(feature="alloc")]
+pub struct DecompressorWriterHeap<ErrType, W: CustomWrite<ErrType>>(DecompressorWriterCustomIo<ErrType,
+                                                                                               W,
+                                                                                               BoxedSlice<u8>,
+                                                                                               BoxAlloc,
+                                                                                               BoxAlloc,
+                                                                                               BoxAlloc>);
+
+#[cfg(feature="alloc")]
+impl<ErrType, W: CustomWrite<ErrType>> DecompressorWriterHeap<ErrType, W> {
+  pub fn new(w: W, buffer_size: usize, invalid_data_error_type: ErrType) -> Self {
+    Self::new_with_custom_dictionary(w, buffer_size, BoxedSlice::<u8>::default(), invalid_data_error_type)
+  }
+  pub fn new_with_custom_dictionary(w: W, buffer_size: usize, dict: BoxedSlice<u8>,
+                                    invalid_data_error_type: ErrType) -> Self {
+    let buffer = <BoxAlloc as Allocator<u8>>::alloc_cell(&mut BoxAlloc, if buffer_size == 0 {4096} else {buffer_size});
+    DecompressorWriterHeap::<ErrType, W>(DecompressorWriterCustomIo::new_with_custom_dictionary(w,
+                                                                                                 buffer,
+                                                                                                 BoxAlloc,
+                                                                                                 BoxAlloc,
+                                                                                                 BoxAlloc,
+                                                                                                 dict,
+                                                                                                 invalid_data_error_type))
+  }
+  pub fn new_with_shared_dictionary(w: W, buffer_size: usize, dict: SharedCustomDictionary,
+                                    invalid_data_error_type: ErrType) -> Self {
+    let buffer = <BoxAlloc as Allocator<u8>>::alloc_cell(&mut BoxAlloc, if buffer_size == 0 {4096} else {buffer_size});
+    DecompressorWriterHeap::<ErrType, W>(DecompressorWriterCustomIo::new_with_shared_dictionary(w,
+                                                                                                 buffer,
+                                                                                                 BoxAlloc,
+                                                                                                 BoxAlloc,
+                                                                                                 BoxAlloc,
+                                                                                                 dict,
+                                                                                                 invalid_data_error_type))
+  }
+  pub fn close(&mut self) -> Result<(), ErrType> {
+    self.0.close()
+  }
+
+  pub fn get_ref(&self) -> &W {
+    self.0.get_ref()
+  }
+  pub fn get_mut(&mut self) -> &mut W {
+    self.0.get_mut()
+  }
+  pub fn into_inner(self) -> Result<W, W> {
+    self.0.into_inner()
+  }
+  /// See DecompressorWriterCustomIo::set_progress_callback.
+  pub fn set_progress_callback(&mut self, progress: Option<BrotliDecoderProgressFunc>,
+                               interval: usize, opaque: usize) {
+    self.0.set_progress_callback(progress, interval, opaque)
+  }
+  /// See DecompressorWriterCustomIo::set_static_dictionary.
+  pub fn set_static_dictionary(&mut self, dictionary: &BrotliStaticDictionary) -> bool {
+    self.0.set_static_dictionary(dictionary)
+  }
+}
+
+#[cfg(feature="alloc")]
+impl<ErrType, W: CustomWrite<ErrType>> CustomWrite<ErrType> for DecompressorWriterHeap<ErrType, W> {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, ErrType> {
+    self.0.write(buf)
+  }
+  fn flush(&mut self) -> Result<(), ErrType> {
+    self.0.flush()
+  }
+}
+
 #[cfg(feature="std")]
 #[cfg(test)]
 mod test {